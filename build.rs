@@ -0,0 +1,47 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+// Statische Seiten schon beim Bauen komprimieren, das spart zur Laufzeit Rechenzeit und RAM
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    compress("src/dashboard.html", &Path::new(&out_dir).join("dashboard.html.gz"));
+    embed_pem("TLS_CERT_FILE", &Path::new(&out_dir).join("server_cert.pem"));
+    embed_pem("TLS_KEY_FILE", &Path::new(&out_dir).join("server_key.pem"));
+
+    // Ein leeres Token passt auf einen leeren Bearer-Header und `?token=`, das Gerät wäre offen
+    println!("cargo:rerun-if-env-changed=API_TOKEN");
+    if std::env::var("API_TOKEN").is_ok_and(|token| token.trim().is_empty()) {
+        panic!("API_TOKEN ist leer, damit könnte jeder das Relais schalten");
+    }
+
+    // Zeitpunkt des Builds für /status, als Unix-Zeit
+    let build_time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", build_time);
+}
+
+// Zertifikat bzw. Schlüssel für HTTPS aus der Datei in `var` übernehmen, nullterminiert wie es
+// mbedTLS für PEM erwartet. Ohne `var` bleibt die Datei leer und der Server spricht HTTP.
+fn embed_pem(var: &str, target: &Path) {
+    println!("cargo:rerun-if-env-changed={}", var);
+    let mut pem = Vec::new();
+    if let Ok(source) = std::env::var(var) {
+        println!("cargo:rerun-if-changed={}", source);
+        pem = std::fs::read(&source).unwrap();
+        pem.push(0);
+    }
+    std::fs::write(target, pem).unwrap();
+}
+
+fn compress(source: &str, target: &Path) {
+    println!("cargo:rerun-if-changed={}", source);
+    let html = std::fs::read(source).unwrap();
+    let mut encoder = GzEncoder::new(File::create(target).unwrap(), Compression::best());
+    encoder.write_all(&html).unwrap();
+    encoder.finish().unwrap();
+}
@@ -0,0 +1,95 @@
+//! Adressfilter für alles, was das Relais aus dem Netz schaltet, zusätzlich zum Token.
+//!
+//! `PUSH_ALLOWLIST` nimmt eine kommagetrennte Liste von Netzen und Adressen, z.B.
+//! `PUSH_ALLOWLIST=192.168.1.10,10.0.0.0/24,fd00::/8`. Ist sie gesetzt, schalten nur Clients aus
+//! diesen Netzen, alle anderen bekommen 403 bzw. 4.03, auch mit gültigem Token. Das gilt für
+//! `/push`, `/release`, `/sequence`, `/relay/...` und CoAP-`/push`. `/status`, `/health` und die
+//! übrigen Endpunkte bleiben davon unberührt.
+//!
+//! Ausgenommen sind MQTT, dort kommt jeder Befehl vom Broker und dessen ACL entscheidet, wer
+//! schalten darf, sowie Taster, Drehgeber und Zeitplan am Gerät selbst.
+//!
+//! Fehlerhafte Einträge werden mit Warnung übersprungen. Bleibt keiner übrig, ist das Schalten für alle
+//! gesperrt, statt stillschweigend wieder offen zu sein.
+
+use log::*;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// Ein Netz in CIDR-Schreibweise, eine einzelne Adresse hat die volle Präfixlänge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Liest `addr` oder `addr/prefix`, Host-Bits hinter dem Präfix werden ignoriert.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = addr.parse().map_err(|_| format!("ungültige Adresse {:?}", value))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            None => max,
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("ungültige Präfixlänge in {:?}", value))?,
+        };
+        Ok(Self { network, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Erlaubte Netze, geteilt zwischen den Handlern.
+#[derive(Debug, Clone)]
+pub struct Allowlist {
+    networks: Arc<Vec<Cidr>>,
+}
+
+impl Allowlist {
+    /// Netze aus `PUSH_ALLOWLIST`, ohne die Variable `None` und damit kein Filter.
+    pub fn from_env() -> Option<Self> {
+        let value = option_env!("PUSH_ALLOWLIST").filter(|value| !value.trim().is_empty())?;
+        let networks: Vec<Cidr> = value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| {
+                Cidr::parse(entry)
+                    .map_err(|err| warn!("PUSH_ALLOWLIST: {}", err))
+                    .ok()
+            })
+            .collect();
+        if networks.is_empty() {
+            warn!("PUSH_ALLOWLIST enthält kein gültiges Netz, Schalten übers Netz ist gesperrt");
+        } else {
+            info!("Schalten übers Netz nur aus {} Netz(en)", networks.len());
+        }
+        Some(Self {
+            networks: Arc::new(networks),
+        })
+    }
+
+    /// `true`, wenn `ip` in einem der Netze liegt. Ohne bekannte Adresse wird abgewiesen.
+    pub fn allows(&self, ip: Option<IpAddr>) -> bool {
+        ip.is_some_and(|ip| self.networks.iter().any(|network| network.contains(ip)))
+    }
+}
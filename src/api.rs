@@ -0,0 +1,625 @@
+use anyhow::Result;
+use embedded_svc::http::server::Request;
+use embedded_svc::http::Method;
+use embedded_svc::io::{Read, Write};
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::http::server::{EspHttpConnection, EspHttpServer};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::*;
+use std::io::Write as _;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::allowlist::Allowlist;
+use crate::keepalive;
+use crate::lockout::AuthLimiter;
+use crate::logs::{log_request_event, sanitize_path, Event, LogQueue};
+use crate::relay::RelayError;
+
+pub type HttpRequest<'a, 'r> = Request<&'a mut EspHttpConnection<'r>>;
+
+// Kleinere Antworten lohnen den Aufwand für gzip nicht
+const GZIP_MIN_BYTES: usize = 256;
+
+// Größter Request-Body ohne MAX_BODY_BYTES
+const DEFAULT_MAX_BODY_BYTES: usize = 8192;
+
+/// Header, die ein Browser bei Cross-Origin-Requests mitschicken darf.
+pub const CORS_ALLOW_HEADERS: &str = "Authorization, Content-Type, Idempotency-Key";
+
+/// Header, die ein Browser bei Cross-Origin-Requests lesen darf.
+pub const CORS_EXPOSE_HEADERS: &str = "Retry-After, Idempotent-Replayed";
+
+/// Verweis auf den laufenden Server, damit Handler z.B. die offenen Verbindungen zählen können.
+#[derive(Clone, Copy)]
+pub struct ServerHandle {
+    handle: esp_idf_sys::httpd_handle_t,
+    max_sockets: usize,
+}
+
+// Der Server lebt so lange wie das Programm, `httpd_get_client_list` ist threadsicher
+unsafe impl Send for ServerHandle {}
+unsafe impl Sync for ServerHandle {}
+
+impl ServerHandle {
+    pub fn new(server: &EspHttpServer<'static>, max_sockets: usize) -> Self {
+        Self {
+            handle: server.handle(),
+            max_sockets,
+        }
+    }
+
+    /// Zahl der gerade offenen Client-Verbindungen, Streams wie `/events` eingeschlossen.
+    pub fn open_connections(self) -> usize {
+        let mut fds = vec![0i32; self.max_sockets];
+        let mut count = fds.len();
+        let result = unsafe { esp_idf_sys::httpd_get_client_list(self.handle, &mut count, fds.as_mut_ptr()) };
+        if result == 0 {
+            count
+        } else {
+            0
+        }
+    }
+}
+
+/// Registrierte Pfade samt Methode, daraus entstehen `Allow` und der CORS-Preflight.
+///
+/// Handler werden über [`Routes::handle`] registriert statt direkt mit `fn_handler`, damit die
+/// Liste nicht von den tatsächlichen Routen abweichen kann.
+#[derive(Clone, Default)]
+pub struct Routes(Arc<Mutex<Vec<(&'static str, Method)>>>);
+
+impl Routes {
+    /// Registriert `handler` wie `fn_handler` und trägt Pfad und Methode ein.
+    pub fn handle<F>(
+        &self,
+        server: &mut EspHttpServer<'static>,
+        path: &'static str,
+        method: Method,
+        handler: F,
+    ) -> Result<()>
+    where
+        F: for<'a, 'r> Fn(HttpRequest<'a, 'r>) -> Result<()> + Send + 'static,
+    {
+        server.fn_handler(path, method, handler)?;
+        self.record(path, method);
+        Ok(())
+    }
+
+    /// Trägt einen Handler ein, der direkt über `httpd_register_uri_handler` registriert wurde.
+    pub fn record(&self, path: &'static str, method: Method) {
+        self.0.lock().unwrap().push((path, method));
+    }
+
+    /// Methoden für `path` als Wert für den `Allow`-Header, leer für unbekannte Pfade.
+    ///
+    /// Routen auf `/*` gelten wie beim Server für alle Pfade darunter.
+    pub fn allowed_methods(&self, path: &str) -> String {
+        let routes = self.0.lock().unwrap();
+        let mut methods: Vec<&str> = Vec::new();
+        for (route, method) in routes.iter() {
+            let matches = match route.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => *route == path,
+            };
+            // Feste Pfade wie /relay/on stehen zusätzlich unter ihrer Wildcard-Route
+            let name = method_name(*method);
+            if matches && !methods.contains(&name) {
+                methods.push(name);
+            }
+        }
+        methods.join(", ")
+    }
+}
+
+fn method_name(method: Method) -> &'static str {
+    match method {
+        Method::Get => "GET",
+        Method::Post => "POST",
+        Method::Put => "PUT",
+        Method::Delete => "DELETE",
+        Method::Options => "OPTIONS",
+        Method::Head => "HEAD",
+        _ => "OTHER",
+    }
+}
+
+/// Erlaubter Ursprung für Browser-Apps aus `CORS_ORIGIN`, ohne Angabe jeder.
+pub fn cors_origin() -> &'static str {
+    option_env!("CORS_ORIGIN").filter(|origin| !origin.is_empty()).unwrap_or("*")
+}
+
+/// Erfolgreiche Antwort eines JSON-Handlers.
+pub struct JsonReply {
+    status: u16,
+    body: String,
+    headers: Vec<(&'static str, String)>,
+    log_path: Option<String>,
+    event: Option<Event>,
+}
+
+impl JsonReply {
+    pub fn ok(body: impl Into<String>) -> Self {
+        Self::new(200, body)
+    }
+
+    pub fn new(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: body.into(),
+            headers: Vec::new(),
+            log_path: None,
+            event: None,
+        }
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: String) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    /// Loggt unter einem anderen Pfad als dem registrierten, z.B. `/push 500ms`.
+    pub fn log_as(mut self, path: String) -> Self {
+        self.log_path = Some(path);
+        self
+    }
+
+    /// Markiert den Logeintrag als Schaltvorgang, siehe [`Event::Switched`].
+    pub fn switched(mut self) -> Self {
+        self.event = Some(Event::Switched);
+        self
+    }
+}
+
+/// Fehler eines JSON-Handlers, wird als `{ "error": "<message>" }` beantwortet.
+///
+/// Alles, was sich in `anyhow::Error` umwandeln lässt, wird per `?` zu einem 500, Eingabefehler
+/// werden ausdrücklich mit [`HttpError::bad_request`] erzeugt.
+#[derive(Debug)]
+pub struct HttpError {
+    status: u16,
+    message: String,
+    headers: Vec<(&'static str, String)>,
+    log_path: Option<String>,
+}
+
+impl HttpError {
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            headers: Vec::new(),
+            log_path: None,
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(400, message)
+    }
+
+    pub fn unauthorized() -> Self {
+        Self::new(401, "unauthorized")
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: String) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Body der Fehlerantwort, `{ "error": "..." }`.
+    pub fn body(&self) -> String {
+        serde_json::json!({ "error": self.message }).to_string()
+    }
+
+    /// Loggt unter einem anderen Pfad als dem registrierten, wie [`JsonReply::log_as`].
+    pub fn log_as(mut self, path: String) -> Self {
+        self.log_path = Some(path);
+        self
+    }
+
+    /// Beantwortet `req` mit diesem Fehler, für Handler ohne [`json_handler`].
+    pub fn respond(self, req: HttpRequest) -> Result<()> {
+        let headers: Vec<(&str, &str)> = self.headers.iter().map(|(name, value)| (*name, value.as_str())).collect();
+        respond_json(req, self.status, &headers, &self.body())
+    }
+}
+
+/// Liest den Request-Body, höchstens `MAX_BODY_BYTES` (Standard 8 KB).
+///
+/// Größere Bodies enden mit 413, bevor sie komplett im RAM liegen: ein zu großer
+/// `Content-Length` sofort, ein Body ohne Längenangabe sobald er die Grenze überschreitet.
+pub fn read_body(req: &mut HttpRequest) -> Result<Vec<u8>, HttpError> {
+    let limit = option_env!("MAX_BODY_BYTES")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES);
+    let too_large = || HttpError::new(413, format!("body must not exceed {} bytes", limit));
+
+    let declared = req.header("Content-Length").and_then(|value| value.parse::<usize>().ok());
+    if declared.is_some_and(|len| len > limit) {
+        return Err(too_large());
+    }
+    let mut body = Vec::with_capacity(declared.unwrap_or(0));
+    let mut buf = [0u8; 128];
+    loop {
+        let len = req.read(&mut buf).map_err(|err| anyhow::anyhow!("{:?}", err))?;
+        if len == 0 {
+            break;
+        }
+        if body.len() + len > limit {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&buf[..len]);
+    }
+    Ok(body)
+}
+
+/// Format eines Request-Bodys laut `Content-Type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFormat {
+    Json,
+    Form,
+}
+
+/// Format aus dem `Content-Type`-Header, ohne Header JSON wie bisher, sonst 415.
+pub fn body_format(req: &HttpRequest) -> Result<BodyFormat, HttpError> {
+    let Some(content_type) = req.header("Content-Type") else {
+        return Ok(BodyFormat::Json);
+    };
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    if media_type.eq_ignore_ascii_case("application/json") {
+        Ok(BodyFormat::Json)
+    } else if media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+        Ok(BodyFormat::Form)
+    } else {
+        Err(HttpError::new(
+            415,
+            "Content-Type must be application/json or application/x-www-form-urlencoded",
+        ))
+    }
+}
+
+/// Dekodierte Schlüssel-Wert-Paare eines Formular-Bodys in ihrer Reihenfolge.
+pub fn form_pairs(body: &[u8]) -> Vec<(String, String)> {
+    String::from_utf8_lossy(body)
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (url_decode(key), url_decode(value))
+        })
+        .collect()
+}
+
+/// Liest `body` als JSON oder Formular in dieselbe Struktur.
+///
+/// Formularwerte werden zu Zahlen oder Wahrheitswerten, wo sie so aussehen, sonst bleiben sie
+/// Text. Bei doppelten Schlüsseln zählt der letzte.
+pub fn parse_body<T: serde::de::DeserializeOwned>(format: BodyFormat, body: &[u8]) -> Result<T, HttpError> {
+    let parsed = match format {
+        BodyFormat::Json => serde_json::from_slice(body),
+        BodyFormat::Form => {
+            let fields = form_pairs(body)
+                .into_iter()
+                .map(|(key, value)| (key, form_value(value)))
+                .collect::<serde_json::Map<_, _>>();
+            serde_json::from_value(fields.into())
+        }
+    };
+    parsed.map_err(|err| HttpError::bad_request(err.to_string()))
+}
+
+fn form_value(value: String) -> serde_json::Value {
+    if let Ok(number) = value.parse::<u64>() {
+        return number.into();
+    }
+    match value.as_str() {
+        "true" | "on" => true.into(),
+        "false" | "off" => false.into(),
+        _ => value.into(),
+    }
+}
+
+/// Dekodiert `+` und `%XX`-Sequenzen.
+fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let hex = |byte: u8| (byte as char).to_digit(16).map(|digit| digit as u8);
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+impl<E: Into<anyhow::Error>> From<E> for HttpError {
+    fn from(err: E) -> Self {
+        Self::new(500, err.into().to_string())
+    }
+}
+
+impl From<RelayError> for HttpError {
+    fn from(err: RelayError) -> Self {
+        let http_error = Self::new(err.status(), err.message());
+        match err.retry_after_secs() {
+            Some(secs) => http_error.with_header("Retry-After", secs.to_string()),
+            None => http_error,
+        }
+    }
+}
+
+/// Schreibt `body` als JSON-Antwort mit zusätzlichen `headers`.
+///
+/// Größere Antworten werden gzip-komprimiert, wenn der Client das per `Accept-Encoding` anbietet.
+pub fn respond_json(req: HttpRequest, status: u16, headers: &[(&str, &str)], body: &str) -> Result<()> {
+    let mut all_headers = vec![
+        ("Content-Type", "application/json"),
+        ("Vary", "Accept-Encoding"),
+        ("Access-Control-Allow-Origin", cors_origin()),
+        ("Access-Control-Expose-Headers", CORS_EXPOSE_HEADERS),
+    ];
+    all_headers.extend(keepalive::response_headers(keepalive::should_close(req.header("Connection"))));
+    all_headers.extend_from_slice(headers);
+    if body.len() >= GZIP_MIN_BYTES && accepts_gzip(&req) {
+        let compressed = gzip(body.as_bytes())?;
+        all_headers.push(("Content-Encoding", "gzip"));
+        let mut resp = req.into_response(status, None, &all_headers)?;
+        resp.write_all(&compressed)?;
+    } else {
+        let mut resp = req.into_response(status, None, &all_headers)?;
+        resp.write_all(body.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// `true`, wenn der Client gzip-komprimierte Antworten annimmt.
+pub fn accepts_gzip(req: &HttpRequest) -> bool {
+    req.header("Accept-Encoding").is_some_and(|header| {
+        header.split(',').any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            parts.next() == Some("gzip") && !parts.any(|param| param.replace(' ', "") == "q=0")
+        })
+    })
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Verpackt einen JSON-Handler für `fn_handler`.
+///
+/// Der Handler bekommt den Request nur geliehen und gibt die Antwort als Wert zurück, so kann
+/// auch ein Fehler noch als JSON beantwortet werden. Jede Antwort landet mit ihrem Status im Log.
+pub fn json_handler<F>(
+    log_queue: &Arc<LogQueue>,
+    path: &'static str,
+    handler: F,
+) -> impl for<'a, 'r> Fn(HttpRequest<'a, 'r>) -> Result<()> + Send + 'static
+where
+    F: for<'a, 'r> Fn(&mut HttpRequest<'a, 'r>) -> Result<JsonReply, HttpError> + Send + 'static,
+{
+    let log_queue = log_queue.clone();
+    move |mut req: HttpRequest| {
+        let access = AccessLog::start(&mut req);
+        // Während der Handler läuft, darf die Verbindung nicht als unbenutzt geschlossen werden
+        let fd = sockfd(&mut req);
+        if let Some(fd) = fd {
+            keepalive::forget(fd);
+        }
+        let close = keepalive::should_close(req.header("Connection"));
+        let reply = handler(&mut req).unwrap_or_else(|err| {
+            if err.status >= 500 {
+                error!("{} fehlgeschlagen: {}", path, err.message);
+            }
+            JsonReply {
+                status: err.status,
+                body: err.body(),
+                headers: err.headers,
+                log_path: err.log_path,
+                event: None,
+            }
+        });
+        let headers: Vec<(&str, &str)> = reply
+            .headers
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+        respond_json(req, reply.status, &headers, &reply.body)?;
+        if let Some(fd) = fd {
+            keepalive::finished(fd, close);
+        }
+        log_queue.metrics().record_latency(path, access.elapsed());
+        let log_path = reply.log_path.as_deref().unwrap_or(path);
+        access.finish_event(&log_queue, reply.status, log_path, reply.event);
+        Ok(())
+    }
+}
+
+/// Stufe der Zugriffszeilen auf der seriellen Konsole aus `ACCESS_LOG_LEVEL`, Standard `info`.
+///
+/// `off` schaltet sie ab, etwa im Betrieb ohne USB. Die Logs auf dem Display bleiben davon
+/// unberührt.
+fn access_log_level() -> Option<Level> {
+    option_env!("ACCESS_LOG_LEVEL")
+        .and_then(|value| value.parse::<LevelFilter>().ok())
+        .unwrap_or(LevelFilter::Info)
+        .to_level()
+}
+
+/// Eckdaten eines Requests, festgehalten bevor die Antwort ihn verbraucht.
+///
+/// [`AccessLog::finish`] schreibt daraus eine Zeile wie
+/// `GET /status?x=1 status=200 ip=192.168.1.20 ms=12` auf die serielle Konsole und trägt den
+/// Request wie bisher in das Log für Display und `/logs` ein.
+pub struct AccessLog {
+    method: String,
+    uri: String,
+    ip: Option<IpAddr>,
+    started: Instant,
+}
+
+impl AccessLog {
+    pub fn start(req: &mut HttpRequest) -> Self {
+        Self {
+            method: format!("{:?}", req.method()).to_uppercase(),
+            uri: sanitize_path(req.uri()),
+            ip: remote_ip(req),
+            started: Instant::now(),
+        }
+    }
+
+    /// Zeit seit Beginn des Requests, dieselbe wie in den Latenz-Metriken.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Schreibt die Zugriffszeile und trägt den Request unter `path` ins Log ein.
+    pub fn finish(self, log_queue: &LogQueue, status: u16, path: &str) {
+        self.finish_event(log_queue, status, path, None);
+    }
+
+    /// Wie [`AccessLog::finish`], mit der Art des Relais-Ereignisses für den Logeintrag.
+    pub fn finish_event(self, log_queue: &LogQueue, status: u16, path: &str, event: Option<Event>) {
+        if let Some(level) = access_log_level() {
+            let ip = self.ip.map_or_else(|| "-".to_string(), |ip| ip.to_string());
+            log!(
+                target: "access",
+                level,
+                "{} {} status={} ip={} ms={}",
+                self.method,
+                self.uri,
+                status,
+                ip,
+                self.elapsed().as_millis()
+            );
+        }
+        log_request_event(log_queue, status, path, event);
+    }
+}
+
+/// Lehnt den Request mit 401 ab, wenn kein gültiges Bearer-Token mitkommt. Handler prüfen das
+/// Token immer über [`require_auth_limited`], sonst ließe es sich dort ohne Sperre durchprobieren.
+fn require_auth(req: &HttpRequest, token: &str) -> Result<(), HttpError> {
+    if is_authorized(req.header("Authorization"), token) {
+        Ok(())
+    } else {
+        Err(HttpError::unauthorized())
+    }
+}
+
+/// Weist Clients außerhalb von `PUSH_ALLOWLIST` mit 403 ab, vor dem Token zu prüfen. Ohne Liste
+/// darf jeder.
+pub fn require_allowed(req: &mut HttpRequest, allowlist: Option<&Allowlist>) -> Result<(), HttpError> {
+    let Some(allowlist) = allowlist else {
+        return Ok(());
+    };
+    let ip = remote_ip(req);
+    if !allowlist.allows(ip) {
+        let path = req.uri().split('?').next().unwrap_or_default();
+        warn!("{} von {:?} abgewiesen, nicht in PUSH_ALLOWLIST", path, ip);
+        return Err(HttpError::new(403, "source address not allowed"));
+    }
+    Ok(())
+}
+
+/// Wie [`require_auth`], sperrt aber Clients nach zu vielen falschen Tokens mit 429.
+///
+/// Lässt sich die Adresse des Clients nicht ermitteln, wird nur das Token geprüft.
+pub fn require_auth_limited(req: &mut HttpRequest, token: &str, limiter: &AuthLimiter) -> Result<(), HttpError> {
+    let Some(ip) = remote_ip(req) else {
+        return require_auth(req, token);
+    };
+    let locked_out = |left: std::time::Duration| {
+        HttpError::new(429, "too many failed attempts")
+            .with_header("Retry-After", left.as_secs().max(1).to_string())
+    };
+    if let Some(left) = limiter.locked(ip) {
+        return Err(locked_out(left));
+    }
+    match require_auth(req, token) {
+        Ok(()) => {
+            limiter.record_success(ip);
+            Ok(())
+        }
+        Err(err) => match limiter.record_failure(ip) {
+            Some(lockout) => {
+                warn!("{} nach zu vielen Fehlversuchen für {}s gesperrt", ip, lockout.as_secs());
+                let path = req.uri().split('?').next().unwrap_or_default();
+                Err(locked_out(lockout).log_as(format!("{} lockout {}", path, ip)))
+            }
+            None => Err(err),
+        },
+    }
+}
+
+/// Socket der Verbindung, über die `req` kam.
+pub fn sockfd(req: &mut HttpRequest) -> Option<i32> {
+    let raw = req.connection().raw_connection().ok()?;
+    let fd = unsafe { esp_idf_sys::httpd_req_to_sockfd(raw) };
+    (fd >= 0).then_some(fd)
+}
+
+/// Adresse des Clients, IPv4 auch dann, wenn der Server auf einem IPv6-Socket lauscht.
+pub fn remote_ip(req: &mut HttpRequest) -> Option<IpAddr> {
+    let fd = sockfd(req)?;
+    let mut addr: esp_idf_sys::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of_val(&addr) as esp_idf_sys::socklen_t;
+    let result = unsafe {
+        esp_idf_sys::lwip_getpeername(fd, &mut addr as *mut _ as *mut esp_idf_sys::sockaddr, &mut len)
+    };
+    if result != 0 {
+        return None;
+    }
+    match addr.ss_family as u32 {
+        esp_idf_sys::AF_INET => {
+            let addr = unsafe { &*(&addr as *const _ as *const esp_idf_sys::sockaddr_in) };
+            Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr))))
+        }
+        esp_idf_sys::AF_INET6 => {
+            let addr = unsafe { &*(&addr as *const _ as *const esp_idf_sys::sockaddr_in6) };
+            let ip = Ipv6Addr::from(unsafe { addr.sin6_addr.un.u8_addr });
+            Some(ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4))
+        }
+        _ => None,
+    }
+}
+
+/// Prüft den `Authorization`-Header gegen das erwartete Bearer-Token.
+pub fn is_authorized(header: Option<&str>, token: &str) -> bool {
+    match header.and_then(|value| value.strip_prefix("Bearer ")) {
+        Some(given) => token_matches(given.trim(), token),
+        None => false,
+    }
+}
+
+/// Vergleicht `given` mit dem erwarteten Token. Ein leeres Token passt nie, sonst würde ein
+/// leerer Header oder `?token=` reichen.
+pub fn token_matches(given: &str, token: &str) -> bool {
+    !token.is_empty() && constant_time_eq(given.as_bytes(), token.as_bytes())
+}
+
+/// Vergleicht zwei Byte-Folgen in konstanter Zeit, damit die Laufzeit nichts über das Token verrät.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
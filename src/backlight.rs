@@ -0,0 +1,65 @@
+#[cfg(feature = "display")]
+use anyhow::Result;
+#[cfg(feature = "display")]
+use esp_idf_hal::ledc::LedcDriver;
+
+/// Helligkeit ohne `BRIGHTNESS` zur Build-Zeit, in Prozent.
+pub const DEFAULT_BRIGHTNESS: u8 = 100;
+
+/// Helligkeit aus `BRIGHTNESS` zur Build-Zeit, sonst [`DEFAULT_BRIGHTNESS`].
+pub fn env_brightness() -> u8 {
+    option_env!("BRIGHTNESS")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_BRIGHTNESS)
+        .min(100)
+}
+
+/// Hintergrundbeleuchtung des Displays über einen LEDC-PWM-Kanal.
+#[cfg(feature = "display")]
+pub struct Backlight {
+    driver: LedcDriver<'static>,
+    brightness: u8,
+    blanked: bool,
+}
+
+#[cfg(feature = "display")]
+impl Backlight {
+    /// `brightness` in Prozent, wirksam ab dem nächsten [`Backlight::wake`].
+    pub fn new(driver: LedcDriver<'static>, brightness: u8) -> Self {
+        Self {
+            driver,
+            brightness: brightness.min(100),
+            blanked: false,
+        }
+    }
+
+    /// Setzt die Helligkeit in Prozent (0–100).
+    pub fn set_brightness(&mut self, pct: u8) -> Result<()> {
+        let pct = pct.min(100);
+        let duty = self.driver.get_max_duty() * pct as u32 / 100;
+        self.driver.set_duty(duty)?;
+        self.brightness = pct;
+        self.blanked = false;
+        Ok(())
+    }
+
+    /// Schaltet die Beleuchtung aus, die eingestellte Helligkeit bleibt erhalten.
+    pub fn blank(&mut self) -> Result<()> {
+        self.driver.set_duty(0)?;
+        self.blanked = true;
+        Ok(())
+    }
+
+    /// Stellt nach `blank` die vorherige Helligkeit wieder her.
+    pub fn wake(&mut self) -> Result<()> {
+        self.set_brightness(self.brightness)
+    }
+
+    pub fn is_blanked(&self) -> bool {
+        self.blanked
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+}
@@ -0,0 +1,152 @@
+//! Optionale Überwachung der Versorgungsspannung für den Akkubetrieb.
+//!
+//! Mit `BATTERY_ADC_GPIO` wird die Spannung hinter einem Spannungsteiler an diesem Pin gemessen
+//! und mit `BATTERY_DIVIDER` (Standard 2, also zwei gleiche Widerstände) auf die Akkuspannung
+//! hochgerechnet. Nur ADC1 (beim ESP32 GPIO32 bis GPIO39) kommt in Frage, ADC2 ist belegt,
+//! solange das WLAN läuft. Beim Heltec WiFi Kit 32 V2 hängt der Akku z.B. an GPIO37 mit Teiler 3,2.
+//!
+//! Unter `BATTERY_LOW_V` (Standard 3,5 V) warnt das Display. Mit `BATTERY_CRITICAL_V` geht das
+//! Gerät darunter mit abgeschaltetem Relais in den Deep Sleep, damit ein LiPo nicht tiefentladen
+//! wird, und prüft alle [`CRITICAL_RECHECK`] erneut.
+
+use anyhow::{bail, Result};
+use esp_idf_sys::esp;
+use log::*;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Warnschwelle ohne `BATTERY_LOW_V`, in Volt.
+const DEFAULT_LOW_V: f32 = 3.5;
+
+/// So viele Messungen werden gemittelt, der ADC des ESP32 rauscht merklich.
+const SAMPLES: u32 = 16;
+
+/// Nach so langem Deep Sleep wegen kritischer Spannung wird erneut gemessen, etwa nach dem Laden.
+pub const CRITICAL_RECHECK: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatteryLevel {
+    Normal,
+    Low,
+    Critical,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatteryConfig {
+    pub gpio: i32,
+    /// Verhältnis von Akkuspannung zu Spannung am Pin
+    pub divider: f32,
+    pub low_v: f32,
+    pub critical_v: Option<f32>,
+}
+
+impl BatteryConfig {
+    /// Liest `BATTERY_ADC_GPIO`, `BATTERY_DIVIDER`, `BATTERY_LOW_V` und `BATTERY_CRITICAL_V`,
+    /// `None` ohne Pin.
+    pub fn from_env() -> Option<Self> {
+        let gpio = option_env!("BATTERY_ADC_GPIO")?.parse().ok()?;
+        Some(Self {
+            gpio,
+            divider: option_env!("BATTERY_DIVIDER")
+                .and_then(|value| value.parse().ok())
+                .filter(|divider| *divider >= 1.0)
+                .unwrap_or(2.0),
+            low_v: option_env!("BATTERY_LOW_V")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_LOW_V),
+            critical_v: option_env!("BATTERY_CRITICAL_V").and_then(|value| value.parse().ok()),
+        })
+    }
+}
+
+/// ADC-Kanal samt Kalibrierung und der zuletzt gemessenen Spannung.
+pub struct Battery {
+    config: BatteryConfig,
+    unit: esp_idf_sys::adc_oneshot_unit_handle_t,
+    channel: esp_idf_sys::adc_channel_t,
+    cali: esp_idf_sys::adc_cali_handle_t,
+    voltage: Mutex<Option<f32>>,
+}
+
+// Gemessen wird nur aus der Hauptschleife, andere Threads lesen nur den letzten Wert
+unsafe impl Send for Battery {}
+unsafe impl Sync for Battery {}
+
+impl Battery {
+    pub fn start(config: BatteryConfig) -> Result<Self> {
+        let mut unit_id = 0;
+        let mut channel = 0;
+        esp!(unsafe { esp_idf_sys::adc_oneshot_io_to_channel(config.gpio, &mut unit_id, &mut channel) })?;
+        if unit_id != esp_idf_sys::adc_unit_t_ADC_UNIT_1 {
+            bail!("GPIO{} gehört nicht zu ADC1, ADC2 ist mit WLAN nicht nutzbar", config.gpio);
+        }
+
+        let mut unit = std::ptr::null_mut();
+        let unit_config = esp_idf_sys::adc_oneshot_unit_init_cfg_t {
+            unit_id,
+            ..Default::default()
+        };
+        esp!(unsafe { esp_idf_sys::adc_oneshot_new_unit(&unit_config, &mut unit) })?;
+        // 12 dB Dämpfung für den vollen Messbereich bis etwa 3,1 V am Pin
+        let atten = esp_idf_sys::adc_atten_t_ADC_ATTEN_DB_12;
+        let channel_config = esp_idf_sys::adc_oneshot_chan_cfg_t {
+            atten,
+            bitwidth: esp_idf_sys::adc_bitwidth_t_ADC_BITWIDTH_DEFAULT,
+        };
+        esp!(unsafe { esp_idf_sys::adc_oneshot_config_channel(unit, channel, &channel_config) })?;
+
+        let mut cali = std::ptr::null_mut();
+        let cali_config = esp_idf_sys::adc_cali_line_fitting_config_t {
+            unit_id,
+            atten,
+            bitwidth: esp_idf_sys::adc_bitwidth_t_ADC_BITWIDTH_DEFAULT,
+            ..Default::default()
+        };
+        esp!(unsafe { esp_idf_sys::adc_cali_create_scheme_line_fitting(&cali_config, &mut cali) })?;
+
+        info!("Akkuspannung an GPIO{}, Teiler {}", config.gpio, config.divider);
+        Ok(Self {
+            config,
+            unit,
+            channel,
+            cali,
+            voltage: Mutex::new(None),
+        })
+    }
+
+    /// Misst die Spannung und merkt sie sich für [`Battery::voltage`].
+    pub fn sample(&self) -> Option<f32> {
+        let mut total_mv = 0u32;
+        for _ in 0..SAMPLES {
+            let mut raw = 0;
+            let mut mv = 0;
+            let ok = unsafe {
+                esp_idf_sys::adc_oneshot_read(self.unit, self.channel, &mut raw) == 0
+                    && esp_idf_sys::adc_cali_raw_to_voltage(self.cali, raw, &mut mv) == 0
+            };
+            if !ok {
+                warn!("Akkuspannung nicht messbar");
+                return None;
+            }
+            total_mv += mv.max(0) as u32;
+        }
+        let voltage = total_mv as f32 / SAMPLES as f32 / 1000.0 * self.config.divider;
+        *self.voltage.lock().unwrap() = Some(voltage);
+        Some(voltage)
+    }
+
+    /// Zuletzt gemessene Akkuspannung in Volt.
+    pub fn voltage(&self) -> Option<f32> {
+        *self.voltage.lock().unwrap()
+    }
+
+    pub fn level(&self) -> BatteryLevel {
+        match self.voltage() {
+            Some(voltage) if self.config.critical_v.is_some_and(|critical| voltage < critical) => {
+                BatteryLevel::Critical
+            }
+            Some(voltage) if voltage < self.config.low_v => BatteryLevel::Low,
+            _ => BatteryLevel::Normal,
+        }
+    }
+}
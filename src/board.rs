@@ -0,0 +1,400 @@
+//! Pinbelegung und Peripherie je Board, zur Build-Zeit über Cargo-Features gewählt.
+//!
+//! Der Typ eines Pins steckt im Typ seines Treibers, deshalb wird das Board über ein Feature
+//! gewählt statt zur Laufzeit. `main` nimmt Pins und Busse nur über dieses Modul, damit es für
+//! alle Boards gleich bleibt. Genau ein Board-Feature muss aktiv sein:
+//!
+//! | Feature       | Chip     | Board                          | Target                   |
+//! |---------------|----------|--------------------------------|--------------------------|
+//! | `board-wb32`  | ESP32    | Heltec HTIT-WB32 (Standard)    | aus `.cargo/config.toml` |
+//! | `board-c3`    | ESP32-C3 | ESP32-C3-DevKitM-1             | `riscv32imc-esp-espidf`  |
+//! | `board-s3`    | ESP32-S3 | ESP32-S3-DevKitC-1             | `xtensa-esp32s3-espidf`  |
+//!
+//! Für ein anderes Board außer dem Feature auch Target und Chip wechseln, z.B.
+//! `MCU=esp32c3 cargo build --target riscv32imc-esp-espidf --no-default-features
+//! --features board-c3,st7789`.
+//!
+//! Belegung:
+//!
+//! | Funktion                 | `board-wb32` | `board-c3` | `board-s3` |
+//! |--------------------------|--------------|------------|------------|
+//! | Relais                   | GPIO5        | GPIO10     | GPIO5      |
+//! | Taster (aktiv low)       | GPIO0        | GPIO9      | GPIO0      |
+//! | Hintergrundbeleuchtung   | GPIO4        | GPIO3      | GPIO38     |
+//! | Display SCLK (`st7789`)  | GPIO14       | GPIO6      | GPIO12     |
+//! | Display MOSI (`st7789`)  | GPIO13       | GPIO7      | GPIO11     |
+//! | Display CS (`st7789`)    | GPIO15       | fest       | GPIO10     |
+//! | Display DC (`st7789`)    | GPIO18       | GPIO4      | GPIO14     |
+//! | Display RST (`st7789`)   | GPIO23       | GPIO5      | GPIO21     |
+//! | I2C SDA/SCL (`ssd1306`)  | GPIO21/22    | GPIO6/7    | GPIO8/9    |
+//!
+//! Das Display hängt bei allen am SPI2-Bus, Takt und Pins lassen sich über `DisplaySpiConfig`
+//! ändern. Beim ESP32-C3 kann der Taster an GPIO9 nicht aus dem Deep Sleep wecken, dort geht das
+//! nur mit GPIO0 bis GPIO5, siehe `power::sleep`.
+//!
+//! Nur auf dem HTIT-WB32 lässt sich das Relais zusätzlich per `relay-gpio*`-Feature verlegen.
+//! Geeignet sind:
+//!   GPIO5                      - Standard, Strapping-Pin, muss beim Booten high sein; stört bei
+//!                                manchen Modulen den Flash-Zugriff
+//!   GPIO25, GPIO26, GPIO27     - frei, ohne Einfluss auf den Boot
+//!   GPIO32, GPIO33             - frei, ohne Einfluss auf den Boot
+//! Ungeeignet sind GPIO6 bis GPIO11 (Flash), GPIO0, GPIO2, GPIO12 und GPIO15 (Strapping),
+//! GPIO34 bis GPIO39 (nur Eingang) sowie die Pins von Display, Taster und Hintergrundbeleuchtung.
+
+#[cfg(not(any(feature = "board-wb32", feature = "board-c3", feature = "board-s3")))]
+compile_error!("Es muss ein board-*-Feature aktiv sein, z.B. board-wb32");
+
+#[cfg(any(
+    all(feature = "board-wb32", any(feature = "board-c3", feature = "board-s3")),
+    all(feature = "board-c3", feature = "board-s3"),
+))]
+compile_error!("Es darf nur ein board-*-Feature aktiv sein");
+
+#[cfg(all(
+    not(feature = "board-wb32"),
+    any(
+        feature = "relay-gpio25",
+        feature = "relay-gpio26",
+        feature = "relay-gpio27",
+        feature = "relay-gpio32",
+        feature = "relay-gpio33",
+    )
+))]
+compile_error!("Die relay-gpio*-Features gibt es nur für board-wb32");
+
+#[cfg(any(
+    all(feature = "relay-gpio25", any(feature = "relay-gpio26", feature = "relay-gpio27")),
+    all(feature = "relay-gpio25", any(feature = "relay-gpio32", feature = "relay-gpio33")),
+    all(feature = "relay-gpio26", any(feature = "relay-gpio27", feature = "relay-gpio32")),
+    all(feature = "relay-gpio26", feature = "relay-gpio33"),
+    all(feature = "relay-gpio27", any(feature = "relay-gpio32", feature = "relay-gpio33")),
+    all(feature = "relay-gpio32", feature = "relay-gpio33"),
+))]
+compile_error!("Es darf nur ein relay-gpio*-Feature aktiv sein");
+
+/// Nummer des Relais-Pins, für die rohen `gpio_*`-Aufrufe vor dem Anlegen des Treibers.
+#[cfg(all(
+    any(feature = "board-wb32", feature = "board-s3"),
+    not(any(
+        feature = "relay-gpio25",
+        feature = "relay-gpio26",
+        feature = "relay-gpio27",
+        feature = "relay-gpio32",
+        feature = "relay-gpio33",
+    ))
+))]
+pub const RELAY_GPIO: i32 = 5;
+#[cfg(feature = "board-c3")]
+pub const RELAY_GPIO: i32 = 10;
+#[cfg(feature = "relay-gpio25")]
+pub const RELAY_GPIO: i32 = 25;
+#[cfg(feature = "relay-gpio26")]
+pub const RELAY_GPIO: i32 = 26;
+#[cfg(feature = "relay-gpio27")]
+pub const RELAY_GPIO: i32 = 27;
+#[cfg(feature = "relay-gpio32")]
+pub const RELAY_GPIO: i32 = 32;
+#[cfg(feature = "relay-gpio33")]
+pub const RELAY_GPIO: i32 = 33;
+
+/// Nimmt den gewählten Relais-Pin aus `Pins`, z.B. `board::relay_pin!(pins)`.
+#[cfg(all(
+    any(feature = "board-wb32", feature = "board-s3"),
+    not(any(
+        feature = "relay-gpio25",
+        feature = "relay-gpio26",
+        feature = "relay-gpio27",
+        feature = "relay-gpio32",
+        feature = "relay-gpio33",
+    ))
+))]
+macro_rules! relay_pin {
+    ($pins:expr) => {
+        $pins.gpio5
+    };
+}
+#[cfg(feature = "board-c3")]
+macro_rules! relay_pin {
+    ($pins:expr) => {
+        $pins.gpio10
+    };
+}
+#[cfg(feature = "relay-gpio25")]
+macro_rules! relay_pin {
+    ($pins:expr) => {
+        $pins.gpio25
+    };
+}
+#[cfg(feature = "relay-gpio26")]
+macro_rules! relay_pin {
+    ($pins:expr) => {
+        $pins.gpio26
+    };
+}
+#[cfg(feature = "relay-gpio27")]
+macro_rules! relay_pin {
+    ($pins:expr) => {
+        $pins.gpio27
+    };
+}
+#[cfg(feature = "relay-gpio32")]
+macro_rules! relay_pin {
+    ($pins:expr) => {
+        $pins.gpio32
+    };
+}
+#[cfg(feature = "relay-gpio33")]
+macro_rules! relay_pin {
+    ($pins:expr) => {
+        $pins.gpio33
+    };
+}
+pub(crate) use relay_pin;
+
+/// Nummer des Tasters, auch für das Aufwachen aus dem Schlaf.
+#[cfg(any(feature = "board-wb32", feature = "board-s3"))]
+pub const BUTTON_GPIO: i32 = 0;
+#[cfg(feature = "board-c3")]
+pub const BUTTON_GPIO: i32 = 9;
+
+/// Nimmt den Taster aus `Pins`, z.B. `board::button_pin!(pins)`.
+#[cfg(any(feature = "board-wb32", feature = "board-s3"))]
+macro_rules! button_pin {
+    ($pins:expr) => {
+        $pins.gpio0
+    };
+}
+#[cfg(feature = "board-c3")]
+macro_rules! button_pin {
+    ($pins:expr) => {
+        $pins.gpio9
+    };
+}
+pub(crate) use button_pin;
+
+#[cfg(feature = "board-wb32")]
+pub const BACKLIGHT_GPIO: i32 = 4;
+#[cfg(feature = "board-c3")]
+pub const BACKLIGHT_GPIO: i32 = 3;
+#[cfg(feature = "board-s3")]
+pub const BACKLIGHT_GPIO: i32 = 38;
+
+/// Nimmt den Pin der Hintergrundbeleuchtung aus `Pins`, z.B. `board::backlight_pin!(pins)`.
+#[cfg(all(feature = "display", feature = "board-wb32"))]
+macro_rules! backlight_pin {
+    ($pins:expr) => {
+        $pins.gpio4
+    };
+}
+#[cfg(all(feature = "display", feature = "board-c3"))]
+macro_rules! backlight_pin {
+    ($pins:expr) => {
+        $pins.gpio3
+    };
+}
+#[cfg(all(feature = "display", feature = "board-s3"))]
+macro_rules! backlight_pin {
+    ($pins:expr) => {
+        $pins.gpio38
+    };
+}
+#[cfg(feature = "display")]
+pub(crate) use backlight_pin;
+
+// Bus des SPI-Displays. Die Initialisierung wird nach Fehlern wiederholt und legt die Peripherie
+// dafür jedes Mal neu an, deshalb ein Typ statt des Felds aus `Peripherals`.
+#[cfg(feature = "st7789")]
+pub type DisplaySpi = esp_idf_hal::spi::SPI2;
+
+/// SPI-Takt des Displays ohne `DISPLAY_SPI_HZ`.
+#[cfg(feature = "st7789")]
+pub const DEFAULT_DISPLAY_SPI_HZ: u32 = 26_000_000;
+
+/// Höchster SPI-Takt, den der ST7789 laut Datenblatt mitmacht.
+#[cfg(feature = "st7789")]
+pub const MAX_DISPLAY_SPI_HZ: u32 = 80_000_000;
+
+/// Takt und Pins des SPI-Displays, ohne Umgebungsvariablen die Belegung des Boards.
+///
+/// Bei langem Flachbandkabel hilft ein niedrigerer Takt über `DISPLAY_SPI_HZ` gegen Bildfehler.
+/// Die Pins lassen sich mit `DISPLAY_SCLK_GPIO`, `DISPLAY_MOSI_GPIO`, `DISPLAY_CS_GPIO`,
+/// `DISPLAY_DC_GPIO` und `DISPLAY_RST_GPIO` verlegen, `DISPLAY_CS_GPIO=none` für Module mit fest
+/// verdrahtetem CS.
+#[cfg(feature = "st7789")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplaySpiConfig {
+    pub baudrate_hz: u32,
+    pub sclk: i32,
+    pub mosi: i32,
+    pub cs: Option<i32>,
+    pub dc: i32,
+    pub rst: i32,
+}
+
+#[cfg(all(feature = "st7789", feature = "board-wb32"))]
+const DISPLAY_SPI_PINS: DisplaySpiConfig = DisplaySpiConfig {
+    baudrate_hz: DEFAULT_DISPLAY_SPI_HZ,
+    sclk: 14,
+    mosi: 13,
+    cs: Some(15),
+    dc: 18,
+    rst: 23,
+};
+#[cfg(all(feature = "st7789", feature = "board-c3"))]
+const DISPLAY_SPI_PINS: DisplaySpiConfig = DisplaySpiConfig {
+    baudrate_hz: DEFAULT_DISPLAY_SPI_HZ,
+    sclk: 6,
+    mosi: 7,
+    // GPIO10 ist das Relais
+    cs: None,
+    dc: 4,
+    rst: 5,
+};
+#[cfg(all(feature = "st7789", feature = "board-s3"))]
+const DISPLAY_SPI_PINS: DisplaySpiConfig = DisplaySpiConfig {
+    baudrate_hz: DEFAULT_DISPLAY_SPI_HZ,
+    sclk: 12,
+    mosi: 11,
+    cs: Some(10),
+    dc: 14,
+    rst: 21,
+};
+
+#[cfg(feature = "st7789")]
+impl DisplaySpiConfig {
+    /// Belegung des Boards mit den Änderungen aus den Umgebungsvariablen.
+    ///
+    /// Ist die Änderung ungültig, etwa ein doppelt belegter Pin, bleibt es mit Warnung bei der
+    /// Belegung des Boards. Die wirksamen Einstellungen werden geloggt.
+    pub fn from_env() -> Self {
+        let config = match Self::parse_env() {
+            Ok(config) => config,
+            Err(err) => {
+                log::warn!("Display-SPI: {}, verwende die Belegung des Boards", err);
+                DISPLAY_SPI_PINS
+            }
+        };
+        log::info!(
+            "Display-SPI: {} Hz, SCLK GPIO{}, MOSI GPIO{}, CS {}, DC GPIO{}, RST GPIO{}",
+            config.baudrate_hz,
+            config.sclk,
+            config.mosi,
+            config.cs.map_or("fest".to_string(), |cs| format!("GPIO{}", cs)),
+            config.dc,
+            config.rst
+        );
+        config
+    }
+
+    fn parse_env() -> Result<Self, String> {
+        let gpio = |name: &str, value: Option<&str>, default: i32| match value {
+            None => Ok(default),
+            Some(value) => value.parse().map_err(|_| format!("{} ist keine GPIO-Nummer", name)),
+        };
+        let config = Self {
+            baudrate_hz: match option_env!("DISPLAY_SPI_HZ") {
+                None => DISPLAY_SPI_PINS.baudrate_hz,
+                Some(value) => value.parse().map_err(|_| "DISPLAY_SPI_HZ ist keine Zahl".to_string())?,
+            },
+            sclk: gpio("DISPLAY_SCLK_GPIO", option_env!("DISPLAY_SCLK_GPIO"), DISPLAY_SPI_PINS.sclk)?,
+            mosi: gpio("DISPLAY_MOSI_GPIO", option_env!("DISPLAY_MOSI_GPIO"), DISPLAY_SPI_PINS.mosi)?,
+            cs: match option_env!("DISPLAY_CS_GPIO") {
+                None => DISPLAY_SPI_PINS.cs,
+                Some("none") => None,
+                value => Some(gpio("DISPLAY_CS_GPIO", value, 0)?),
+            },
+            dc: gpio("DISPLAY_DC_GPIO", option_env!("DISPLAY_DC_GPIO"), DISPLAY_SPI_PINS.dc)?,
+            rst: gpio("DISPLAY_RST_GPIO", option_env!("DISPLAY_RST_GPIO"), DISPLAY_SPI_PINS.rst)?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Prüft Takt und Pins: gültige Nummern, keiner doppelt und keiner von Relais, Taster oder
+    /// Hintergrundbeleuchtung.
+    fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_DISPLAY_SPI_HZ).contains(&self.baudrate_hz) {
+            return Err(format!("DISPLAY_SPI_HZ muss zwischen 1 und {} liegen", MAX_DISPLAY_SPI_HZ));
+        }
+        let pins = [Some(self.sclk), Some(self.mosi), self.cs, Some(self.dc), Some(self.rst)];
+        let pins: Vec<i32> = pins.into_iter().flatten().collect();
+        for (index, pin) in pins.iter().enumerate() {
+            if !(0..esp_idf_sys::SOC_GPIO_PIN_COUNT as i32).contains(pin) {
+                return Err(format!("GPIO{} gibt es nicht", pin));
+            }
+            if pins[..index].contains(pin) {
+                return Err(format!("GPIO{} ist doppelt belegt", pin));
+            }
+            if [RELAY_GPIO, BUTTON_GPIO, BACKLIGHT_GPIO].contains(pin) {
+                return Err(format!("GPIO{} ist vom Board belegt", pin));
+            }
+        }
+        Ok(())
+    }
+}
+
+// I2C des OLEDs, aus demselben Grund als Typen
+#[cfg(feature = "ssd1306")]
+pub type DisplayI2c = esp_idf_hal::i2c::I2C0;
+#[cfg(all(feature = "ssd1306", feature = "board-wb32"))]
+pub type DisplaySda = esp_idf_hal::gpio::Gpio21;
+#[cfg(all(feature = "ssd1306", feature = "board-wb32"))]
+pub type DisplayScl = esp_idf_hal::gpio::Gpio22;
+#[cfg(all(feature = "ssd1306", feature = "board-c3"))]
+pub type DisplaySda = esp_idf_hal::gpio::Gpio6;
+#[cfg(all(feature = "ssd1306", feature = "board-c3"))]
+pub type DisplayScl = esp_idf_hal::gpio::Gpio7;
+#[cfg(all(feature = "ssd1306", feature = "board-s3"))]
+pub type DisplaySda = esp_idf_hal::gpio::Gpio8;
+#[cfg(all(feature = "ssd1306", feature = "board-s3"))]
+pub type DisplayScl = esp_idf_hal::gpio::Gpio9;
+
+// Dieselben Pins als Nummern für [`used_gpios`]
+#[cfg(all(feature = "ssd1306", feature = "board-wb32"))]
+const DISPLAY_I2C_GPIOS: [i32; 2] = [21, 22];
+#[cfg(all(feature = "ssd1306", feature = "board-c3"))]
+const DISPLAY_I2C_GPIOS: [i32; 2] = [6, 7];
+#[cfg(all(feature = "ssd1306", feature = "board-s3"))]
+const DISPLAY_I2C_GPIOS: [i32; 2] = [8, 9];
+
+// I2C des Umgebungssensors, getrennt vom OLED. Der ESP32-C3 hat nur einen Bus, dort schlägt der
+// BME280 zusammen mit `ssd1306` beim Start fehl und das Gerät läuft ohne Sensor weiter.
+#[cfg(all(feature = "sensor", not(feature = "board-c3")))]
+pub type SensorI2c = esp_idf_hal::i2c::I2C1;
+#[cfg(all(feature = "sensor", feature = "board-c3"))]
+pub type SensorI2c = esp_idf_hal::i2c::I2C0;
+
+/// Alle Pins, die Board, Display und die übrige Peripherie belegen, mit ihrer Aufgabe.
+///
+/// Gelesen werden dieselben Variablen wie beim Anlegen der Treiber, ein Wert, der keine Zahl
+/// ist, belegt keinen Pin. Die Relais aus `RELAYS` gehören nicht dazu.
+pub fn used_gpios() -> Vec<(i32, &'static str)> {
+    let mut used = vec![(RELAY_GPIO, "Relais"), (BUTTON_GPIO, "Taster"), (BACKLIGHT_GPIO, "Hintergrundbeleuchtung")];
+    #[cfg(feature = "st7789")]
+    {
+        let display = DisplaySpiConfig::parse_env().unwrap_or(DISPLAY_SPI_PINS);
+        let pins = [Some(display.sclk), Some(display.mosi), display.cs, Some(display.dc), Some(display.rst)];
+        used.extend(pins.into_iter().flatten().map(|gpio| (gpio, "Display")));
+    }
+    #[cfg(feature = "ssd1306")]
+    used.extend(DISPLAY_I2C_GPIOS.map(|gpio| (gpio, "Display")));
+
+    let mut configured = |value: Option<&str>, owner| {
+        if let Some(gpio) = value.and_then(|value| value.parse().ok()) {
+            used.push((gpio, owner));
+        }
+    };
+    configured(option_env!("RELAY_FEEDBACK_GPIO"), "Rückmeldung des Relais");
+    configured(option_env!("STATUS_LED_GPIO"), "Status-LED");
+    configured(option_env!("BUZZER_GPIO"), "Summer");
+    configured(option_env!("BATTERY_ADC_GPIO"), "Akkumessung");
+    #[cfg(feature = "sensor")]
+    for value in [option_env!("SENSOR_GPIO"), option_env!("SENSOR_SDA_GPIO"), option_env!("SENSOR_SCL_GPIO")] {
+        configured(value, "Sensor");
+    }
+    #[cfg(feature = "encoder")]
+    for value in [option_env!("ENCODER_A_GPIO"), option_env!("ENCODER_B_GPIO"), option_env!("ENCODER_SW_GPIO")] {
+        configured(value, "Drehgeber");
+    }
+    used
+}
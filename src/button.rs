@@ -0,0 +1,127 @@
+use anyhow::Result;
+use esp_idf_hal::gpio::{Input, InputPin, PinDriver};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::settings::Settings;
+
+/// So lange muss der Pegel ohne andere Einstellung stabil sein, bevor er als gültig gilt.
+pub const DEFAULT_DEBOUNCE_MS: u64 = 30;
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// So lange muss der Taster für das Zurücksetzen auf Werkseinstellungen gehalten werden.
+pub const FACTORY_RESET_HOLD: Duration = Duration::from_secs(10);
+
+/// Ab so langem Halten gilt der Druck als Beginn des Zurücksetzens und löst keinen Puls mehr aus.
+pub const LONG_PRESS: Duration = Duration::from_secs(2);
+
+/// Zeitfenster für einen Doppelklick ohne `BUTTON_DOUBLE_PRESS_MS`.
+const DEFAULT_DOUBLE_PRESS_MS: u64 = 400;
+
+/// Erkanntes Tastenmuster.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Press {
+    Single,
+    /// Zweiter Druck innerhalb des Zeitfensters nach dem ersten
+    Double,
+    /// Taster [`FACTORY_RESET_HOLD`] lang gehalten
+    Hold,
+}
+
+/// Zeitfenster aus `BUTTON_DOUBLE_PRESS_MS`, `0` schaltet die Erkennung ab.
+///
+/// Mit Erkennung meldet sich ein einfacher Druck erst, wenn das Fenster ohne zweiten Druck
+/// abgelaufen ist, ohne Erkennung beim Loslassen.
+fn double_press_window() -> Option<Duration> {
+    let ms = option_env!("BUTTON_DOUBLE_PRESS_MS")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_DOUBLE_PRESS_MS);
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+/// Zugriff auf den Zustand des Tasters, z.B. für den Countdown beim Zurücksetzen.
+#[derive(Clone)]
+pub struct Button {
+    pressed_since: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Button {
+    /// Wie lange der Taster schon gedrückt ist, `None` wenn er gerade nicht gedrückt ist.
+    #[cfg(feature = "display")]
+    pub fn held_for(&self) -> Option<Duration> {
+        self.pressed_since.lock().unwrap().map(|since| since.elapsed())
+    }
+}
+
+/// Fragt einen Taster gegen Masse (aktiv low, Pull-up) ab und ruft `on_press` einmal pro
+/// entprelltem Tastendruck bzw. Doppelklick auf. Die Entprellzeit kommt laufend aus `settings`.
+///
+/// Ein einfacher Druck, der bei Ablauf des Fensters noch gehalten wird, meldet sich erst beim
+/// Loslassen und gar nicht, wenn er [`LONG_PRESS`] überschritten hat. Bleibt der Taster
+/// [`FACTORY_RESET_HOLD`] lang gedrückt, folgt einmal [`Press::Hold`], ohne dass vorher ein Puls
+/// das Tor geöffnet hat.
+pub fn spawn(
+    pin: PinDriver<'static, impl InputPin, Input>,
+    settings: Arc<Settings>,
+    on_press: impl Fn(Press) + Send + 'static,
+) -> Result<Button> {
+    let window = double_press_window();
+    let button = Button {
+        pressed_since: Arc::new(Mutex::new(None)),
+    };
+    let pressed_since = button.pressed_since.clone();
+    std::thread::Builder::new()
+        .name("button".into())
+        .stack_size(4096)
+        .spawn(move || {
+            let mut stable_pressed = pin.is_low();
+            let mut last_raw = stable_pressed;
+            let mut changed_at = Instant::now();
+            // Zeitpunkt eines ersten Drucks, auf dessen zweiten noch gewartet wird
+            let mut pending: Option<Instant> = None;
+            // Einfacher Druck, der beim Loslassen meldet, sofern er nicht zu lang war
+            let mut deferred = false;
+            let mut hold_reported = false;
+
+            loop {
+                let raw = pin.is_low();
+                if raw != last_raw {
+                    last_raw = raw;
+                    changed_at = Instant::now();
+                } else if raw != stable_pressed && changed_at.elapsed() >= settings.debounce() {
+                    stable_pressed = raw;
+                    let held = std::mem::replace(&mut *pressed_since.lock().unwrap(), stable_pressed.then(Instant::now))
+                        .map(|since| since.elapsed());
+                    hold_reported = false;
+                    if stable_pressed {
+                        match pending.take() {
+                            Some(_) if window.is_some() => on_press(Press::Double),
+                            _ => pending = Some(Instant::now()),
+                        }
+                    } else if held.is_some_and(|held| held >= LONG_PRESS) {
+                        // Losgelassen nach langem Halten, z.B. einem abgebrochenen Zurücksetzen
+                        pending = None;
+                        deferred = false;
+                    } else if std::mem::take(&mut deferred) {
+                        on_press(Press::Single);
+                    }
+                }
+                // Ohne Doppelklick-Erkennung läuft das Fenster sofort ab
+                if pending.is_some_and(|first| first.elapsed() >= window.unwrap_or_default()) {
+                    pending = None;
+                    if stable_pressed {
+                        deferred = true;
+                    } else {
+                        on_press(Press::Single);
+                    }
+                }
+                let held = pressed_since.lock().unwrap().map(|since| since.elapsed());
+                if !hold_reported && held.is_some_and(|held| held >= FACTORY_RESET_HOLD) {
+                    hold_reported = true;
+                    on_press(Press::Hold);
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        })?;
+    Ok(button)
+}
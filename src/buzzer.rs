@@ -0,0 +1,100 @@
+//! Optionaler Piezo-Summer für eine hörbare Rückmeldung.
+//!
+//! Mit `BUZZER_GPIO` piept ein passiver Piezo bei jedem Puls des Relais einmal kurz und bei
+//! Fehlern wie einer Sicherheitsabschaltung zweimal. Der Ton kommt per LEDC-PWM mit
+//! `BUZZER_FREQ_HZ` (Standard 2700 Hz, die Resonanz der meisten kleinen Piezos). Aktive Summer
+//! mit eigenem Oszillator piepen auch damit, dann allerdings in ihrer eigenen Tonhöhe.
+
+use anyhow::Result;
+use esp_idf_hal::gpio::AnyOutputPin;
+use esp_idf_hal::ledc::{config::TimerConfig, LedcChannel, LedcDriver, LedcTimer, LedcTimerDriver};
+use esp_idf_hal::peripheral::Peripheral;
+use esp_idf_hal::prelude::*;
+use log::*;
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+use std::time::Duration;
+
+use crate::relay::Relay;
+
+const DEFAULT_FREQ_HZ: u32 = 2700;
+
+// So oft wird nachgesehen, ob ein neuer Puls begonnen hat
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+const BEEP: Duration = Duration::from_millis(60);
+const ERROR_BEEP: Duration = Duration::from_millis(120);
+const ERROR_GAP: Duration = Duration::from_millis(80);
+
+/// Griff auf den Summer, gepiept wird in einem eigenen Thread.
+#[derive(Clone)]
+pub struct Buzzer {
+    errors: SyncSender<()>,
+}
+
+impl Buzzer {
+    /// Startet den Summer an `BUZZER_GPIO`, ohne die Variable `None`.
+    ///
+    /// Wie die Status-LED liest er Pulse selbst aus `relay`, Fehler meldet die Hauptschleife.
+    pub fn from_env<T: LedcTimer, C: LedcChannel>(
+        timer: impl Peripheral<P = T> + 'static,
+        channel: impl Peripheral<P = C> + 'static,
+        relay: Relay,
+    ) -> Result<Option<Self>> {
+        let Some(gpio) = option_env!("BUZZER_GPIO").and_then(|value| value.parse().ok()) else {
+            return Ok(None);
+        };
+        let freq_hz = option_env!("BUZZER_FREQ_HZ")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_FREQ_HZ);
+        let timer = LedcTimerDriver::new(timer, &TimerConfig::default().frequency(freq_hz.Hz().into()))?;
+        // Der Pin kommt erst zur Build-Zeit, der Typ `Gpio<N>` steht hier deshalb nicht fest
+        let pin = unsafe { AnyOutputPin::new(gpio) };
+        let mut driver = LedcDriver::new(channel, timer, pin)?;
+        driver.set_duty(0)?;
+
+        // Ein Fehler, der noch nicht gepiept wurde, reicht, weitere gehen verloren
+        let (errors, receiver) = mpsc::sync_channel(1);
+        std::thread::Builder::new()
+            .name("buzzer".into())
+            .stack_size(3072)
+            .spawn(move || {
+                let mut last_pulse = relay.state().last_pulse;
+                loop {
+                    match receiver.recv_timeout(POLL_INTERVAL) {
+                        Ok(()) => {
+                            beep(&mut driver, ERROR_BEEP);
+                            std::thread::sleep(ERROR_GAP);
+                            beep(&mut driver, ERROR_BEEP);
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                    // Jeder neue Puls und jede Pulsfolge setzt `last_pulse` neu
+                    let pulse = relay.state().last_pulse;
+                    if pulse != last_pulse {
+                        last_pulse = pulse;
+                        beep(&mut driver, BEEP);
+                    }
+                }
+            })?;
+
+        info!("Summer an GPIO{} mit {} Hz", gpio, freq_hz);
+        Ok(Some(Self { errors }))
+    }
+
+    /// Piept zweimal, ohne zu warten.
+    pub fn error(&self) {
+        let _ = self.errors.try_send(());
+    }
+}
+
+/// Halbe Einschaltdauer gibt beim Piezo den lautesten Ton.
+fn beep(driver: &mut LedcDriver<'static>, duration: Duration) {
+    let duty = driver.get_max_duty() / 2;
+    if let Err(err) = driver.set_duty(duty) {
+        warn!("Summer: {:?}", err);
+        return;
+    }
+    std::thread::sleep(duration);
+    let _ = driver.set_duty(0);
+}
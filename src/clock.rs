@@ -0,0 +1,109 @@
+use anyhow::Result;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, Utc};
+use esp_idf_svc::sntp::{EspSntp, SntpConf};
+use log::*;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+
+// Die Systemuhr ist global, also auch ihr Synchronisationsstatus und die Zeitzone
+static SYNCED: AtomicBool = AtomicBool::new(false);
+static OFFSET_SECS: AtomicI32 = AtomicI32::new(0);
+static TIME_FORMAT: OnceLock<String> = OnceLock::new();
+
+/// Format für Uhrzeiten in Log und Display ohne gültiges `TIME_FORMAT`.
+pub const DEFAULT_TIME_FORMAT: &str = "%H:%M:%S";
+
+/// So lange wird nach dem Verbinden auf die erste Synchronisation gewartet.
+const FIRST_SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Setzt die Zeitzone aus `TZ_OFFSET` (z.B. `+01:00`), ohne Angabe gilt UTC.
+pub fn init_timezone() {
+    let Some(value) = option_env!("TZ_OFFSET") else {
+        return;
+    };
+    match parse_offset(value) {
+        Some(offset) => OFFSET_SECS.store(offset.local_minus_utc(), Ordering::Relaxed),
+        None => warn!("Ungültiger TZ_OFFSET \"{}\", verwende UTC", value),
+    }
+}
+
+/// Übernimmt das strftime-Format für Uhrzeiten, z.B. `%d.%m. %H:%M` oder `%I:%M:%S %p`.
+///
+/// Ein ungültiges Format wird mit einer Warnung verworfen, dann bleibt es bei
+/// [`DEFAULT_TIME_FORMAT`]. Wirkt nur beim ersten Aufruf.
+pub fn init_time_format(format: Option<String>) {
+    let Some(format) = format else {
+        return;
+    };
+    if StrftimeItems::new(&format).any(|item| item == Item::Error) {
+        warn!("Ungültiges TIME_FORMAT \"{}\", verwende {}", format, DEFAULT_TIME_FORMAT);
+        return;
+    }
+    let _ = TIME_FORMAT.set(format);
+}
+
+/// Format für Uhrzeiten in Log und Display.
+pub fn time_format() -> &'static str {
+    TIME_FORMAT.get().map_or(DEFAULT_TIME_FORMAT, String::as_str)
+}
+
+/// Wandelt `+HH:MM` bzw. `-HH:MM` in einen festen Offset.
+fn parse_offset(value: &str) -> Option<FixedOffset> {
+    let (sign, rest) = match value.as_bytes().first()? {
+        b'+' => (1, &value[1..]),
+        b'-' => (-1, &value[1..]),
+        _ => (1, value),
+    };
+    let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if hours > 14 || minutes >= 60 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Startet SNTP und wartet kurz auf die erste Synchronisation.
+///
+/// Das zurückgegebene `EspSntp` muss am Leben bleiben, sonst wird nicht weiter synchronisiert.
+pub fn start_sntp() -> Result<EspSntp<'static>> {
+    let sntp = EspSntp::new_with_callback(&SntpConf::default(), |_| {
+        if !SYNCED.swap(true, Ordering::SeqCst) {
+            info!("Uhrzeit per SNTP synchronisiert");
+        } else {
+            debug!("Uhrzeit per SNTP nachgestellt");
+        }
+    })?;
+
+    let started = Instant::now();
+    while !is_synced() && started.elapsed() < FIRST_SYNC_TIMEOUT {
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    if !is_synced() {
+        warn!("Noch keine SNTP-Synchronisation, Zeitstempel sind vorerst ungültig");
+    }
+    Ok(sntp)
+}
+
+/// Stößt sofort eine neue SNTP-Synchronisation an, `false` wenn SNTP nicht läuft.
+pub fn resync_sntp() -> bool {
+    unsafe { esp_idf_sys::esp_sntp_enabled() && esp_idf_sys::esp_sntp_restart() }
+}
+
+pub fn is_synced() -> bool {
+    SYNCED.load(Ordering::SeqCst)
+}
+
+/// Unix-Zeit `timestamp` in der konfigurierten Zeitzone.
+pub fn local_time(timestamp: i64) -> Option<DateTime<FixedOffset>> {
+    let offset = FixedOffset::east_opt(OFFSET_SECS.load(Ordering::Relaxed)).unwrap();
+    Some(DateTime::<Utc>::from_timestamp(timestamp, 0)?.with_timezone(&offset))
+}
+
+/// Aktuelle Zeit in der konfigurierten Zeitzone.
+pub fn now() -> DateTime<FixedOffset> {
+    let offset = FixedOffset::east_opt(OFFSET_SECS.load(Ordering::Relaxed)).unwrap();
+    DateTime::<Utc>::from(SystemTime::now()).with_timezone(&offset)
+}
@@ -0,0 +1,310 @@
+//! Minimaler CoAP-Server (RFC 7252) über UDP für Netze, in denen HTTP zu schwer ist.
+//!
+//! Angeboten werden dieselben Ressourcen wie per HTTP:
+//!
+//! - `POST coap://<ip>/push?token=<API_TOKEN>&ms=500` löst einen Puls aus, ohne `ms` gilt die
+//!   Pulsdauer aus `/config`.
+//! - `GET coap://<ip>/status` liefert den Zustand des Relais als JSON.
+//!
+//! Das Token steht als Uri-Query in der Anfrage, weil CoAP keinen `Authorization`-Header kennt.
+//! Falsche Tokens zählen wie bei HTTP für die Sperre des Absenders, gesperrt gibt es 4.29. Mit
+//! `PUSH_ALLOWLIST` bekommen Absender außerhalb der Netze 4.03.
+//! Ohne DTLS geht es damit im Klartext übers Netz, der Server ist deshalb nur für abgeschottete
+//! Netze gedacht. Bestätigbare Anfragen (CON) werden huckepack im ACK beantwortet. Kommt eine
+//! CON-Anfrage erneut, weil das ACK verloren ging, wird das gespeicherte ACK wiederholt, statt das
+//! Relais ein zweites Mal zu schalten (RFC 7252, Abschnitt 4.5). Blockweise Übertragung und
+//! Observe werden nicht unterstützt.
+
+use anyhow::Result;
+use log::*;
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::allowlist::Allowlist;
+use crate::api::token_matches;
+use crate::lockout::AuthLimiter;
+use crate::logs::{log_source, log_switch, LogQueue, Source};
+use crate::metrics::{Metrics, Summary};
+use crate::relay::Relay;
+use crate::settings::{Settings, MAX_PULSE_MS, MIN_PULSE_MS};
+use crate::telegram::Notifier;
+
+/// Standard-Port für CoAP ohne DTLS.
+pub const COAP_PORT: u16 = 5683;
+
+/// So lange kann ein Client eine CON-Anfrage wiederholen, EXCHANGE_LIFETIME aus RFC 7252.
+const EXCHANGE_LIFETIME: Duration = Duration::from_secs(247);
+// So viele ACKs werden für Wiederholungen aufgehoben, die ältesten fallen zuerst heraus
+const RECENT_LEN: usize = 16;
+
+// Nachrichtentypen
+const TYPE_CON: u8 = 0;
+const TYPE_NON: u8 = 1;
+const TYPE_ACK: u8 = 2;
+
+// Methoden und Antwort-Codes im Format Klasse.Detail, z.B. 0x44 für 2.04
+const METHOD_GET: u8 = 0x01;
+const METHOD_POST: u8 = 0x02;
+const CHANGED: u8 = 0x44;
+const CONTENT: u8 = 0x45;
+const BAD_REQUEST: u8 = 0x80;
+const UNAUTHORIZED: u8 = 0x81;
+const FORBIDDEN: u8 = 0x83;
+const NOT_FOUND: u8 = 0x84;
+const METHOD_NOT_ALLOWED: u8 = 0x85;
+const CONFLICT: u8 = 0x89;
+const TOO_MANY_REQUESTS: u8 = 0x9d;
+
+// Optionen
+const OPTION_URI_PATH: u16 = 11;
+const OPTION_CONTENT_FORMAT: u16 = 12;
+const OPTION_URI_QUERY: u16 = 15;
+const CONTENT_FORMAT_JSON: u8 = 50;
+
+/// Was die CoAP-Ressourcen mit den HTTP-Handlern teilen.
+pub struct Context {
+    pub relay: Relay,
+    pub settings: Arc<Settings>,
+    pub metrics: Arc<Metrics>,
+    pub log_queue: Arc<LogQueue>,
+    pub telegram: Option<Notifier>,
+    pub api_token: &'static str,
+    /// Dieselbe Sperre wie für die HTTP-Endpunkte
+    pub auth_limiter: Arc<AuthLimiter>,
+    /// Netze aus `PUSH_ALLOWLIST`, ohne die Variable darf jeder
+    pub allowlist: Option<Allowlist>,
+}
+
+/// Eine geparste Anfrage, Pfad und Query als einzelne Segmente.
+struct Request<'a> {
+    kind: u8,
+    code: u8,
+    message_id: u16,
+    token: &'a [u8],
+    path: Vec<&'a str>,
+    query: Vec<&'a str>,
+}
+
+/// Startet den Server als eigenen Thread auf [`COAP_PORT`].
+pub fn spawn(context: Context) -> Result<()> {
+    let socket = UdpSocket::bind(("0.0.0.0", COAP_PORT))?;
+    std::thread::Builder::new()
+        .name("coap".into())
+        .stack_size(4096)
+        .spawn(move || serve(socket, &context))?;
+    info!("CoAP-Server auf Port {}", COAP_PORT);
+    Ok(())
+}
+
+/// Zuletzt gesendetes ACK je Absender und Message-ID.
+struct Exchange {
+    peer: SocketAddr,
+    message_id: u16,
+    at: Instant,
+    response: Vec<u8>,
+}
+
+fn serve(socket: UdpSocket, context: &Context) {
+    let mut buf = [0u8; 256];
+    let mut next_message_id: u16 = 1;
+    let mut recent: VecDeque<Exchange> = VecDeque::with_capacity(RECENT_LEN);
+    loop {
+        let Ok((len, peer)) = socket.recv_from(&mut buf) else {
+            continue;
+        };
+        // ACK, RST und Antworten auf eigene Nachrichten werden nicht erwartet
+        let Some(request) = parse(&buf[..len]).filter(|request| request.kind <= TYPE_NON && request.code < 0x20)
+        else {
+            continue;
+        };
+        recent.retain(|exchange| exchange.at.elapsed() < EXCHANGE_LIFETIME);
+        if request.kind == TYPE_CON {
+            let repeated = recent
+                .iter()
+                .find(|exchange| exchange.peer == peer && exchange.message_id == request.message_id);
+            if let Some(exchange) = repeated {
+                debug!("CoAP-Wiederholung {} von {}, ACK erneut gesendet", request.message_id, peer);
+                let _ = socket.send_to(&exchange.response, peer);
+                continue;
+            }
+        }
+        let (code, payload) = handle(&request, context, peer.ip());
+
+        let (kind, message_id) = if request.kind == TYPE_CON {
+            (TYPE_ACK, request.message_id)
+        } else {
+            next_message_id = next_message_id.wrapping_add(1);
+            (TYPE_NON, next_message_id)
+        };
+        let response = build_response(kind, code, message_id, request.token, payload.as_deref());
+        let _ = socket.send_to(&response, peer);
+        if request.kind == TYPE_CON {
+            if recent.len() == RECENT_LEN {
+                recent.pop_front();
+            }
+            recent.push_back(Exchange {
+                peer,
+                message_id: request.message_id,
+                at: Instant::now(),
+                response,
+            });
+        }
+    }
+}
+
+fn handle(request: &Request, context: &Context, peer: IpAddr) -> (u8, Option<String>) {
+    match (request.path.as_slice(), request.code) {
+        (["push"], METHOD_POST) => push(request, context, peer),
+        (["push"], _) => (METHOD_NOT_ALLOWED, None),
+        (["status"], METHOD_GET) => {
+            let summary = Summary::collect(&context.metrics, &context.relay);
+            (CONTENT, serde_json::to_string(&summary).ok())
+        }
+        (["status"], _) => (METHOD_NOT_ALLOWED, None),
+        _ => (NOT_FOUND, None),
+    }
+}
+
+/// Wie `POST /push`: gleiche Grenzen, gleiche Ablehnungen, gleiche Zähler.
+fn push(request: &Request, context: &Context, peer: IpAddr) -> (u8, Option<String>) {
+    let log_queue = &context.log_queue;
+    let limiter = &context.auth_limiter;
+    if context.allowlist.as_ref().is_some_and(|allowlist| !allowlist.allows(Some(peer))) {
+        warn!("CoAP-push von {} abgewiesen, nicht in PUSH_ALLOWLIST", peer);
+        log_source(log_queue, Source::Coap, 403, "push");
+        return (FORBIDDEN, Some("source address not allowed".to_string()));
+    }
+    if limiter.locked(peer).is_some() {
+        log_source(log_queue, Source::Coap, 429, "push");
+        return (TOO_MANY_REQUESTS, Some("too many failed attempts".to_string()));
+    }
+    let authorized = query_value(&request.query, "token")
+        .is_some_and(|given| token_matches(given, context.api_token));
+    if !authorized {
+        if let Some(lockout) = limiter.record_failure(peer) {
+            warn!("{} nach zu vielen Fehlversuchen für {}s gesperrt", peer, lockout.as_secs());
+            log_source(log_queue, Source::Coap, 429, &format!("push lockout {}", peer));
+            return (TOO_MANY_REQUESTS, Some("too many failed attempts".to_string()));
+        }
+        log_source(log_queue, Source::Coap, 401, "push");
+        return (UNAUTHORIZED, None);
+    }
+    limiter.record_success(peer);
+
+    let pulse_ms = match query_value(&request.query, "ms") {
+        None => context.settings.get().pulse_ms,
+        Some(value) => match value.parse::<u64>() {
+            Ok(ms) if (MIN_PULSE_MS..=MAX_PULSE_MS).contains(&ms) => ms,
+            _ => {
+                log_source(log_queue, Source::Coap, 400, "push");
+                return (BAD_REQUEST, None);
+            }
+        },
+    };
+
+    match context.relay.pulse(std::time::Duration::from_millis(pulse_ms)) {
+        Ok(()) => {
+            context.metrics.record_push(pulse_ms);
+            log_switch(log_queue, Source::Coap, &format!("push {}ms", pulse_ms));
+            if let Some(telegram) = &context.telegram {
+                telegram.notify_push("CoAP");
+            }
+            (CHANGED, None)
+        }
+        Err(err) => {
+            log_source(log_queue, Source::Coap, err.status(), "push");
+            let code = if err.status() == 429 { TOO_MANY_REQUESTS } else { CONFLICT };
+            (code, Some(err.message().to_string()))
+        }
+    }
+}
+
+fn query_value<'a>(query: &[&'a str], key: &str) -> Option<&'a str> {
+    query.iter().find_map(|pair| {
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        (k == key).then_some(v)
+    })
+}
+
+/// Liest Header, Token und die Uri-Optionen, andere Optionen werden übersprungen.
+fn parse(message: &[u8]) -> Option<Request<'_>> {
+    let header = message.get(..4)?;
+    // Version 1 in den obersten beiden Bits
+    if header[0] >> 6 != 1 {
+        return None;
+    }
+    let kind = (header[0] >> 4) & 0x03;
+    let token_len = (header[0] & 0x0f) as usize;
+    if token_len > 8 {
+        return None;
+    }
+    let token = message.get(4..4 + token_len)?;
+
+    let mut request = Request {
+        kind,
+        code: header[1],
+        message_id: u16::from_be_bytes([header[2], header[3]]),
+        token,
+        path: Vec::new(),
+        query: Vec::new(),
+    };
+
+    let mut pos = 4 + token_len;
+    let mut number = 0u16;
+    while let Some(&byte) = message.get(pos) {
+        // Payload-Marker, der Body wird nicht gebraucht
+        if byte == 0xff {
+            break;
+        }
+        pos += 1;
+        let delta = read_extended(message, &mut pos, byte >> 4)?;
+        let len = read_extended(message, &mut pos, byte & 0x0f)? as usize;
+        number = number.checked_add(delta)?;
+        let value = message.get(pos..pos + len)?;
+        pos += len;
+        match number {
+            OPTION_URI_PATH => request.path.push(std::str::from_utf8(value).ok()?),
+            OPTION_URI_QUERY => request.query.push(std::str::from_utf8(value).ok()?),
+            _ => {}
+        }
+    }
+    Some(request)
+}
+
+/// Wert eines Options-Nibbles, 13 und 14 kündigen ein bzw. zwei zusätzliche Bytes an.
+fn read_extended(message: &[u8], pos: &mut usize, nibble: u8) -> Option<u16> {
+    match nibble {
+        0..=12 => Some(nibble as u16),
+        13 => {
+            let value = *message.get(*pos)? as u16 + 13;
+            *pos += 1;
+            Some(value)
+        }
+        14 => {
+            let bytes = message.get(*pos..*pos + 2)?;
+            *pos += 2;
+            u16::from_be_bytes([bytes[0], bytes[1]]).checked_add(269)
+        }
+        _ => None,
+    }
+}
+
+fn build_response(kind: u8, code: u8, message_id: u16, token: &[u8], payload: Option<&str>) -> Vec<u8> {
+    let mut response = Vec::with_capacity(8 + token.len() + payload.map_or(0, str::len));
+    response.push(0x40 | (kind << 4) | token.len() as u8);
+    response.push(code);
+    response.extend_from_slice(&message_id.to_be_bytes());
+    response.extend_from_slice(token);
+    if let Some(payload) = payload {
+        // JSON nur bei 2.05, Fehlermeldungen sind Klartext ohne Content-Format
+        if code == CONTENT {
+            response.push(((OPTION_CONTENT_FORMAT as u8) << 4) | 1);
+            response.push(CONTENT_FORMAT_JSON);
+        }
+        response.push(0xff);
+        response.extend_from_slice(payload.as_bytes());
+    }
+    response
+}
@@ -0,0 +1,693 @@
+use anyhow::{anyhow, bail, Result};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::net::Ipv4Addr;
+
+#[cfg(feature = "display")]
+use crate::display::{FontSize, Fonts, Rotation};
+use crate::logs::LogEntry;
+use crate::schedule::ScheduleEntry;
+use crate::settings::{is_valid_hostname, DeviceSettings};
+use crate::system;
+
+// NVS-Namespace und Schlüssel für die WLAN-Zugangsdaten
+const WIFI_NAMESPACE: &str = "wifi";
+const KEY_NETWORKS: &str = "networks";
+const KEY_LAST_SSID: &str = "last_ssid";
+// Einzelnes Netz, wie es ältere Firmware gespeichert hat
+const KEY_SSID: &str = "ssid";
+const KEY_PASSWORD: &str = "password";
+
+/// Höchstzahl gespeicherter WLAN-Netze.
+pub const MAX_NETWORKS: usize = 5;
+
+// NVS-Namespace für allgemeine Geräteeinstellungen
+const DEVICE_NAMESPACE: &str = "device";
+const KEY_HOSTNAME: &str = "hostname";
+const KEY_STATIC_IP: &str = "static_ip";
+const KEY_GATEWAY: &str = "gateway";
+const KEY_NETMASK: &str = "netmask";
+const KEY_DNS: &str = "dns";
+const KEY_HTTP_PORT: &str = "http_port";
+const KEY_BOOTS: &str = "boots";
+const KEY_PULSE_MS: &str = "pulse_ms";
+const KEY_DEBOUNCE_MS: &str = "debounce_ms";
+const KEY_BRIGHTNESS: &str = "brightness";
+const KEY_TICK_MS: &str = "tick_ms";
+const KEY_ACTIVE_LOW: &str = "active_low";
+const KEY_RESTORE_LATCH: &str = "restore_latch";
+const KEY_LATCHED: &str = "latched";
+const KEY_FORCE_SETUP: &str = "force_setup";
+
+// NVS-Namespace für Zähler, die einen Neustart überleben sollen
+const METRICS_NAMESPACE: &str = "metrics";
+const KEY_PUSHES: &str = "pushes";
+
+// NVS-Namespace für den gespeicherten Log-Verlauf
+const LOGS_NAMESPACE: &str = "logs";
+const KEY_HISTORY: &str = "history";
+
+// NVS-Namespace für den MQTT-Broker
+const MQTT_NAMESPACE: &str = "mqtt";
+const KEY_MQTT_URL: &str = "url";
+const KEY_MQTT_USER: &str = "user";
+const KEY_MQTT_PASSWORD: &str = "password";
+const KEY_MQTT_PREFIX: &str = "prefix";
+const KEY_MQTT_DISCOVERY: &str = "discovery";
+
+// NVS-Namespace für Zertifikat und Schlüssel des HTTPS-Servers
+const TLS_NAMESPACE: &str = "tls";
+const KEY_TLS_CERT: &str = "cert";
+const KEY_TLS_KEY: &str = "key";
+
+// NVS-Namespace für Telegram-Benachrichtigungen
+const TELEGRAM_NAMESPACE: &str = "telegram";
+const KEY_TELEGRAM_TOKEN: &str = "bot_token";
+const KEY_TELEGRAM_CHAT: &str = "chat_id";
+
+// NVS-Namespace für die Weiterleitung an einen Syslog-Server
+const SYSLOG_NAMESPACE: &str = "syslog";
+const KEY_SYSLOG_HOST: &str = "host";
+const KEY_SYSLOG_PORT: &str = "port";
+const KEY_SYSLOG_FACILITY: &str = "facility";
+
+// NVS-Namespace für den Webhook bei Relais-Ereignissen
+const WEBHOOK_NAMESPACE: &str = "webhook";
+const KEY_WEBHOOK_URL: &str = "url";
+const KEY_WEBHOOK_SECRET: &str = "secret";
+
+// NVS-Namespace für Display-Einstellungen
+const DISPLAY_NAMESPACE: &str = "display";
+#[cfg(feature = "display")]
+const KEY_ROTATION: &str = "rotation";
+const KEY_INVERTED: &str = "inverted";
+const KEY_TIME_FORMAT: &str = "time_format";
+#[cfg(feature = "display")]
+const KEY_FONT: &str = "font";
+#[cfg(feature = "display")]
+const KEY_HEADER_FONT: &str = "header_font";
+
+// NVS-Namespace für geplante Pulse
+const SCHEDULE_NAMESPACE: &str = "schedule";
+const KEY_ENTRIES: &str = "entries";
+
+/// Topic-Präfix, falls weder NVS noch `MQTT_PREFIX` einen vorgeben.
+pub const DEFAULT_MQTT_PREFIX: &str = "doofman";
+
+/// Port des HTTP-Servers, falls weder NVS noch `HTTP_PORT` einen vorgeben.
+pub const DEFAULT_HTTP_PORT: u16 = 80;
+
+/// Port des Servers mit HTTPS, falls weder NVS noch `HTTP_PORT` einen vorgeben.
+pub const DEFAULT_HTTPS_PORT: u16 = 443;
+
+/// Präfix des Gerätenamens, falls weder NVS noch `DEVICE_NAME` einen vorgeben.
+const DEFAULT_DEVICE_NAME_PREFIX: &str = "doofman";
+
+/// Feste IP-Konfiguration statt DHCP.
+#[derive(Clone, Debug)]
+pub struct StaticIpConfig {
+    pub ip: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    /// Netzmaske als Präfixlänge, z.B. 24 für 255.255.255.0
+    pub prefix_len: u8,
+    pub dns: Option<Ipv4Addr>,
+}
+
+/// WLAN-Zugangsdaten, wie sie im NVS abgelegt werden.
+///
+/// Für ein verstecktes Netz reichen `ssid`, `password` und `hidden`. `bssid` und `channel` sind
+/// optional, sparen aber die Suche über alle Kanäle und helfen bei Routern, die auf eine Anfrage
+/// nach der SSID nicht antworten.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WifiConfig {
+    pub ssid: String,
+    /// Bei WPA2-Enterprise das Passwort zu `enterprise.username`
+    pub password: String,
+    /// Mit Angabe WPA2-Enterprise (PEAP), sonst WPA2-PSK
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enterprise: Option<EnterpriseConfig>,
+    /// Das Netz sendet seine SSID nicht und taucht deshalb in keinem Scan auf
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hidden: bool,
+    /// MAC des Access Points wie `aa:bb:cc:dd:ee:ff`, verbindet nur mit genau diesem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bssid: Option<String>,
+    /// Kanal des Access Points, 1 bis 14
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<u8>,
+}
+
+/// Liest eine BSSID wie `aa:bb:cc:dd:ee:ff`, auch mit `-` als Trenner.
+pub fn parse_bssid(value: &str) -> Option<[u8; 6]> {
+    let mut bssid = [0u8; 6];
+    let mut parts = value.trim().split([':', '-']);
+    for byte in &mut bssid {
+        let part = parts.next().filter(|part| part.len() == 2)?;
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    parts.next().is_none().then_some(bssid)
+}
+
+/// Anmeldung an einem WPA2-Enterprise-Netz per PEAP.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EnterpriseConfig {
+    /// Äußere Identität, oft `anonymous@firma.de` oder wie `username`
+    pub identity: String,
+    pub username: String,
+}
+
+fn open(partition: &EspDefaultNvsPartition, namespace: &str) -> Result<EspNvs<NvsDefault>> {
+    Ok(EspNvs::new(partition.clone(), namespace, true)?)
+}
+
+/// Gespeicherte WLAN-Netze in Reihenfolge, leer wenn noch keine gespeichert sind.
+///
+/// Die Liste liegt als JSON unter `networks`. Ein einzelnes Netz aus älterer Firmware
+/// (`ssid`/`password`) wird als Liste mit einem Eintrag gelesen.
+pub fn load_wifi_networks(partition: &EspDefaultNvsPartition) -> Result<Vec<WifiConfig>> {
+    let nvs = open(partition, WIFI_NAMESPACE)?;
+
+    let mut buf = [0u8; 1024];
+    if let Some(json) = nvs.get_str(KEY_NETWORKS, &mut buf)? {
+        return Ok(serde_json::from_str(json)?);
+    }
+
+    let mut ssid_buf = [0u8; 33];
+    let mut password_buf = [0u8; 65];
+    let ssid = nvs.get_str(KEY_SSID, &mut ssid_buf)?;
+    let password = nvs.get_str(KEY_PASSWORD, &mut password_buf)?;
+    Ok(match (ssid, password) {
+        (Some(ssid), Some(password)) if !ssid.is_empty() => vec![WifiConfig {
+            ssid: ssid.to_string(),
+            password: password.to_string(),
+            ..Default::default()
+        }],
+        _ => Vec::new(),
+    })
+}
+
+pub fn store_wifi_networks(partition: &EspDefaultNvsPartition, networks: &[WifiConfig]) -> Result<()> {
+    let mut nvs = open(partition, WIFI_NAMESPACE)?;
+    nvs.set_str(KEY_NETWORKS, &serde_json::to_string(networks)?)?;
+    Ok(())
+}
+
+/// Fügt ein Netz vorne in die gespeicherte Liste ein, ein gleichnamiges wird ersetzt.
+pub fn add_wifi_network(partition: &EspDefaultNvsPartition, network: &WifiConfig) -> Result<()> {
+    let mut networks = load_wifi_networks(partition)?;
+    networks.retain(|known| known.ssid != network.ssid);
+    networks.insert(0, network.clone());
+    networks.truncate(MAX_NETWORKS);
+    store_wifi_networks(partition, &networks)
+}
+
+/// Netze aus den Umgebungsvariablen zur Build-Zeit: `WIFI_SSID`/`WIFI_PASS` sowie
+/// `WIFI_1_SSID`/`WIFI_1_PASS` bis `WIFI_4_SSID`/`WIFI_4_PASS`.
+///
+/// Mit `WIFI_EAP_USERNAME` ist das erste Netz ein WPA2-Enterprise-Netz, `WIFI_PASS` ist dann das
+/// Passwort dazu und `WIFI_EAP_IDENTITY` die äußere Identität (ohne Angabe der Benutzername).
+///
+/// Ist das Netz aus `WIFI_SSID` versteckt, braucht es `WIFI_HIDDEN=1`, optional mit
+/// `WIFI_BSSID` und `WIFI_CHANNEL`.
+pub fn env_wifi_networks() -> Vec<WifiConfig> {
+    let enterprise = option_env!("WIFI_EAP_USERNAME")
+        .filter(|username| !username.is_empty())
+        .map(|username| EnterpriseConfig {
+            identity: option_env!("WIFI_EAP_IDENTITY")
+                .filter(|identity| !identity.is_empty())
+                .unwrap_or(username)
+                .to_string(),
+            username: username.to_string(),
+        });
+    let mut networks: Vec<WifiConfig> = [
+        (option_env!("WIFI_SSID"), option_env!("WIFI_PASS")),
+        (option_env!("WIFI_1_SSID"), option_env!("WIFI_1_PASS")),
+        (option_env!("WIFI_2_SSID"), option_env!("WIFI_2_PASS")),
+        (option_env!("WIFI_3_SSID"), option_env!("WIFI_3_PASS")),
+        (option_env!("WIFI_4_SSID"), option_env!("WIFI_4_PASS")),
+    ]
+    .into_iter()
+    .filter_map(|(ssid, password)| {
+        let ssid = ssid.filter(|ssid| !ssid.is_empty())?;
+        Some(WifiConfig {
+            ssid: ssid.to_string(),
+            password: password.unwrap_or_default().to_string(),
+            ..Default::default()
+        })
+    })
+    .collect();
+    if let Some(first) = networks.first_mut() {
+        if option_env!("WIFI_SSID").is_some_and(|ssid| ssid == first.ssid) {
+            first.enterprise = enterprise;
+            first.hidden = option_env!("WIFI_HIDDEN") == Some("1");
+            first.bssid = option_env!("WIFI_BSSID").filter(|bssid| !bssid.is_empty()).map(str::to_string);
+            first.channel = option_env!("WIFI_CHANNEL").and_then(|channel| channel.parse().ok());
+        }
+    }
+    networks
+}
+
+/// SSID des Netzes, mit dem zuletzt eine Verbindung zustande kam.
+pub fn load_last_ssid(partition: &EspDefaultNvsPartition) -> Result<Option<String>> {
+    let nvs = open(partition, WIFI_NAMESPACE)?;
+    let mut buf = [0u8; 33];
+    Ok(nvs.get_str(KEY_LAST_SSID, &mut buf)?.map(str::to_string))
+}
+
+pub fn store_last_ssid(partition: &EspDefaultNvsPartition, ssid: &str) -> Result<()> {
+    let mut nvs = open(partition, WIFI_NAMESPACE)?;
+    nvs.set_str(KEY_LAST_SSID, ssid)?;
+    Ok(())
+}
+
+/// Gerätename für mDNS, DHCP, MQTT, Syslog und Telegram: aus dem NVS, sonst `DEVICE_NAME` (oder
+/// wie früher `HOSTNAME`) zur Build-Zeit, sonst `doofman-` mit den letzten Stellen der MAC, damit
+/// sich mehrere Geräte im selben Netz unterscheiden.
+///
+/// Kein gültiges DNS-Label, etwa wegen Leerzeichen, wird mit Warnung durch den Standard ersetzt.
+pub fn load_hostname(partition: &EspDefaultNvsPartition) -> Result<String> {
+    let nvs = open(partition, DEVICE_NAMESPACE)?;
+    let configured = nvs_or_env(&nvs, KEY_HOSTNAME, option_env!("DEVICE_NAME").or(option_env!("HOSTNAME")))?;
+    let default = || {
+        let chip_id = system::chip_id();
+        format!("{}-{}", DEFAULT_DEVICE_NAME_PREFIX, &chip_id[chip_id.len() - 6..])
+    };
+    Ok(match configured {
+        Some(name) if is_valid_hostname(&name) => name,
+        Some(name) => {
+            warn!("Gerätename \"{}\" ist kein gültiger Hostname, verwende Standard", name);
+            default()
+        }
+        None => default(),
+    })
+}
+
+/// Port des HTTP-Servers aus NVS oder `HTTP_PORT`, sonst [`DEFAULT_HTTP_PORT`] bzw. mit `tls`
+/// [`DEFAULT_HTTPS_PORT`].
+///
+/// Ein ungültiger Wert ist ein Fehler, damit der Server nicht unbemerkt auf einem anderen Port
+/// lauscht als erwartet.
+pub fn load_http_port(partition: &EspDefaultNvsPartition, tls: bool) -> Result<u16> {
+    let nvs = open(partition, DEVICE_NAMESPACE)?;
+    let Some(port) = nvs_or_env(&nvs, KEY_HTTP_PORT, option_env!("HTTP_PORT"))? else {
+        return Ok(if tls { DEFAULT_HTTPS_PORT } else { DEFAULT_HTTP_PORT });
+    };
+    match port.parse::<u16>() {
+        Ok(port) if port != 0 => Ok(port),
+        _ => bail!("HTTP_PORT \"{}\" muss zwischen 1 und 65535 liegen", port),
+    }
+}
+
+/// Über `/config` gespeicherte Einstellungen, fehlende Felder aus `defaults`. Den Hostnamen liest
+/// schon [`load_hostname`], er steht deshalb bereits in `defaults`.
+pub fn load_settings(partition: &EspDefaultNvsPartition, defaults: DeviceSettings) -> Result<DeviceSettings> {
+    let nvs = open(partition, DEVICE_NAMESPACE)?;
+    let mut settings = defaults;
+    if let Some(pulse_ms) = nvs.get_u64(KEY_PULSE_MS)? {
+        settings.pulse_ms = pulse_ms;
+    }
+    if let Some(debounce_ms) = nvs.get_u64(KEY_DEBOUNCE_MS)? {
+        settings.debounce_ms = debounce_ms;
+    }
+    if let Some(brightness) = nvs.get_u8(KEY_BRIGHTNESS)? {
+        settings.brightness = brightness;
+    }
+    if let Some(tick_ms) = nvs.get_u64(KEY_TICK_MS)? {
+        settings.tick_ms = tick_ms;
+    }
+    if let Some(active_low) = nvs.get_u8(KEY_ACTIVE_LOW)? {
+        settings.relay_active_low = active_low != 0;
+    }
+    if let Some(restore_latch) = nvs.get_u8(KEY_RESTORE_LATCH)? {
+        settings.restore_latch = restore_latch != 0;
+    }
+    Ok(settings)
+}
+
+pub fn store_settings(partition: &EspDefaultNvsPartition, settings: &DeviceSettings) -> Result<()> {
+    let mut nvs = open(partition, DEVICE_NAMESPACE)?;
+    nvs.set_u64(KEY_PULSE_MS, settings.pulse_ms)?;
+    nvs.set_u64(KEY_DEBOUNCE_MS, settings.debounce_ms)?;
+    nvs.set_u8(KEY_BRIGHTNESS, settings.brightness)?;
+    nvs.set_u64(KEY_TICK_MS, settings.tick_ms)?;
+    nvs.set_u8(KEY_ACTIVE_LOW, settings.relay_active_low as u8)?;
+    nvs.set_u8(KEY_RESTORE_LATCH, settings.restore_latch as u8)?;
+    nvs.set_str(KEY_HOSTNAME, &settings.hostname)?;
+    Ok(())
+}
+
+/// Namespaces, die das Zurücksetzen auf Werkseinstellungen löscht. Das TLS-Zertifikat bleibt, es
+/// gehört zum Gerät und nicht zu seiner Einrichtung.
+const RESET_NAMESPACES: &[&str] = &[
+    WIFI_NAMESPACE,
+    DEVICE_NAMESPACE,
+    METRICS_NAMESPACE,
+    LOGS_NAMESPACE,
+    MQTT_NAMESPACE,
+    TELEGRAM_NAMESPACE,
+    SYSLOG_NAMESPACE,
+    WEBHOOK_NAMESPACE,
+    DISPLAY_NAMESPACE,
+    SCHEDULE_NAMESPACE,
+];
+
+/// Löscht Zugangsdaten, Einstellungen und Zähler. Beim nächsten Start geht es ohne Rückgriff auf
+/// die Zugangsdaten aus dem Build direkt ins Einrichtungsportal.
+pub fn factory_reset(partition: &EspDefaultNvsPartition) -> Result<()> {
+    for namespace in RESET_NAMESPACES {
+        let name = CString::new(*namespace)?;
+        let mut handle = 0;
+        esp_idf_sys::esp!(unsafe {
+            esp_idf_sys::nvs_open(name.as_ptr(), esp_idf_sys::nvs_open_mode_t_NVS_READWRITE, &mut handle)
+        })?;
+        let result = esp_idf_sys::esp!(unsafe { esp_idf_sys::nvs_erase_all(handle) })
+            .and_then(|()| esp_idf_sys::esp!(unsafe { esp_idf_sys::nvs_commit(handle) }));
+        unsafe { esp_idf_sys::nvs_close(handle) };
+        result?;
+    }
+    let mut nvs = open(partition, DEVICE_NAMESPACE)?;
+    nvs.set_u8(KEY_FORCE_SETUP, 1)?;
+    Ok(())
+}
+
+/// `true` einmalig nach [`factory_reset`], der Eintrag wird dabei entfernt.
+pub fn take_force_setup(partition: &EspDefaultNvsPartition) -> Result<bool> {
+    let mut nvs = open(partition, DEVICE_NAMESPACE)?;
+    if nvs.get_u8(KEY_FORCE_SETUP)?.is_none() {
+        return Ok(false);
+    }
+    nvs.remove(KEY_FORCE_SETUP)?;
+    Ok(true)
+}
+
+/// Zuletzt gespeicherter Dauerbetrieb des Relais, ohne Eintrag aus.
+pub fn load_latched(partition: &EspDefaultNvsPartition) -> Result<bool> {
+    let nvs = open(partition, DEVICE_NAMESPACE)?;
+    Ok(nvs.get_u8(KEY_LATCHED)?.is_some_and(|latched| latched != 0))
+}
+
+pub fn store_latched(partition: &EspDefaultNvsPartition, latched: bool) -> Result<()> {
+    let mut nvs = open(partition, DEVICE_NAMESPACE)?;
+    nvs.set_u8(KEY_LATCHED, latched as u8)?;
+    Ok(())
+}
+
+/// Zählt den Start mit und liefert, der wievielte es ist, der erste ist 1.
+pub fn record_boot(partition: &EspDefaultNvsPartition) -> Result<u32> {
+    let mut nvs = open(partition, DEVICE_NAMESPACE)?;
+    let boots = nvs.get_u32(KEY_BOOTS)?.unwrap_or(0).wrapping_add(1);
+    nvs.set_u32(KEY_BOOTS, boots)?;
+    Ok(boots)
+}
+
+/// Drehung des Displays aus NVS oder `DISPLAY_ROTATION` (0, 90, 180 oder 270), sonst 0°.
+#[cfg(feature = "display")]
+pub fn load_display_rotation(partition: &EspDefaultNvsPartition) -> Result<Rotation> {
+    let nvs = open(partition, DISPLAY_NAMESPACE)?;
+    let Some(rotation) = nvs_or_env(&nvs, KEY_ROTATION, option_env!("DISPLAY_ROTATION"))? else {
+        return Ok(Rotation::default());
+    };
+    Rotation::parse(&rotation)
+        .ok_or_else(|| anyhow!("DISPLAY_ROTATION \"{}\" muss 0, 90, 180 oder 270 sein", rotation))
+}
+
+/// Schriften aus NVS oder `DISPLAY_FONT` für die Logzeilen und `DISPLAY_HEADER_FONT` für die
+/// Kopfzeilen (je `6x10`, `9x15` oder `10x20`). Ohne eigene Angabe folgen die Kopfzeilen den Logs.
+#[cfg(feature = "display")]
+pub fn load_display_fonts(partition: &EspDefaultNvsPartition) -> Result<Fonts> {
+    let nvs = open(partition, DISPLAY_NAMESPACE)?;
+    let parse = |name: &str, value: String| {
+        FontSize::parse(&value).ok_or_else(|| anyhow!("{} \"{}\" muss 6x10, 9x15 oder 10x20 sein", name, value))
+    };
+    let log = match nvs_or_env(&nvs, KEY_FONT, option_env!("DISPLAY_FONT"))? {
+        Some(value) => parse("DISPLAY_FONT", value)?,
+        None => FontSize::default(),
+    };
+    let header = match nvs_or_env(&nvs, KEY_HEADER_FONT, option_env!("DISPLAY_HEADER_FONT"))? {
+        Some(value) => parse("DISPLAY_HEADER_FONT", value)?,
+        None => log,
+    };
+    Ok(Fonts { header, log })
+}
+
+/// Invertierte Anzeige aus NVS, ohne gespeicherte Wahl aus `DISPLAY_INVERT=1`.
+pub fn load_display_inverted(partition: &EspDefaultNvsPartition) -> Result<bool> {
+    let nvs = open(partition, DISPLAY_NAMESPACE)?;
+    Ok(match nvs.get_u8(KEY_INVERTED)? {
+        Some(inverted) => inverted != 0,
+        None => option_env!("DISPLAY_INVERT") == Some("1"),
+    })
+}
+
+/// strftime-Format für Uhrzeiten in Log und Display aus NVS oder `TIME_FORMAT`, ungeprüft.
+pub fn load_time_format(partition: &EspDefaultNvsPartition) -> Result<Option<String>> {
+    let nvs = open(partition, DISPLAY_NAMESPACE)?;
+    nvs_or_env(&nvs, KEY_TIME_FORMAT, option_env!("TIME_FORMAT"))
+}
+
+pub fn store_display_inverted(partition: &EspDefaultNvsPartition, inverted: bool) -> Result<()> {
+    let mut nvs = open(partition, DISPLAY_NAMESPACE)?;
+    nvs.set_u8(KEY_INVERTED, inverted as u8)?;
+    Ok(())
+}
+
+/// Zertifikat und Schlüssel im PEM-Format aus dem NVS, `None` wenn nicht beide hinterlegt sind.
+pub fn load_tls_identity(partition: &EspDefaultNvsPartition) -> Result<Option<(String, String)>> {
+    let nvs = open(partition, TLS_NAMESPACE)?;
+    let mut cert_buf = vec![0u8; 4096];
+    let mut key_buf = vec![0u8; 4096];
+    let cert = nvs.get_str(KEY_TLS_CERT, &mut cert_buf)?.filter(|cert| !cert.is_empty());
+    let key = nvs.get_str(KEY_TLS_KEY, &mut key_buf)?.filter(|key| !key.is_empty());
+    Ok(match (cert, key) {
+        (Some(cert), Some(key)) => Some((cert.to_string(), key.to_string())),
+        _ => None,
+    })
+}
+
+/// Gespeicherter Push-Zähler, 0 wenn noch keiner geschrieben wurde.
+pub fn load_push_count(partition: &EspDefaultNvsPartition) -> Result<u32> {
+    let nvs = open(partition, METRICS_NAMESPACE)?;
+    Ok(nvs.get_u32(KEY_PUSHES)?.unwrap_or(0))
+}
+
+pub fn store_push_count(partition: &EspDefaultNvsPartition, pushes: u32) -> Result<()> {
+    let mut nvs = open(partition, METRICS_NAMESPACE)?;
+    nvs.set_u32(KEY_PUSHES, pushes)?;
+    Ok(())
+}
+
+/// Gespeicherter Log-Verlauf, älteste zuerst.
+pub fn load_log_history(partition: &EspDefaultNvsPartition) -> Result<Vec<LogEntry>> {
+    let nvs = open(partition, LOGS_NAMESPACE)?;
+    let mut buf = vec![0u8; 4096];
+    match nvs.get_str(KEY_HISTORY, &mut buf)? {
+        Some(json) => Ok(serde_json::from_str(json)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+pub fn store_log_history(partition: &EspDefaultNvsPartition, entries: &[LogEntry]) -> Result<()> {
+    let mut nvs = open(partition, LOGS_NAMESPACE)?;
+    nvs.set_str(KEY_HISTORY, &serde_json::to_string(entries)?)?;
+    Ok(())
+}
+
+pub fn load_schedule(partition: &EspDefaultNvsPartition) -> Result<Vec<ScheduleEntry>> {
+    let nvs = open(partition, SCHEDULE_NAMESPACE)?;
+    let mut buf = vec![0u8; 1024];
+    match nvs.get_str(KEY_ENTRIES, &mut buf)? {
+        Some(json) => Ok(serde_json::from_str(json)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+pub fn store_schedule(partition: &EspDefaultNvsPartition, entries: &[ScheduleEntry]) -> Result<()> {
+    let mut nvs = open(partition, SCHEDULE_NAMESPACE)?;
+    nvs.set_str(KEY_ENTRIES, &serde_json::to_string(entries)?)?;
+    Ok(())
+}
+
+/// Liest einen Text aus dem NVS, sonst aus der Umgebungsvariable zur Build-Zeit.
+///
+/// Der Puffer richtet sich nach der Länge im NVS, lange URLs und Passwörter passen also. Lässt
+/// sich der Wert nicht lesen, gilt mit Warnung die Umgebungsvariable.
+fn nvs_or_env(nvs: &EspNvs<NvsDefault>, key: &str, env: Option<&'static str>) -> Result<Option<String>> {
+    let stored = nvs.str_len(key).and_then(|len| {
+        let Some(len) = len else {
+            return Ok(None);
+        };
+        let mut buf = vec![0u8; len];
+        Ok(nvs.get_str(key, &mut buf)?.map(str::to_string))
+    });
+    Ok(match stored {
+        Ok(Some(value)) if !value.is_empty() => Some(value),
+        Ok(_) => env.filter(|value| !value.is_empty()).map(str::to_string),
+        Err(err) => {
+            warn!("NVS-Wert {} nicht lesbar, verwende die Umgebungsvariable: {:?}", key, err);
+            env.filter(|value| !value.is_empty()).map(str::to_string)
+        }
+    })
+}
+
+/// Feste IP aus NVS oder `STATIC_IP`/`STATIC_GATEWAY`/`STATIC_NETMASK`/`STATIC_DNS`.
+///
+/// Ohne `STATIC_IP` bleibt es bei DHCP (`None`). Ist sie gesetzt, aber ungültig oder
+/// unvollständig, gibt es einen Fehler, damit das Gerät nicht mit falscher Adresse startet.
+pub fn load_static_ip(partition: &EspDefaultNvsPartition) -> Result<Option<StaticIpConfig>> {
+    let nvs = open(partition, DEVICE_NAMESPACE)?;
+    let Some(ip) = nvs_or_env(&nvs, KEY_STATIC_IP, option_env!("STATIC_IP"))? else {
+        return Ok(None);
+    };
+    let gateway = nvs_or_env(&nvs, KEY_GATEWAY, option_env!("STATIC_GATEWAY"))?
+        .ok_or_else(|| anyhow!("STATIC_IP gesetzt, aber STATIC_GATEWAY fehlt"))?;
+    let netmask = nvs_or_env(&nvs, KEY_NETMASK, option_env!("STATIC_NETMASK"))?
+        .unwrap_or_else(|| "24".to_string());
+    let dns = nvs_or_env(&nvs, KEY_DNS, option_env!("STATIC_DNS"))?;
+
+    let parse = |name: &str, value: &str| -> Result<Ipv4Addr> {
+        value
+            .parse()
+            .map_err(|_| anyhow!("{} \"{}\" ist keine gültige IPv4-Adresse", name, value))
+    };
+    let config = StaticIpConfig {
+        ip: parse("STATIC_IP", &ip)?,
+        gateway: parse("STATIC_GATEWAY", &gateway)?,
+        prefix_len: parse_prefix_len(&netmask)?,
+        dns: dns.as_deref().map(|dns| parse("STATIC_DNS", dns)).transpose()?,
+    };
+
+    let mask = u32::MAX.checked_shl(32 - config.prefix_len as u32).unwrap_or(0);
+    if u32::from(config.ip) & mask != u32::from(config.gateway) & mask {
+        bail!("Gateway {} liegt nicht im Netz von {}/{}", config.gateway, config.ip, config.prefix_len);
+    }
+    Ok(Some(config))
+}
+
+/// Zugang zum MQTT-Broker.
+#[derive(Clone, Debug)]
+pub struct MqttConfig {
+    /// z.B. `mqtt://192.168.1.10:1883`
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Alle Topics liegen darunter, z.B. `doofman/cmd`
+    pub prefix: String,
+    /// Home-Assistant-Discovery veröffentlichen, abschaltbar über `MQTT_DISCOVERY=0`
+    pub discovery: bool,
+}
+
+/// Broker aus NVS oder `MQTT_URL`/`MQTT_USER`/`MQTT_PASSWORD`/`MQTT_PREFIX`.
+///
+/// Ohne `MQTT_URL` bleibt MQTT aus (`None`). Die Home-Assistant-Discovery ist an, solange
+/// `MQTT_DISCOVERY` nicht `0` ist.
+pub fn load_mqtt_config(partition: &EspDefaultNvsPartition) -> Result<Option<MqttConfig>> {
+    let nvs = open(partition, MQTT_NAMESPACE)?;
+    let Some(url) = nvs_or_env(&nvs, KEY_MQTT_URL, option_env!("MQTT_URL"))? else {
+        return Ok(None);
+    };
+    Ok(Some(MqttConfig {
+        url,
+        username: nvs_or_env(&nvs, KEY_MQTT_USER, option_env!("MQTT_USER"))?,
+        password: nvs_or_env(&nvs, KEY_MQTT_PASSWORD, option_env!("MQTT_PASSWORD"))?,
+        prefix: nvs_or_env(&nvs, KEY_MQTT_PREFIX, option_env!("MQTT_PREFIX"))?
+            .unwrap_or_else(|| DEFAULT_MQTT_PREFIX.to_string()),
+        discovery: nvs_or_env(&nvs, KEY_MQTT_DISCOVERY, option_env!("MQTT_DISCOVERY"))?.as_deref()
+            != Some("0"),
+    }))
+}
+
+/// Zugang zum Telegram-Bot.
+#[derive(Clone, Debug)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    pub chat_id: String,
+}
+
+/// Bot aus NVS oder `TELEGRAM_BOT_TOKEN`/`TELEGRAM_CHAT_ID`, ohne beides keine Benachrichtigung.
+pub fn load_telegram_config(partition: &EspDefaultNvsPartition) -> Result<Option<TelegramConfig>> {
+    let nvs = open(partition, TELEGRAM_NAMESPACE)?;
+    let bot_token = nvs_or_env(&nvs, KEY_TELEGRAM_TOKEN, option_env!("TELEGRAM_BOT_TOKEN"))?;
+    let chat_id = nvs_or_env(&nvs, KEY_TELEGRAM_CHAT, option_env!("TELEGRAM_CHAT_ID"))?;
+    Ok(match (bot_token, chat_id) {
+        (Some(bot_token), Some(chat_id)) => Some(TelegramConfig { bot_token, chat_id }),
+        (None, None) => None,
+        _ => bail!("TELEGRAM_BOT_TOKEN und TELEGRAM_CHAT_ID müssen beide gesetzt sein"),
+    })
+}
+
+/// Ziel für die Weiterleitung der Logeinträge per Syslog.
+#[derive(Clone, Debug)]
+pub struct SyslogConfig {
+    pub host: String,
+    pub port: u16,
+    /// Facility nach RFC 5424, 16 bis 23 entsprechen `local0` bis `local7`
+    pub facility: u8,
+}
+
+/// Server aus NVS oder `SYSLOG_HOST`/`SYSLOG_PORT`/`SYSLOG_FACILITY`, ohne Host keine Weiterleitung.
+///
+/// Port ist standardmäßig 514, die Facility `local0`. Sie darf als Zahl oder als `local0` bis
+/// `local7` angegeben werden.
+pub fn load_syslog_config(partition: &EspDefaultNvsPartition) -> Result<Option<SyslogConfig>> {
+    let nvs = open(partition, SYSLOG_NAMESPACE)?;
+    let Some(host) = nvs_or_env(&nvs, KEY_SYSLOG_HOST, option_env!("SYSLOG_HOST"))? else {
+        return Ok(None);
+    };
+    let port = match nvs_or_env(&nvs, KEY_SYSLOG_PORT, option_env!("SYSLOG_PORT"))? {
+        Some(port) => port.parse().map_err(|_| anyhow!("Ungültiger SYSLOG_PORT \"{}\"", port))?,
+        None => 514,
+    };
+    let facility = match nvs_or_env(&nvs, KEY_SYSLOG_FACILITY, option_env!("SYSLOG_FACILITY"))? {
+        Some(facility) => parse_facility(&facility)?,
+        None => 16,
+    };
+    Ok(Some(SyslogConfig { host, port, facility }))
+}
+
+/// Ziel für den Webhook bei Relais-Ereignissen.
+#[derive(Clone, Debug)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Schlüssel für die HMAC-Signatur, ohne wird unsigniert gesendet
+    pub secret: Option<String>,
+}
+
+/// URL aus NVS oder `WEBHOOK_URL`, ohne URL kein Webhook. `WEBHOOK_SECRET` ist optional.
+pub fn load_webhook_config(partition: &EspDefaultNvsPartition) -> Result<Option<WebhookConfig>> {
+    let nvs = open(partition, WEBHOOK_NAMESPACE)?;
+    let Some(url) = nvs_or_env(&nvs, KEY_WEBHOOK_URL, option_env!("WEBHOOK_URL"))? else {
+        return Ok(None);
+    };
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        bail!("WEBHOOK_URL muss mit http:// oder https:// beginnen");
+    }
+    let secret = nvs_or_env(&nvs, KEY_WEBHOOK_SECRET, option_env!("WEBHOOK_SECRET"))?
+        .filter(|secret| !secret.is_empty());
+    Ok(Some(WebhookConfig { url, secret }))
+}
+
+fn parse_facility(value: &str) -> Result<u8> {
+    let facility = match value.strip_prefix("local") {
+        Some(n) => n.parse::<u8>().ok().filter(|n| *n <= 7).map(|n| n + 16),
+        None => value.parse::<u8>().ok().filter(|facility| *facility <= 23),
+    };
+    facility.ok_or_else(|| anyhow!("Ungültige SYSLOG_FACILITY \"{}\"", value))
+}
+
+/// Akzeptiert `24` oder `255.255.255.0`.
+fn parse_prefix_len(netmask: &str) -> Result<u8> {
+    if let Ok(prefix_len) = netmask.parse::<u8>() {
+        if (1..=32).contains(&prefix_len) {
+            return Ok(prefix_len);
+        }
+    } else if let Ok(mask) = netmask.parse::<Ipv4Addr>() {
+        let mask = u32::from(mask);
+        // Nur zusammenhängende Masken wie 255.255.255.0
+        if mask != 0 && mask.leading_ones() + mask.trailing_zeros() == 32 {
+            return Ok(mask.leading_ones() as u8);
+        }
+    }
+    bail!("Ungültige Netzmaske \"{}\"", netmask)
+}
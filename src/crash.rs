@@ -0,0 +1,41 @@
+//! Ausfallsicheres Verhalten bei einem Panic.
+//!
+//! Das Relais schaltet schon der Hook aus [`crate::relay::Relay::spawn`] ab. Dieser Hook schreibt
+//! Thread und Stelle direkt auf den UART und merkt sich die Meldung. Panict ein anderer Thread
+//! als die Hauptschleife, zeigt diese beim nächsten Durchlauf einen PANIC-Bildschirm statt der
+//! veralteten Daten und startet das Gerät neu, statt mit einem toten Thread weiterzulaufen. Panict
+//! die Hauptschleife selbst, bricht das Programm direkt ab und der Chip startet neu.
+
+use std::ffi::CString;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// So lange bleibt der PANIC-Bildschirm stehen, bevor neu gestartet wird.
+pub const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+static MESSAGE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Installiert den Hook, muss vor `Relay::spawn` laufen, damit dessen Abschaltung zuerst kommt.
+pub fn install() {
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let location = info
+            .location()
+            .map_or("unbekannt".to_string(), |location| format!("{}:{}", location.file(), location.line()));
+        let thread = std::thread::current().name().unwrap_or("?").to_string();
+        // Am Logger vorbei, der könnte selbst der Grund für den Panic sein
+        if let Ok(line) = CString::new(format!("PANIC in Thread '{}' bei {}", thread, location)) {
+            unsafe { esp_idf_sys::esp_rom_printf(c"%s\n".as_ptr(), line.as_ptr()) };
+        }
+        // Hält der panickende Thread die Meldung gerade selbst, lieber keine als ein Deadlock
+        if let Ok(mut message) = MESSAGE.try_lock() {
+            message.get_or_insert(format!("{} {}", thread, location));
+        }
+        previous_hook(info);
+    }));
+}
+
+/// Meldung des ersten Panics in einem anderen Thread, `None` solange alles läuft.
+pub fn take() -> Option<String> {
+    MESSAGE.lock().ok()?.take()
+}
@@ -0,0 +1,150 @@
+//! Statusanzeige mit IP, WLAN, Relais und den letzten Requests.
+//!
+//! Welches Display angesteuert wird, entscheidet das Cargo-Feature: `st7789` (Standard) für das
+//! Farb-TFT des HTIT-WB32, `ssd1306` für monochrome OLEDs. Die Hauptschleife spricht nur über
+//! [`StatusDisplay`] mit dem Display.
+//!
+//! Ohne das Feature `display` fehlt das ganze Modul samt embedded-graphics und QR-Code, die
+//! Hauptschleife zeichnet dann nichts und lässt die Hintergrundbeleuchtung aus.
+
+use anyhow::Result;
+
+use crate::logs::LogEntry;
+use crate::relay::RelayState;
+
+mod retry;
+mod screen;
+#[cfg(feature = "ssd1306")]
+pub mod ssd1306;
+#[cfg(feature = "st7789")]
+pub mod st7789;
+
+#[cfg(all(feature = "st7789", feature = "ssd1306"))]
+compile_error!("Die Features `st7789` und `ssd1306` schließen sich aus");
+#[cfg(not(any(feature = "st7789", feature = "ssd1306")))]
+compile_error!("Das Feature `display` braucht `st7789` oder `ssd1306`");
+
+pub use retry::Retrying;
+pub use screen::Screen;
+
+/// Was die Hauptschleife von einem Display braucht.
+///
+/// Die Setter merken sich nur den neuen Inhalt, gezeichnet wird erst mit [`flush`], und dann nur
+/// die Zeilen, die sich geändert haben.
+///
+/// [`flush`]: StatusDisplay::flush
+pub trait StatusDisplay {
+    /// Kopfzeilen wie IP und WLAN-Zustand, `url` erscheint als QR-Code, soweit Platz ist.
+    fn show_ip(&mut self, header: &[String], url: &str);
+
+    fn set_relay_state(&mut self, relay: RelayState);
+
+    /// Uhrzeit wie `12:34` groß über den Kopfzeilen, `None` zeigt `--:--`, solange die Uhr nicht
+    /// synchronisiert ist.
+    fn set_clock(&mut self, time: Option<String>);
+
+    /// WLAN-Signalstärke in dBm als Zeile mit Balken, `None` blendet sie aus. Jeder Aufruf fließt
+    /// außerdem in den Verlauf der letzten Minute neben der Uhr ein.
+    fn set_rssi(&mut self, rssi: Option<i8>);
+
+    /// Aktuelle Logeinträge, älteste zuerst. Angezeigt werden die neuesten, die passen.
+    fn push_log(&mut self, entries: &[LogEntry]);
+
+    /// Zeichnet, was sich seit dem letzten Aufruf geändert hat.
+    fn flush(&mut self) -> Result<()>;
+
+    /// Ersetzt sofort den ganzen Bildschirm durch eine PANIC-Meldung mit `message`, damit nach
+    /// einem Absturz keine veralteten Daten stehen bleiben.
+    fn show_panic(&mut self, message: &str) -> Result<()>;
+
+    /// Ersetzt sofort den ganzen Bildschirm durch den Startbildschirm mit Gerätename, Firmware und
+    /// dem Fortschritt `progress`, bis die Hauptschleife das erste Mal zeichnet.
+    fn show_splash(&mut self, name: &str, version: &str, progress: &str) -> Result<()>;
+
+    /// Dunkle Schrift auf hellem Grund statt hell auf dunkel, wirkt ab dem nächsten `flush`.
+    fn set_inverted(&mut self, inverted: bool);
+
+    /// Beim nächsten [`flush`](StatusDisplay::flush) den Bildschirm löschen und alles neu zeichnen,
+    /// z.B. nach dem Wecken aus der Abschaltung.
+    fn invalidate(&mut self);
+}
+
+/// Drehung des Displays im Uhrzeigersinn, für gedreht montierte Geräte.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl Rotation {
+    /// Akzeptiert `0`, `90`, `180` und `270`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "0" => Some(Rotation::Deg0),
+            "90" => Some(Rotation::Deg90),
+            "180" => Some(Rotation::Deg180),
+            "270" => Some(Rotation::Deg270),
+            _ => None,
+        }
+    }
+
+    /// `true`, wenn Breite und Höhe gegenüber der Grundstellung vertauscht sind.
+    pub fn swaps_axes(self) -> bool {
+        matches!(self, Rotation::Deg90 | Rotation::Deg270)
+    }
+}
+
+/// Schrift für Display-Zeilen, aus den Mono-Schriften von embedded-graphics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FontSize {
+    /// 6x10, der bisherige Standard
+    #[default]
+    Small,
+    /// 9x15
+    Medium,
+    /// 10x20, aus einigen Metern noch lesbar
+    Large,
+}
+
+impl FontSize {
+    /// Akzeptiert `6x10`, `9x15` und `10x20`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "6x10" => Some(FontSize::Small),
+            "9x15" => Some(FontSize::Medium),
+            "10x20" => Some(FontSize::Large),
+            _ => None,
+        }
+    }
+}
+
+/// Schriften für Kopfzeilen (IP, WLAN, Relais) und Logzeilen, die Uhr ist immer groß.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Fonts {
+    pub header: FontSize,
+    pub log: FontSize,
+}
+
+/// Bedeutung einer Zeile, jedes Display setzt sie in seine eigenen Farben um.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tone {
+    Normal,
+    Good,
+    Warning,
+    Error,
+}
+
+impl Tone {
+    /// 2xx gut, 4xx Warnung, 5xx Fehler, alles andere neutral.
+    pub fn for_status(status: Option<u16>) -> Self {
+        match status {
+            Some(200..=299) => Tone::Good,
+            Some(400..=499) => Tone::Warning,
+            Some(500..=599) => Tone::Error,
+            _ => Tone::Normal,
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use anyhow::Result;
+use log::*;
+use std::time::{Duration, Instant};
+
+use super::StatusDisplay;
+use crate::logs::LogEntry;
+use crate::relay::RelayState;
+
+// Abstand zwischen zwei Versuchen, ein fehlendes Display zu initialisieren
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+// So viele Zeichenfehler in Folge, bevor das Display neu initialisiert wird
+const MAX_FAILURES: u32 = 3;
+
+/// Hält ein Display, das auch fehlen darf, und versucht die Initialisierung regelmäßig erneut.
+///
+/// Schlägt die Initialisierung fehl, z.B. bei einem losen Flachbandkabel, läuft das Gerät ohne
+/// Anzeige weiter. Relais, WLAN und HTTP hängen so nie vom Display ab. Die Setter werden ohne
+/// Display verworfen, die Hauptschleife setzt ohnehin bei jedem Durchlauf alles neu.
+///
+/// Ein einzelner Fehler beim Zeichnen wird nur geloggt, das nächste `flush` zeichnet alles neu.
+/// Erst nach [`MAX_FAILURES`] Fehlern in Folge wird das Display verworfen und sofort neu
+/// initialisiert, danach wie ein fehlendes alle [`RETRY_INTERVAL`].
+pub struct Retrying<D, F> {
+    display: Option<D>,
+    init: F,
+    next_attempt: Instant,
+    failures: u32,
+}
+
+impl<D, F> Retrying<D, F>
+where
+    D: StatusDisplay,
+    F: FnMut() -> Result<D>,
+{
+    /// Initialisiert sofort, ein Fehler wird nur geloggt.
+    pub fn new(mut init: F) -> Self {
+        let display = match init() {
+            Ok(display) => Some(display),
+            Err(err) => {
+                error!("Display nicht verfügbar, weiter ohne Anzeige: {:?}", err);
+                None
+            }
+        };
+        Self {
+            display,
+            init,
+            next_attempt: Instant::now() + RETRY_INTERVAL,
+            failures: 0,
+        }
+    }
+
+    fn retry(&mut self) {
+        if self.display.is_some() || Instant::now() < self.next_attempt {
+            return;
+        }
+        self.next_attempt = Instant::now() + RETRY_INTERVAL;
+        match (self.init)() {
+            Ok(display) => {
+                info!("Display wieder verfügbar");
+                self.display = Some(display);
+            }
+            Err(err) => debug!("Display weiterhin nicht verfügbar: {:?}", err),
+        }
+    }
+}
+
+impl<D, F> StatusDisplay for Retrying<D, F>
+where
+    D: StatusDisplay,
+    F: FnMut() -> Result<D>,
+{
+    fn show_ip(&mut self, header: &[String], url: &str) {
+        if let Some(display) = &mut self.display {
+            display.show_ip(header, url);
+        }
+    }
+
+    fn set_relay_state(&mut self, relay: RelayState) {
+        if let Some(display) = &mut self.display {
+            display.set_relay_state(relay);
+        }
+    }
+
+    fn set_clock(&mut self, time: Option<String>) {
+        if let Some(display) = &mut self.display {
+            display.set_clock(time);
+        }
+    }
+
+    fn set_rssi(&mut self, rssi: Option<i8>) {
+        if let Some(display) = &mut self.display {
+            display.set_rssi(rssi);
+        }
+    }
+
+    fn push_log(&mut self, entries: &[LogEntry]) {
+        if let Some(display) = &mut self.display {
+            display.push_log(entries);
+        }
+    }
+
+    /// Zeichnet, sofern ein Display da ist, sonst wird ggf. die Initialisierung wiederholt.
+    ///
+    /// Fehler beim Zeichnen zählen, nach [`MAX_FAILURES`] in Folge wird neu initialisiert.
+    fn flush(&mut self) -> Result<()> {
+        self.retry();
+        let Some(display) = &mut self.display else {
+            return Ok(());
+        };
+        match display.flush() {
+            Ok(()) => self.failures = 0,
+            Err(err) => {
+                self.failures += 1;
+                if self.failures < MAX_FAILURES {
+                    warn!("Display-Fehler ({}/{}), zeichne neu: {:?}", self.failures, MAX_FAILURES, err);
+                    // Was halb gezeichnet ist, lässt sich nicht mehr mit dem Stand vergleichen
+                    display.invalidate();
+                } else {
+                    error!("Display-Fehler {} Mal in Folge, initialisiere neu: {:?}", self.failures, err);
+                    self.display = None;
+                    self.failures = 0;
+                    self.next_attempt = Instant::now();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn show_panic(&mut self, message: &str) -> Result<()> {
+        match &mut self.display {
+            Some(display) => display.show_panic(message),
+            None => Ok(()),
+        }
+    }
+
+    fn show_splash(&mut self, name: &str, version: &str, progress: &str) -> Result<()> {
+        match &mut self.display {
+            Some(display) => display.show_splash(name, version, progress),
+            None => Ok(()),
+        }
+    }
+
+    fn set_inverted(&mut self, inverted: bool) {
+        if let Some(display) = &mut self.display {
+            display.set_inverted(inverted);
+        }
+    }
+
+    fn invalidate(&mut self) {
+        if let Some(display) = &mut self.display {
+            display.invalidate();
+        }
+    }
+}
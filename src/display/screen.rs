@@ -0,0 +1,598 @@
+use anyhow::{anyhow, Result};
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_10X20, FONT_6X10, FONT_9X15},
+        MonoFont, MonoTextStyle,
+    },
+    prelude::*,
+    primitives::{Polyline, PrimitiveStyle, Rectangle},
+    text::Text,
+};
+use qrcodegen::{QrCode, QrCodeEcc};
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+use super::{FontSize, Fonts, Rotation, StatusDisplay, Tone};
+use crate::lang::Msg;
+use crate::logs::LogEntry;
+use crate::relay::RelayState;
+
+const LINE_SPACING: i32 = 2;
+// Größere Schrift für die Uhr ganz oben, darunter beginnen die Kopfzeilen
+const CLOCK_FONT: &MonoFont = &FONT_10X20;
+const CLOCK_HEIGHT: i32 = CLOCK_FONT.character_size.height as i32 + LINE_SPACING;
+const LOG_GAP: i32 = 8;
+const QR_QUIET_ZONE: i32 = 2;
+// Signalbalken hinter dem RSSI-Text, der höchste so hoch wie die Großbuchstaben
+const SIGNAL_BARS: u8 = 4;
+const SIGNAL_BAR_WIDTH: i32 = 3;
+const SIGNAL_BAR_GAP: i32 = 1;
+const SIGNAL_MARGIN: i32 = 4;
+// Verlauf der Signalstärke neben der Uhr, ein Pixel pro Messwert
+const RSSI_HISTORY_LEN: usize = 60;
+const RSSI_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+// Spanne, auf die der Verlauf skaliert wird, ohne Verbindung liegt er am Boden
+const RSSI_FLOOR: i8 = -90;
+const RSSI_CEILING: i8 = -30;
+
+/// Schrift einer Zeilenart, Zeilenhöhe und Grundlinien ergeben sich daraus.
+#[derive(Clone, Copy)]
+struct LineFont(&'static MonoFont<'static>);
+
+impl LineFont {
+    fn new(size: FontSize) -> Self {
+        Self(match size {
+            FontSize::Small => &FONT_6X10,
+            FontSize::Medium => &FONT_9X15,
+            FontSize::Large => &FONT_10X20,
+        })
+    }
+
+    fn line_height(self) -> i32 {
+        self.0.character_size.height as i32 + LINE_SPACING
+    }
+
+    fn first_baseline(self) -> i32 {
+        self.0.baseline as i32 + LINE_SPACING
+    }
+
+    /// Pixelzeilen der Schrift unterhalb der Grundlinie
+    fn descent(self) -> i32 {
+        self.0.character_size.height as i32 - self.0.baseline as i32 - 1
+    }
+
+    /// Oberkante des Streifens einer Zeile mit Grundlinie `baseline`.
+    fn line_top(self, baseline: i32) -> i32 {
+        baseline - self.0.baseline as i32 - LINE_SPACING
+    }
+}
+
+/// Schriften von Kopf- und Logzeilen.
+#[derive(Clone, Copy)]
+struct LineFonts {
+    header: LineFont,
+    log: LineFont,
+}
+
+impl From<Fonts> for LineFonts {
+    fn from(fonts: Fonts) -> Self {
+        Self {
+            header: LineFont::new(fonts.header),
+            log: LineFont::new(fonts.log),
+        }
+    }
+}
+
+/// Farben eines Displays.
+pub trait Palette: PixelColor {
+    const BACKGROUND: Self;
+    fn tone(tone: Tone) -> Self;
+}
+
+/// Ein Display, auf das [`Screen`] zeichnen kann.
+pub trait Panel: DrawTarget {
+    /// Bringt das Gezeichnete auf den Bildschirm, nötig bei Displays mit eigenem Puffer.
+    fn present(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Bildschirmaufteilung.
+#[derive(Clone, Copy, Debug)]
+pub struct Layout {
+    pub width: u32,
+    pub height: u32,
+    /// Kantenlänge des QR-Codes oben rechts, 0 ohne QR-Code
+    pub qr_area: i32,
+}
+
+impl Layout {
+    /// Aufteilung nach dem Drehen, bei 90° und 270° mit vertauschter Breite und Höhe.
+    pub fn rotated(self, rotation: Rotation) -> Self {
+        if rotation.swaps_axes() {
+            Self {
+                width: self.height,
+                height: self.width,
+                // Der QR-Code muss weiterhin neben die Kopfzeilen passen
+                qr_area: self.qr_area.min(self.height as i32 / 2),
+            }
+        } else {
+            self
+        }
+    }
+
+    fn qr_x(&self) -> i32 {
+        self.width as i32 - self.qr_area
+    }
+
+    /// Grundlinie der Zeile `index`: erst die `header_len` Kopfzeilen, die Logs beginnen mit
+    /// etwas Abstand darunter, frühestens aber unterhalb des QR-Codes.
+    fn line_baseline(&self, index: usize, header_len: usize, fonts: LineFonts) -> i32 {
+        if index < header_len {
+            return CLOCK_HEIGHT + fonts.header.first_baseline() + index as i32 * fonts.header.line_height();
+        }
+        let mut log_top =
+            CLOCK_HEIGHT + header_len as i32 * fonts.header.line_height() + LOG_GAP + fonts.log.first_baseline();
+        if self.qr_area > 0 {
+            log_top = log_top.max(self.qr_area + fonts.log.line_height());
+        }
+        log_top + (index - header_len) as i32 * fonts.log.line_height()
+    }
+
+    /// Anzahl der Logzeilen, deren Glyphen unter `header_len` Kopfzeilen vollständig auf das Display passen.
+    fn visible_log_lines(&self, header_len: usize, fonts: LineFonts) -> usize {
+        let first_baseline = self.line_baseline(header_len, header_len, fonts);
+        let last_row = self.height as i32 - 1 - fonts.log.descent();
+        if first_baseline > last_row {
+            return 0;
+        }
+        ((last_row - first_baseline) / fonts.log.line_height() + 1) as usize
+    }
+}
+
+/// Eine Textzeile auf dem Display mit ihrer Bedeutung.
+#[derive(Clone, PartialEq)]
+struct DisplayLine {
+    text: String,
+    tone: Tone,
+    /// Gefüllte Signalbalken hinter dem Text
+    signal: Option<u8>,
+}
+
+impl DisplayLine {
+    fn new(text: String, tone: Tone) -> Self {
+        Self { text, tone, signal: None }
+    }
+}
+
+/// Anzahl gefüllter Balken für `rssi`, grob nach den üblichen Stufen für WLAN.
+fn signal_bars(rssi: i8) -> u8 {
+    match rssi {
+        -55.. => 4,
+        -67..=-56 => 3,
+        -75..=-68 => 2,
+        -85..=-76 => 1,
+        _ => 0,
+    }
+}
+
+/// Ringpuffer der letzten [`RSSI_HISTORY_LEN`] Messwerte, `None` ohne Verbindung.
+struct RssiHistory {
+    samples: [Option<i8>; RSSI_HISTORY_LEN],
+    len: usize,
+    next: usize,
+    last_sample: Option<Instant>,
+}
+
+impl RssiHistory {
+    fn new() -> Self {
+        Self {
+            samples: [None; RSSI_HISTORY_LEN],
+            len: 0,
+            next: 0,
+            last_sample: None,
+        }
+    }
+
+    /// Merkt sich `rssi`, höchstens einmal je [`RSSI_SAMPLE_INTERVAL`], damit der Verlauf nicht
+    /// schneller läuft, wenn die Hauptschleife während eines Pulses öfter aufwacht.
+    fn sample(&mut self, rssi: Option<i8>) {
+        if self.last_sample.is_some_and(|at| at.elapsed() < RSSI_SAMPLE_INTERVAL) {
+            return;
+        }
+        self.last_sample = Some(Instant::now());
+        self.samples[self.next] = rssi;
+        self.next = (self.next + 1) % RSSI_HISTORY_LEN;
+        self.len = (self.len + 1).min(RSSI_HISTORY_LEN);
+    }
+
+    /// Messwerte, älteste zuerst.
+    fn values(&self) -> Vec<Option<i8>> {
+        let start = (self.next + RSSI_HISTORY_LEN - self.len) % RSSI_HISTORY_LEN;
+        (0..self.len)
+            .map(|offset| self.samples[(start + offset) % RSSI_HISTORY_LEN])
+            .collect()
+    }
+}
+
+/// Zuletzt gezeichneter Bildschirminhalt, eine Zeile pro Eintrag (Kopfzeilen zuerst, dann die Logs).
+#[derive(Default)]
+struct RenderedScreen {
+    lines: Vec<DisplayLine>,
+    header_len: usize,
+    qr_url: String,
+    /// `None`, solange die Uhr seit dem letzten Löschen nicht gezeichnet wurde
+    clock: Option<String>,
+    /// Gezeichneter RSSI-Verlauf, `None` wie bei `clock`
+    rssi_history: Option<Vec<Option<i8>>>,
+}
+
+/// Textbasierte Statusanzeige für jedes [`Panel`], gemeinsam für alle unterstützten Displays.
+pub struct Screen<D> {
+    display: D,
+    layout: Layout,
+    fonts: LineFonts,
+    header: Vec<String>,
+    url: String,
+    clock: String,
+    relay: RelayState,
+    rssi: Option<i8>,
+    rssi_history: RssiHistory,
+    logs: Vec<LogEntry>,
+    rendered: RenderedScreen,
+    needs_clear: bool,
+    inverted: bool,
+}
+
+fn draw_error(err: impl Debug) -> anyhow::Error {
+    anyhow!("Display: {:?}", err)
+}
+
+impl<D> Screen<D>
+where
+    D: Panel,
+    D::Color: Palette,
+    D::Error: Debug,
+{
+    pub fn new(display: D, layout: Layout, fonts: Fonts) -> Self {
+        Self {
+            display,
+            layout,
+            fonts: fonts.into(),
+            header: Vec::new(),
+            url: String::new(),
+            clock: String::new(),
+            relay: RelayState::default(),
+            rssi: None,
+            rssi_history: RssiHistory::new(),
+            logs: Vec::new(),
+            rendered: RenderedScreen::default(),
+            needs_clear: true,
+            inverted: false,
+        }
+    }
+
+    fn background(&self) -> D::Color {
+        if self.inverted {
+            D::Color::tone(Tone::Normal)
+        } else {
+            D::Color::BACKGROUND
+        }
+    }
+
+    /// Schriftfarbe für `tone`, invertiert wird normale Schrift zur Hintergrundfarbe.
+    fn foreground(&self, tone: Tone) -> D::Color {
+        let color = D::Color::tone(tone);
+        if self.inverted && color == D::Color::tone(Tone::Normal) {
+            D::Color::BACKGROUND
+        } else {
+            color
+        }
+    }
+
+    /// Zeichnet `filled` von [`SIGNAL_BARS`] ansteigenden Balken ab `x`, leere nur als Umriss.
+    fn draw_signal_bars(&mut self, x: i32, baseline: i32, filled: u8, tone: Tone) -> Result<()> {
+        let max_height = self.fonts.header.0.baseline as i32;
+        for bar in 0..SIGNAL_BARS {
+            let height = max_height * (bar as i32 + 1) / SIGNAL_BARS as i32;
+            let style = if bar < filled {
+                PrimitiveStyle::with_fill(self.foreground(tone))
+            } else {
+                PrimitiveStyle::with_stroke(self.foreground(Tone::Normal), 1)
+            };
+            Rectangle::new(
+                Point::new(x + bar as i32 * (SIGNAL_BAR_WIDTH + SIGNAL_BAR_GAP), baseline + 1 - height),
+                Size::new(SIGNAL_BAR_WIDTH as u32, height as u32),
+            )
+            .into_styled(style)
+            .draw(&mut self.display)
+            .map_err(draw_error)?;
+        }
+        Ok(())
+    }
+
+    /// Zeichnet die Uhrzeit in [`CLOCK_FONT`] links oben neben den QR-Code.
+    fn draw_clock(&mut self) -> Result<()> {
+        Rectangle::new(Point::zero(), Size::new(self.layout.qr_x() as u32, CLOCK_HEIGHT as u32))
+            .into_styled(PrimitiveStyle::with_fill(self.background()))
+            .draw(&mut self.display)
+            .map_err(draw_error)?;
+        let text_style = MonoTextStyle::new(CLOCK_FONT, self.foreground(Tone::Normal));
+        let baseline = CLOCK_FONT.baseline as i32 + LINE_SPACING;
+        Text::new(&self.clock, Point::new(0, baseline), text_style)
+            .draw(&mut self.display)
+            .map_err(draw_error)?;
+        Ok(())
+    }
+
+    /// Zeichnet den RSSI-Verlauf rechts neben der Uhr, neueste Werte am rechten Rand.
+    ///
+    /// Ist neben der Uhr kein Platz, etwa bei einem hochkant gedrehten OLED, entfällt er.
+    fn draw_rssi_history(&mut self, values: &[Option<i8>]) -> Result<()> {
+        let clock_width = CLOCK_FONT.character_size.width as i32 * self.clock.len() as i32;
+        let x = self.layout.qr_x() - SIGNAL_MARGIN - RSSI_HISTORY_LEN as i32;
+        if x < clock_width + SIGNAL_MARGIN {
+            return Ok(());
+        }
+        let top = LINE_SPACING;
+        let height = CLOCK_HEIGHT - 2 * LINE_SPACING;
+        Rectangle::new(Point::new(x, 0), Size::new(RSSI_HISTORY_LEN as u32, CLOCK_HEIGHT as u32))
+            .into_styled(PrimitiveStyle::with_fill(self.background()))
+            .draw(&mut self.display)
+            .map_err(draw_error)?;
+
+        let offset = RSSI_HISTORY_LEN - values.len();
+        let points: Vec<Point> = values
+            .iter()
+            .enumerate()
+            .map(|(index, rssi)| {
+                let rssi = rssi.unwrap_or(RSSI_FLOOR).clamp(RSSI_FLOOR, RSSI_CEILING) as i32;
+                let y = top + (RSSI_CEILING as i32 - rssi) * (height - 1) / (RSSI_CEILING - RSSI_FLOOR) as i32;
+                Point::new(x + (offset + index) as i32, y)
+            })
+            .collect();
+        let tone = match values.last().copied().flatten().map(signal_bars) {
+            Some(3..) => Tone::Good,
+            Some(2) => Tone::Warning,
+            _ => Tone::Error,
+        };
+        Polyline::new(&points)
+            .into_styled(PrimitiveStyle::with_stroke(self.foreground(tone), 1))
+            .draw(&mut self.display)
+            .map_err(draw_error)?;
+        Ok(())
+    }
+
+    /// Zeichnet `url` als QR-Code, dunkle Module auf hellem Grund, auch invertiert.
+    fn draw_qr_code(&mut self, url: &str) -> Result<()> {
+        let qr = QrCode::encode_text(url, QrCodeEcc::Low).map_err(|err| anyhow!("{:?}", err))?;
+        let area = self.layout.qr_area;
+        let qr_x = self.layout.qr_x();
+        let size = qr.size();
+        let scale = (area / (size + 2 * QR_QUIET_ZONE)).max(1);
+        let offset = (area - size * scale) / 2;
+
+        Rectangle::new(Point::new(qr_x, 0), Size::new(area as u32, area as u32))
+            .into_styled(PrimitiveStyle::with_fill(D::Color::tone(Tone::Normal)))
+            .draw(&mut self.display)
+            .map_err(draw_error)?;
+        for y in 0..size {
+            for x in 0..size {
+                if qr.get_module(x, y) {
+                    Rectangle::new(
+                        Point::new(qr_x + offset + x * scale, offset + y * scale),
+                        Size::new(scale as u32, scale as u32),
+                    )
+                    .into_styled(PrimitiveStyle::with_fill(D::Color::BACKGROUND))
+                    .draw(&mut self.display)
+                    .map_err(draw_error)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Ersetzt den ganzen Bildschirm durch `title` in [`CLOCK_FONT`] und darunter `lines`.
+    ///
+    /// Umbrochen wird nach Zeichen, Dateipfade in Panic-Meldungen haben keine Leerzeichen. Beim
+    /// nächsten `flush` wird wieder alles neu gezeichnet.
+    fn draw_message(&mut self, title: &str, tone: Tone, lines: &[&str]) -> Result<()> {
+        let background = self.background();
+        self.display.clear(background).map_err(draw_error)?;
+        let title_style = MonoTextStyle::new(CLOCK_FONT, self.foreground(tone));
+        Text::new(title, Point::new(0, CLOCK_FONT.baseline as i32 + LINE_SPACING), title_style)
+            .draw(&mut self.display)
+            .map_err(draw_error)?;
+
+        let font = self.fonts.log;
+        let text_style = MonoTextStyle::new(font.0, self.foreground(Tone::Normal));
+        let columns = (self.layout.width / font.0.character_size.width).max(1) as usize;
+        let wrapped = lines.iter().flat_map(|line| {
+            let chars: Vec<char> = line.chars().collect();
+            let mut chunks: Vec<String> = chars.chunks(columns).map(|chunk| chunk.iter().collect()).collect();
+            if chunks.is_empty() {
+                chunks.push(String::new());
+            }
+            chunks
+        });
+        for (index, line) in wrapped.enumerate() {
+            let baseline = CLOCK_HEIGHT + font.first_baseline() + index as i32 * font.line_height();
+            if baseline > self.layout.height as i32 - 1 - font.descent() {
+                break;
+            }
+            Text::new(&line, Point::new(0, baseline), text_style)
+                .draw(&mut self.display)
+                .map_err(draw_error)?;
+        }
+        self.needs_clear = true;
+        self.display.present()
+    }
+}
+
+impl<D> StatusDisplay for Screen<D>
+where
+    D: Panel,
+    D::Color: Palette,
+    D::Error: Debug,
+{
+    fn show_ip(&mut self, header: &[String], url: &str) {
+        self.header = header.to_vec();
+        self.url = url.to_string();
+    }
+
+    fn set_relay_state(&mut self, relay: RelayState) {
+        self.relay = relay;
+    }
+
+    fn set_clock(&mut self, time: Option<String>) {
+        self.clock = time.unwrap_or_else(|| "--:--".to_string());
+    }
+
+    fn set_rssi(&mut self, rssi: Option<i8>) {
+        self.rssi = rssi;
+        self.rssi_history.sample(rssi);
+    }
+
+    fn push_log(&mut self, entries: &[LogEntry]) {
+        self.logs = entries.to_vec();
+    }
+
+    fn show_panic(&mut self, message: &str) -> Result<()> {
+        self.draw_message("PANIC", Tone::Error, &[message])
+    }
+
+    fn show_splash(&mut self, name: &str, version: &str, progress: &str) -> Result<()> {
+        let version = format!("Firmware {}", version);
+        self.draw_message(name, Tone::Good, &[&version, "", progress])
+    }
+
+    fn set_inverted(&mut self, inverted: bool) {
+        if inverted != self.inverted {
+            self.inverted = inverted;
+            self.needs_clear = true;
+        }
+    }
+
+    fn invalidate(&mut self) {
+        self.needs_clear = true;
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let mut lines: Vec<DisplayLine> = self
+            .header
+            .iter()
+            .map(|text| DisplayLine::new(text.clone(), Tone::Normal))
+            .collect();
+        if let Some(rssi) = self.rssi {
+            let bars = signal_bars(rssi);
+            let tone = match bars {
+                3.. => Tone::Good,
+                2 => Tone::Warning,
+                _ => Tone::Error,
+            };
+            lines.push(DisplayLine {
+                signal: Some(bars),
+                ..DisplayLine::new(format!("RSSI: {} dBm", rssi), tone)
+            });
+        }
+        // Relais-Zustand: angezogen grün, abgefallen rot
+        lines.push(if self.relay.latched {
+            DisplayLine::new(Msg::RelayLatched.text().to_string(), Tone::Good)
+        } else if self.relay.deferred_until.is_some() {
+            DisplayLine::new(Msg::RelayCooldown.text().to_string(), Tone::Warning)
+        } else if self.relay.pulsing {
+            DisplayLine::new(Msg::RelayOpen.text().to_string(), Tone::Good)
+        } else {
+            DisplayLine::new(Msg::RelayClosed.text().to_string(), Tone::Error)
+        });
+        let header_len = lines.len();
+
+        // Nur die neuesten Logzeilen, die unter die Kopfzeilen auf den Bildschirm passen, ältere
+        // rutschen oben aus dem Bild, bleiben aber im Puffer
+        let visible_logs = self.layout.visible_log_lines(header_len, self.fonts);
+        let skip = self.logs.len().saturating_sub(visible_logs);
+        lines.extend(
+            self.logs
+                .iter()
+                .skip(skip)
+                .map(|entry| DisplayLine::new(entry.to_string(), Tone::for_status(entry.status))),
+        );
+
+        let qr_url = if self.layout.qr_area > 0 { self.url.clone() } else { String::new() };
+        let rssi_history = self.rssi_history.values();
+        if !self.needs_clear
+            && lines == self.rendered.lines
+            && header_len == self.rendered.header_len
+            && qr_url == self.rendered.qr_url
+            && self.rendered.clock.as_ref() == Some(&self.clock)
+            && self.rendered.rssi_history.as_ref() == Some(&rssi_history)
+        {
+            return Ok(());
+        }
+
+        // Ändert sich die Anzahl der Kopfzeilen, verschieben sich alle Logzeilen
+        if self.needs_clear || header_len != self.rendered.header_len {
+            let background = self.background();
+            self.display.clear(background).map_err(draw_error)?;
+            self.rendered = RenderedScreen {
+                header_len,
+                ..Default::default()
+            };
+            self.needs_clear = false;
+        }
+
+        if self.rendered.clock.as_ref() != Some(&self.clock) {
+            self.draw_clock()?;
+            self.rendered.clock = Some(self.clock.clone());
+            // Die Uhr löscht ihre ganze Zeile, der Verlauf daneben muss mit
+            self.rendered.rssi_history = None;
+        }
+        if self.rendered.rssi_history.as_ref() != Some(&rssi_history) {
+            self.draw_rssi_history(&rssi_history)?;
+            self.rendered.rssi_history = Some(rssi_history);
+        }
+
+        // QR-Code nur neu erzeugen, wenn sich die URL (also die IP) geändert hat
+        if qr_url != self.rendered.qr_url {
+            self.draw_qr_code(&qr_url)?;
+            self.rendered.qr_url = qr_url;
+        }
+
+        let line_count = lines.len().max(self.rendered.lines.len());
+        for index in 0..line_count {
+            let new_line = lines.get(index);
+            if new_line == self.rendered.lines.get(index) {
+                continue;
+            }
+
+            // Nur den Streifen dieser Zeile löschen statt des ganzen Bildschirms,
+            // bei Kopfzeilen nur bis zum QR-Code
+            let baseline = self.layout.line_baseline(index, header_len, self.fonts);
+            let (font, width) = if index < header_len {
+                (self.fonts.header, self.layout.qr_x() as u32)
+            } else {
+                (self.fonts.log, self.layout.width)
+            };
+            Rectangle::new(
+                Point::new(0, font.line_top(baseline)),
+                Size::new(width, font.line_height() as u32),
+            )
+            .into_styled(PrimitiveStyle::with_fill(self.background()))
+            .draw(&mut self.display)
+            .map_err(draw_error)?;
+
+            if let Some(line) = new_line {
+                let text_style = MonoTextStyle::new(font.0, self.foreground(line.tone));
+                let end = Text::new(&line.text, Point::new(0, baseline), text_style)
+                    .draw(&mut self.display)
+                    .map_err(draw_error)?;
+                if let Some(bars) = line.signal {
+                    self.draw_signal_bars(end.x + SIGNAL_MARGIN, baseline, bars, line.tone)?;
+                }
+            }
+        }
+
+        self.rendered.lines = lines;
+        self.display.present()
+    }
+}
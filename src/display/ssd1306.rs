@@ -0,0 +1,53 @@
+use anyhow::{anyhow, Result};
+use embedded_graphics::pixelcolor::BinaryColor;
+use ssd1306::mode::BufferedGraphicsMode;
+use ssd1306::prelude::*;
+use ssd1306::{I2CDisplayInterface, Ssd1306};
+
+use super::screen::{Layout, Palette, Panel, Screen};
+use super::{Fonts, Rotation, Tone};
+
+// 128x64-OLED, für einen lesbaren QR-Code ist kein Platz
+const LAYOUT: Layout = Layout {
+    width: 128,
+    height: 64,
+    qr_area: 0,
+};
+
+impl Palette for BinaryColor {
+    const BACKGROUND: Self = BinaryColor::Off;
+
+    // Monochrom: die Bedeutung steckt nur im Text, z.B. im Status-Code der Logzeile
+    fn tone(_tone: Tone) -> Self {
+        BinaryColor::On
+    }
+}
+
+impl<DI> Panel for Ssd1306<DI, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>
+where
+    DI: WriteOnlyDataCommand,
+{
+    fn present(&mut self) -> Result<()> {
+        self.flush().map_err(|err| anyhow!("{:?}", err))
+    }
+}
+
+pub fn init<I2C>(
+    i2c: I2C,
+    rotation: Rotation,
+    fonts: Fonts,
+) -> Result<Screen<Ssd1306<I2CInterface<I2C>, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>>>
+where
+    I2C: embedded_hal::blocking::i2c::Write,
+{
+    let interface = I2CDisplayInterface::new(i2c);
+    let display_rotation = match rotation {
+        Rotation::Deg0 => DisplayRotation::Rotate0,
+        Rotation::Deg90 => DisplayRotation::Rotate90,
+        Rotation::Deg180 => DisplayRotation::Rotate180,
+        Rotation::Deg270 => DisplayRotation::Rotate270,
+    };
+    let mut display = Ssd1306::new(interface, DisplaySize128x64, display_rotation).into_buffered_graphics_mode();
+    display.init().map_err(|err| anyhow!("{:?}", err))?;
+    Ok(Screen::new(display, LAYOUT.rotated(rotation), fonts))
+}
@@ -0,0 +1,108 @@
+use anyhow::{anyhow, Result};
+use display_interface_spi::SPIInterfaceNoCS;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_hal::blocking::spi;
+use embedded_hal::digital::v2::OutputPin;
+use esp_idf_hal::delay::Ets;
+use esp_idf_hal::gpio::{AnyIOPin, AnyOutputPin, Output, PinDriver};
+use esp_idf_hal::spi::{config::Config, SpiDeviceDriver, SpiDriver, SpiDriverConfig};
+use esp_idf_hal::units::Hertz;
+use st7789::{Orientation, ST7789};
+
+use super::screen::{Layout, Palette, Panel, Screen};
+use super::{Fonts, Rotation, Tone};
+use crate::backlight::Backlight;
+use crate::board::{DisplaySpi, DisplaySpiConfig};
+
+/// SPI-Gerät und Steuerleitungen, wie sie [`init`] braucht.
+pub type SpiParts = (
+    SpiDeviceDriver<'static, SpiDriver<'static>>,
+    PinDriver<'static, AnyOutputPin, Output>,
+    PinDriver<'static, AnyOutputPin, Output>,
+);
+
+// Farb-TFT des HTIT-WB32 im Hochformat, QR-Code mit der Geräte-URL oben rechts.
+// Quer montiert ist mehr Platz pro Zeile, dafür passen weniger Logzeilen darunter.
+const LAYOUT: Layout = Layout {
+    width: 240,
+    height: 320,
+    qr_area: 90,
+};
+
+impl Palette for Rgb565 {
+    const BACKGROUND: Self = Rgb565::BLACK;
+
+    fn tone(tone: Tone) -> Self {
+        match tone {
+            Tone::Normal => Rgb565::WHITE,
+            Tone::Good => Rgb565::GREEN,
+            Tone::Warning => Rgb565::YELLOW,
+            Tone::Error => Rgb565::RED,
+        }
+    }
+}
+
+impl<SPI, DC, RST> Panel for ST7789<SPIInterfaceNoCS<SPI, DC>, RST>
+where
+    SPI: spi::Write<u8>,
+    DC: OutputPin,
+    RST: OutputPin,
+{
+}
+
+/// Legt Bus, CS und die Steuerleitungen DC und RST nach `config` an.
+pub fn open_spi(config: &DisplaySpiConfig) -> Result<SpiParts> {
+    // Die Pins kommen nur aus `DisplaySpiConfig` und werden nirgends sonst aus `Pins` genommen,
+    // der Bus nur hier. Nach einem Fehlschlag ist beides wieder frei.
+    let (spi, sclk, mosi, cs, dc, rst) = unsafe {
+        (
+            DisplaySpi::new(),
+            AnyOutputPin::new(config.sclk),
+            AnyOutputPin::new(config.mosi),
+            config.cs.map(|cs| AnyOutputPin::new(cs)),
+            AnyOutputPin::new(config.dc),
+            AnyOutputPin::new(config.rst),
+        )
+    };
+    let driver = SpiDriver::new(spi, sclk, mosi, Option::<AnyIOPin>::None, &SpiDriverConfig::new())?;
+    let device = SpiDeviceDriver::new(driver, cs, &Config::new().baudrate(Hertz(config.baudrate_hz)))?;
+    Ok((device, PinDriver::output(dc)?, PinDriver::output(rst)?))
+}
+
+pub fn init<SPI, DC, RST>(
+    spi: SPI,
+    dc: DC,
+    rst: RST,
+    rotation: Rotation,
+    fonts: Fonts,
+    backlight: &mut Backlight,
+) -> Result<Screen<ST7789<SPIInterfaceNoCS<SPI, DC>, RST>>>
+where
+    SPI: spi::Write<u8>,
+    DC: OutputPin,
+    RST: OutputPin,
+{
+    // Display initialisieren (angepasst an das HTIT-WB32 Modell)
+    // Dieser Code muss an die spezifische Hardware angepasst werden
+
+    let interface = SPIInterfaceNoCS::new(spi, dc);
+    let mut display = ST7789::new(interface, rst, LAYOUT.width as u16, LAYOUT.height as u16);
+
+    display.init(&mut Ets).map_err(|err| anyhow!("{:?}", err))?;
+    let orientation = match rotation {
+        Rotation::Deg0 => Orientation::Portrait,
+        Rotation::Deg90 => Orientation::Landscape,
+        Rotation::Deg180 => Orientation::PortraitSwapped,
+        Rotation::Deg270 => Orientation::LandscapeSwapped,
+    };
+    display
+        .set_orientation(orientation)
+        .map_err(|err| anyhow!("{:?}", err))?;
+
+    // Beleuchtung erst nach der Initialisierung einschalten, sonst ist Rauschen zu sehen
+    let brightness = backlight.brightness();
+    backlight.set_brightness(brightness)?;
+
+    Ok(Screen::new(display, LAYOUT.rotated(rotation), fonts))
+}
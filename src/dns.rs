@@ -0,0 +1,105 @@
+use anyhow::Result;
+use log::*;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Pfade, mit denen Android, iOS/macOS, Windows und Firefox nach dem Verbinden prüfen, ob sie
+/// im Internet sind. Kommt statt der erwarteten Antwort eine Weiterleitung, öffnet das System
+/// von selbst das Anmeldefenster, hier also das Einrichtungsportal.
+pub const CAPTIVE_PROBES: &[&str] = &[
+    "/generate_204",
+    "/gen_204",
+    "/hotspot-detect.html",
+    "/library/test/success.html",
+    "/connecttest.txt",
+    "/ncsi.txt",
+    "/canonical.html",
+];
+
+/// Minimaler DNS-Server für das Captive Portal: beantwortet jede Anfrage mit der eigenen IP.
+pub struct DnsResponder {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DnsResponder {
+    pub fn start(ip: Ipv4Addr) -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:53")?;
+        // Timeout, damit der Thread das Stopp-Signal regelmäßig prüft
+        socket.set_read_timeout(Some(Duration::from_millis(500)))?;
+
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = {
+            let running = running.clone();
+            std::thread::Builder::new()
+                .name("dns".into())
+                .stack_size(4096)
+                .spawn(move || serve(socket, ip, &running))?
+        };
+
+        info!("DNS-Responder gestartet");
+        Ok(Self {
+            running,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for DnsResponder {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        info!("DNS-Responder gestoppt");
+    }
+}
+
+fn serve(socket: UdpSocket, ip: Ipv4Addr, running: &AtomicBool) {
+    let mut buf = [0u8; 512];
+    while running.load(Ordering::SeqCst) {
+        let Ok((len, peer)) = socket.recv_from(&mut buf) else {
+            continue;
+        };
+        if let Some(response) = build_response(&buf[..len], ip) {
+            let _ = socket.send_to(&response, peer);
+        }
+    }
+}
+
+/// Baut eine Antwort mit einem A-Record auf `ip` für die erste Frage der Anfrage.
+fn build_response(query: &[u8], ip: Ipv4Addr) -> Option<Vec<u8>> {
+    // Header (12 Bytes) plus mindestens ein Label-Ende, Typ und Klasse
+    if query.len() < 17 {
+        return None;
+    }
+    // Nur Anfragen beantworten, keine Antworten anderer Server
+    if query[2] & 0x80 != 0 {
+        return None;
+    }
+
+    // Ende des Namens der ersten Frage suchen
+    let mut pos = 12;
+    while *query.get(pos)? != 0 {
+        pos += 1 + query[pos] as usize;
+    }
+    let question_end = pos + 5;
+    if question_end > query.len() {
+        return None;
+    }
+
+    let mut response = Vec::with_capacity(question_end + 16);
+    response.extend_from_slice(&query[..2]); // ID
+    response.extend_from_slice(&[0x81, 0x80]); // Antwort, Rekursion verfügbar
+    response.extend_from_slice(&[0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]);
+    response.extend_from_slice(&query[12..question_end]);
+    response.extend_from_slice(&[0xc0, 0x0c]); // Zeiger auf den Namen der Frage
+    response.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]); // Typ A, Klasse IN
+    response.extend_from_slice(&60u32.to_be_bytes()); // TTL
+    response.extend_from_slice(&[0x00, 0x04]);
+    response.extend_from_slice(&ip.octets());
+    Some(response)
+}
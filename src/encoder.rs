@@ -0,0 +1,120 @@
+//! Optionaler Drehgeber mit Taster für das Menü am Gerät, nur mit dem Cargo-Feature `encoder`.
+//!
+//! Die Pins kommen aus `ENCODER_A_GPIO`, `ENCODER_B_GPIO` und `ENCODER_SW_GPIO`, alle schalten
+//! gegen Masse, der interne Pull-up ist an. Ein eigener Thread fragt sie so oft ab, wie es der
+//! FreeRTOS-Takt erlaubt (bei 100 Hz alle 10 ms), für ein Menü von Hand reicht das. A und B laufen
+//! durch eine Zustandstabelle, die ungültige Sprünge verwirft, zu schnelles Drehen verliert also
+//! Schritte, statt falsch zu zählen. `ENCODER_STEPS` gültige Übergänge (Standard 4, eine Rastung
+//! der üblichen Drehgeber) ergeben einen Schritt. Der Taster wird wie der Board-Taster entprellt.
+
+use anyhow::Result;
+use esp_idf_hal::gpio::{AnyInputPin, PinDriver, Pull};
+use log::*;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+const DEBOUNCE: Duration = Duration::from_millis(30);
+const DEFAULT_STEPS: i8 = 4;
+
+/// Richtung je Übergang, Index ist `alter Zustand << 2 | neuer Zustand` aus den Pegeln von A und B.
+/// Übergänge, bei denen beide Pins zugleich wechseln, kann es ohne Prellen nicht geben und zählen 0.
+const TRANSITIONS: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+/// Eingabe am Drehgeber.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    /// Eine Rastung, `1` im Uhrzeigersinn, `-1` dagegen
+    Turn(i8),
+    Press,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EncoderConfig {
+    pub a: i32,
+    pub b: i32,
+    pub switch: i32,
+    /// Gültige Übergänge je Schritt
+    pub steps: i8,
+}
+
+impl EncoderConfig {
+    /// Pins aus `ENCODER_*_GPIO`, `None` wenn einer fehlt.
+    pub fn from_env() -> Option<Self> {
+        let gpio = |name: &str, value: Option<&str>| {
+            let gpio = value.and_then(|value| value.parse().ok());
+            if gpio.is_none() {
+                warn!("Drehgeber: {} fehlt", name);
+            }
+            gpio
+        };
+        if option_env!("ENCODER_A_GPIO").is_none() {
+            info!("Drehgeber: ENCODER_A_GPIO nicht gesetzt, kein Menü");
+            return None;
+        }
+        Some(Self {
+            a: gpio("ENCODER_A_GPIO", option_env!("ENCODER_A_GPIO"))?,
+            b: gpio("ENCODER_B_GPIO", option_env!("ENCODER_B_GPIO"))?,
+            switch: gpio("ENCODER_SW_GPIO", option_env!("ENCODER_SW_GPIO"))?,
+            steps: option_env!("ENCODER_STEPS")
+                .and_then(|value| value.parse().ok())
+                .filter(|steps| (1..=4).contains(steps))
+                .unwrap_or(DEFAULT_STEPS),
+        })
+    }
+}
+
+/// Startet den Abfrage-Thread, `on_input` läuft in diesem Thread und darf nicht blockieren.
+pub fn spawn(config: EncoderConfig, on_input: impl Fn(Input) + Send + 'static) -> Result<()> {
+    // Die Pins stehen nur in `ENCODER_*_GPIO` und werden nirgends sonst aus `Pins` genommen
+    let input = |gpio: i32| -> Result<_> {
+        let mut pin = PinDriver::input(unsafe { AnyInputPin::new(gpio) })?;
+        pin.set_pull(Pull::Up)?;
+        Ok(pin)
+    };
+    let a = input(config.a)?;
+    let b = input(config.b)?;
+    let switch = input(config.switch)?;
+    info!("Drehgeber an GPIO{}/GPIO{}, Taster an GPIO{}", config.a, config.b, config.switch);
+
+    std::thread::Builder::new()
+        .name("encoder".into())
+        .stack_size(4096)
+        .spawn(move || {
+            let levels = || ((a.is_high() as usize) << 1) | b.is_high() as usize;
+            let mut state = levels();
+            let mut count: i8 = 0;
+
+            let mut stable_pressed = switch.is_low();
+            let mut last_raw = stable_pressed;
+            let mut changed_at = Instant::now();
+
+            loop {
+                let next = levels();
+                if next != state {
+                    count += TRANSITIONS[(state << 2) | next];
+                    state = next;
+                    if count.abs() >= config.steps {
+                        on_input(Input::Turn(count.signum()));
+                        count = 0;
+                    }
+                    // In der Ruhelage beginnt jede Rastung neu, halbe Schritte gehen verloren
+                    if state == 0b11 {
+                        count = 0;
+                    }
+                }
+
+                let raw = switch.is_low();
+                if raw != last_raw {
+                    last_raw = raw;
+                    changed_at = Instant::now();
+                } else if raw != stable_pressed && changed_at.elapsed() >= DEBOUNCE {
+                    stable_pressed = raw;
+                    if stable_pressed {
+                        on_input(Input::Press);
+                    }
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        })?;
+    Ok(())
+}
@@ -0,0 +1,18 @@
+// Eingebettete HTML-Seiten, damit kein Dateisystem nötig ist
+
+// Formular des Einrichtungsportals im AP-Modus
+pub const SETUP_HTML: &str = r#"<!DOCTYPE html>
+<html><head><meta name="viewport" content="width=device-width, initial-scale=1"><title>doofman setup</title></head>
+<body><h1>WLAN einrichten</h1>
+<form method="post" action="/setup">
+<p><label>SSID<br><input name="ssid" maxlength="32" required></label></p>
+<p><label>Passwort<br><input name="password" type="password" maxlength="64"></label></p>
+<p><label><input name="hidden" type="checkbox"> Verstecktes Netz</label></p>
+<p><button type="submit">Speichern und verbinden</button></p>
+</form></body></html>"#;
+
+// Bedienoberfläche unter /. Das Token für /push wird einmalig abgefragt und im Browser gespeichert.
+pub const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+
+// Dieselbe Seite, beim Bauen von build.rs mit gzip komprimiert
+pub const DASHBOARD_HTML_GZ: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/dashboard.html.gz"));
@@ -0,0 +1,40 @@
+//! Ausgehende POST-Anfragen für Webhook und Telegram.
+//!
+//! HTTPS wird gegen das Zertifikats-Bundle von ESP-IDF geprüft, eine `http://`-URL geht ohne TLS
+//! raus. Gesendet wird blockierend, die Aufrufer haben dafür jeweils einen eigenen Thread.
+
+use anyhow::{bail, Result};
+use embedded_svc::http::client::Client;
+use embedded_svc::io::Write;
+use esp_idf_svc::http::client::{Configuration as HttpConfiguration, EspHttpConnection};
+use std::time::Duration;
+
+/// Stack für die sendenden Threads, TLS braucht deutlich mehr als die übrigen Threads.
+pub const STACK_SIZE: usize = 8192;
+
+/// Sendet `body` als JSON an `url`, `headers` kommen zu Content-Type und Content-Length dazu.
+/// Liefert den Status der Antwort, ein Fehler heißt, dass keine Antwort kam.
+pub fn post_json(url: &str, body: &str, headers: &[(&str, &str)], timeout: Duration) -> Result<u16> {
+    let connection = EspHttpConnection::new(&HttpConfiguration {
+        crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
+        timeout: Some(timeout),
+        ..Default::default()
+    })?;
+    let mut client = Client::wrap(connection);
+
+    let content_length = body.len().to_string();
+    let mut all_headers = vec![
+        ("Content-Type", "application/json"),
+        ("Content-Length", content_length.as_str()),
+    ];
+    all_headers.extend_from_slice(headers);
+    let mut request = client.post(url, &all_headers)?;
+    request.write_all(body.as_bytes())?;
+    request.flush()?;
+    let response = request.submit()?;
+    let status = response.status();
+    if status == 0 {
+        bail!("keine Antwort");
+    }
+    Ok(status)
+}
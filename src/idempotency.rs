@@ -0,0 +1,61 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// So viele Schlüssel werden höchstens gemerkt, der am längsten unbenutzte fällt zuerst heraus.
+const CAPACITY: usize = 16;
+
+/// Höchstlänge eines `Idempotency-Key`, längere werden abgelehnt.
+pub const MAX_KEY_LEN: usize = 64;
+
+/// Lebensdauer eines Schlüssels ohne `IDEMPOTENCY_TTL_S`.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+struct Entry {
+    key: String,
+    at: Instant,
+    status: u16,
+    body: String,
+}
+
+/// Kleiner LRU-Cache für `Idempotency-Key`, damit eine Wiederholung nach verlorener Antwort
+/// nicht ein zweites Mal schaltet.
+pub struct IdempotencyCache {
+    entries: Mutex<VecDeque<Entry>>,
+    ttl: Duration,
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(VecDeque::with_capacity(CAPACITY)),
+            ttl,
+        }
+    }
+
+    /// Gespeicherte Antwort zu `key`, sofern er innerhalb der TTL schon einmal benutzt wurde.
+    pub fn get(&self, key: &str) -> Option<(u16, String)> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|entry| entry.at.elapsed() < self.ttl);
+        let index = entries.iter().position(|entry| entry.key == key)?;
+        // Zuletzt benutzt ans Ende, damit er als Letzter verdrängt wird
+        let entry = entries.remove(index)?;
+        let result = (entry.status, entry.body.clone());
+        entries.push_back(entry);
+        Some(result)
+    }
+
+    pub fn insert(&self, key: &str, status: u16, body: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|entry| entry.key != key);
+        if entries.len() >= CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(Entry {
+            key: key.to_string(),
+            at: Instant::now(),
+            status,
+            body: body.to_string(),
+        });
+    }
+}
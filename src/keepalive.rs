@@ -0,0 +1,147 @@
+//! HTTP Keep-Alive mit einstellbarer Leerlaufzeit.
+//!
+//! Der ESP-IDF-Server hält HTTP/1.1-Verbindungen offen, bis der Client sie schließt oder alle
+//! Sockets belegt sind und die am längsten unbenutzte geschlossen wird. Damit pollende Clients
+//! wissen, wie lange sie eine Verbindung wiederverwenden dürfen, kündigen JSON-Antworten
+//! `Connection: keep-alive` und `Keep-Alive: timeout=<s>` an. Ein Thread schließt Verbindungen,
+//! auf denen so lange kein Request mehr kam, und gibt ihre Sockets frei.
+//!
+//! `HTTP_KEEP_ALIVE_S` setzt die Leerlaufzeit (Standard 15 s), `0` schließt jede Verbindung nach
+//! der Antwort. Schickt der Client selbst `Connection: close`, wird die Verbindung ebenso
+//! geschlossen. Streams wie `/events`, `/ws/logs` und wartende `/poll`-Requests nehmen sich mit
+//! [`forget`] aus, sie würden sonst mitten im Warten getrennt.
+
+use anyhow::Result;
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::http::server::EspHttpServer;
+use log::*;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(15);
+
+// So oft werden die offenen Verbindungen auf Leerlauf geprüft
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+struct KeepAlive {
+    handle: esp_idf_sys::httpd_handle_t,
+    max_sockets: usize,
+    /// `None` schließt jede Verbindung nach der Antwort
+    idle_timeout: Option<Duration>,
+    header: String,
+    /// Ende des letzten Requests je Socket, nur für Verbindungen, die eine JSON-Antwort bekamen
+    last_seen: Mutex<HashMap<i32, Instant>>,
+}
+
+// Der Server lebt so lange wie das Programm, `httpd_sess_trigger_close` ist threadsicher
+unsafe impl Send for KeepAlive {}
+unsafe impl Sync for KeepAlive {}
+
+static KEEP_ALIVE: OnceLock<KeepAlive> = OnceLock::new();
+
+/// Leerlaufzeit aus `HTTP_KEEP_ALIVE_S`, `None` wenn Keep-Alive abgeschaltet ist.
+fn idle_timeout() -> Option<Duration> {
+    let timeout = option_env!("HTTP_KEEP_ALIVE_S")
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_IDLE_TIMEOUT);
+    Some(timeout).filter(|timeout| !timeout.is_zero())
+}
+
+/// Startet die Prüfung auf Leerlauf für `server`, vor dem ersten Request aufzurufen.
+pub fn start(server: &EspHttpServer<'static>, max_sockets: usize) -> Result<()> {
+    let idle_timeout = idle_timeout();
+    let keep_alive = KEEP_ALIVE.get_or_init(|| KeepAlive {
+        handle: server.handle(),
+        max_sockets,
+        idle_timeout,
+        header: format!("timeout={}", idle_timeout.unwrap_or_default().as_secs()),
+        last_seen: Mutex::new(HashMap::new()),
+    });
+
+    match idle_timeout {
+        Some(timeout) => info!("HTTP Keep-Alive mit {}s Leerlaufzeit", timeout.as_secs()),
+        None => {
+            info!("HTTP Keep-Alive abgeschaltet");
+            return Ok(());
+        }
+    }
+
+    std::thread::Builder::new()
+        .name("keepalive".into())
+        .stack_size(3072)
+        .spawn(move || loop {
+            std::thread::sleep(CHECK_INTERVAL);
+            keep_alive.close_idle();
+        })?;
+    Ok(())
+}
+
+/// `true`, wenn die Verbindung nach dieser Antwort geschlossen wird.
+pub fn should_close(connection: Option<&str>) -> bool {
+    let client_closes = connection.is_some_and(|value| {
+        value
+            .split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case("close"))
+    });
+    client_closes || KEEP_ALIVE.get().is_some_and(|keep_alive| keep_alive.idle_timeout.is_none())
+}
+
+/// Header, die der Antwort die Wiederverwendung der Verbindung ankündigen oder verweigern.
+pub fn response_headers(close: bool) -> Vec<(&'static str, &'static str)> {
+    if close {
+        return vec![("Connection", "close")];
+    }
+    match KEEP_ALIVE.get() {
+        Some(keep_alive) => vec![("Connection", "keep-alive"), ("Keep-Alive", keep_alive.header.as_str())],
+        None => Vec::new(),
+    }
+}
+
+/// Vermerkt die beantwortete Anfrage auf Socket `fd` und schließt ihn, wenn `close` gilt.
+pub fn finished(fd: i32, close: bool) {
+    let Some(keep_alive) = KEEP_ALIVE.get() else {
+        return;
+    };
+    if close {
+        keep_alive.last_seen.lock().unwrap().remove(&fd);
+        // Erst nach dem laufenden Handler, die Antwort ist dann schon raus
+        unsafe { esp_idf_sys::httpd_sess_trigger_close(keep_alive.handle, fd) };
+    } else {
+        keep_alive.last_seen.lock().unwrap().insert(fd, Instant::now());
+    }
+}
+
+/// Nimmt Socket `fd` von der Prüfung aus, solange auf ihm ein Stream läuft.
+pub fn forget(fd: i32) {
+    if let Some(keep_alive) = KEEP_ALIVE.get() {
+        keep_alive.last_seen.lock().unwrap().remove(&fd);
+    }
+}
+
+impl KeepAlive {
+    fn close_idle(&self) {
+        let Some(timeout) = self.idle_timeout else {
+            return;
+        };
+        let mut fds = vec![0i32; self.max_sockets];
+        let mut count = fds.len();
+        if unsafe { esp_idf_sys::httpd_get_client_list(self.handle, &mut count, fds.as_mut_ptr()) } != 0 {
+            return;
+        }
+        let open = &fds[..count];
+
+        let mut last_seen = self.last_seen.lock().unwrap();
+        // Geschlossene Sockets vergessen, ihre Nummer bekommt sonst die nächste Verbindung
+        last_seen.retain(|fd, _| open.contains(fd));
+        last_seen.retain(|fd, seen| {
+            if seen.elapsed() < timeout {
+                return true;
+            }
+            debug!("Verbindung {} nach {}s Leerlauf geschlossen", fd, timeout.as_secs());
+            unsafe { esp_idf_sys::httpd_sess_trigger_close(self.handle, *fd) };
+            false
+        });
+    }
+}
@@ -0,0 +1,258 @@
+//! Texte für Display, Telegram und die WLAN-Meldungen in Deutsch oder Englisch.
+//!
+//! `DOOFMAN_LANG=de` (Standard) oder `DOOFMAN_LANG=en` zur Build-Zeit wählt die Sprache für
+//! alles, was auf dem Display steht, für die Telegram-Nachrichten und für die Logmeldungen rund
+//! um die WLAN-Verbindung. Relais-Ereignisse wie die Sicherheitsabschaltung erscheinen auf dem
+//! Display übersetzt, in `/logs` und beim Webhook bleiben sie Englisch. Die übrigen Logmeldungen
+//! der seriellen Konsole sind immer Deutsch, JSON-Schlüssel und Werte der API immer Englisch.
+
+use log::*;
+use std::sync::OnceLock;
+
+use crate::wifi::LinkState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    De,
+    En,
+}
+
+impl Lang {
+    fn from_env() -> Self {
+        match option_env!("DOOFMAN_LANG") {
+            None | Some("de") => Lang::De,
+            Some("en") => Lang::En,
+            Some(other) => {
+                warn!("Ungültiges DOOFMAN_LANG {:?}, erwartet de oder en", other);
+                Lang::De
+            }
+        }
+    }
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// Gewählte Sprache, beim ersten Aufruf aus `DOOFMAN_LANG` gelesen.
+pub fn current() -> Lang {
+    *LANG.get_or_init(Lang::from_env)
+}
+
+/// Feste Texte, formatierte stehen unten als eigene Funktionen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Msg {
+    Starting,
+    Simulation,
+    BatteryLow,
+    Uptime,
+    LastPush,
+    Boot,
+    ClockNotSynced,
+    LowMemory,
+    #[cfg_attr(not(feature = "sensor"), allow(dead_code))]
+    Climate,
+    On,
+    Off,
+    RelayLatched,
+    RelayCooldown,
+    RelayOpen,
+    RelayClosed,
+    WifiTimeout,
+    WifiNotFound,
+    WifiAuthFailed,
+    WifiStarted,
+    WifiConnected,
+    WifiReconnected,
+    WifiLost,
+    // Kopfzeilen des Displays
+    DeviceName,
+    Ip,
+    Wifi,
+    LinkConnected,
+    LinkReconnecting,
+    LinkAccessPoint,
+    // Relais-Ereignisse in der Logliste
+    EventSafetyCutoff,
+    EventRelayMismatch,
+    EventSequenceFinished,
+    EventLatchRestored,
+    EventScheduledPush,
+    // Telegram
+    TimeUnknown,
+    Button,
+    // Menü des Drehgebers
+    #[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+    MenuTitle,
+    #[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+    MenuTrigger,
+    #[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+    MenuLogs,
+    #[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+    MenuConfig,
+    #[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+    MenuReboot,
+    #[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+    MenuClose,
+    #[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+    ConfirmReboot,
+    #[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+    No,
+    #[cfg_attr(not(feature = "encoder"), allow(dead_code))]
+    Yes,
+}
+
+impl Msg {
+    pub fn text(self) -> &'static str {
+        match (self, current()) {
+            (Msg::Starting, Lang::De) => "Starte...",
+            (Msg::Starting, Lang::En) => "Starting...",
+            (Msg::Simulation, Lang::De) => "SIMULATION: Relais schaltet nicht",
+            (Msg::Simulation, Lang::En) => "SIMULATION: relay does not switch",
+            (Msg::BatteryLow, Lang::De) => "AKKU SCHWACH",
+            (Msg::BatteryLow, Lang::En) => "BATTERY LOW",
+            (Msg::Uptime, Lang::De) => "Laufzeit",
+            (Msg::Uptime, Lang::En) => "Uptime",
+            (Msg::LastPush, Lang::De) => "Letzter Push",
+            (Msg::LastPush, Lang::En) => "Last push",
+            (Msg::Boot, Lang::De) => "Start",
+            (Msg::Boot, Lang::En) => "Boot",
+            (Msg::ClockNotSynced, Lang::De) => "Zeit nicht synchronisiert",
+            (Msg::ClockNotSynced, Lang::En) => "Time not synchronized",
+            (Msg::LowMemory, Lang::De) => "Wenig Speicher",
+            (Msg::LowMemory, Lang::En) => "Low memory",
+            (Msg::Climate, Lang::De) => "Klima",
+            (Msg::Climate, Lang::En) => "Climate",
+            (Msg::On, Lang::De) => "AN",
+            (Msg::On, Lang::En) => "ON",
+            (Msg::Off, Lang::De) => "aus",
+            (Msg::Off, Lang::En) => "off",
+            (Msg::RelayLatched, Lang::De) => "Relais: AN (dauerhaft)",
+            (Msg::RelayLatched, Lang::En) => "Relay: ON (latched)",
+            (Msg::RelayCooldown, Lang::De) => "Relais: PAUSE",
+            (Msg::RelayCooldown, Lang::En) => "Relay: COOLDOWN",
+            (Msg::RelayOpen, Lang::De) => "Relais: OFFEN",
+            (Msg::RelayOpen, Lang::En) => "Relay: OPEN",
+            (Msg::RelayClosed, Lang::De) => "Relais: ZU",
+            (Msg::RelayClosed, Lang::En) => "Relay: CLOSED",
+            (Msg::WifiTimeout, Lang::De) => "WLAN: Zeitüberschreitung",
+            (Msg::WifiTimeout, Lang::En) => "WiFi: timed out",
+            (Msg::WifiNotFound, Lang::De) => "WLAN: Netz nicht gefunden",
+            (Msg::WifiNotFound, Lang::En) => "WiFi: network not found",
+            (Msg::WifiAuthFailed, Lang::De) => "WLAN: Anmeldung fehlgeschlagen",
+            (Msg::WifiAuthFailed, Lang::En) => "WiFi: authentication failed",
+            (Msg::WifiStarted, Lang::De) => "WLAN gestartet",
+            (Msg::WifiStarted, Lang::En) => "WiFi started",
+            (Msg::WifiConnected, Lang::De) => "Mit WLAN verbunden",
+            (Msg::WifiConnected, Lang::En) => "Connected to WiFi",
+            (Msg::WifiReconnected, Lang::De) => "WLAN wieder verbunden",
+            (Msg::WifiReconnected, Lang::En) => "WiFi reconnected",
+            (Msg::WifiLost, Lang::De) => "WLAN-Verbindung verloren",
+            (Msg::WifiLost, Lang::En) => "WiFi connection lost",
+            (Msg::DeviceName, Lang::De) => "Name",
+            (Msg::DeviceName, Lang::En) => "Name",
+            (Msg::Ip, Lang::De) => "IP",
+            (Msg::Ip, Lang::En) => "IP",
+            (Msg::Wifi, Lang::De) => "WLAN",
+            (Msg::Wifi, Lang::En) => "WiFi",
+            (Msg::LinkConnected, Lang::De) => "verbunden",
+            (Msg::LinkConnected, Lang::En) => "connected",
+            (Msg::LinkReconnecting, Lang::De) => "verbinde neu",
+            (Msg::LinkReconnecting, Lang::En) => "reconnecting",
+            (Msg::LinkAccessPoint, Lang::De) => "Access Point",
+            (Msg::LinkAccessPoint, Lang::En) => "access point",
+            (Msg::EventSafetyCutoff, Lang::De) => "Sicherheitsabschaltung",
+            (Msg::EventSafetyCutoff, Lang::En) => "safety cutoff",
+            (Msg::EventRelayMismatch, Lang::De) => "Rückmeldung falsch",
+            (Msg::EventRelayMismatch, Lang::En) => "relay mismatch",
+            (Msg::EventSequenceFinished, Lang::De) => "Folge beendet",
+            (Msg::EventSequenceFinished, Lang::En) => "sequence finished",
+            (Msg::EventLatchRestored, Lang::De) => "Dauerbetrieb wiederhergestellt",
+            (Msg::EventLatchRestored, Lang::En) => "latch restored",
+            (Msg::EventScheduledPush, Lang::De) => "Zeitplan-Push",
+            (Msg::EventScheduledPush, Lang::En) => "scheduled push",
+            (Msg::TimeUnknown, Lang::De) => "Zeit unbekannt",
+            (Msg::TimeUnknown, Lang::En) => "time unknown",
+            (Msg::Button, Lang::De) => "Taster",
+            (Msg::Button, Lang::En) => "button",
+            (Msg::MenuTitle, Lang::De) => "MENÜ",
+            (Msg::MenuTitle, Lang::En) => "MENU",
+            (Msg::MenuTrigger, Lang::De) => "Relais auslösen",
+            (Msg::MenuTrigger, Lang::En) => "Trigger relay",
+            (Msg::MenuLogs, Lang::De) => "Logs anzeigen",
+            (Msg::MenuLogs, Lang::En) => "View logs",
+            (Msg::MenuConfig, Lang::De) => "Einstellungen",
+            (Msg::MenuConfig, Lang::En) => "Settings",
+            (Msg::MenuReboot, Lang::De) => "Neustart",
+            (Msg::MenuReboot, Lang::En) => "Reboot",
+            (Msg::MenuClose, Lang::De) => "Schließen",
+            (Msg::MenuClose, Lang::En) => "Close",
+            (Msg::ConfirmReboot, Lang::De) => "Wirklich neu starten?",
+            (Msg::ConfirmReboot, Lang::En) => "Really reboot?",
+            (Msg::No, Lang::De) => "Nein",
+            (Msg::No, Lang::En) => "No",
+            (Msg::Yes, Lang::De) => "Ja",
+            (Msg::Yes, Lang::En) => "Yes",
+        }
+    }
+}
+
+/// `vor 12s` bzw. `12s ago`.
+pub fn ago(secs: u64) -> String {
+    match current() {
+        Lang::De => format!("vor {}s", secs),
+        Lang::En => format!("{}s ago", secs),
+    }
+}
+
+pub fn reset_in(secs: u64) -> String {
+    match current() {
+        Lang::De => format!("Zurücksetzen in {}s", secs),
+        Lang::En => format!("Reset in {}s", secs),
+    }
+}
+
+/// Fortschritt auf dem Startbildschirm, ab dem zweiten Versuch mit Nummer.
+pub fn wifi_attempt(ssid: &str, attempt: u32) -> String {
+    match (current(), attempt) {
+        (Lang::De, 1) => format!("WLAN {}...", ssid),
+        (Lang::De, _) => format!("WLAN {}, Versuch {}...", ssid, attempt),
+        (Lang::En, 1) => format!("WiFi {}...", ssid),
+        (Lang::En, _) => format!("WiFi {}, attempt {}...", ssid, attempt),
+    }
+}
+
+pub fn connected_to(ssid: &str) -> String {
+    match current() {
+        Lang::De => format!("Verbunden mit {}", ssid),
+        Lang::En => format!("Connected to {}", ssid),
+    }
+}
+
+pub fn setup_via(ap_ssid: &str) -> String {
+    match current() {
+        Lang::De => format!("Kein WLAN, Einrichtung über {}", ap_ssid),
+        Lang::En => format!("No WiFi, set up via {}", ap_ssid),
+    }
+}
+
+/// Logzeile vor dem Verbindungsaufbau.
+pub fn connecting(ssid: &str, hidden: bool) -> String {
+    match (current(), hidden) {
+        (Lang::De, false) => format!("Verbinde mit WLAN \"{}\"...", ssid),
+        (Lang::De, true) => format!("Verbinde mit verstecktem WLAN \"{}\"...", ssid),
+        (Lang::En, false) => format!("Connecting to WiFi \"{}\"...", ssid),
+        (Lang::En, true) => format!("Connecting to hidden WiFi \"{}\"...", ssid),
+    }
+}
+
+/// `WLAN: Zuhause verbunden` bzw. `WiFi: Home connected`.
+pub fn wifi_status(ssid: &str, link: LinkState) -> String {
+    format!("{}: {} {}", Msg::Wifi.text(), ssid, link.message())
+}
+
+/// Text der Telegram-Nachricht für eine Auslösung über `source`.
+pub fn triggered(device: &str, source: &str, time: &str) -> String {
+    match current() {
+        Lang::De => format!("{}: ausgelöst über {} ({})", device, source, time),
+        Lang::En => format!("{}: triggered via {} ({})", device, source, time),
+    }
+}
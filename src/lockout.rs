@@ -0,0 +1,97 @@
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// So viele Clients werden höchstens verfolgt, der am längsten unauffällige fällt zuerst heraus.
+const CAPACITY: usize = 16;
+
+/// Fehlversuche ohne `AUTH_MAX_FAILURES`, ab denen gesperrt wird.
+pub const DEFAULT_MAX_FAILURES: u32 = 5;
+
+/// Zeitfenster für die Fehlversuche ohne `AUTH_WINDOW_S`.
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Sperrdauer ohne `AUTH_LOCKOUT_S`.
+pub const DEFAULT_LOCKOUT: Duration = Duration::from_secs(300);
+
+struct Client {
+    ip: IpAddr,
+    first_failure: Instant,
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Sperrt Clients nach zu vielen falschen Tokens, damit sich das Token nicht durchprobieren lässt.
+pub struct AuthLimiter {
+    clients: Mutex<Vec<Client>>,
+    max_failures: u32,
+    window: Duration,
+    lockout: Duration,
+}
+
+impl AuthLimiter {
+    /// Grenzen aus `AUTH_MAX_FAILURES`, `AUTH_WINDOW_S` und `AUTH_LOCKOUT_S`.
+    pub fn from_env() -> Self {
+        let parse = |value: Option<&str>| value.and_then(|value| value.parse::<u64>().ok());
+        Self {
+            clients: Mutex::new(Vec::with_capacity(CAPACITY)),
+            max_failures: parse(option_env!("AUTH_MAX_FAILURES"))
+                .map_or(DEFAULT_MAX_FAILURES, |failures| failures.max(1) as u32),
+            window: parse(option_env!("AUTH_WINDOW_S")).map_or(DEFAULT_WINDOW, Duration::from_secs),
+            lockout: parse(option_env!("AUTH_LOCKOUT_S")).map_or(DEFAULT_LOCKOUT, Duration::from_secs),
+        }
+    }
+
+    /// Verbleibende Sperrzeit, wenn `ip` gerade gesperrt ist.
+    pub fn locked(&self, ip: IpAddr) -> Option<Duration> {
+        let clients = self.clients.lock().unwrap();
+        let until = clients.iter().find(|client| client.ip == ip)?.locked_until?;
+        until.checked_duration_since(Instant::now()).filter(|left| !left.is_zero())
+    }
+
+    /// Merkt einen Fehlversuch, liefert die Sperrdauer, wenn `ip` damit gesperrt wird.
+    pub fn record_failure(&self, ip: IpAddr) -> Option<Duration> {
+        let mut clients = self.clients.lock().unwrap();
+        let now = Instant::now();
+        let index = match clients.iter().position(|client| client.ip == ip) {
+            Some(index) => index,
+            None => {
+                if clients.len() >= CAPACITY {
+                    // Gesperrte Clients bleiben, solange es unauffälligere gibt
+                    let index = clients
+                        .iter()
+                        .position(|client| client.locked_until.map_or(true, |until| until <= now))
+                        .unwrap_or(0);
+                    clients.remove(index);
+                }
+                clients.push(Client {
+                    ip,
+                    first_failure: now,
+                    failures: 0,
+                    locked_until: None,
+                });
+                clients.len() - 1
+            }
+        };
+
+        let client = &mut clients[index];
+        if now.duration_since(client.first_failure) > self.window
+            || client.locked_until.is_some_and(|until| until <= now)
+        {
+            client.first_failure = now;
+            client.failures = 0;
+            client.locked_until = None;
+        }
+        client.failures += 1;
+        if client.failures >= self.max_failures && client.locked_until.is_none() {
+            client.locked_until = Some(now + self.lockout);
+            return Some(self.lockout);
+        }
+        None
+    }
+
+    /// Ein gültiges Token setzt die Fehlversuche zurück.
+    pub fn record_success(&self, ip: IpAddr) {
+        self.clients.lock().unwrap().retain(|client| client.ip != ip);
+    }
+}
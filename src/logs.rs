@@ -0,0 +1,491 @@
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use heapless::spsc::Queue;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::clock;
+use crate::config;
+use crate::lang::Msg;
+use crate::metrics::Metrics;
+
+/// Anzahl der Einträge im Ringpuffer.
+///
+/// Jeder Eintrag kostet rund 56 Byte für `LogEntry` selbst plus die Länge des Pfads auf dem Heap,
+/// bei typischen Pfaden wie `/push 500ms` also rund 70 Byte. Große Werte bringen auf dem Display
+/// nichts, dort passen ohnehin nur so viele Zeilen, wie die Höhe erlaubt.
+pub const LOG_CAPACITY: usize = 10;
+
+/// Längere Pfade werden gekürzt, damit ein Eintrag nicht beliebig viel Heap belegt, siehe
+/// [`sanitize_path`].
+const MAX_PATH_LEN: usize = 48;
+
+/// Höchstgröße des gespeicherten Verlaufs als JSON, ältere Einträge fallen heraus.
+///
+/// Der Verlauf wird bei jedem Schreiben komplett neu ins NVS geschrieben, größere Werte kosten
+/// also nicht nur Platz, sondern auch Flash-Zyklen.
+const HISTORY_MAX_BYTES: usize = 2048;
+
+/// So oft schreibt ein eigener Thread den Verlauf ins NVS, wenn neue Einträge dazugekommen sind.
+/// Vor einem geplanten Neustart schreibt [`LogQueue::flush_history`] sofort.
+const HISTORY_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Pfade, deren erfolgreiche Requests nicht im Log landen, ergänzbar über `LOG_IGNORE_PATHS`.
+const DEFAULT_QUIET_PATHS: &[&str] = &["/favicon.ico"];
+
+/// Ein Logeintrag, für `/logs` als JSON-Objekt, auf dem Display als kurze Textzeile.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogEntry {
+    /// Laufende Nummer, steigt mit jedem Eintrag, auch über Neustarts mit gespeichertem Verlauf
+    #[serde(default)]
+    pub seq: u64,
+    /// Unix-Zeit in Sekunden, `None` solange die Uhr nicht synchronisiert ist
+    pub timestamp: Option<i64>,
+    /// HTTP-Status, nach dem das Display die Zeile einfärbt, `None` bei Ereignissen ohne Request
+    pub status: Option<u16>,
+    /// Pfad samt Details wie `/push 500ms`, bei Ereignissen deren Beschreibung
+    pub path: String,
+    /// Auslöser außerhalb des HTTP-Servers, `None` für Requests und Ereignisse
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
+    /// Art des Relais-Ereignisses, unabhängig vom Wortlaut in `path`, `None` für alles andere
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<Event>,
+}
+
+/// Was ein Eintrag am Relais bewirkt hat, für Empfänger wie den Webhook.
+///
+/// Gesetzt wird das dort, wo geschaltet wird, nicht aus dem Text des Eintrags erraten.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    /// Erfolgreicher Schaltbefehl über HTTP, Taster, MQTT, CoAP oder Drehgeber
+    Switched,
+    ScheduledPush,
+    SafetyCutoff,
+    RelayMismatch,
+    SequenceFinished,
+    LatchRestored,
+}
+
+impl Event {
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::Switched => "switched",
+            Event::ScheduledPush => "scheduled_push",
+            Event::SafetyCutoff => "safety_cutoff",
+            Event::RelayMismatch => "relay_mismatch",
+            Event::SequenceFinished => "sequence_finished",
+            Event::LatchRestored => "latch_restored",
+        }
+    }
+
+    /// Beschreibung am Anfang von `path`, immer Englisch, `None` für Schaltbefehle mit eigenem Pfad.
+    fn text(self) -> Option<&'static str> {
+        match self {
+            Event::Switched => None,
+            Event::ScheduledPush => Some("scheduled push"),
+            Event::SafetyCutoff => Some("safety cutoff"),
+            Event::RelayMismatch => Some("relay mismatch"),
+            Event::SequenceFinished => Some("sequence finished"),
+            Event::LatchRestored => Some("latch restored"),
+        }
+    }
+
+    /// Dieselbe Beschreibung in der Sprache des Displays.
+    fn message(self) -> Option<&'static str> {
+        let msg = match self {
+            Event::Switched => return None,
+            Event::ScheduledPush => Msg::EventScheduledPush,
+            Event::SafetyCutoff => Msg::EventSafetyCutoff,
+            Event::RelayMismatch => Msg::EventRelayMismatch,
+            Event::SequenceFinished => Msg::EventSequenceFinished,
+            Event::LatchRestored => Msg::EventLatchRestored,
+        };
+        Some(msg.text())
+    }
+}
+
+/// Woher ein Eintrag kommt, wenn nicht vom HTTP-Server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Button,
+    Mqtt,
+    #[cfg(feature = "coap")]
+    Coap,
+    #[cfg(feature = "encoder")]
+    Encoder,
+}
+
+impl Source {
+    pub fn label(self) -> &'static str {
+        match self {
+            Source::Button => "button",
+            Source::Mqtt => "mqtt",
+            #[cfg(feature = "coap")]
+            Source::Coap => "coap",
+            #[cfg(feature = "encoder")]
+            Source::Encoder => "encoder",
+        }
+    }
+}
+
+/// Alles hinter der Uhrzeit, gemeinsam für Display und `/logs.txt`. Mit `localized` stehen
+/// Relais-Ereignisse in der Sprache aus [`crate::lang`] da, sonst wie in `path`.
+struct Details<'a> {
+    entry: &'a LogEntry,
+    localized: bool,
+}
+
+impl fmt::Display for Details<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entry = self.entry;
+        if let Some(status) = entry.status {
+            write!(f, " {}", status)?;
+        }
+        if let Some(source) = entry.source {
+            write!(f, " {}", source.label())?;
+        }
+        let translated = entry.event.filter(|_| self.localized).and_then(|event| {
+            let detail = entry.path.strip_prefix(event.text()?)?;
+            Some((event.message()?, detail))
+        });
+        match translated {
+            Some((message, detail)) => write!(f, " {}{}", message, detail),
+            None => write!(f, " {}", entry.path),
+        }
+    }
+}
+
+/// Kompakte Zeile wie `12:34:56 200 mqtt push` für das Display und `/ws/logs`.
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.timestamp.and_then(clock::local_time) {
+            Some(time) => write!(f, "{}", time.format(clock::time_format()))?,
+            None => f.write_str("--:--:--")?,
+        }
+        write!(f, "{}", Details { entry: self, localized: true })
+    }
+}
+
+impl LogEntry {
+    /// Zeile für `/logs.txt`: wie auf dem Display, aber mit vollem Datum in ISO-Schreibweise.
+    pub fn text_line(&self) -> String {
+        let time = match self.timestamp.and_then(clock::local_time) {
+            Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            None => "----------  --:--:--".to_string(),
+        };
+        format!("{}{}", time, Details { entry: self, localized: false })
+    }
+}
+
+// Die heapless-Queue hält einen Platz frei, daher eins mehr als die gewünschte Kapazität
+type Entries = Queue<LogEntry, { LOG_CAPACITY + 1 }>;
+
+type Listener = Box<dyn Fn(&LogEntry) + Send + Sync>;
+
+/// Ringpuffer der letzten Requests für Display und `/logs`.
+///
+/// Zusätzlich können sich Listener registrieren, die jeden neuen Eintrag sofort erhalten,
+/// z.B. die WebSocket-Clients von `/ws/logs`.
+///
+/// Mit `history` wird außerdem ein längerer Verlauf im NVS geführt, der einen Neustart übersteht
+/// und beim Start die Queue wieder füllt.
+///
+/// Erfolgreiche Requests auf Pfaden wie `/favicon.ico` werden nur gezählt, damit sie die wenigen
+/// Zeilen nicht mit Rauschen füllen. Fehler auf diesen Pfaden erscheinen weiterhin.
+pub struct LogQueue {
+    entries: Mutex<Entries>,
+    listeners: Mutex<Vec<Listener>>,
+    metrics: Arc<Metrics>,
+    last_activity: Mutex<Instant>,
+    history: Option<History>,
+    quiet_paths: Vec<&'static str>,
+    /// Nummer des letzten Eintrags, 0 vor dem ersten
+    seq: AtomicU64,
+}
+
+struct History {
+    nvs: EspDefaultNvsPartition,
+    state: Arc<Mutex<HistoryState>>,
+}
+
+/// Verlauf im RAM, ins NVS geschrieben wird er vom Thread aus [`History::spawn_flush`].
+#[derive(Default)]
+struct HistoryState {
+    entries: VecDeque<LogEntry>,
+    /// JSON-Länge je Eintrag, damit die Rotation nicht jedes Mal alles serialisiert
+    lens: VecDeque<usize>,
+    /// Länge des ganzen Verlaufs als JSON-Array
+    bytes: usize,
+    /// Seit dem letzten Schreiben geändert
+    dirty: bool,
+}
+
+impl HistoryState {
+    fn new(entries: Vec<LogEntry>) -> Self {
+        let mut state = Self::default();
+        for entry in entries {
+            state.push(entry);
+        }
+        state.dirty = false;
+        state
+    }
+
+    fn push(&mut self, entry: LogEntry) {
+        // Eintrag plus Komma, die Klammern des Arrays kommen unten dazu
+        let len = serde_json::to_string(&entry).map_or(0, |json| json.len()) + 1;
+        self.entries.push_back(entry);
+        self.lens.push_back(len);
+        self.bytes += len;
+        // Rotation: von vorne kürzen, bis der Verlauf wieder in den Platz passt
+        while self.entries.len() > 1 && self.bytes + 1 > HISTORY_MAX_BYTES {
+            self.entries.pop_front();
+            self.bytes -= self.lens.pop_front().unwrap_or_default();
+        }
+        self.dirty = true;
+    }
+}
+
+impl History {
+    /// Schreibt den Verlauf, wenn er sich geändert hat. Das NVS wird ohne Sperre beschrieben.
+    fn flush(nvs: &EspDefaultNvsPartition, state: &Mutex<HistoryState>) {
+        let entries: Vec<LogEntry> = {
+            let mut state = lock(state);
+            if !state.dirty {
+                return;
+            }
+            state.dirty = false;
+            state.entries.iter().cloned().collect()
+        };
+        if let Err(err) = config::store_log_history(nvs, &entries) {
+            warn!("Log-Verlauf nicht gespeichert: {:?}", err);
+            lock(state).dirty = true;
+        }
+    }
+
+    /// Schreibt den Verlauf alle [`HISTORY_FLUSH_INTERVAL`], ohne Thread bleibt er nur im RAM.
+    fn spawn_flush(&self) {
+        let nvs = self.nvs.clone();
+        let state = self.state.clone();
+        let spawned = std::thread::Builder::new()
+            .name("log-history".into())
+            .stack_size(4096)
+            .spawn(move || loop {
+                std::thread::sleep(HISTORY_FLUSH_INTERVAL);
+                History::flush(&nvs, &state);
+            });
+        if let Err(err) = spawned {
+            warn!("Log-Verlauf wird nicht gespeichert: {:?}", err);
+        }
+    }
+}
+
+impl LogQueue {
+    pub fn new(metrics: Arc<Metrics>, history: Option<EspDefaultNvsPartition>) -> Self {
+        let history = history.map(|nvs| {
+            let entries = config::load_log_history(&nvs).unwrap_or_else(|err| {
+                warn!("Log-Verlauf nicht lesbar: {:?}", err);
+                Vec::new()
+            });
+            let history = History {
+                nvs,
+                state: Arc::new(Mutex::new(HistoryState::new(entries))),
+            };
+            history.spawn_flush();
+            history
+        });
+
+        let mut queue = Entries::new();
+        let mut seq = 0;
+        if let Some(history) = &history {
+            let state = lock(&history.state);
+            let entries = &state.entries;
+            for entry in entries.iter().skip(entries.len().saturating_sub(LOG_CAPACITY)) {
+                queue.enqueue(entry.clone()).unwrap();
+            }
+            // Weiterzählen, damit Clients mit einer alten Nummer nichts doppelt bekommen
+            seq = entries.iter().map(|entry| entry.seq).max().unwrap_or_default();
+        }
+
+        Self {
+            entries: Mutex::new(queue),
+            listeners: Mutex::new(Vec::new()),
+            metrics,
+            last_activity: Mutex::new(Instant::now()),
+            history,
+            quiet_paths: quiet_paths(),
+            seq: AtomicU64::new(seq),
+        }
+    }
+
+    /// Die Zähler, in die `log_request` schreibt.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Zeitpunkt des letzten Requests, beim Start der Boot-Zeitpunkt.
+    pub fn last_activity(&self) -> Instant {
+        *lock(&self.last_activity)
+    }
+
+    /// Kopie der Einträge, älteste zuerst.
+    ///
+    /// Die Sperre gilt nur für das Kopieren, langsame Leser wie das Display zeichnen aus der Kopie,
+    /// ohne `log_request` in den HTTP-Handlern aufzuhalten.
+    pub fn snapshot(&self) -> Vec<LogEntry> {
+        self.entries().iter().cloned().collect()
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        lock(&self.entries)
+    }
+
+    /// Einträge ab der Unix-Zeit `since`, aus dem gespeicherten Verlauf, falls er geführt wird.
+    ///
+    /// Einträge ohne Zeitstempel sind vor der Synchronisierung entstanden und werden ausgelassen.
+    pub fn since(&self, since: i64) -> Vec<LogEntry> {
+        let is_newer = |entry: &&LogEntry| entry.timestamp.is_some_and(|time| time >= since);
+        match &self.history {
+            Some(history) => lock(&history.state).entries.iter().filter(is_newer).cloned().collect(),
+            None => self.entries().iter().filter(is_newer).cloned().collect(),
+        }
+    }
+
+    /// Nummer des neuesten Eintrags, für `/poll?since=`.
+    pub fn seq(&self) -> u64 {
+        self.seq.load(Ordering::SeqCst)
+    }
+
+    /// Einträge aus der Queue mit einer Nummer größer als `seq`, älteste zuerst.
+    pub fn after(&self, seq: u64) -> Vec<LogEntry> {
+        self.entries().iter().filter(|entry| entry.seq > seq).cloned().collect()
+    }
+
+    /// Schreibt einen geänderten Verlauf sofort ins NVS, vor einem geplanten Neustart oder Deep
+    /// Sleep. Ohne `LOG_PERSIST` passiert nichts.
+    pub fn flush_history(&self) {
+        if let Some(history) = &self.history {
+            History::flush(&history.nvs, &history.state);
+        }
+    }
+
+    pub fn subscribe(&self, listener: impl Fn(&LogEntry) + Send + Sync + 'static) {
+        lock(&self.listeners).push(Box::new(listener));
+    }
+
+    fn push(&self, mut entry: LogEntry) {
+        *lock(&self.last_activity) = Instant::now();
+        {
+            let mut queue = self.entries();
+            // Unter der Sperre vergeben, damit die Nummern in der Queue aufsteigen
+            entry.seq = self.seq.fetch_add(1, Ordering::SeqCst) + 1;
+            if queue.is_full() {
+                queue.dequeue();
+            }
+            queue.enqueue(entry.clone()).unwrap();
+        }
+
+        if let Some(history) = &self.history {
+            lock(&history.state).push(entry.clone());
+        }
+
+        // Listener erst nach dem Freigeben der Queue benachrichtigen
+        for listener in lock(&self.listeners).iter() {
+            listener(&entry);
+        }
+    }
+}
+
+/// Sperrt `mutex`, auch wenn ein Thread mit der Sperre in einem Panic gestorben ist.
+///
+/// `unwrap()` würde danach bei jedem Request und jedem Zeichnen erneut einen Panic auslösen. Der
+/// Inhalt bleibt auch dann brauchbar, schlimmstenfalls fehlt der eine Eintrag, bei dem es passiert
+/// ist.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        warn!("Log-Sperre nach einem Panic wieder freigegeben");
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Standardpfade plus die kommagetrennten Pfade aus `LOG_IGNORE_PATHS`, z.B. `/health,/status`.
+fn quiet_paths() -> Vec<&'static str> {
+    let mut paths = DEFAULT_QUIET_PATHS.to_vec();
+    if let Some(extra) = option_env!("LOG_IGNORE_PATHS") {
+        paths.extend(extra.split(',').map(str::trim).filter(|path| !path.is_empty()));
+    }
+    paths
+}
+
+impl LogEntry {
+    fn new(status: Option<u16>, path: &str, source: Option<Source>, event: Option<Event>) -> Self {
+        // Ohne SNTP läuft die Uhr ab 1970, dann lieber keinen Zeitstempel als einen falschen
+        let timestamp = clock::is_synced().then(|| clock::now().timestamp());
+        Self {
+            seq: 0,
+            timestamp,
+            status,
+            path: sanitize_path(path),
+            source,
+            event,
+        }
+    }
+}
+
+/// Kürzt `path` auf [`MAX_PATH_LEN`] Zeichen und ersetzt alles außer druckbarem ASCII durch `?`.
+///
+/// Pfade kommen ungeprüft vom Client. Steuerzeichen würden die Zeile auf dem Display zerreißen,
+/// und die Schrift des Displays kennt ohnehin nur ASCII.
+pub fn sanitize_path(path: &str) -> String {
+    path.chars()
+        .take(MAX_PATH_LEN)
+        .map(|c| if c.is_ascii_graphic() || c == ' ' { c } else { '?' })
+        .collect()
+}
+
+pub fn log_request(log_queue: &LogQueue, status: u16, path: &str) {
+    log_request_event(log_queue, status, path, None);
+}
+
+/// Wie [`log_request`], `event` markiert z.B. einen erfolgreichen Schaltvorgang.
+pub fn log_request_event(log_queue: &LogQueue, status: u16, path: &str, event: Option<Event>) {
+    log_queue.metrics.record_request(status);
+    let base_path = path.split(' ').next().unwrap_or_default();
+    if status < 400 && log_queue.quiet_paths.contains(&base_path) {
+        return;
+    }
+    log_queue.push(LogEntry::new(Some(status), path, None, event));
+}
+
+/// Wie [`log_request`], aber für Auslöser außerhalb des HTTP-Servers wie Taster oder MQTT.
+///
+/// Gezählt wird der Auslöser für sich, nicht unter den HTTP-Requests.
+pub fn log_source(log_queue: &LogQueue, source: Source, status: u16, path: &str) {
+    log_queue.metrics.record_trigger(source);
+    log_queue.push(LogEntry::new(Some(status), path, Some(source), None));
+}
+
+/// Wie [`log_source`] mit 200 für einen Befehl, der geschaltet hat.
+pub fn log_switch(log_queue: &LogQueue, source: Source, path: &str) {
+    log_queue.metrics.record_trigger(source);
+    log_queue.push(LogEntry::new(Some(200), path, Some(source), Some(Event::Switched)));
+}
+
+/// Ereignis ohne Request, z.B. eine Sicherheitsabschaltung. Zählt nicht in die Metriken.
+pub fn log_event(log_queue: &LogQueue, event: &str) {
+    log_queue.push(LogEntry::new(None, event, None, None));
+}
+
+/// Wie [`log_event`] für ein Ereignis am Relais, `detail` wie ` 500ms` oder ` tor` kommt hinter
+/// die Beschreibung des Ereignisses.
+pub fn log_relay_event(log_queue: &LogQueue, event: Event, detail: &str) {
+    let text = format!("{}{}", event.text().unwrap_or_else(|| event.name()), detail);
+    log_queue.push(LogEntry::new(None, &text, None, Some(event)));
+}
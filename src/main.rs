@@ -1,197 +1,1997 @@
 use esp_idf_sys as _; // Bindings to the ESP-IDF SDK
 
+mod allowlist;
+mod api;
+mod backlight;
+mod battery;
+mod board;
+mod button;
+mod buzzer;
+mod clock;
+#[cfg(feature = "coap")]
+mod coap;
+mod config;
+mod crash;
+#[cfg(feature = "display")]
+mod display;
+mod dns;
+#[cfg(feature = "encoder")]
+mod encoder;
+mod html;
+mod https;
+mod idempotency;
+mod keepalive;
+// Ohne Display bleiben die meisten Texte ungenutzt
+#[cfg_attr(not(feature = "display"), allow(dead_code))]
+mod lang;
+mod lockout;
+mod logs;
+mod maintenance;
+mod mdns;
+mod memory;
+#[cfg(feature = "encoder")]
+mod menu;
+mod metrics;
+mod mqtt;
+mod outbox;
+mod poll;
+mod power;
+mod ramp;
+mod relay;
+mod relays;
+mod schedule;
+#[cfg(feature = "sensor")]
+mod sensor;
+mod settings;
+mod sse;
+mod stats;
+mod status_led;
+mod streams;
+mod syslog;
+mod system;
+mod telegram;
+mod tls;
+mod wakeup;
+mod watchdog;
+mod webhook;
+mod wifi;
+mod ws;
+
 use anyhow::Result;
 use embedded_svc::http::server::{HttpServer, Request, Response};
 use embedded_svc::io::Write;
-use esp_idf_hal::gpio::{Gpio5, Output, PinDriver};
+use esp_idf_hal::gpio::{Pin, PinDriver, Pull};
+#[cfg(feature = "display")]
+use esp_idf_hal::ledc::{config::TimerConfig, LedcDriver, LedcTimerDriver};
 use esp_idf_hal::prelude::*;
+use esp_idf_hal::task::watchdog::{TWDTConfig, TWDTDriver};
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::http::server::EspHttpServer;
 use esp_idf_svc::netif::*;
 use esp_idf_svc::nvs::EspDefaultNvsPartition;
 use esp_idf_svc::wifi::*;
-use heapless::spsc::Queue;
 use log::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
 use dotenv::dotenv;
 
-use embedded_graphics::{
-    mono_font::{ascii::FONT_6X10, MonoTextStyle},
-    pixelcolor::Rgb565,
-    prelude::*,
-    text::Text,
+use allowlist::Allowlist;
+use api::{
+    accepts_gzip, body_format, cors_origin, form_pairs, json_handler, parse_body, read_body, require_allowed,
+    require_auth_limited, respond_json, AccessLog, BodyFormat, HttpError, JsonReply, Routes, ServerHandle,
+};
+#[cfg(feature = "display")]
+use backlight::Backlight;
+use battery::{Battery, BatteryConfig, BatteryLevel};
+use button::Press;
+use buzzer::Buzzer;
+use config::WifiConfig;
+#[cfg(feature = "display")]
+use display::StatusDisplay;
+use dns::DnsResponder;
+use idempotency::IdempotencyCache;
+use lang::Msg;
+use lockout::AuthLimiter;
+use logs::{log_event, log_relay_event, log_source, log_switch, Event, LogEntry, LogQueue, Source};
+use mdns::Mdns;
+use memory::{MemoryLevel, MemoryMonitor};
+use metrics::{Metrics, Summary};
+use ramp::Ramp;
+use relay::{BusyPolicy, Feedback, LatchAction, Relay, RelayConfig, RelayError, SequenceStep, Trigger};
+use relays::RelaySet;
+use schedule::{Cron, Schedule, ScheduleEntry};
+use settings::{
+    DeviceSettings, Settings, SettingsUpdate, DEFAULT_PULSE_MS, DEFAULT_TICK_MS, MAX_PULSE_MS, MAX_TICK_MS,
+    MIN_PULSE_MS, MIN_TICK_MS,
 };
-use st7789::Orientation;
+use status_led::{LedState, StatusLed};
+use wakeup::Wakeup;
+use wifi::{LinkState, NetworkStatus};
+
+// Die Pinbelegung hängt vom Board ab, siehe board.rs. Relais und Hintergrundbeleuchtung brauchen
+// getrennte Pins, sonst schaltet jeder Push das Display.
+
+// Display geht nach so vielen Sekunden ohne Request aus, 0 schaltet das ab
+#[cfg(feature = "display")]
+const DEFAULT_BLANK_TIMEOUT_S: u64 = 60;
+
+// Der Task-Watchdog startet das Gerät neu, wenn die Hauptschleife so lange nicht durchläuft.
+// Großzügig gewählt, weil ein Verbindungsversuch in der Schleife bis zu 30s dauern kann.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Grenzen für /sequence: Anzahl der Schritte und Gesamtdauer aller Schritte
+const MAX_SEQUENCE_STEPS: usize = 16;
+const MAX_SEQUENCE_MS: u64 = 10_000;
+
+// Mindestabstand zwischen zwei Pulsen ohne MIN_PUSH_INTERVAL_MS
+const DEFAULT_MIN_PUSH_INTERVAL_MS: u64 = 1000;
+
+// Maximale Einschaltdauer ohne MAX_ON_TIME_MS, danach schaltet die Sicherheitsabschaltung ab
+const DEFAULT_MAX_ON_TIME_MS: u64 = 10_000;
+
+// Abkühlzeit nach jedem Abfallen ohne RELAY_COOLDOWN_MS, 0 schaltet sie ab
+const DEFAULT_RELAY_COOLDOWN_MS: u64 = 0;
+
+// Ab so langem Halten des Tasters zeigt das Display den Countdown zum Zurücksetzen
+#[cfg(feature = "display")]
+const FACTORY_RESET_HINT: Duration = button::LONG_PRESS;
+
+// So lange nach dem Start zeigt das Display, der wievielte Start es war und warum
+#[cfg(feature = "display")]
+const BOOT_INFO_SECS: u64 = 30;
+
+// HTTP-Server ohne HTTP_MAX_SOCKETS bzw. HTTP_STACK_SIZE. Jeder Socket kostet im lwIP rund
+// 2 KB für Verwaltung und Puffer, mit HTTPS kommen pro Verbindung etwa 40 KB für die TLS-Sitzung
+// dazu. Der Server bedient alle Verbindungen aus einem Task, dessen Stack einmal belegt wird.
+// Mehr als CONFIG_LWIP_MAX_SOCKETS minus 3 (intern vom Server belegt) sind nicht möglich.
+const DEFAULT_HTTP_MAX_SOCKETS: usize = 4;
+const DEFAULT_HTTP_STACK_SIZE: usize = 6144;
+
+// Ohne Ereignis läuft die Hauptschleife im Takt aus /config bzw. DISPLAY_TICK_MS, für Uhr, RSSI
+// und WLAN-Überwachung. Solange ein Relais angezogen ist, öfter, damit das Ende eines Pulses gleich
+// zu sehen ist
+const ACTIVE_TICK: Duration = Duration::from_millis(100);
+
+// Heap, Stack und Akku werden in diesem Abstand gemessen
+const MEMORY_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+// Abstand, in dem ein fehlgeschlagener Start von mDNS oder SNTP wiederholt wird
+const SERVICE_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+// Pause zwischen dem Abschalten der Relais und dem Neustart nach /reboot, lässt Logs und Syslog raus
+const REBOOT_DELAY: Duration = Duration::from_millis(500);
 
 fn main() -> Result<()> {
     dotenv().ok();
 
     esp_idf_sys::link_patches();
     esp_idf_svc::log::EspLogger::initialize_default();
+    crash::install();
+    clock::init_timezone();
+    info!("Sprache: {:?}", lang::current());
 
     // Initialisiere Peripherie
-    let peripherals = Peripherals::take().unwrap();
+    let peripherals = Peripherals::take()?;
     let pins = peripherals.pins;
 
-    // Initialisiere GPIO für das Relais an Pin 5
-    let mut relay = PinDriver::output(pins.gpio5)?;
+    let default_nvs = EspDefaultNvsPartition::take()?;
+    // Vor dem ersten Logeintrag, damit alle Zeitstempel gleich aussehen
+    clock::init_time_format(config::load_time_format(&default_nvs)?);
+
+    // Über /config änderbare Einstellungen, ohne gespeicherte Werte aus dem Build
+    let settings = Arc::new(Settings::load(
+        default_nvs.clone(),
+        DeviceSettings {
+            pulse_ms: DEFAULT_PULSE_MS,
+            debounce_ms: button::DEFAULT_DEBOUNCE_MS,
+            brightness: backlight::env_brightness(),
+            // Feinerer Takt für die Uhr oder gröberer zum Stromsparen über DISPLAY_TICK_MS
+            tick_ms: option_env!("DISPLAY_TICK_MS")
+                .and_then(|value| value.parse().ok())
+                .filter(|ms| (MIN_TICK_MS..=MAX_TICK_MS).contains(ms))
+                .unwrap_or(DEFAULT_TICK_MS),
+            // Relais-Module mit Low-aktivem Eingang über RELAY_ACTIVE_LOW=1
+            relay_active_low: option_env!("RELAY_ACTIVE_LOW") == Some("1"),
+            // Dauerbetrieb über einen Neustart retten mit RELAY_RESTORE_LATCH=1
+            restore_latch: option_env!("RELAY_RESTORE_LATCH") == Some("1"),
+            hostname: config::load_hostname(&default_nvs)?,
+        },
+    )?);
+    let DeviceSettings {
+        relay_active_low,
+        hostname,
+        ..
+    } = settings.get();
+
+    // Initialisiere GPIO für das Relais
+    let relay_pin = board::relay_pin!(pins);
+    assert_eq!(relay_pin.pin(), board::RELAY_GPIO);
+    // Ausgangspegel vor dem Umschalten auf Ausgang setzen, damit ein Low-aktives Relais beim
+    // Booten nicht kurz anzieht
+    unsafe { esp_idf_sys::gpio_set_level(board::RELAY_GPIO, relay_active_low as u32) };
+    let simulate = option_env!("SIMULATE") == Some("1");
+    if simulate {
+        warn!("SIMULATE=1: Relais werden nicht geschaltet");
+    }
+    // Der Pin gehört dem Relais-Worker, die Handler teilen sich nur den Auftrags-Kanal
+    let relay_config = RelayConfig {
+        active_low: relay_active_low,
+        min_interval: Duration::from_millis(
+            option_env!("MIN_PUSH_INTERVAL_MS")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_MIN_PUSH_INTERVAL_MS),
+        ),
+        max_on_time: Duration::from_millis(
+            option_env!("MAX_ON_TIME_MS")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_MAX_ON_TIME_MS),
+        ),
+        // Laut Datenblatt mancher Relais nötige Pause zwischen zwei Pulsen
+        cooldown: Duration::from_millis(
+            option_env!("RELAY_COOLDOWN_MS")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_RELAY_COOLDOWN_MS),
+        ),
+        // Optionaler Hilfskontakt über RELAY_FEEDBACK_GPIO
+        feedback: Feedback::from_env(),
+        // Optional PWM-Rampe statt hartem Schalten für Halbleiterrelais, z.B. mit RELAY_RAMP_UP_MS
+        ramp: Ramp::from_env(peripherals.ledc.timer2, peripherals.ledc.channel2),
+        // Puls während eines laufenden ablehnen oder mit PUSH_BUSY_POLICY=queue einreihen
+        busy_policy: BusyPolicy::from_env(),
+        // Mit SIMULATE=1 schaltet kein Relais, alles andere verhält sich wie sonst
+        simulate,
+    };
+    let relay = Relay::spawn(PinDriver::output(relay_pin)?, relay_config)?;
+    // Weitere Relais aus RELAYS, das erste bleibt `relay` für /push, Taster und Zeitplan
+    let relays = RelaySet::spawn(relay.clone(), relay_config)?;
 
-    // WLAN-Konfiguration aus Umgebungsvariablen
-    let ssid = env!("WIFI_SSID");
-    let password = env!("WIFI_PASS");
+    // Optionale RGB-LED über STATUS_LED_GPIO, blau bis das WLAN steht
+    let status_led = StatusLed::from_env(peripherals.rmt.channel0, relay.clone())?;
+    // Optionaler Piezo über BUZZER_GPIO, piept bei Pulsen und Fehlern
+    let buzzer = Buzzer::from_env(peripherals.ledc.timer1, peripherals.ledc.channel1, relay.clone())?;
+
+    // Token für geschützte Endpunkte wie /push
+    let api_token = env!("API_TOKEN");
+    // Nach zu vielen falschen Tokens wird der Client eine Weile mit 429 abgewiesen, gemeinsam für
+    // alle geschützten Endpunkte, damit sich das Token auch nicht über einen anderen durchprobieren lässt
+    let auth_limiter = Arc::new(AuthLimiter::from_env());
+    // Mit PUSH_ALLOWLIST schalten HTTP und CoAP nur aus bestimmten Netzen, noch vor dem Token geprüft
+    let allowlist = Allowlist::from_env();
+    // /metrics ist offen, außer METRICS_AUTH=1 verlangt auch dort das Token
+    let metrics_auth = option_env!("METRICS_AUTH") == Some("1");
+
+    // Initialisiere Display, ohne das Feature `display` bleiben Pin und LEDC-Kanal unberührt
+    #[cfg(feature = "display")]
+    let backlight = {
+        let backlight_pin = board::backlight_pin!(pins);
+        assert_eq!(backlight_pin.pin(), board::BACKLIGHT_GPIO);
+        // Hintergrundbeleuchtung per PWM, damit sie sich dimmen lässt
+        let backlight_timer = LedcTimerDriver::new(
+            peripherals.ledc.timer0,
+            &TimerConfig::default().frequency(5.kHz().into()),
+        )?;
+        Arc::new(Mutex::new(Backlight::new(
+            LedcDriver::new(peripherals.ledc.channel0, backlight_timer, backlight_pin)?,
+            settings.get().brightness,
+        )))
+    };
+    // Gedreht montierte Geräte über DISPLAY_ROTATION
+    #[cfg(feature = "display")]
+    let rotation = config::load_display_rotation(&default_nvs)?;
+    // Größere Schrift für an der Wand montierte Geräte über DISPLAY_FONT und DISPLAY_HEADER_FONT
+    #[cfg(feature = "display")]
+    let fonts = config::load_display_fonts(&default_nvs)?;
+    // Ein fehlendes oder defektes Display ist kein Grund, ohne Relais-Steuerung dazustehen:
+    // ohne Display läuft alles andere weiter, die Initialisierung wird regelmäßig wiederholt.
+    // Ein fehlgeschlagener Versuch gibt die Peripherie frei, deshalb wird sie jedes Mal neu erzeugt
+    // und die Felder aus `peripherals` bleiben ungenutzt.
+    #[cfg(feature = "st7789")]
+    let mut display = {
+        let backlight = backlight.clone();
+        // Niedrigerer Takt für lange Kabel oder verlegte Pins über DISPLAY_SPI_HZ usw.
+        let spi_config = board::DisplaySpiConfig::from_env();
+        display::Retrying::new(move || {
+            let (spi, dc, rst) = display::st7789::open_spi(&spi_config)?;
+            display::st7789::init(spi, dc, rst, rotation, fonts, &mut backlight.lock().unwrap())
+        })
+    };
+    // SSD1306-OLED am I2C-Bus des Boards
+    #[cfg(feature = "ssd1306")]
+    let mut display = display::Retrying::new(move || {
+        let i2c = unsafe {
+            esp_idf_hal::i2c::I2cDriver::new(
+                board::DisplayI2c::new(),
+                board::DisplaySda::new(),
+                board::DisplayScl::new(),
+                &esp_idf_hal::i2c::I2cConfig::new().baudrate(400.kHz().into()),
+            )?
+        };
+        display::ssd1306::init(i2c, rotation, fonts)
+    });
+
+    // Startbildschirm bis zur ersten Zeichnung der Hauptschleife, statt schwarz während des
+    // WLAN-Verbindungsaufbaus
+    #[cfg(feature = "display")]
+    let firmware_version = env!("CARGO_PKG_VERSION");
+    #[cfg(feature = "display")]
+    let mut splash = |progress: &str| {
+        if let Err(err) = display.show_splash(&hostname, firmware_version, progress) {
+            warn!("Startbildschirm nicht gezeichnet: {:?}", err);
+        }
+    };
+    // Ohne Display steht der Fortschritt nur in den Logs des WLAN-Aufbaus
+    #[cfg(not(feature = "display"))]
+    let splash = |_progress: &str| {};
+    splash(Msg::Starting.text());
 
     // WLAN initialisieren und verbinden
     let sys_loop = EspSystemEventLoop::take()?;
-    let default_nvs = EspDefaultNvsPartition::take()?;
-    let mut wifi = EspWifi::new(peripherals.modem, sys_loop.clone(), Some(default_nvs))?;
 
-    let wifi_config = WifiConfiguration::Client(ClientConfiguration {
-        ssid: ssid.into(),
-        password: password.into(),
-        ..Default::default()
-    });
+    // WLAN-Netze aus dem NVS, beim ersten Start aus den Umgebungsvariablen zur Build-Zeit
+    let mut networks = config::load_wifi_networks(&default_nvs)?;
+    // Nach dem Zurücksetzen über den Taster ohne Netz direkt ins Einrichtungsportal
+    let force_setup = config::take_force_setup(&default_nvs)?;
+    if force_setup {
+        info!("Zurückgesetzt, starte Einrichtungsportal");
+    } else if networks.is_empty() {
+        networks = config::env_wifi_networks();
+        if networks.is_empty() {
+            anyhow::bail!("Keine WLAN-Zugangsdaten im NVS und WIFI_SSID nicht gesetzt");
+        }
+        config::store_wifi_networks(&default_nvs, &networks)?;
+        info!("{} WLAN-Netz(e) im NVS gespeichert", networks.len());
+    }
+    // Startzähler und Grund des letzten Resets, um Abstürze von Stromproblemen zu unterscheiden
+    let boot_count = config::record_boot(&default_nvs)?;
+    info!("Start Nr. {}, Reset-Grund: {}", boot_count, system::reset_reason());
+    let chip = system::chip_info();
+    info!(
+        "{} rev {}, Firmware {}, ESP-IDF {}",
+        chip.model, chip.revision, chip.firmware_version, chip.idf_version
+    );
+
+    let last_ssid = config::load_last_ssid(&default_nvs)?;
 
-    wifi.set_configuration(&wifi_config)?;
-    wifi.start()?;
-    info!("WLAN gestartet");
-    wifi.connect()?;
-    info!("Verbinde mit WLAN...");
+    let mut wifi = EspWifi::new(peripherals.modem, sys_loop.clone(), Some(default_nvs.clone()))?;
 
-    // Warte auf Verbindung
-    while !wifi.is_connected().unwrap() {
-        std::thread::sleep(Duration::from_millis(500));
+    // Feste IP statt DHCP, falls konfiguriert
+    let static_ip = config::load_static_ip(&default_nvs)?;
+    if let Some(static_ip) = &static_ip {
+        wifi::apply_static_ip(&mut wifi, static_ip)?;
     }
-    info!("Mit WLAN verbunden");
+    // Erst nach dem Austausch des Netifs, damit der Router das Gerät unter seinem Namen führt
+    wifi.sta_netif_mut().set_hostname(&hostname)?;
+    let ip_mode = if static_ip.is_some() { "static" } else { "dhcp" };
+
+    // Gelingt die Verbindung mit keinem Netz, als Access Point mit Einrichtungsportal weitermachen
+    let provisioning = Arc::new(AtomicBool::new(false));
+    let mut dns_responder = None;
+    let connected = wifi::connect_any(
+        &mut wifi,
+        &networks,
+        last_ssid.as_deref(),
+        wifi::connect_timeout(),
+        |ssid, attempt| splash(&lang::wifi_attempt(ssid, attempt)),
+    );
+    let status = match connected {
+        Ok(connected) => {
+            splash(&lang::connected_to(&connected.ssid));
+            config::store_last_ssid(&default_nvs, &connected.ssid)?;
+            NetworkStatus {
+                link: LinkState::Connected,
+                ip: wifi.sta_netif().get_ip_info()?.ip.to_string(),
+                ip6: wifi::ipv6_addresses(&wifi),
+                ssid: connected.ssid,
+                failure: None,
+            }
+        }
+        Err(failure) => {
+            splash(&lang::setup_via(wifi::AP_SSID));
+            let ap_ip = wifi::start_access_point(&mut wifi)?;
+            dns_responder = Some(DnsResponder::start(ap_ip)?);
+            provisioning.store(true, Ordering::SeqCst);
+            NetworkStatus {
+                link: LinkState::AccessPoint,
+                ip: ap_ip.to_string(),
+                ip6: Vec::new(),
+                ssid: wifi::AP_SSID.to_string(),
+                failure: Some(failure),
+            }
+        }
+    };
+
+    // IP-Adresse abrufen, sie kann sich nach einer Einrichtung oder einem Reconnect noch ändern
+    info!("IP-Adresse: {}", status.ip);
+    for ip6 in &status.ip6 {
+        info!("IPv6-Adresse: {}", ip6);
+    }
+    if let Some(led) = &status_led {
+        led.set_state(led_state(status.link));
+    }
+    let network = Arc::new(Mutex::new(status));
+
+    // mDNS erst ankündigen, wenn eine Station-Verbindung besteht
+    // HTTPS, sobald ein Zertifikat hinterlegt ist, siehe tls.rs
+    let tls_identity = tls::load(&default_nvs)?;
+    let tls_enabled = tls_identity.is_some();
+    // Port des HTTP-Servers, wird auch per mDNS angekündigt
+    let http_port = config::load_http_port(&default_nvs, tls_enabled)?;
+    // Geteilt mit dem Wartungs-Thread, der den Dienst regelmäßig neu ankündigt
+    let mdns: Arc<Mutex<Option<Mdns>>> = Arc::new(Mutex::new(None));
+    let mut sntp = None;
+    if !provisioning.load(Ordering::SeqCst) {
+        *mdns.lock().unwrap() = Some(Mdns::start(&hostname, http_port, tls_enabled)?);
+        sntp = Some(clock::start_sntp()?);
+    }
+    // Alle MAINTENANCE_INTERVAL_S (Standard stündlich) mDNS neu ankündigen und SNTP anstoßen
+    maintenance::spawn(mdns.clone(), network.clone())?;
+
+    // Über das Portal eingegebene Zugangsdaten gehen an die Hauptschleife
+    let (setup_tx, setup_rx) = mpsc::channel::<WifiConfig>();
+
+    // Dunkle Schrift auf hellem Grund für helle Räume, per /display/invert umschaltbar
+    let display_inverted = Arc::new(AtomicBool::new(config::load_display_inverted(&default_nvs)?));
 
-    // IP-Adresse abrufen
-    let ip_info = wifi.sta_netif().get_ip_info()?;
-    let ip_address = ip_info.ip.to_string();
-    info!("IP-Adresse: {}", ip_address);
+    // Per /reboot angeforderter Neustart, ausgeführt von der Hauptschleife nach der Antwort
+    let reboot_requested = Arc::new(AtomicBool::new(false));
 
-    // Initialisiere Display
-    // Hier muss die spezifische Initialisierung für das HTIT-WB32 Display erfolgen
-    // Zum Beispiel:
-    let display = initialize_display(peripherals.spi2, pins.gpio18, pins.gpio23, pins.gpio5)?;
+    // Zähler für /status
+    let metrics = Arc::new(Metrics::new(default_nvs.clone()));
+    let memory = Arc::new(MemoryMonitor::from_env());
+
+    // Akkuspannung über BATTERY_ADC_GPIO, ohne den Pin wird nicht gemessen
+    let battery = match BatteryConfig::from_env().map(Battery::start) {
+        Some(Ok(battery)) => {
+            battery.sample();
+            Some(Arc::new(battery))
+        }
+        Some(Err(err)) => {
+            warn!("Akkumessung nicht verfügbar: {:?}", err);
+            None
+        }
+        None => None,
+    };
+
+    // Temperatur und Feuchte über SENSOR_TYPE, ein defekter Sensor hält den Start nicht auf
+    #[cfg(feature = "sensor")]
+    let sensor = match sensor::SensorConfig::from_env().map(sensor::Sensor::start) {
+        Some(Ok(sensor)) => Some(sensor),
+        Some(Err(err)) => {
+            warn!("Sensor nicht verfügbar: {:?}", err);
+            None
+        }
+        None => None,
+    };
 
     // Log-Queue für die Anzeige
-    let log_queue: Arc<Mutex<Queue<String, 10>>> = Arc::new(Mutex::new(Queue::new()));
+    // Mit LOG_PERSIST=1 überstehen die Logs einen Neustart, ins NVS geht der Verlauf höchstens einmal
+    // pro Minute und vor einem geplanten Neustart
+    let log_history = (option_env!("LOG_PERSIST") == Some("1")).then(|| default_nvs.clone());
+    let log_queue = Arc::new(LogQueue::new(metrics.clone(), log_history));
+
+    // Optionaler Selbsttest mit SELF_TEST=1 für die Installation: das Relais klickt einmal kurz
+    if option_env!("SELF_TEST") == Some("1") {
+        match relay.self_test() {
+            Ok(Some(true)) => log_event(&log_queue, "self-test ok"),
+            Ok(Some(false)) => log_event(&log_queue, "self-test failed: no feedback"),
+            Ok(None) => log_event(&log_queue, "self-test done"),
+            Err(err) => log_event(&log_queue, &format!("self-test skipped: {}", err.message())),
+        }
+    }
+
+    // Gespeicherten Dauerbetrieb erst nach Sicherheitsabschaltung und Selbsttest wiederherstellen,
+    // die maximale Einschaltdauer gilt auch für ihn
+    let mut stored_latch = config::load_latched(&default_nvs)?;
+    let mut latch_restored = false;
+    if stored_latch && settings.get().restore_latch {
+        match relay.with_trigger(Trigger::System).latch(LatchAction::On) {
+            Ok(_) => {
+                latch_restored = true;
+                log_relay_event(&log_queue, Event::LatchRestored, "");
+            }
+            Err(err) => log_event(&log_queue, &format!("latch not restored: {}", err.message())),
+        }
+    }
+
+    // Optionale Benachrichtigung per Telegram, ohne Verbindung läuft der Rest normal weiter
+    let telegram = match config::load_telegram_config(&default_nvs)? {
+        Some(telegram_config) => match telegram::Notifier::start(telegram_config, &hostname) {
+            Ok(notifier) => Some(notifier),
+            Err(err) => {
+                warn!("Telegram konnte nicht gestartet werden: {:?}", err);
+                None
+            }
+        },
+        None => None,
+    };
+
+    // Optionale Weiterleitung aller Logeinträge an einen Syslog-Server
+    if let Some(syslog_config) = config::load_syslog_config(&default_nvs)? {
+        if let Err(err) = syslog::start(syslog_config, &hostname, &log_queue) {
+            warn!("Syslog konnte nicht gestartet werden: {:?}", err);
+        }
+    }
+
+    // Optionaler Webhook für jedes Relais-Ereignis, unabhängig von MQTT und Telegram
+    if let Some(webhook_config) = config::load_webhook_config(&default_nvs)? {
+        if let Err(err) = webhook::start(webhook_config, &hostname, &log_queue, relays.clone(), network.clone()) {
+            warn!("Webhook konnte nicht gestartet werden: {:?}", err);
+        }
+    }
+
+    // Taster löst denselben Puls aus wie /push, ein laufender Puls wird nicht doppelt geschaltet.
+    // Ein Doppelklick schaltet das Relais dauerhaft ein bzw. wieder aus wie /relay/toggle.
+    // Langes Halten setzt auf Werkseinstellungen zurück, das Display zählt dabei herunter.
+    #[cfg_attr(not(feature = "display"), allow(unused_variables))]
+    let button = {
+        let button_pin = board::button_pin!(pins);
+        assert_eq!(button_pin.pin(), board::BUTTON_GPIO);
+        let mut button_pin = PinDriver::input(button_pin)?;
+        button_pin.set_pull(Pull::Up)?;
+
+        let log_queue = log_queue.clone();
+        let relay = relay.with_trigger(Trigger::Button);
+        let metrics = metrics.clone();
+        let telegram = telegram.clone();
+        let pulse_settings = settings.clone();
+        let reset_nvs = default_nvs.clone();
+        button::spawn(button_pin, settings.clone(), move |press| match press {
+            Press::Single => {
+                let pulse = pulse_settings.pulse();
+                match relay.pulse(pulse) {
+                    Ok(()) => {
+                        metrics.record_push(pulse.as_millis() as u64);
+                        log_switch(&log_queue, Source::Button, "push");
+                        if let Some(telegram) = &telegram {
+                            telegram.notify_push(Msg::Button.text());
+                        }
+                    }
+                    Err(err) => log_source(&log_queue, Source::Button, err.status(), "push"),
+                }
+            }
+            Press::Double => match relay.latch(LatchAction::Toggle) {
+                Ok(true) => log_switch(&log_queue, Source::Button, "double-press on"),
+                Ok(false) => log_switch(&log_queue, Source::Button, "double-press off"),
+                Err(err) => log_source(&log_queue, Source::Button, err.status(), "double-press"),
+            },
+            Press::Hold => {
+                warn!("Taster gehalten, setze auf Werkseinstellungen zurück");
+                log_event(&log_queue, "factory reset");
+                if let Err(err) = config::factory_reset(&reset_nvs) {
+                    error!("Zurücksetzen fehlgeschlagen: {:?}", err);
+                    return;
+                }
+                unsafe { esp_idf_sys::esp_restart() };
+            }
+        })?
+    };
+
+    // MQTT nur mit konfiguriertem Broker, ohne Verbindung zum Broker läuft der Rest normal weiter
+    if let Some(mqtt_config) = config::load_mqtt_config(&default_nvs)? {
+        if let Err(err) = mqtt::start(
+            &mqtt_config,
+            &hostname,
+            settings.clone(),
+            relay.with_trigger(Trigger::Mqtt),
+            metrics.clone(),
+            log_queue.clone(),
+        ) {
+            warn!("MQTT konnte nicht gestartet werden: {:?}", err);
+        }
+    }
+
+    // CoAP mit denselben Ressourcen wie HTTP, nur mit dem coap-Feature
+    #[cfg(feature = "coap")]
+    coap::spawn(coap::Context {
+        relay: relay.with_trigger(Trigger::Coap),
+        settings: settings.clone(),
+        metrics: metrics.clone(),
+        log_queue: log_queue.clone(),
+        telegram: telegram.clone(),
+        api_token,
+        auth_limiter: auth_limiter.clone(),
+        allowlist: allowlist.clone(),
+    })?;
 
-    // HTTP-Server konfigurieren
-    let server_config = esp_idf_svc::http::server::Configuration::default();
+    // Geplante Pulse nach Uhrzeit, erst nach der SNTP-Synchronisation
+    let schedule = Arc::new(Schedule::load(default_nvs.clone()));
+    schedule::spawn(
+        schedule.clone(),
+        settings.clone(),
+        relay.with_trigger(Trigger::Schedule),
+        metrics.clone(),
+        log_queue.clone(),
+    )?;
+
+    // HTTP-Server konfigurieren, Verbindungen und Stack lassen sich für mehr Last anheben
+    let http_max_sockets = option_env!("HTTP_MAX_SOCKETS")
+        .and_then(|value| value.parse().ok())
+        .filter(|sockets| *sockets > 0)
+        .unwrap_or(DEFAULT_HTTP_MAX_SOCKETS);
+    let http_stack_size = option_env!("HTTP_STACK_SIZE")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_HTTP_STACK_SIZE);
+    let server_config = esp_idf_svc::http::server::Configuration {
+        http_port,
+        https_port: http_port,
+        max_open_sockets: http_max_sockets,
+        stack_size: http_stack_size,
+        server_certificate: tls_identity.map(|identity| identity.cert),
+        private_key: tls_identity.map(|identity| identity.key),
+        // Für den gemeinsamen OPTIONS-Handler auf /*
+        uri_match_wildcard: true,
+        // Standard sind 32, mit den Prüfpfaden des Captive Portals wird das knapp
+        max_uri_handlers: 48,
+        // Sind alle Sockets belegt, weicht die am längsten unbenutzte Keep-Alive-Verbindung
+        lru_purge_enable: true,
+        ..Default::default()
+    };
     let mut server = EspHttpServer::new(&server_config)?;
+    info!(
+        "{}-Server auf Port {}, höchstens {} Verbindungen",
+        if tls_enabled { "HTTPS" } else { "HTTP" },
+        http_port,
+        http_max_sockets
+    );
+    let server_handle = ServerHandle::new(&server, http_max_sockets);
+    // Alle Routen laufen über `routes`, daraus entstehen `Allow` und der CORS-Preflight
+    let routes = Routes::default();
+    keepalive::start(&server, http_max_sockets)?;
+    // /events und /ws/logs teilen sich MAX_STREAM_CLIENTS Plätze, damit /push immer durchkommt
+    streams::init(http_max_sockets);
 
-    // Endpunkt /health
+    // Bedienoberfläche, im AP-Modus Weiterleitung auf das Einrichtungsportal
     {
         let log_queue = log_queue.clone();
-        server.fn_handler("/health", embedded_svc::http::Method::Get, move |req| {
-            let response_body = r#"{ "status": "up" }"#;
-            let mut resp = req.into_ok_response()?;
-            resp.write_all(response_body.as_bytes())?;
+        let provisioning = provisioning.clone();
+        routes.handle(&mut server, "/", embedded_svc::http::Method::Get, move |mut req| {
+            let access = AccessLog::start(&mut req);
+            if provisioning.load(Ordering::SeqCst) {
+                req.into_response(302, None, &[("Location", "/setup")])?;
+                access.finish(&log_queue, 302, "/");
+                return Ok(());
+            }
+            if accepts_gzip(&req) {
+                let mut resp = req.into_response(
+                    200,
+                    None,
+                    &[
+                        ("Content-Type", "text/html; charset=utf-8"),
+                        ("Content-Encoding", "gzip"),
+                        ("Vary", "Accept-Encoding"),
+                    ],
+                )?;
+                resp.write_all(html::DASHBOARD_HTML_GZ)?;
+            } else {
+                let mut resp = req.into_response(
+                    200,
+                    None,
+                    &[("Content-Type", "text/html; charset=utf-8"), ("Vary", "Accept-Encoding")],
+                )?;
+                resp.write_all(html::DASHBOARD_HTML.as_bytes())?;
+            }
+            access.finish(&log_queue, 200, "/");
+            Ok(())
+        })?;
+    }
 
-            // Loggen
-            log_request(&log_queue, 200, "/health");
+    // Endpunkt /health als Readiness-Probe: 200 nur mit WLAN-Verbindung und laufendem
+    // Relais-Worker, sonst 503 mit dem ersten Grund, z.B. während eines Reconnects
+    {
+        let network = network.clone();
+        let relays = relays.clone();
+        let handler = json_handler(&log_queue, "/health", move |_req| {
+            let reason = if network.lock().unwrap().link != LinkState::Connected {
+                Some("wifi")
+            } else if !relays.iter().all(|named| named.relay.is_running()) {
+                Some("relay")
+            } else {
+                None
+            };
+            Ok(match reason {
+                None => JsonReply::ok(r#"{ "status": "up" }"#),
+                Some(reason) => JsonReply::new(503, format!(r#"{{ "status": "degraded", "reason": "{}" }}"#, reason)),
+            })
+        });
+        routes.handle(&mut server, "/health", embedded_svc::http::Method::Get, handler)?;
+    }
 
+    // Browser fragen von selbst nach /favicon.ico, ohne Icon gibt es 204 statt eines 404
+    {
+        let log_queue = log_queue.clone();
+        routes.handle(&mut server, "/favicon.ico", embedded_svc::http::Method::Get, move |mut req| {
+            let access = AccessLog::start(&mut req);
+            req.into_response(204, None, &[("Cache-Control", "max-age=604800")])?;
+            access.finish(&log_queue, 204, "/favicon.ico");
             Ok(())
         })?;
     }
 
-    // Endpunkt /push
+    // Endpunkt /status
+    {
+        let metrics = metrics.clone();
+        let network = network.clone();
+        let relay = relay.clone();
+        let relays = relays.clone();
+        let memory = memory.clone();
+        let battery = battery.clone();
+        #[cfg(feature = "sensor")]
+        let sensor = sensor.clone();
+        let hostname = hostname.clone();
+        let handler = json_handler(&log_queue, "/status", move |_req| {
+            let network = network.lock().unwrap().clone();
+            let relay_state = relay.state();
+            let cooldown = relay.cooldown_remaining();
+            #[cfg(feature = "sensor")]
+            let sensor_reading = serde_json::to_value(sensor.as_ref().and_then(|sensor| sensor.latest()))?;
+            #[cfg(not(feature = "sensor"))]
+            let sensor_reading = serde_json::Value::Null;
+            // Die mit MQTT und CoAP geteilten Kennzahlen, ergänzt um alles, was nur /status zeigt
+            let mut body = serde_json::to_value(Summary::collect(&metrics, &relay))?;
+            let extra = [
+                serde_json::json!({
+                    "device_name": hostname,
+                    "simulate": simulate,
+                    "wifi": network.link.as_str(),
+                    "ssid": network.ssid,
+                    "wifi_error": network.failure.map(|failure| failure.as_str()),
+                    "ip": network.ip,
+                    "ip6": network.ip6,
+                    "ip_mode": ip_mode,
+                    "not_found": metrics.not_found(),
+                }),
+                serde_json::json!({
+                    "relay_mismatch": relay_state.mismatch,
+                    "latch_restored": latch_restored,
+                    "relay_cooling_down": cooldown.is_some(),
+                    "relay_cooldown_ms": cooldown.map(|left| left.as_millis() as u64),
+                    "relay_queued": relay_state.queued,
+                    "reset_reason": system::reset_reason(),
+                    "boot_count": boot_count,
+                }),
+                serde_json::json!({
+                    "http_port": http_port,
+                    "open_connections": server_handle.open_connections(),
+                    "max_connections": http_max_sockets,
+                    "stream_clients": streams::active(),
+                    "max_stream_clients": streams::max_clients(),
+                    "wake_reason": system::wake_reason(),
+                    "power_mode": power::mode(),
+                }),
+                serde_json::json!({
+                    "min_free_heap": memory.min_free_heap(),
+                    "main_stack_free": memory.main_stack_free(),
+                    "low_memory": memory.is_low(),
+                    "battery_v": battery
+                        .as_ref()
+                        .and_then(|battery| battery.voltage())
+                        .map(|voltage| (voltage as f64 * 100.0).round() / 100.0),
+                    "rssi_stats": stats::rssi().to_json(),
+                    "free_heap_stats": stats::free_heap().to_json(),
+                }),
+                serde_json::json!({
+                    "sensor": sensor_reading,
+                    "activations": relay.activations(),
+                    "relays": relays_json(&relays),
+                    "endpoints": endpoints_json(&metrics),
+                    "chip": system::chip_info(),
+                }),
+            ];
+            if let Some(fields) = body.as_object_mut() {
+                for part in extra {
+                    if let serde_json::Value::Object(part) = part {
+                        fields.extend(part);
+                    }
+                }
+            }
+            let response_body = serde_json::to_string(&body)?;
+            Ok(JsonReply::ok(response_body))
+        });
+        routes.handle(&mut server, "/status", embedded_svc::http::Method::Get, handler)?;
+    }
+
+    // Endpunkt /logs, optional mit ?limit=N für die neuesten N Einträge und ?since=<Unix-Zeit>
+    // für alle Einträge ab diesem Zeitpunkt, mit LOG_PERSIST auch aus der Zeit vor dem Neustart
+    {
+        let logs = log_queue.clone();
+        let handler = json_handler(&log_queue, "/logs", move |req| {
+            let entries = select_logs(&logs, req.uri())?;
+            Ok(JsonReply::ok(serde_json::to_string(&entries)?))
+        });
+        routes.handle(&mut server, "/logs", embedded_svc::http::Method::Get, handler)?;
+    }
+
+    // Derselbe Ausschnitt als Textdatei zum Archivieren, eine Zeile pro Eintrag
     {
         let log_queue = log_queue.clone();
+        routes.handle(&mut server, "/logs.txt", embedded_svc::http::Method::Get, move |mut req| {
+            let access = AccessLog::start(&mut req);
+            let entries = match select_logs(&log_queue, req.uri()) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    respond_json(req, err.status(), &[], &err.body())?;
+                    access.finish(&log_queue, err.status(), "/logs.txt");
+                    return Ok(());
+                }
+            };
+            let mut body = String::new();
+            for entry in &entries {
+                body.push_str(&entry.text_line());
+                body.push('\n');
+            }
+            let mut resp = req.into_response(
+                200,
+                None,
+                &[
+                    ("Content-Type", "text/plain; charset=utf-8"),
+                    ("Content-Disposition", "attachment; filename=\"doofman-logs.txt\""),
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                ],
+            )?;
+            resp.write_all(body.as_bytes())?;
+            access.finish(&log_queue, 200, "/logs.txt");
+            Ok(())
+        })?;
+    }
+
+    // Endpunkt /push
+    //
+    // Standardmäßig kommt die Antwort, sobald der Puls angestoßen ist. Mit `?wait=true` erst, wenn
+    // er samt Abkühlzeit vorbei ist, dann mit `completed` (Ende innerhalb der Höchstdauer erkannt)
+    // und `mismatch` (Rückmeldung passte nicht). So lange bedient der Server keine anderen Requests.
+    {
+        let relay = relay.clone();
+        let settings = settings.clone();
+        let metrics = metrics.clone();
+        // Wiederholte Requests mit demselben Idempotency-Key schalten nur einmal
+        let idempotency = IdempotencyCache::new(
+            option_env!("IDEMPOTENCY_TTL_S")
+                .and_then(|value| value.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(idempotency::DEFAULT_TTL),
+        );
+        let telegram = telegram.clone();
+        let auth_limiter = auth_limiter.clone();
+        let allowlist = allowlist.clone();
+        let handler = json_handler(&log_queue, "/push", move |req| {
+            require_allowed(req, allowlist.as_ref())?;
+
+            // Nur mit gültigem Bearer-Token schalten
+            require_auth_limited(req, api_token, &auth_limiter)?;
+
+            let idempotency_key = req.header("Idempotency-Key").map(str::to_string);
+            if let Some(key) = &idempotency_key {
+                if key.is_empty() || key.len() > idempotency::MAX_KEY_LEN {
+                    return Err(HttpError::bad_request("invalid Idempotency-Key"));
+                }
+                // Schon erfolgreich geschaltet: ursprüngliche Antwort, aber kein zweiter Puls
+                if let Some((status, body)) = idempotency.get(key) {
+                    return Ok(JsonReply::new(status, body)
+                        .with_header("Idempotent-Replayed", "true".to_string())
+                        .log_as("/push replayed".to_string()));
+                }
+            }
+
+            // ?hold=true hält das Relais ohne automatisches Abfallen wie /relay/on, ?hold=false
+            // lässt es wieder los. Die Sicherheitsabschaltung nach der Höchstdauer greift auch hier.
+            if let Some(hold) = parse_hold(req.uri()).map_err(HttpError::bad_request)? {
+                relay.latch(if hold { LatchAction::On } else { LatchAction::Off })?;
+                if hold {
+                    if let Some(telegram) = &telegram {
+                        telegram.notify_push("/push?hold");
+                    }
+                }
+                let response_body = format!(r#"{{ "success": true, "held": {} }}"#, hold);
+                if let Some(key) = &idempotency_key {
+                    idempotency.insert(key, 200, &response_body);
+                }
+                let log_path = if hold { "/push hold" } else { "/push release" };
+                return Ok(JsonReply::ok(response_body).log_as(log_path.to_string()).switched());
+            }
+
+            // Pulsdauer aus ?ms=... lesen, ohne Parameter gilt die Einstellung aus /config
+            let pulse_ms = parse_pulse_ms(req.uri(), settings.get().pulse_ms).map_err(HttpError::bad_request)?;
+            let wait = parse_wait(req.uri()).map_err(HttpError::bad_request)?;
+
+            // Puls an den Relais-Worker übergeben, läuft bereits einer oder ist das Relais
+            // dauerhaft eingeschaltet, gibt es sofort 409, kommt er zu früh nach dem letzten 429.
+            // Mit PUSH_BUSY_POLICY=queue wird ein Puls hinter dem laufenden eingereiht (`queued`).
+            // Abgelehnte Pulse werden nicht gemerkt, eine Wiederholung darf es erneut versuchen.
+            // In der Abkühlzeit wird er angenommen und startet nach `deferred_ms`.
+            let queued = relay
+                .pulse_or_queue(Duration::from_millis(pulse_ms))
+                .inspect_err(|err| {
+                    if *err == RelayError::Busy {
+                        warn!("/push abgelehnt, es läuft bereits ein Puls");
+                    }
+                })?;
+            let deferred_ms = relay.cooldown_remaining().map(|left| left.as_millis() as u64);
+            metrics.record_push(pulse_ms);
+            if let Some(telegram) = &telegram {
+                telegram.notify_push("/push");
+            }
+
+            // Mit ?wait=true erst antworten, wenn das Relais wieder abgefallen ist. Der Server
+            // beantwortet solange keine anderen Requests, daher höchstens bis zur Höchstdauer.
+            let completed = if wait {
+                let completed = relay.wait_idle();
+                format!(r#", "completed": {}, "mismatch": {}"#, completed, relay.state().mismatch)
+            } else {
+                String::new()
+            };
+            let response_body = format!(
+                r#"{{ "success": true, "ms": {}, "deferred_ms": {}, "queued": {}{} }}"#,
+                pulse_ms,
+                serde_json::to_string(&deferred_ms)?,
+                queued,
+                completed
+            );
+            if let Some(key) = &idempotency_key {
+                idempotency.insert(key, 200, &response_body);
+            }
+            Ok(JsonReply::ok(response_body).log_as(format!("/push {}ms", pulse_ms)).switched())
+        });
+        routes.handle(&mut server, "/push", embedded_svc::http::Method::Post, handler)?;
+    }
+
+    // Endpunkt /release, Gegenstück zu /push?hold=true
+    {
         let relay = relay.clone();
-        server.fn_handler("/push", embedded_svc::http::Method::Get, move |req| {
-            // Relais für 500ms schließen
-            relay.set_high()?;
-            std::thread::sleep(Duration::from_millis(500));
-            relay.set_low()?;
-
-            let response_body = r#"{ "success": true }"#;
-            let mut resp = req.into_ok_response()?;
+        let auth_limiter = auth_limiter.clone();
+        let allowlist = allowlist.clone();
+        let handler = json_handler(&log_queue, "/release", move |req| {
+            require_allowed(req, allowlist.as_ref())?;
+            require_auth_limited(req, api_token, &auth_limiter)?;
+            relay.latch(LatchAction::Off)?;
+            Ok(JsonReply::ok(r#"{ "success": true, "held": false }"#).switched())
+        });
+        routes.handle(&mut server, "/release", embedded_svc::http::Method::Post, handler)?;
+    }
+
+    // Endpunkt /sequence für Pulsfolgen wie [{ "on_ms": 200, "off_ms": 300 }, ...]
+    {
+        let relay = relay.clone();
+        let auth_limiter = auth_limiter.clone();
+        let allowlist = allowlist.clone();
+        let handler = json_handler(&log_queue, "/sequence", move |req| {
+            require_allowed(req, allowlist.as_ref())?;
+            require_auth_limited(req, api_token, &auth_limiter)?;
+            let format = body_format(req)?;
+            let body = read_body(req)?;
+            let steps = parse_sequence(format, &body).map_err(HttpError::bad_request)?;
+            let count = steps.len();
+            // Wie bei /push: läuft schon ein Puls oder eine Folge, gibt es 409
+            relay.sequence(steps)?;
+            let response_body = format!(r#"{{ "success": true, "steps": {} }}"#, count);
+            Ok(JsonReply::ok(response_body).log_as(format!("/sequence {} steps", count)).switched())
+        });
+        routes.handle(&mut server, "/sequence", embedded_svc::http::Method::Post, handler)?;
+    }
+
+    // Dauerbetrieb über /relay/on, /relay/off und /relay/toggle, ohne automatisches Abschalten
+    for (path, action) in [
+        ("/relay/on", LatchAction::On),
+        ("/relay/off", LatchAction::Off),
+        ("/relay/toggle", LatchAction::Toggle),
+    ] {
+        let relay = relay.clone();
+        let auth_limiter = auth_limiter.clone();
+        let allowlist = allowlist.clone();
+        let handler = json_handler(&log_queue, path, move |req| {
+            require_allowed(req, allowlist.as_ref())?;
+            require_auth_limited(req, api_token, &auth_limiter)?;
+            let on = relay.latch(action)?;
+            Ok(JsonReply::ok(format!(r#"{{ "success": true, "latched": {} }}"#, on)).switched())
+        });
+        routes.handle(&mut server, path, embedded_svc::http::Method::Post, handler)?;
+    }
+
+    // Einzelne Relais über /relay/<name>/push, /relay/<name>/on, /relay/<name>/off und
+    // /relay/<name>/toggle. Nach den festen /relay/*-Pfaden registriert, die zuerst greifen.
+    {
+        let relays = relays.clone();
+        let settings = settings.clone();
+        let metrics = metrics.clone();
+        let telegram = telegram.clone();
+        let auth_limiter = auth_limiter.clone();
+        let allowlist = allowlist.clone();
+        let handler = json_handler(&log_queue, "/relay/*", move |req| {
+            require_allowed(req, allowlist.as_ref())?;
+            require_auth_limited(req, api_token, &auth_limiter)?;
+            let path = req.uri().split('?').next().unwrap_or_default();
+            let (name, action) = path
+                .strip_prefix("/relay/")
+                .and_then(|rest| rest.split_once('/'))
+                .ok_or_else(|| HttpError::new(404, "not found"))?;
+            let named = relays.get(name).ok_or_else(|| HttpError::new(404, "unknown relay"))?;
+            let latch = match action {
+                "push" => {
+                    let default_ms = named.pulse_ms.unwrap_or(settings.get().pulse_ms);
+                    let pulse_ms = parse_pulse_ms(req.uri(), default_ms).map_err(HttpError::bad_request)?;
+                    named.relay.pulse(Duration::from_millis(pulse_ms))?;
+                    metrics.record_push(pulse_ms);
+                    if let Some(telegram) = &telegram {
+                        telegram.notify_push(path);
+                    }
+                    let response_body = format!(r#"{{ "success": true, "relay": "{}", "ms": {} }}"#, name, pulse_ms);
+                    return Ok(JsonReply::ok(response_body).log_as(format!("{} {}ms", path, pulse_ms)).switched());
+                }
+                "on" => LatchAction::On,
+                "off" => LatchAction::Off,
+                "toggle" => LatchAction::Toggle,
+                _ => return Err(HttpError::new(404, "not found")),
+            };
+            let on = named.relay.latch(latch)?;
+            let response_body = format!(r#"{{ "success": true, "relay": "{}", "latched": {} }}"#, name, on);
+            Ok(JsonReply::ok(response_body).log_as(path.to_string()).switched())
+        });
+        routes.handle(&mut server, "/relay/*", embedded_svc::http::Method::Post, handler)?;
+    }
+
+    // Endpunkt /reboot, nur mit ?confirm=true, damit ein versehentlicher Aufruf nichts tut
+    {
+        let reboot_requested = reboot_requested.clone();
+        let auth_limiter = auth_limiter.clone();
+        let handler = json_handler(&log_queue, "/reboot", move |req| {
+            require_auth_limited(req, api_token, &auth_limiter)?;
+            if query_param(req.uri(), "confirm") != Some("true") {
+                return Err(HttpError::bad_request("confirm=true required"));
+            }
+            reboot_requested.store(true, Ordering::SeqCst);
+            Ok(JsonReply::ok(r#"{ "success": true, "rebooting": true }"#))
+        });
+        routes.handle(&mut server, "/reboot", embedded_svc::http::Method::Post, handler)?;
+    }
+
+    // Endpunkt /metrics im Prometheus-Format
+    {
+        let log_queue = log_queue.clone();
+        let metrics = metrics.clone();
+        let auth_limiter = auth_limiter.clone();
+        routes.handle(&mut server, "/metrics", embedded_svc::http::Method::Get, move |mut req| {
+            let access = AccessLog::start(&mut req);
+            if metrics_auth {
+                if let Err(err) = require_auth_limited(&mut req, api_token, &auth_limiter) {
+                    let status = err.status();
+                    err.respond(req)?;
+                    access.finish(&log_queue, status, "/metrics");
+                    return Ok(());
+                }
+            }
+
+            let response_body = metrics.prometheus();
+            let mut resp = req.into_response(
+                200,
+                None,
+                &[
+                    ("Content-Type", "text/plain; version=0.0.4"),
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                ],
+            )?;
             resp.write_all(response_body.as_bytes())?;
+            metrics.record_latency("/metrics", access.elapsed());
+            access.finish(&log_queue, 200, "/metrics");
+
+            Ok(())
+        })?;
+    }
+
+    // Endpunkt /brightness?pct=0..100, die Helligkeit bleibt auch nach einem Neustart. Ohne Display
+    // wird sie wie die Invertierung nur gespeichert, damit die API überall gleich bleibt.
+    {
+        #[cfg(feature = "display")]
+        let backlight = backlight.clone();
+        let settings = settings.clone();
+        let auth_limiter = auth_limiter.clone();
+        let handler = json_handler(&log_queue, "/brightness", move |req| {
+            require_auth_limited(req, api_token, &auth_limiter)?;
+            let pct = query_param(req.uri(), "pct")
+                .and_then(|value| value.parse::<u8>().ok())
+                .filter(|pct| *pct <= 100)
+                .ok_or_else(|| HttpError::bad_request("pct must be between 0 and 100"))?;
+
+            settings
+                .apply(SettingsUpdate {
+                    brightness: Some(pct),
+                    ..Default::default()
+                })
+                .map_err(HttpError::bad_request)?;
+            #[cfg(feature = "display")]
+            backlight.lock().unwrap().set_brightness(pct)?;
+            let response_body = format!(r#"{{ "brightness": {} }}"#, pct);
+            Ok(JsonReply::ok(response_body).log_as(format!("/brightness {}%", pct)))
+        });
+        routes.handle(&mut server, "/brightness", embedded_svc::http::Method::Post, handler)?;
+    }
+
+    // Endpunkt /display/invert?on=true|false, ohne Parameter wird umgeschaltet
+    {
+        let display_inverted = display_inverted.clone();
+        let default_nvs = default_nvs.clone();
+        let auth_limiter = auth_limiter.clone();
+        let handler = json_handler(&log_queue, "/display/invert", move |req| {
+            require_auth_limited(req, api_token, &auth_limiter)?;
+            let inverted = match query_param(req.uri(), "on") {
+                Some("true" | "1") => true,
+                Some("false" | "0") => false,
+                Some(_) => return Err(HttpError::bad_request("on must be true or false")),
+                None => !display_inverted.load(Ordering::SeqCst),
+            };
+            config::store_display_inverted(&default_nvs, inverted)?;
+            display_inverted.store(inverted, Ordering::SeqCst);
+            Ok(JsonReply::ok(format!(r#"{{ "inverted": {} }}"#, inverted)))
+        });
+        routes.handle(&mut server, "/display/invert", embedded_svc::http::Method::Post, handler)?;
+    }
+
+    // Endpunkt /status/reset setzt Minimum, Maximum und Mittel von RSSI und Heap zurück, nur mit Token
+    {
+        let auth_limiter = auth_limiter.clone();
+        let handler = json_handler(&log_queue, "/status/reset", move |req| {
+            require_auth_limited(req, api_token, &auth_limiter)?;
+            stats::reset();
+            Ok(JsonReply::ok(r#"{ "success": true }"#))
+        });
+        routes.handle(&mut server, "/status/reset", embedded_svc::http::Method::Post, handler)?;
+    }
+
+    // Endpunkt /metrics/reset, nur mit Token
+    {
+        let metrics = metrics.clone();
+        let auth_limiter = auth_limiter.clone();
+        let handler = json_handler(&log_queue, "/metrics/reset", move |req| {
+            require_auth_limited(req, api_token, &auth_limiter)?;
+            metrics.reset();
+            Ok(JsonReply::ok(r#"{ "success": true }"#))
+        });
+        routes.handle(&mut server, "/metrics/reset", embedded_svc::http::Method::Post, handler)?;
+    }
+
+    // Zeitplan unter /schedule als Liste von { "cron": "0 8 * * 1-5", "ms": 500 }
+    {
+        let schedule = schedule.clone();
+        let handler = json_handler(&log_queue, "/schedule", move |_req| {
+            Ok(JsonReply::ok(serde_json::to_string(&schedule.entries())?))
+        });
+        routes.handle(&mut server, "/schedule", embedded_svc::http::Method::Get, handler)?;
+    }
+    {
+        let schedule = schedule.clone();
+        let auth_limiter = auth_limiter.clone();
+        let handler = json_handler(&log_queue, "/schedule", move |req| {
+            require_auth_limited(req, api_token, &auth_limiter)?;
+            let body = read_body(req)?;
+            let entries: Vec<ScheduleEntry> = serde_json::from_slice(&body)
+                .map_err(|_| HttpError::bad_request("body must be a list of { cron, ms }"))?;
+            if entries.len() > schedule::MAX_ENTRIES {
+                return Err(HttpError::bad_request(format!(
+                    "at most {} entries allowed",
+                    schedule::MAX_ENTRIES
+                )));
+            }
+            let entries = entries
+                .into_iter()
+                .map(|entry| {
+                    if entry.ms.is_some_and(|ms| !(MIN_PULSE_MS..=MAX_PULSE_MS).contains(&ms)) {
+                        return Err(HttpError::bad_request(format!(
+                            "ms must be between {} and {}",
+                            MIN_PULSE_MS, MAX_PULSE_MS
+                        )));
+                    }
+                    let cron = Cron::parse(&entry.cron)
+                        .map_err(|err| HttpError::bad_request(format!("{}: {}", entry.cron, err)))?;
+                    Ok((entry, cron))
+                })
+                .collect::<Result<Vec<_>, HttpError>>()?;
 
-            // Loggen
-            log_request(&log_queue, 200, "/push");
+            schedule.replace(entries)?;
+            Ok(JsonReply::ok(serde_json::to_string(&schedule.entries())?))
+        });
+        routes.handle(&mut server, "/schedule", embedded_svc::http::Method::Put, handler)?;
+    }
 
+    // Einstellungen unter /config, Pulsdauer, Entprellzeit, Helligkeit und Takt wirken sofort.
+    // PUT nimmt JSON oder ein Formular, andere Content-Types bekommen 415.
+    {
+        let settings = settings.clone();
+        let handler = json_handler(&log_queue, "/config", move |_req| Ok(JsonReply::ok(settings_json(&settings)?)));
+        routes.handle(&mut server, "/config", embedded_svc::http::Method::Get, handler)?;
+    }
+    {
+        let settings = settings.clone();
+        #[cfg(feature = "display")]
+        let backlight = backlight.clone();
+        let auth_limiter = auth_limiter.clone();
+        let handler = json_handler(&log_queue, "/config", move |req| {
+            require_auth_limited(req, api_token, &auth_limiter)?;
+            let format = body_format(req)?;
+            let body = read_body(req)?;
+            // JSON oder Formular, unbekannte Felder lehnt serde ab, damit Tippfehler nicht still
+            // ignoriert werden
+            let update: SettingsUpdate = parse_body(format, &body)?;
+            #[cfg(feature = "display")]
+            let brightness = update.brightness;
+            settings.apply(update).map_err(HttpError::bad_request)?;
+            #[cfg(feature = "display")]
+            if let Some(pct) = brightness {
+                backlight.lock().unwrap().set_brightness(pct)?;
+            }
+            Ok(JsonReply::ok(settings_json(&settings)?).log_as("/config updated".to_string()))
+        });
+        routes.handle(&mut server, "/config", embedded_svc::http::Method::Put, handler)?;
+    }
+
+    // 405 für GET auf /push: das Relais löst ein physisches Schloss aus und schaltet nur per POST,
+    // damit Link-Vorschauen oder Crawler es nicht versehentlich schalten. Bewusst nicht über
+    // `routes`, sonst stünde GET in `Allow`.
+    {
+        let routes = routes.clone();
+        let handler = json_handler(&log_queue, "/push", move |_req| {
+            let allow = routes.allowed_methods("/push");
+            Err(HttpError::new(405, "method not allowed").with_header("Allow", allow))
+        });
+        server.fn_handler("/push", embedded_svc::http::Method::Get, handler)?;
+    }
+
+    // CORS-Preflight für alle über `routes` registrierten Pfade, damit Browser-Apps die API
+    // aufrufen dürfen. Die Liste wird erst beim Request gelesen, spätere Routen zählen also mit.
+    {
+        let log_queue = log_queue.clone();
+        let routes = routes.clone();
+        server.fn_handler("/*", embedded_svc::http::Method::Options, move |mut req| {
+            let access = AccessLog::start(&mut req);
+            let path = req.uri().split('?').next().unwrap_or_default().to_string();
+            let allow = routes.allowed_methods(&path);
+            if allow.is_empty() {
+                req.into_response(404, None, &[])?;
+                access.finish(&log_queue, 404, &path);
+                return Ok(());
+            }
+            let allow = format!("{}, OPTIONS", allow);
+            req.into_response(
+                204,
+                None,
+                &[
+                    ("Allow", allow.as_str()),
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                    ("Access-Control-Allow-Methods", allow.as_str()),
+                    ("Access-Control-Allow-Headers", api::CORS_ALLOW_HEADERS),
+                    ("Access-Control-Max-Age", "600"),
+                ],
+            )?;
+            access.finish(&log_queue, 204, &path);
             Ok(())
         })?;
     }
 
-    // 404 für alle anderen Pfade
+    // Ziel der internen Lebendprüfung, bewusst ohne Logeintrag und nicht in `routes`
+    server.fn_handler(watchdog::PROBE_PATH, embedded_svc::http::Method::Get, |req| {
+        req.into_ok_response()?.write_all(b"ok")?;
+        Ok(())
+    })?;
+
+    // Live-Logs per WebSocket
+    ws::register_log_stream(&mut server, &log_queue)?;
+    sse::register_event_stream(&mut server, &routes, &log_queue)?;
+    // Long-Polling auf neue Logeinträge und Schaltvorgänge des ersten Relais
+    poll::register_long_poll(&mut server, &routes, log_queue.clone(), relay.clone())?;
+
+    // Einrichtungsportal, nur im AP-Modus aktiv
     {
         let log_queue = log_queue.clone();
-        server.handler(move |req| {
+        let provisioning = provisioning.clone();
+        routes.handle(&mut server, "/setup", embedded_svc::http::Method::Get, move |mut req| {
+            let access = AccessLog::start(&mut req);
+            if !provisioning.load(Ordering::SeqCst) {
+                req.into_response(404, None, &[])?;
+                access.finish(&log_queue, 404, "/setup");
+                return Ok(());
+            }
+            let mut resp = req.into_response(200, None, &[("Content-Type", "text/html")])?;
+            resp.write_all(html::SETUP_HTML.as_bytes())?;
+            access.finish(&log_queue, 200, "/setup");
+            Ok(())
+        })?;
+    }
+    {
+        let log_queue = log_queue.clone();
+        let provisioning = provisioning.clone();
+        let default_nvs = default_nvs.clone();
+        routes.handle(&mut server, "/setup", embedded_svc::http::Method::Post, move |mut req| {
+            let access = AccessLog::start(&mut req);
+            if !provisioning.load(Ordering::SeqCst) {
+                req.into_response(404, None, &[])?;
+                access.finish(&log_queue, 404, "/setup");
+                return Ok(());
+            }
+
+            let body = match read_body(&mut req) {
+                Ok(body) => body,
+                Err(err) => {
+                    let status = err.status();
+                    req.into_response(status, None, &[])?;
+                    access.finish(&log_queue, status, "/setup");
+                    return Ok(());
+                }
+            };
+            let mut credentials = WifiConfig::default();
+            for (key, value) in form_pairs(&body) {
+                match key.as_str() {
+                    "ssid" => credentials.ssid = value,
+                    "password" => credentials.password = value,
+                    // Checkbox, fehlt im Formular, wenn sie nicht angehakt ist
+                    "hidden" => credentials.hidden = true,
+                    _ => {}
+                }
+            }
+            // Was der Treiber nicht nimmt, darf gar nicht erst ins NVS, sonst scheitert jeder Start
+            let invalid = if credentials.ssid.is_empty() {
+                Some("SSID fehlt".to_string())
+            } else if credentials.ssid.len() > wifi::MAX_SSID_LEN {
+                Some(format!("SSID zu lang, höchstens {} Byte", wifi::MAX_SSID_LEN))
+            } else if credentials.password.len() > wifi::MAX_PASSWORD_LEN {
+                Some(format!("Passwort zu lang, höchstens {} Byte", wifi::MAX_PASSWORD_LEN))
+            } else {
+                None
+            };
+            if let Some(message) = invalid {
+                let mut resp = req.into_response(400, None, &[("Content-Type", "text/html")])?;
+                resp.write_all(message.as_bytes())?;
+                access.finish(&log_queue, 400, "/setup");
+                return Ok(());
+            }
+
+            config::add_wifi_network(&default_nvs, &credentials)?;
+            let mut resp = req.into_response(200, None, &[("Content-Type", "text/html")])?;
+            resp.write_all("Gespeichert, verbinde mit dem WLAN...".as_bytes())?;
+            access.finish(&log_queue, 200, "/setup");
+
+            setup_tx.send(credentials)?;
+            Ok(())
+        })?;
+    }
+
+    // Prüfpfade der Betriebssysteme, im AP-Modus öffnet die Weiterleitung das Portal von selbst.
+    // Der DNS-Responder schickt ihre Hosts wie connectivitycheck.gstatic.com hierher.
+    for &path in dns::CAPTIVE_PROBES {
+        let log_queue = log_queue.clone();
+        let provisioning = provisioning.clone();
+        let network = network.clone();
+        routes.handle(&mut server, path, embedded_svc::http::Method::Get, move |mut req| {
+            let access = AccessLog::start(&mut req);
+            if !provisioning.load(Ordering::SeqCst) {
+                req.into_response(404, None, &[])?;
+                access.finish(&log_queue, 404, path);
+                return Ok(());
+            }
+            // Absolut, der Client hält sich noch für verbunden mit dem angefragten Host
+            let location = format!("{}setup", base_url(tls_enabled, &network.lock().unwrap().host(), http_port));
+            req.into_response(302, None, &[("Location", location.as_str()), ("Cache-Control", "no-store")])?;
+            access.finish(&log_queue, 302, path);
+            Ok(())
+        })?;
+    }
+
+    // 404 für alle anderen Pfade, im AP-Modus Weiterleitung auf das Portal
+    {
+        let log_queue = log_queue.clone();
+        let provisioning = provisioning.clone();
+        let network = network.clone();
+        server.handler(move |mut req| {
+            let access = AccessLog::start(&mut req);
             let path = req.path().to_string();
+            if provisioning.load(Ordering::SeqCst) {
+                let location = format!("{}setup", base_url(tls_enabled, &network.lock().unwrap().host(), http_port));
+                req.into_response(302, None, &[("Location", location.as_str())])?;
+                access.finish(&log_queue, 302, &path);
+                return Ok(());
+            }
             let resp = req.into_response(404, None, &[])?;
-            log_request(&log_queue, 404, &path);
+            access.finish(&log_queue, 404, &path);
             Ok(())
         })?;
     }
 
     // Hauptschleife zur Aktualisierung des Displays
+    let mut supervisor = wifi::Supervisor::new();
+
+    // Hauptschleife beim Task-Watchdog anmelden, der HTTP-Server wird separat geprüft
+    let mut twdt = TWDTDriver::new(
+        peripherals.twdt,
+        &TWDTConfig {
+            duration: WATCHDOG_TIMEOUT,
+            panic_on_trigger: true,
+            ..Default::default()
+        },
+    )?;
+    let mut main_watchdog = twdt.watch_current_task()?;
+    let mut http_probe = watchdog::HttpProbe::new(http_port, tls_enabled);
+
+    #[cfg(feature = "display")]
+    let blank_timeout = Duration::from_secs(
+        option_env!("BLANK_TIMEOUT_S")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_BLANK_TIMEOUT_S),
+    );
+    let sleep_config = power::SleepConfig::from_env(board::BUTTON_GPIO);
+    let power_save = power::PowerSave::from_env();
+    if let Some(power_save) = &power_save {
+        // Ohne Stromsparen läuft das Gerät genauso, nur mit dem Takt aus der sdkconfig
+        if let Err(err) = power_save.start() {
+            warn!("Stromsparen nicht eingerichtet: {:?}", err);
+        }
+    }
+    // Jeder neue Logeintrag weckt die Hauptschleife sofort statt erst zum nächsten Takt
+    let wakeup = Arc::new(Wakeup::new());
+    {
+        let wakeup = wakeup.clone();
+        log_queue.subscribe(move |_| wakeup.notify());
+    }
+    // Menü am Gerät über den Drehgeber, jede Eingabe weckt die Hauptschleife wie ein Logeintrag
+    #[cfg(feature = "encoder")]
+    let (mut menu, menu_inputs) = {
+        let (inputs, receiver) = mpsc::channel();
+        if let Some(encoder_config) = encoder::EncoderConfig::from_env() {
+            let wakeup = wakeup.clone();
+            let on_input = move |input: encoder::Input| {
+                let _ = inputs.send(input);
+                wakeup.notify();
+            };
+            if let Err(err) = encoder::spawn(encoder_config, on_input) {
+                warn!("Drehgeber nicht verfügbar: {:?}", err);
+            }
+        }
+        (menu::Menu::new(), receiver)
+    };
+    let mut last_memory_sample: Option<Instant> = None;
+    let mut last_service_start: Option<Instant> = None;
+    info!("Aufgewacht durch: {}", system::wake_reason());
     loop {
-        // Display aktualisieren
-        update_display(&display, &ip_address, &log_queue)?;
+        main_watchdog.feed()?;
+        http_probe.check();
+
+        // Ein anderer Thread ist abgestürzt, das Relais ist schon aus, also anzeigen und neu starten
+        if let Some(message) = crash::take() {
+            #[cfg(feature = "display")]
+            if let Err(err) = display.show_panic(&message) {
+                error!("PANIC-Bildschirm nicht gezeichnet: {:?}", err);
+            }
+            std::thread::sleep(crash::RESTART_DELAY);
+            unsafe { esp_idf_sys::esp_restart() };
+        }
 
-        std::thread::sleep(Duration::from_millis(1000));
+        // Die Antwort auf /reboot ist seit dem letzten Durchlauf raus, erst alle Relais abschalten
+        if reboot_requested.load(Ordering::SeqCst) {
+            warn!("Neustart per /reboot");
+            log_event(&log_queue, "reboot requested");
+            log_queue.flush_history();
+            relays.shutdown();
+            std::thread::sleep(REBOOT_DELAY);
+            unsafe { esp_idf_sys::esp_restart() };
+        }
+
+        // Ereignisse weiterer Relais tragen deren Namen, die des ersten bleiben wie bisher
+        for (index, named) in relays.iter().enumerate() {
+            let suffix = if index == 0 { String::new() } else { format!(" {}", named.name) };
+            if named.relay.take_cutoff() {
+                log_relay_event(&log_queue, Event::SafetyCutoff, &suffix);
+                if let Some(led) = &status_led {
+                    led.error();
+                }
+                if let Some(buzzer) = &buzzer {
+                    buzzer.error();
+                }
+            }
+            if named.relay.take_mismatch() {
+                log_relay_event(&log_queue, Event::RelayMismatch, &suffix);
+                if let Some(led) = &status_led {
+                    led.error();
+                }
+                if let Some(buzzer) = &buzzer {
+                    buzzer.error();
+                }
+            }
+        }
+        if relay.take_sequence_done() {
+            log_relay_event(&log_queue, Event::SequenceFinished, "");
+        }
+        // Dauerbetrieb bei jeder Änderung speichern, auch nach einer Sicherheitsabschaltung
+        let latched = relay.state().latched;
+        if latched != stored_latch && settings.get().restore_latch {
+            match config::store_latched(&default_nvs, latched) {
+                Ok(()) => stored_latch = latched,
+                Err(err) => warn!("Dauerbetrieb nicht gespeichert: {:?}", err),
+            }
+        }
+
+        // Speicher überwachen, bei kritisch wenig Heap lieber kontrolliert neu starten
+        if !last_memory_sample.is_some_and(|at| at.elapsed() < MEMORY_SAMPLE_INTERVAL) {
+            last_memory_sample = Some(Instant::now());
+            let was_low = memory.is_low();
+            let level = memory.sample();
+            stats::record(system::wifi_rssi(), memory.free_heap());
+            match level {
+                MemoryLevel::Critical => {
+                    error!("Kritisch wenig Speicher ({} Bytes), starte neu", memory.free_heap());
+                    log_event(&log_queue, "low memory reboot");
+                    log_queue.flush_history();
+                    unsafe { esp_idf_sys::esp_restart() };
+                }
+                MemoryLevel::Low if !was_low => {
+                    log_event(&log_queue, &format!("low memory {}B", memory.free_heap()));
+                }
+                _ => {}
+            }
+
+            // Akku mit derselben Rate messen, bei kritischer Spannung den LiPo schützen
+            if let Some(battery) = &battery {
+                let was_low = battery.level() != BatteryLevel::Normal;
+                let voltage = battery.sample().unwrap_or_default();
+                match battery.level() {
+                    BatteryLevel::Critical => {
+                        error!("Akku kritisch ({:.2} V), schalte ab", voltage);
+                        log_event(&log_queue, &format!("battery critical {:.2}V", voltage));
+                        // Auch ein Dauerbetrieb muss dafür enden, an allen Relais
+                        relays.all_off();
+                        if relays.prepare_sleep() {
+                            log_queue.flush_history();
+                            power::sleep(&power::SleepConfig {
+                                mode: power::SleepMode::Deep,
+                                idle: Duration::ZERO,
+                                wake_after: Some(battery::CRITICAL_RECHECK),
+                                wake_gpio: board::BUTTON_GPIO,
+                            });
+                        }
+                    }
+                    BatteryLevel::Low if !was_low => {
+                        log_event(&log_queue, &format!("low battery {:.2}V", voltage));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Neue Zugangsdaten aus dem Portal übernehmen, ein Fehler lässt das Portal offen
+        if let Ok(credentials) = setup_rx.try_recv() {
+            let result = wifi::connect(&mut wifi, &credentials, wifi::connect_timeout()).unwrap_or_else(|err| {
+                warn!("Zugangsdaten aus dem Portal nicht übernommen: {:?}", err);
+                Err(wifi::ConnectFailure::Timeout)
+            });
+            if result.is_ok() {
+                let ip = wifi.sta_netif().get_ip_info().map(|ip_info| ip_info.ip.to_string()).unwrap_or_default();
+                info!("IP-Adresse: {}", ip);
+                if let Err(err) = config::store_last_ssid(&default_nvs, &credentials.ssid) {
+                    warn!("Letztes WLAN nicht gespeichert: {:?}", err);
+                }
+                *network.lock().unwrap() = NetworkStatus {
+                    link: LinkState::Connected,
+                    ip,
+                    ip6: wifi::ipv6_addresses(&wifi),
+                    ssid: credentials.ssid,
+                    failure: None,
+                };
+                provisioning.store(false, Ordering::SeqCst);
+                drop(dns_responder.take());
+            } else {
+                network.lock().unwrap().failure = result.err();
+                if let Err(err) = wifi::start_access_point(&mut wifi) {
+                    warn!("Access Point nicht gestartet: {:?}", err);
+                }
+            }
+        }
+
+        // mDNS und SNTP nach der Einrichtung starten, fehlgeschlagenes wird später wiederholt
+        let services_missing = mdns.lock().unwrap().is_none() || sntp.is_none();
+        if services_missing
+            && !provisioning.load(Ordering::SeqCst)
+            && !last_service_start.is_some_and(|at| at.elapsed() < SERVICE_RETRY_INTERVAL)
+        {
+            last_service_start = Some(Instant::now());
+            let mut mdns = mdns.lock().unwrap();
+            if mdns.is_none() {
+                match Mdns::start(&hostname, http_port, tls_enabled) {
+                    Ok(started) => *mdns = Some(started),
+                    Err(err) => warn!("mDNS nicht gestartet: {:?}", err),
+                }
+            }
+            if sntp.is_none() {
+                match clock::start_sntp() {
+                    Ok(started) => sntp = Some(started),
+                    Err(err) => warn!("SNTP nicht gestartet: {:?}", err),
+                }
+            }
+        }
+
+        // Verbindung überwachen und nach einem Abbruch die neue IP übernehmen
+        if !provisioning.load(Ordering::SeqCst) {
+            let state = supervisor.check(&mut wifi);
+            let previous = std::mem::replace(&mut network.lock().unwrap().link, state);
+            // Globale IPv6-Adressen kommen erst nach dem Router Advertisement, also laufend nachsehen
+            if state == LinkState::Connected {
+                network.lock().unwrap().ip6 = wifi::ipv6_addresses(&wifi);
+            }
+            if previous != LinkState::Connected && state == LinkState::Connected {
+                if let Ok(ip_info) = wifi.sta_netif().get_ip_info() {
+                    info!("IP-Adresse: {}", ip_info.ip);
+                    network.lock().unwrap().ip = ip_info.ip.to_string();
+                }
+                if let Some(mdns) = mdns.lock().unwrap().as_mut() {
+                    if let Err(err) = mdns.announce() {
+                        warn!("mDNS-Ankündigung fehlgeschlagen: {:?}", err);
+                    }
+                }
+            }
+        }
+
+        // Im Batteriebetrieb nach Inaktivität schlafen, aber nie mit angezogenem Relais
+        let idle = log_queue.last_activity().elapsed();
+        if let Some(sleep_config) = &sleep_config {
+            if idle >= sleep_config.idle && relays.prepare_sleep() {
+                log_queue.flush_history();
+                power::sleep(sleep_config);
+                // Nur nach einem Light Sleep geht es hier weiter
+                relays.resume();
+                log_event(&log_queue, &format!("wake {}", system::wake_reason()));
+                continue;
+            }
+        }
+
+        // Im Dauerbetrieb nach Inaktivität Takt und WLAN drosseln, beim nächsten Request zurück
+        if let Some(power_save) = &power_save {
+            if let Err(err) = power_save.update(idle) {
+                warn!("Energiemodus nicht umgestellt: {:?}", err);
+            }
+        }
+
+        // Eingaben am Drehgeber, das Menü schaltet nur, was auch der Taster und /reboot können
+        #[cfg(feature = "encoder")]
+        let menu_open = {
+            for input in menu_inputs.try_iter() {
+                match menu.handle(input) {
+                    Some(menu::Action::Pulse) => {
+                        let pulse = settings.pulse();
+                        match relay.with_trigger(Trigger::Encoder).pulse(pulse) {
+                            Ok(()) => {
+                                metrics.record_push(pulse.as_millis() as u64);
+                                log_switch(&log_queue, Source::Encoder, "push");
+                            }
+                            Err(err) => log_source(&log_queue, Source::Encoder, err.status(), "push"),
+                        }
+                    }
+                    Some(menu::Action::Reboot) => reboot_requested.store(true, Ordering::SeqCst),
+                    None => {}
+                }
+            }
+            menu.expire();
+            menu.is_open()
+        };
+        #[cfg(all(feature = "display", not(feature = "encoder")))]
+        let menu_open = false;
+
+        // Nach Inaktivität Display abschalten, beim nächsten Request wieder wecken. Solange der
+        // Taster gehalten wird, bleibt es an, damit der Countdown zum Zurücksetzen sichtbar ist,
+        // und ebenso bei offenem Menü.
+        #[cfg(feature = "display")]
+        let held = button.held_for();
+        #[cfg(feature = "display")]
+        {
+            let mut backlight = backlight.lock().unwrap();
+            if !blank_timeout.is_zero() && idle >= blank_timeout && held.is_none() && !menu_open {
+                if !backlight.is_blanked() {
+                    info!("Display aus nach {}s ohne Request", idle.as_secs());
+                    if let Err(err) = backlight.blank() {
+                        warn!("Hintergrundbeleuchtung nicht abgeschaltet: {:?}", err);
+                    }
+                }
+                drop(backlight);
+                wakeup.wait(settings.tick());
+                continue;
+            }
+            if backlight.is_blanked() {
+                if let Err(err) = backlight.wake() {
+                    warn!("Hintergrundbeleuchtung nicht eingeschaltet: {:?}", err);
+                }
+                display.invalidate();
+            }
+        }
+
+        // Display aktualisieren, sofern sich etwas geändert hat
+        let status = network.lock().unwrap().clone();
+        if let Some(led) = &status_led {
+            led.set_state(led_state(status.link));
+        }
+        #[cfg(feature = "display")]
+        {
+            let mut header = Vec::new();
+            // Ganz oben, damit niemand vergisst, dass die Relais nicht wirklich schalten
+            if simulate {
+                header.push(Msg::Simulation.text().to_string());
+            }
+            // Ganz oben, damit die Warnung nicht zwischen den übrigen Zeilen untergeht
+            if let Some(battery) = battery.as_ref().filter(|battery| battery.level() != BatteryLevel::Normal) {
+                header.push(format!("{}: {:.2} V", Msg::BatteryLow.text(), battery.voltage().unwrap_or_default()));
+            }
+            header.push(format!("{}: {}", Msg::DeviceName.text(), hostname));
+            if status.ip != "0.0.0.0" {
+                header.push(format!("{}: {}", Msg::Ip.text(), status.ip));
+            }
+            if let Some(ip6) = status.ip6.first() {
+                header.push(format!("IPv6: {}", ip6));
+            }
+            #[cfg(feature = "sensor")]
+            if let Some(sensor) = &sensor {
+                header.push(sensor::Reading::display_line(sensor.latest()));
+            }
+            header.push(lang::wifi_status(&status.ssid, status.link));
+            // Im Einrichtungsmodus sagen, warum es mit dem gespeicherten Netz nicht geklappt hat
+            if let Some(failure) = status.failure.filter(|_| status.link == LinkState::AccessPoint) {
+                header.push(failure.message().to_string());
+            }
+            if metrics.uptime_secs() < BOOT_INFO_SECS {
+                header.push(format!("{} #{}: {}", Msg::Boot.text(), boot_count, system::reset_reason()));
+            }
+            if !clock::is_synced() {
+                header.push(Msg::ClockNotSynced.text().to_string());
+            }
+            if memory.is_low() {
+                header.push(format!("{}: {} KB", Msg::LowMemory.text(), memory.free_heap() / 1024));
+            }
+            if let Some(held) = held.filter(|held| *held >= FACTORY_RESET_HINT) {
+                let remaining = button::FACTORY_RESET_HOLD.saturating_sub(held);
+                header.push(lang::reset_in(remaining.as_secs() + 1));
+            }
+            header.push(format_uptime(metrics.uptime_secs()));
+            let relay_state = relay.state();
+            if let Some(last_pulse) = relay_state.last_pulse {
+                header.push(format_last_push(last_pulse.elapsed()));
+            }
+            // Das erste Relais zeigt die Statuszeile, weitere je eine Kopfzeile
+            for named in relays.iter().skip(1) {
+                let state = if named.relay.state().is_active() { Msg::On } else { Msg::Off };
+                header.push(format!("{}: {}", named.name, state.text()));
+            }
+            display.set_inverted(display_inverted.load(Ordering::SeqCst));
+            display.set_clock(clock::is_synced().then(|| clock::now().format("%H:%M").to_string()));
+            display.set_rssi(system::wifi_rssi());
+            // Erst kopieren, dann zeichnen: das langsame SPI-Zeichnen in `flush` läuft ohne Sperre
+            let logs = log_queue.snapshot();
+            // Das offene Menü ersetzt Kopf- und Logzeilen, Uhr und Relais-Zustand bleiben
+            #[cfg(feature = "encoder")]
+            let (header, logs) = if menu_open {
+                (menu.lines(&logs, &menu_config_lines(&settings.get(), &status)), Vec::new())
+            } else {
+                (header, logs)
+            };
+            display.show_ip(&header, &base_url(tls_enabled, &status.host(), http_port));
+            display.set_relay_state(relay_state);
+            display.push_log(&logs);
+            // Ein Fehler beim Zeichnen darf die Schleife nie beenden, Relais und Server laufen weiter
+            if let Err(err) = display.flush() {
+                warn!("Display nicht gezeichnet: {:?}", err);
+            }
+        }
+
+        // Bis zum nächsten Logeintrag oder Takt warten, ein Puls endet ohne Logeintrag. Ein schneller
+        // Takt kostet kaum SPI-Verkehr, `flush` zeichnet nur, was sich geändert hat.
+        let tick = if relays.iter().any(|named| named.relay.state().is_active()) {
+            ACTIVE_TICK.min(settings.tick())
+        } else {
+            settings.tick()
+        };
+        wakeup.wait(tick);
     }
 }
 
-fn log_request(log_queue: &Arc<Mutex<Queue<String, 10>>>, status: u16, path: &str) {
-    let timestamp = SystemTime::now();
-    let datetime: chrono::DateTime<chrono::Local> = timestamp.into();
-    let log_entry = format!("{} {} {}", datetime.format("%H:%M:%S"), status, path);
+/// Farbe der Status-LED zur WLAN-Verbindung, das Einrichtungsportal zählt als Verbindungsaufbau.
+fn led_state(link: LinkState) -> LedState {
+    match link {
+        LinkState::Connected => LedState::Connected,
+        LinkState::Reconnecting => LedState::Disconnected,
+        LinkState::AccessPoint => LedState::Connecting,
+    }
+}
+
+/// Einstellungen für das Menü am Gerät, mit den Schlüsseln aus `/config`.
+#[cfg(feature = "encoder")]
+fn menu_config_lines(settings: &DeviceSettings, status: &NetworkStatus) -> Vec<String> {
+    vec![
+        format!("hostname: {}", settings.hostname),
+        format!("ip: {}", status.ip),
+        format!("ssid: {}", status.ssid),
+        format!("pulse_ms: {}", settings.pulse_ms),
+        format!("debounce_ms: {}", settings.debounce_ms),
+        format!("tick_ms: {}", settings.tick_ms),
+        format!("brightness: {}", settings.brightness),
+        format!("restore_latch: {}", settings.restore_latch),
+        format!("firmware: {}", env!("CARGO_PKG_VERSION")),
+    ]
+}
 
-    let mut queue = log_queue.lock().unwrap();
-    if queue.is_full() {
-        queue.dequeue();
+/// Laufzeit als `Laufzeit: 2d 03:04:05`.
+#[cfg(feature = "display")]
+fn format_uptime(secs: u64) -> String {
+    format!(
+        "{}: {}d {:02}:{:02}:{:02}",
+        Msg::Uptime.text(),
+        secs / 86_400,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Zeitpunkt der letzten Auslösung, egal ob per HTTP, Taster, MQTT oder Zeitplan.
+///
+/// Mit synchronisierter Uhr als Uhrzeit, sonst als Abstand zu jetzt.
+#[cfg(feature = "display")]
+fn format_last_push(ago: Duration) -> String {
+    if clock::is_synced() {
+        let at = clock::now() - chrono::Duration::from_std(ago).unwrap_or_else(|_| chrono::Duration::zero());
+        format!("{}: {}", Msg::LastPush.text(), at.format(clock::time_format()))
+    } else {
+        format!("{}: {}", Msg::LastPush.text(), lang::ago(ago.as_secs()))
     }
-    queue.enqueue(log_entry).unwrap();
 }
 
-fn initialize_display(
-    spi: impl embedded_hal::spi::FullDuplex<u8> + 'static,
-    dc: impl OutputPin + 'static,
-    rst: impl OutputPin + 'static,
-    bl: impl OutputPin + 'static,
-) -> Result<st7789::ST7789<SpiInterfaceNoCS<SPI, DC>, RST>> {
-    // Display initialisieren (angepasst an das HTIT-WB32 Modell)
-    // Dieser Code muss an die spezifische Hardware angepasst werden
-
-    let interface = SPIInterfaceNoCS::new(spi, dc);
-    let mut display = st7789::ST7789::new(
-        interface,
-        rst,
-        240, // Breite
-        320, // Höhe
-    );
+/// Adresse der Bedienoberfläche, der Standardport 80 bzw. 443 bei HTTPS wird weggelassen.
+fn base_url(tls: bool, ip: &str, port: u16) -> String {
+    let (scheme, default_port) = if tls { ("https", 443) } else { ("http", 80) };
+    if port == default_port {
+        format!("{}://{}/", scheme, ip)
+    } else {
+        format!("{}://{}:{}/", scheme, ip, port)
+    }
+}
+
+/// Einträge für `/logs` und `/logs.txt` nach `?limit=N` und `?since=<Unix-Zeit>` aus `uri`.
+fn select_logs(logs: &LogQueue, uri: &str) -> Result<Vec<LogEntry>, HttpError> {
+    let limit = query_param(uri, "limit").and_then(|value| value.parse::<usize>().ok());
+    let since = query_param(uri, "since")
+        .map(|value| {
+            value
+                .parse::<i64>()
+                .map_err(|_| HttpError::bad_request("since must be a unix timestamp"))
+        })
+        .transpose()?;
+    let mut entries = match since {
+        Some(since) => logs.since(since),
+        None => logs.snapshot(),
+    };
+    let skip = limit.map_or(0, |limit| entries.len().saturating_sub(limit));
+    entries.drain(..skip);
+    Ok(entries)
+}
 
-    display.init(&mut Delay)?;
-    display.set_orientation(Orientation::Portrait)?;
+/// Liest einen Query-Parameter aus einer URI wie `/push?ms=1500`.
+fn query_param<'a>(uri: &'a str, key: &str) -> Option<&'a str> {
+    let (_, query) = uri.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
 
-    Ok(display)
+/// Einstellungen für `/config`, dazu die Felder, die erst nach einem Neustart wirken.
+fn settings_json(settings: &Settings) -> Result<String> {
+    let mut json = serde_json::to_value(settings.get())?;
+    json["reboot_required"] = serde_json::json!(settings.reboot_required());
+    Ok(json.to_string())
 }
 
-fn update_display(
-    display: &impl DrawTarget<Color = Rgb565>,
-    ip_address: &str,
-    log_queue: &Arc<Mutex<Queue<String, 10>>>,
-) -> Result<()> {
-    // Bildschirm löschen
-    display.clear(Rgb565::BLACK)?;
+/// Statistik je Pfad für `/status`, z.B. `{ "/push": { "count": 3, "avg_ms": 12.5 }, ... }`.
+fn endpoints_json(metrics: &Metrics) -> serde_json::Value {
+    metrics
+        .endpoints()
+        .map(|(path, count, average_ms)| {
+            let stats = serde_json::json!({ "count": count, "avg_ms": (average_ms * 100.0).round() / 100.0 });
+            (path.to_string(), stats)
+        })
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
 
-    // WLAN-Status und IP-Adresse anzeigen
-    let text_style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
-    Text::new(&format!("IP: {}", ip_address), Point::new(0, 10), text_style)
-        .draw(display)?;
+/// Zustand aller Relais für `/status`, in der Reihenfolge aus `RELAYS` nach dem ersten.
+fn relays_json(relays: &RelaySet) -> serde_json::Value {
+    relays
+        .iter()
+        .map(|named| {
+            let state = named.relay.state();
+            serde_json::json!({
+                "name": named.name,
+                "active": state.is_active(),
+                "latched": state.latched,
+                "mismatch": state.mismatch,
+                "cooldown_ms": named.relay.cooldown_remaining().map(|left| left.as_millis() as u64),
+                "pulse_ms": named.pulse_ms,
+                "activations": named.relay.activations(),
+            })
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
 
-    // Logs anzeigen
-    let queue = log_queue.lock().unwrap();
-    let mut y = 30;
-    for log_entry in queue.iter() {
-        Text::new(log_entry, Point::new(0, y), text_style).draw(display)?;
-        y += 12;
+/// Liest `?hold=true` bzw. `?hold=false` für `/push`, `None` ohne den Parameter.
+fn parse_hold(uri: &str) -> Result<Option<bool>, &'static str> {
+    match query_param(uri, "hold") {
+        None => Ok(None),
+        Some("true" | "1") => Ok(Some(true)),
+        Some("false" | "0") => Ok(Some(false)),
+        Some(_) => Err("hold must be true or false"),
+    }
+}
+
+/// Liest `?wait=true` für `/push`: ohne den Parameter kommt die Antwort sofort nach dem Start des
+/// Pulses, mit `true` erst nach seinem Ende samt Abkühlzeit und Prüfung der Rückmeldung.
+fn parse_wait(uri: &str) -> Result<bool, &'static str> {
+    match query_param(uri, "wait") {
+        None | Some("false" | "0") => Ok(false),
+        Some("true" | "1") => Ok(true),
+        Some(_) => Err("wait must be true or false"),
+    }
+}
+
+/// Ermittelt die Pulsdauer für `/push`. Fehlt `ms`, wird `default_ms` verwendet.
+fn parse_pulse_ms(uri: &str, default_ms: u64) -> Result<u64, String> {
+    let Some(value) = query_param(uri, "ms") else {
+        return Ok(default_ms);
+    };
+    let ms: u64 = value.parse().map_err(|_| "ms must be a number".to_string())?;
+    if !(MIN_PULSE_MS..=MAX_PULSE_MS).contains(&ms) {
+        return Err(format!("ms must be between {} and {}", MIN_PULSE_MS, MAX_PULSE_MS));
+    }
+    Ok(ms)
+}
+
+/// Liest die Schritte für `/sequence` aus einem JSON-Array von `{ "on_ms", "off_ms" }` oder einem
+/// Formular mit wiederholten `on_ms`/`off_ms`.
+fn parse_sequence(format: BodyFormat, body: &[u8]) -> Result<Vec<SequenceStep>, String> {
+    #[derive(serde::Deserialize)]
+    struct Step {
+        on_ms: u64,
+        #[serde(default)]
+        off_ms: u64,
     }
 
-    Ok(())
+    let steps: Vec<Step> = match format {
+        BodyFormat::Json => {
+            serde_json::from_slice(body).map_err(|_| "body must be a list of { on_ms, off_ms }".to_string())?
+        }
+        // Als Formular beginnt jedes on_ms einen Schritt, ein folgendes off_ms gehört dazu:
+        // on_ms=200&off_ms=300&on_ms=200
+        BodyFormat::Form => {
+            let mut steps: Vec<Step> = Vec::new();
+            for (key, value) in form_pairs(body) {
+                let ms = value.parse().map_err(|_| format!("{} must be a number", key))?;
+                match (key.as_str(), steps.last_mut()) {
+                    ("on_ms", _) => steps.push(Step { on_ms: ms, off_ms: 0 }),
+                    ("off_ms", Some(step)) => step.off_ms = ms,
+                    ("off_ms", None) => return Err("off_ms must follow an on_ms".to_string()),
+                    _ => return Err(format!("unknown field {}", key)),
+                }
+            }
+            steps
+        }
+    };
+    if steps.is_empty() || steps.len() > MAX_SEQUENCE_STEPS {
+        return Err(format!("sequence must have 1 to {} steps", MAX_SEQUENCE_STEPS));
+    }
+    if steps.iter().any(|step| !(MIN_PULSE_MS..=MAX_PULSE_MS).contains(&step.on_ms)) {
+        return Err(format!("on_ms must be between {} and {}", MIN_PULSE_MS, MAX_PULSE_MS));
+    }
+    let total = steps
+        .iter()
+        .fold(0u64, |total, step| total.saturating_add(step.on_ms).saturating_add(step.off_ms));
+    if total > MAX_SEQUENCE_MS {
+        return Err(format!("sequence must not take longer than {}ms", MAX_SEQUENCE_MS));
+    }
+    Ok(steps
+        .into_iter()
+        .map(|step| SequenceStep {
+            on: Duration::from_millis(step.on_ms),
+            off: Duration::from_millis(step.off_ms),
+        })
+        .collect())
 }
@@ -0,0 +1,54 @@
+//! Seltene Wartung für lange laufende Geräte: mDNS neu ankündigen und SNTP neu anstoßen.
+//!
+//! Nach einigen Tagen Laufzeit verschwindet der mDNS-Eintrag manchmal aus den Caches im Netz
+//! und die Uhr läuft weg, wenn eine Synchronisation verloren ging. Ein eigener Thread kündigt
+//! deshalb alle `MAINTENANCE_INTERVAL_S` Sekunden (Standard eine Stunde, `0` schaltet ab) den
+//! Dienst neu an und stößt SNTP an. Handler und Display warten so nie darauf.
+
+use anyhow::Result;
+use log::*;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::clock;
+use crate::mdns::Mdns;
+use crate::wifi::{LinkState, NetworkStatus};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Abstand aus `MAINTENANCE_INTERVAL_S`, `None` wenn abgeschaltet.
+fn interval() -> Option<Duration> {
+    let interval = option_env!("MAINTENANCE_INTERVAL_S")
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_INTERVAL);
+    Some(interval).filter(|interval| !interval.is_zero())
+}
+
+/// Startet den Wartungs-Thread. Ohne WLAN-Verbindung, etwa im Einrichtungsportal, wird die
+/// Runde übersprungen.
+pub fn spawn(mdns: Arc<Mutex<Option<Mdns>>>, network: Arc<Mutex<NetworkStatus>>) -> Result<()> {
+    let Some(interval) = interval() else {
+        info!("Wartung von mDNS und SNTP abgeschaltet");
+        return Ok(());
+    };
+    std::thread::Builder::new()
+        .name("maintenance".into())
+        .stack_size(4096)
+        .spawn(move || loop {
+            std::thread::sleep(interval);
+            if network.lock().unwrap().link != LinkState::Connected {
+                continue;
+            }
+            if let Some(mdns) = mdns.lock().unwrap().as_mut() {
+                match mdns.announce() {
+                    Ok(()) => info!("Wartung: mDNS neu angekündigt"),
+                    Err(err) => warn!("Wartung: mDNS-Ankündigung fehlgeschlagen: {:?}", err),
+                }
+            }
+            if clock::resync_sntp() {
+                info!("Wartung: SNTP-Synchronisation angestoßen");
+            }
+        })?;
+    Ok(())
+}
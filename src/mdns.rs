@@ -0,0 +1,36 @@
+use anyhow::Result;
+use esp_idf_svc::mdns::EspMdns;
+use log::*;
+
+/// Meldet das Gerät als `<hostname>.local` mit seinem HTTP- bzw. HTTPS-Dienst im Netz an.
+pub struct Mdns {
+    mdns: EspMdns,
+    hostname: String,
+    port: u16,
+    tls: bool,
+}
+
+impl Mdns {
+    pub fn start(hostname: &str, port: u16, tls: bool) -> Result<Self> {
+        let mut mdns = Self {
+            mdns: EspMdns::take()?,
+            hostname: hostname.to_string(),
+            port,
+            tls,
+        };
+        mdns.announce()?;
+        Ok(mdns)
+    }
+
+    /// Setzt Hostname und Dienst neu, z.B. nach einem Reconnect.
+    pub fn announce(&mut self) -> Result<()> {
+        self.mdns.set_hostname(&self.hostname)?;
+        self.mdns.set_instance_name(&self.hostname)?;
+        self.mdns.remove_services()?;
+        let (service, scheme) = if self.tls { ("_https", "https") } else { ("_http", "http") };
+        self.mdns
+            .add_service(Some(&self.hostname), service, "_tcp", self.port, &[])?;
+        info!("mDNS: {}://{}.local:{}/", scheme, self.hostname, self.port);
+        Ok(())
+    }
+}
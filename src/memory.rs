@@ -0,0 +1,86 @@
+use log::*;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::system;
+
+/// Warnschwelle für den freien Heap ohne `HEAP_WARN_BYTES`.
+pub const DEFAULT_WARN_BYTES: u32 = 20_000;
+
+/// Ergebnis einer Messung.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryLevel {
+    Ok,
+    /// Unter `HEAP_WARN_BYTES`
+    Low,
+    /// Unter `HEAP_REBOOT_BYTES`, ein kontrollierter Neustart ist fällig
+    Critical,
+}
+
+/// Beobachtet freien Heap und Stack der Hauptschleife, um Lecks und Fragmentierung nach
+/// längerer Laufzeit zu erkennen.
+///
+/// Gemessen wird aus der Hauptschleife heraus, die Handler lesen nur die letzten Werte.
+pub struct MemoryMonitor {
+    free_heap: AtomicU32,
+    min_free_heap: AtomicU32,
+    main_stack_free: AtomicU32,
+    low: AtomicBool,
+    warn_below: u32,
+    reboot_below: Option<u32>,
+}
+
+impl MemoryMonitor {
+    /// Schwellen aus `HEAP_WARN_BYTES` und `HEAP_REBOOT_BYTES`, ohne Letztere kein Neustart.
+    pub fn from_env() -> Self {
+        let parse = |value: Option<&str>| value.and_then(|value| value.parse::<u32>().ok());
+        Self {
+            free_heap: AtomicU32::new(system::free_heap()),
+            min_free_heap: AtomicU32::new(system::min_free_heap()),
+            main_stack_free: AtomicU32::new(0),
+            low: AtomicBool::new(false),
+            warn_below: parse(option_env!("HEAP_WARN_BYTES")).unwrap_or(DEFAULT_WARN_BYTES),
+            reboot_below: parse(option_env!("HEAP_REBOOT_BYTES")).filter(|bytes| *bytes > 0),
+        }
+    }
+
+    /// Misst neu, muss aus der Hauptschleife aufgerufen werden, deren Stack gemessen wird.
+    pub fn sample(&self) -> MemoryLevel {
+        let free_heap = system::free_heap();
+        self.free_heap.store(free_heap, Ordering::Relaxed);
+        self.min_free_heap.store(system::min_free_heap(), Ordering::Relaxed);
+        self.main_stack_free.store(system::current_task_stack_free(), Ordering::Relaxed);
+
+        let level = match self.reboot_below {
+            Some(reboot_below) if free_heap < reboot_below => MemoryLevel::Critical,
+            _ if free_heap < self.warn_below => MemoryLevel::Low,
+            _ => MemoryLevel::Ok,
+        };
+        let low = level != MemoryLevel::Ok;
+        if self.low.swap(low, Ordering::Relaxed) != low {
+            if low {
+                warn!("Wenig Speicher: {} Bytes Heap frei", free_heap);
+            } else {
+                info!("Speicher wieder ausreichend: {} Bytes Heap frei", free_heap);
+            }
+        }
+        level
+    }
+
+    pub fn free_heap(&self) -> u32 {
+        self.free_heap.load(Ordering::Relaxed)
+    }
+
+    /// Kleinster freier Heap seit dem Start.
+    pub fn min_free_heap(&self) -> u32 {
+        self.min_free_heap.load(Ordering::Relaxed)
+    }
+
+    /// Ungenutzter Stack der Hauptschleife in Bytes, seit dem Start nie unterschritten.
+    pub fn main_stack_free(&self) -> u32 {
+        self.main_stack_free.load(Ordering::Relaxed)
+    }
+
+    pub fn is_low(&self) -> bool {
+        self.low.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,173 @@
+//! Menü am Gerät für den Drehgeber, nur mit dem Cargo-Feature `encoder`.
+//!
+//! Ein Druck öffnet das Menü, Drehen wählt, Drücken bestätigt: Relais auslösen, Logs ansehen,
+//! Einstellungen ansehen oder nach einer Rückfrage neu starten. In Logs und Einstellungen blättert
+//! Drehen, Drücken geht zurück. Ohne Eingabe schließt das Menü nach [`TIMEOUT`] von selbst.
+//!
+//! Gezeichnet wird über die Kopfzeilen der Hauptschleife, das Display braucht dafür nichts Neues.
+
+use std::time::{Duration, Instant};
+
+use crate::encoder::Input;
+use crate::lang::Msg;
+use crate::logs::LogEntry;
+
+/// So lange bleibt das Menü ohne Eingabe offen.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Zeilen je Seite in Logs und Einstellungen, passt auch mit großer Schrift.
+const PAGE_LINES: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Item {
+    Trigger,
+    Logs,
+    Config,
+    Reboot,
+    Close,
+}
+
+const ITEMS: [Item; 5] = [Item::Trigger, Item::Logs, Item::Config, Item::Reboot, Item::Close];
+
+impl Item {
+    fn label(self) -> &'static str {
+        match self {
+            Item::Trigger => Msg::MenuTrigger.text(),
+            Item::Logs => Msg::MenuLogs.text(),
+            Item::Config => Msg::MenuConfig.text(),
+            Item::Reboot => Msg::MenuReboot.text(),
+            Item::Close => Msg::MenuClose.text(),
+        }
+    }
+
+    fn index(self) -> usize {
+        ITEMS.iter().position(|item| *item == self).unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum View {
+    Main { selected: usize },
+    Logs { offset: usize },
+    Config { offset: usize },
+    ConfirmReboot { yes: bool },
+}
+
+/// Was die Hauptschleife nach einer Eingabe tun soll.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Pulse,
+    Reboot,
+}
+
+pub struct Menu {
+    view: Option<View>,
+    last_input: Instant,
+    /// Zeilen der zuletzt gezeigten Liste, begrenzt das Blättern
+    content_len: usize,
+}
+
+impl Menu {
+    pub fn new() -> Self {
+        Self {
+            view: None,
+            last_input: Instant::now(),
+            content_len: 0,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.view.is_some()
+    }
+
+    /// Schließt das Menü nach [`TIMEOUT`] ohne Eingabe.
+    pub fn expire(&mut self) {
+        if self.last_input.elapsed() >= TIMEOUT {
+            self.view = None;
+        }
+    }
+
+    /// Verarbeitet eine Eingabe, ein geschlossenes Menü öffnet sich dabei nur.
+    pub fn handle(&mut self, input: Input) -> Option<Action> {
+        self.last_input = Instant::now();
+        let Some(view) = self.view else {
+            self.view = Some(View::Main { selected: 0 });
+            return None;
+        };
+        let max_offset = self.content_len.saturating_sub(PAGE_LINES);
+        self.view = match (view, input) {
+            (View::Main { selected }, Input::Turn(step)) => Some(View::Main {
+                selected: (selected as isize + step as isize).rem_euclid(ITEMS.len() as isize) as usize,
+            }),
+            (View::Main { selected }, Input::Press) => match ITEMS[selected] {
+                Item::Trigger => return Some(Action::Pulse),
+                Item::Logs => Some(View::Logs { offset: 0 }),
+                Item::Config => Some(View::Config { offset: 0 }),
+                Item::Reboot => Some(View::ConfirmReboot { yes: false }),
+                Item::Close => None,
+            },
+            (View::Logs { offset }, Input::Turn(step)) => Some(View::Logs {
+                offset: scroll(offset, step, max_offset),
+            }),
+            (View::Config { offset }, Input::Turn(step)) => Some(View::Config {
+                offset: scroll(offset, step, max_offset),
+            }),
+            (View::Logs { .. }, Input::Press) => Some(View::Main {
+                selected: Item::Logs.index(),
+            }),
+            (View::Config { .. }, Input::Press) => Some(View::Main {
+                selected: Item::Config.index(),
+            }),
+            (View::ConfirmReboot { yes }, Input::Turn(_)) => Some(View::ConfirmReboot { yes: !yes }),
+            (View::ConfirmReboot { yes: true }, Input::Press) => {
+                self.view = None;
+                return Some(Action::Reboot);
+            }
+            (View::ConfirmReboot { yes: false }, Input::Press) => Some(View::Main {
+                selected: Item::Reboot.index(),
+            }),
+        };
+        None
+    }
+
+    /// Kopfzeilen für das Display, `logs` älteste zuerst wie in der Log-Queue, `config` fertige
+    /// Zeilen der Einstellungen. Leer, solange das Menü zu ist.
+    pub fn lines(&mut self, logs: &[LogEntry], config: &[String]) -> Vec<String> {
+        let Some(view) = self.view else {
+            return Vec::new();
+        };
+        match view {
+            View::Main { selected } => {
+                let mut lines = vec![Msg::MenuTitle.text().to_string()];
+                lines.extend(ITEMS.iter().enumerate().map(|(index, item)| {
+                    let marker = if index == selected { ">" } else { " " };
+                    format!("{} {}", marker, item.label())
+                }));
+                lines
+            }
+            View::Logs { offset } => {
+                // Neueste zuerst, Drehen blättert zu älteren
+                let entries: Vec<String> = logs.iter().rev().map(|entry| entry.to_string()).collect();
+                self.page(Item::Logs.label(), &entries, offset)
+            }
+            View::Config { offset } => self.page(Item::Config.label(), config, offset),
+            View::ConfirmReboot { yes } => vec![
+                Msg::ConfirmReboot.text().to_string(),
+                format!("{} {}", if yes { " " } else { ">" }, Msg::No.text()),
+                format!("{} {}", if yes { ">" } else { " " }, Msg::Yes.text()),
+            ],
+        }
+    }
+
+    fn page(&mut self, title: &str, content: &[String], offset: usize) -> Vec<String> {
+        self.content_len = content.len();
+        let offset = offset.min(content.len().saturating_sub(PAGE_LINES));
+        let mut lines = vec![format!("{} ({}/{})", title, offset + content.len().min(1), content.len())];
+        lines.extend(content.iter().skip(offset).take(PAGE_LINES).cloned());
+        lines
+    }
+}
+
+fn scroll(offset: usize, step: i8, max_offset: usize) -> usize {
+    offset.saturating_add_signed(step as isize).min(max_offset)
+}
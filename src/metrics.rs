@@ -0,0 +1,226 @@
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use log::*;
+use serde::Serialize;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::logs::Source;
+use crate::relay::Relay;
+use crate::system;
+
+/// Der Push-Zähler wird alle so viele Pushes ins NVS geschrieben, um den Flash zu schonen.
+const PERSIST_EVERY: u32 = 10;
+
+/// Pfade mit eigener Statistik, alle anderen landen gemeinsam unter [`OTHER_ENDPOINT`].
+///
+/// Die Liste ist fest, damit beliebige Pfade von außen keinen Speicher belegen können.
+const ENDPOINTS: [&str; 5] = ["/push", "/status", "/logs", "/metrics", "/config"];
+const OTHER_ENDPOINT: &str = "other";
+
+/// Auslöser außerhalb des HTTP-Servers, je mit eigenem Zähler statt unter den Requests.
+const SOURCES: [&str; 4] = ["button", "mqtt", "coap", "encoder"];
+
+/// Kennzahlen, die `/status`, der MQTT-Status und CoAP `status` gleichlautend melden.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub uptime_s: u64,
+    pub free_heap: u32,
+    pub rssi: Option<i8>,
+    pub pushes: u32,
+    pub requests: u32,
+    pub relay_on_ms: u64,
+    pub relay_active: bool,
+    pub relay_latched: bool,
+}
+
+impl Summary {
+    pub fn collect(metrics: &Metrics, relay: &Relay) -> Self {
+        let relay_state = relay.state();
+        Self {
+            uptime_s: metrics.uptime_secs(),
+            free_heap: system::free_heap(),
+            rssi: system::wifi_rssi(),
+            pushes: metrics.pushes(),
+            requests: metrics.requests(),
+            relay_on_ms: metrics.relay_on_ms(),
+            relay_active: relay_state.is_active(),
+            relay_latched: relay_state.latched,
+        }
+    }
+}
+
+/// Anzahl und summierte Bearbeitungszeit der Requests auf einen Pfad.
+#[derive(Default)]
+struct EndpointStats {
+    count: AtomicU32,
+    total_us: AtomicU64,
+}
+
+impl EndpointStats {
+    fn average_ms(&self) -> f64 {
+        match self.count.load(Ordering::Relaxed) {
+            0 => 0.0,
+            count => self.total_us.load(Ordering::Relaxed) as f64 / count as f64 / 1000.0,
+        }
+    }
+}
+
+/// Zähler seit dem Start, von den Handlern geteilt.
+///
+/// Nur der Push-Zähler überlebt einen Neustart, alle anderen beginnen bei 0.
+pub struct Metrics {
+    boot: Instant,
+    nvs: EspDefaultNvsPartition,
+    requests: AtomicU32,
+    not_found: AtomicU32,
+    pushes: AtomicU32,
+    relay_on_ms: AtomicU64,
+    /// Je ein Eintrag pro Pfad aus [`ENDPOINTS`], der letzte für alle anderen
+    endpoints: [EndpointStats; ENDPOINTS.len() + 1],
+    /// Je ein Eintrag pro Auslöser aus [`SOURCES`]
+    triggers: [AtomicU32; SOURCES.len()],
+}
+
+impl Metrics {
+    pub fn new(nvs: EspDefaultNvsPartition) -> Self {
+        let pushes = config::load_push_count(&nvs).unwrap_or_else(|err| {
+            warn!("Push-Zähler nicht lesbar: {:?}", err);
+            0
+        });
+        Self {
+            boot: Instant::now(),
+            nvs,
+            requests: AtomicU32::new(0),
+            not_found: AtomicU32::new(0),
+            pushes: AtomicU32::new(pushes),
+            relay_on_ms: AtomicU64::new(0),
+            endpoints: Default::default(),
+            triggers: Default::default(),
+        }
+    }
+
+    /// Zählt einen Auslöser außerhalb des HTTP-Servers, aufgerufen aus `log_source`.
+    pub fn record_trigger(&self, source: Source) {
+        if let Some(index) = SOURCES.iter().position(|label| *label == source.label()) {
+            self.triggers[index].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Zählt einen beantworteten HTTP-Request, aufgerufen aus `log_request`.
+    pub fn record_request(&self, status: u16) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if status == 404 {
+            self.not_found.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Rechnet die Bearbeitungszeit eines HTTP-Requests dem Pfad zu, aufgerufen aus `json_handler`.
+    pub fn record_latency(&self, path: &str, elapsed: Duration) {
+        let index = ENDPOINTS.iter().position(|known| *known == path).unwrap_or(ENDPOINTS.len());
+        let stats = &self.endpoints[index];
+        stats.count.fetch_add(1, Ordering::Relaxed);
+        stats.total_us.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Statistik je Pfad als `(Pfad, Anzahl, mittlere Dauer in ms)`.
+    pub fn endpoints(&self) -> impl Iterator<Item = (&'static str, u32, f64)> + '_ {
+        ENDPOINTS
+            .iter()
+            .copied()
+            .chain([OTHER_ENDPOINT])
+            .zip(&self.endpoints)
+            .map(|(path, stats)| (path, stats.count.load(Ordering::Relaxed), stats.average_ms()))
+    }
+
+    /// Zählt einen erfolgreich ausgelösten Puls und seine Dauer.
+    pub fn record_push(&self, on_ms: u64) {
+        self.relay_on_ms.fetch_add(on_ms, Ordering::Relaxed);
+        let pushes = self.pushes.fetch_add(1, Ordering::Relaxed) + 1;
+        if pushes % PERSIST_EVERY == 0 {
+            self.persist_pushes(pushes);
+        }
+    }
+
+    /// Setzt alle Zähler zurück, auch den gespeicherten Push-Zähler.
+    pub fn reset(&self) {
+        self.requests.store(0, Ordering::Relaxed);
+        self.not_found.store(0, Ordering::Relaxed);
+        self.pushes.store(0, Ordering::Relaxed);
+        self.relay_on_ms.store(0, Ordering::Relaxed);
+        for stats in &self.endpoints {
+            stats.count.store(0, Ordering::Relaxed);
+            stats.total_us.store(0, Ordering::Relaxed);
+        }
+        for count in &self.triggers {
+            count.store(0, Ordering::Relaxed);
+        }
+        self.persist_pushes(0);
+    }
+
+    fn persist_pushes(&self, pushes: u32) {
+        if let Err(err) = config::store_push_count(&self.nvs, pushes) {
+            warn!("Push-Zähler nicht gespeichert: {:?}", err);
+        }
+    }
+
+    pub fn requests(&self) -> u32 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn not_found(&self) -> u32 {
+        self.not_found.load(Ordering::Relaxed)
+    }
+
+    pub fn pushes(&self) -> u32 {
+        self.pushes.load(Ordering::Relaxed)
+    }
+
+    pub fn relay_on_ms(&self) -> u64 {
+        self.relay_on_ms.load(Ordering::Relaxed)
+    }
+
+    pub fn uptime_secs(&self) -> u64 {
+        self.boot.elapsed().as_secs()
+    }
+
+    /// Alle Zähler und Messwerte im Textformat von Prometheus.
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: &dyn std::fmt::Display| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+
+        metric("doofman_requests_total", "counter", "HTTP requests handled", &self.requests());
+        metric("doofman_not_found_total", "counter", "HTTP requests answered with 404", &self.not_found());
+        metric("doofman_pushes_total", "counter", "Relay pulses triggered", &self.pushes());
+        metric("doofman_relay_on_milliseconds_total", "counter", "Total relay on-time", &self.relay_on_ms());
+        metric("doofman_free_heap_bytes", "gauge", "Free heap", &system::free_heap());
+        if let Some(rssi) = system::wifi_rssi() {
+            metric("doofman_wifi_rssi_dbm", "gauge", "WiFi signal strength", &rssi);
+        }
+        metric("doofman_uptime_seconds", "gauge", "Seconds since boot", &self.uptime_secs());
+
+        let _ = writeln!(out, "# HELP doofman_http_requests_by_path_total HTTP requests handled per path");
+        let _ = writeln!(out, "# TYPE doofman_http_requests_by_path_total counter");
+        for (path, count, _) in self.endpoints() {
+            let _ = writeln!(out, "doofman_http_requests_by_path_total{{path=\"{}\"}} {}", path, count);
+        }
+        let _ = writeln!(out, "# HELP doofman_http_latency_avg_milliseconds Average handling time per path");
+        let _ = writeln!(out, "# TYPE doofman_http_latency_avg_milliseconds gauge");
+        for (path, _, average_ms) in self.endpoints() {
+            let _ = writeln!(out, "doofman_http_latency_avg_milliseconds{{path=\"{}\"}} {:.3}", path, average_ms);
+        }
+        let _ = writeln!(out, "# HELP doofman_triggers_total Commands from button, MQTT, CoAP and encoder");
+        let _ = writeln!(out, "# TYPE doofman_triggers_total counter");
+        for (source, count) in SOURCES.iter().zip(&self.triggers) {
+            let count = count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "doofman_triggers_total{{source=\"{}\"}} {}", source, count);
+        }
+
+        out
+    }
+}
@@ -0,0 +1,257 @@
+//! MQTT-Anbindung, z.B. für Home Assistant.
+//!
+//! Geräte-Topics unterhalb von `MQTT_PREFIX` (Standard `doofman`) siehe [`Topics`].
+//!
+//! Solange `MQTT_DISCOVERY` nicht `0` ist, werden beim Verbinden zusätzlich retained
+//! Discovery-Nachrichten für Home Assistant veröffentlicht:
+//!
+//! - `homeassistant/button/<id>/push/config`: Taster für einen Puls
+//! - `homeassistant/switch/<id>/relay/config`: Schalter für den Dauerbetrieb
+//! - `homeassistant/sensor/<id>/{rssi,uptime_s,free_heap}/config`: Sensoren aus `<prefix>/status`
+//!
+//! `<id>` ist `doofman_` plus die Werks-MAC. Wer die Geräte lieber selbst in YAML anlegt, setzt
+//! `MQTT_DISCOVERY=0` und löscht die retained Configs einmalig mit einer leeren Nachricht.
+//!
+//! Schaltet das Relais, während der Broker nicht erreichbar ist, landen die Zustandswechsel im
+//! [`Outbox`]-Puffer und werden nach dem Verbinden der Reihe nach auf `<prefix>/state`
+//! nachgereicht, mit `OFFLINE_EVENTS=drop` nur der aktuelle Zustand.
+
+use anyhow::Result;
+use esp_idf_svc::mqtt::client::{
+    EspMqttClient, EventPayload, LwtConfiguration, MqttClientConfiguration, QoS,
+};
+use log::*;
+use serde_json::json;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::MqttConfig;
+use crate::logs::{log_source, log_switch, LogQueue, Source};
+use crate::metrics::{Metrics, Summary};
+use crate::outbox::Outbox;
+use crate::relay::{LatchAction, Relay};
+use crate::settings::Settings;
+use crate::system;
+
+// So oft wird der Relais-Zustand auf Änderungen geprüft
+const STATE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+// So oft werden die Statusdaten veröffentlicht
+const STATUS_INTERVAL: Duration = Duration::from_secs(30);
+// Präfix, unter dem Home Assistant nach Discovery-Nachrichten sucht
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Topics unterhalb des Präfixes.
+///
+/// - `<prefix>/cmd`: Befehle `push`, `on` und `off`
+/// - `<prefix>/state`: `ON`/`OFF`, retained
+/// - `<prefix>/status`: Statusdaten als JSON wie bei `/status`
+/// - `<prefix>/availability`: `online`/`offline`, retained, `offline` als Last Will
+pub struct Topics {
+    pub cmd: String,
+    pub state: String,
+    pub status: String,
+    pub availability: String,
+}
+
+impl Topics {
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            cmd: format!("{}/cmd", prefix),
+            state: format!("{}/state", prefix),
+            status: format!("{}/status", prefix),
+            availability: format!("{}/availability", prefix),
+        }
+    }
+}
+
+enum Event {
+    Connected,
+    Disconnected,
+    Command(String),
+}
+
+/// Verbindet sich mit dem Broker und bedient ihn in einem eigenen Thread.
+///
+/// Befehle laufen über denselben Relais-Handle wie `/push` und `/relay/*`, es gelten also
+/// dieselben Sperren. Die Verbindung baut der ESP-IDF-Client bei Abbrüchen selbst wieder auf.
+pub fn start(
+    config: &MqttConfig,
+    client_id: &str,
+    settings: Arc<Settings>,
+    relay: Relay,
+    metrics: Arc<Metrics>,
+    log_queue: Arc<LogQueue>,
+) -> Result<()> {
+    let topics = Topics::new(&config.prefix);
+    let discovery = config.discovery.then(|| discovery_messages(&topics, client_id));
+    let mqtt_config = MqttClientConfiguration {
+        client_id: Some(client_id),
+        username: config.username.as_deref(),
+        password: config.password.as_deref(),
+        lwt: Some(LwtConfiguration {
+            topic: &topics.availability,
+            payload: b"offline",
+            qos: QoS::AtLeastOnce,
+            retain: true,
+        }),
+        ..Default::default()
+    };
+
+    // Im Callback steht der Client noch nicht zur Verfügung, daher alles an den Thread weiterreichen
+    let (events, receiver) = mpsc::channel::<Event>();
+    let cmd_topic = topics.cmd.clone();
+    let mut client = EspMqttClient::new_cb(&config.url, &mqtt_config, move |event| {
+        let event = match event.payload() {
+            EventPayload::Connected(_) => Event::Connected,
+            EventPayload::Disconnected => Event::Disconnected,
+            EventPayload::Received { topic, data, .. } if topic == Some(cmd_topic.as_str()) => {
+                Event::Command(String::from_utf8_lossy(data).trim().to_string())
+            }
+            _ => return,
+        };
+        let _ = events.send(event);
+    })?;
+
+    std::thread::Builder::new()
+        .name("mqtt".into())
+        .stack_size(6144)
+        .spawn(move || {
+            let mut connected = false;
+            // Zuletzt gesehener Zustand, Wechsel warten in `pending` auf den Versand
+            let mut observed_state = None;
+            let mut pending = Outbox::from_env("MQTT");
+            let mut last_status = Instant::now();
+            loop {
+                match receiver.recv_timeout(STATE_POLL_INTERVAL) {
+                    Ok(Event::Connected) => {
+                        info!("MQTT verbunden");
+                        connected = true;
+                        let _ = client.publish(&topics.availability, QoS::AtLeastOnce, true, b"online");
+                        if let Err(err) = client.subscribe(&topics.cmd, QoS::AtLeastOnce) {
+                            warn!("MQTT: {} nicht abonniert: {:?}", topics.cmd, err);
+                        }
+                        for (topic, payload) in discovery.iter().flatten() {
+                            if let Err(err) = client.publish(topic, QoS::AtLeastOnce, true, payload.as_bytes()) {
+                                warn!("MQTT: Discovery {} nicht veröffentlicht: {:?}", topic, err);
+                            }
+                        }
+                        // Nach dem Verbinden alles neu veröffentlichen, aufgelaufene Wechsel reichen dafür
+                        if pending.is_empty() {
+                            pending.push(relay.state().is_active());
+                        }
+                        last_status = Instant::now() - STATUS_INTERVAL;
+                    }
+                    Ok(Event::Disconnected) => {
+                        warn!("MQTT getrennt");
+                        connected = false;
+                    }
+                    Ok(Event::Command(command)) => {
+                        handle_command(&command, settings.pulse(), &relay, &metrics, &log_queue);
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                let active = relay.state().is_active();
+                if observed_state != Some(active) {
+                    observed_state = Some(active);
+                    pending.push(active);
+                }
+                pending.flush(connected, |active| {
+                    let payload: &[u8] = if *active { b"ON" } else { b"OFF" };
+                    client.publish(&topics.state, QoS::AtLeastOnce, true, payload).is_ok()
+                });
+                if !connected {
+                    continue;
+                }
+
+                if last_status.elapsed() >= STATUS_INTERVAL {
+                    last_status = Instant::now();
+                    let payload = serde_json::to_vec(&Summary::collect(&metrics, &relay)).unwrap_or_default();
+                    if let Err(err) = client.publish(&topics.status, QoS::AtMostOnce, false, &payload) {
+                        warn!("MQTT: Status nicht veröffentlicht: {:?}", err);
+                    }
+                }
+            }
+        })?;
+
+    info!("MQTT: {} mit Präfix {}", config.url, config.prefix);
+    Ok(())
+}
+
+fn handle_command(command: &str, pulse: Duration, relay: &Relay, metrics: &Metrics, log_queue: &LogQueue) {
+    let (path, result) = match command {
+        "push" => {
+            let result = relay.pulse(pulse);
+            if result.is_ok() {
+                metrics.record_push(pulse.as_millis() as u64);
+            }
+            ("push", result)
+        }
+        "on" => ("on", relay.latch(LatchAction::On).map(|_| ())),
+        "off" => ("off", relay.latch(LatchAction::Off).map(|_| ())),
+        _ => {
+            warn!("MQTT: unbekannter Befehl \"{}\"", command);
+            log_source(log_queue, Source::Mqtt, 400, command);
+            return;
+        }
+    };
+    match result {
+        Ok(()) => log_switch(log_queue, Source::Mqtt, path),
+        Err(err) => log_source(log_queue, Source::Mqtt, err.status(), path),
+    }
+}
+
+/// Discovery-Configs für Home Assistant als `(Topic, Payload)`.
+fn discovery_messages(topics: &Topics, name: &str) -> Vec<(String, String)> {
+    let id = format!("doofman_{}", system::chip_id());
+    let device = json!({
+        "identifiers": [id],
+        "name": name,
+        "manufacturer": "doofman",
+        "model": "ESP32 Relais",
+    });
+    let entity = |component: &str, object: &str, mut config: serde_json::Value| {
+        config["unique_id"] = json!(format!("{}_{}", id, object));
+        config["object_id"] = json!(format!("{}_{}", name, object));
+        config["availability_topic"] = json!(topics.availability);
+        config["device"] = device.clone();
+        (
+            format!("{}/{}/{}/{}/config", DISCOVERY_PREFIX, component, id, object),
+            config.to_string(),
+        )
+    };
+    let sensor = |object: &str, label: &str, unit: &str, device_class: Option<&str>| {
+        let mut config = json!({
+            "name": label,
+            "state_topic": topics.status,
+            "value_template": format!("{{{{ value_json.{} }}}}", object),
+            "unit_of_measurement": unit,
+            "entity_category": "diagnostic",
+        });
+        if let Some(device_class) = device_class {
+            config["device_class"] = json!(device_class);
+        }
+        entity("sensor", object, config)
+    };
+
+    vec![
+        entity("button", "push", json!({
+            "name": "Öffnen",
+            "command_topic": topics.cmd,
+            "payload_press": "push",
+        })),
+        entity("switch", "relay", json!({
+            "name": "Relais",
+            "command_topic": topics.cmd,
+            "state_topic": topics.state,
+            "payload_on": "on",
+            "payload_off": "off",
+            "state_on": "ON",
+            "state_off": "OFF",
+        })),
+        sensor("rssi", "WLAN-Signal", "dBm", Some("signal_strength")),
+        sensor("uptime_s", "Laufzeit", "s", Some("duration")),
+        sensor("free_heap", "Freier Heap", "B", Some("data_size")),
+    ]
+}
@@ -0,0 +1,94 @@
+//! Puffer für ausgehende Ereignisse von Webhook und MQTT, solange WLAN oder Empfänger weg sind.
+//!
+//! `OFFLINE_EVENTS=buffer` (Standard) hebt bis zu `OFFLINE_BUFFER_LEN` Ereignisse (Standard 16,
+//! höchstens 64) auf und sendet sie nach dem Wiederverbinden der Reihe nach, läuft der Puffer
+//! über, fallen die ältesten heraus. Mit `OFFLINE_EVENTS=drop` wird wie früher verworfen, was
+//! nicht sofort rausgeht. Relais, Taster und Display hängen in keinem Fall davon ab.
+
+use log::*;
+use std::collections::VecDeque;
+
+const DEFAULT_LEN: usize = 16;
+const MAX_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OfflinePolicy {
+    #[default]
+    Buffer,
+    Drop,
+}
+
+impl OfflinePolicy {
+    /// Aus `OFFLINE_EVENTS`, ungültige Werte puffern mit Warnung.
+    pub fn from_env() -> Self {
+        match option_env!("OFFLINE_EVENTS") {
+            None | Some("buffer") => OfflinePolicy::Buffer,
+            Some("drop") => OfflinePolicy::Drop,
+            Some(other) => {
+                warn!("Ungültiges OFFLINE_EVENTS {:?}, erwartet buffer oder drop", other);
+                OfflinePolicy::Buffer
+            }
+        }
+    }
+}
+
+/// Warteschlange eines Senders, gehört dessen Thread.
+pub struct Outbox<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+    policy: OfflinePolicy,
+    name: &'static str,
+}
+
+impl<T> Outbox<T> {
+    /// Größe aus `OFFLINE_BUFFER_LEN`, `name` erscheint in den Logmeldungen.
+    pub fn from_env(name: &'static str) -> Self {
+        let capacity = option_env!("OFFLINE_BUFFER_LEN")
+            .and_then(|value| value.parse().ok())
+            .filter(|len| (1..=MAX_LEN).contains(len))
+            .unwrap_or(DEFAULT_LEN);
+        Self {
+            items: VecDeque::with_capacity(capacity),
+            capacity,
+            policy: OfflinePolicy::from_env(),
+            name,
+        }
+    }
+
+    pub fn push(&mut self, item: T) {
+        if self.items.len() == self.capacity {
+            self.items.pop_front();
+            warn!("{}: Puffer voll, ältestes Ereignis verworfen", self.name);
+        }
+        self.items.push_back(item);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Sendet die wartenden Ereignisse der Reihe nach mit `send`, das `false` liefert, wenn der
+    /// Empfänger nicht erreichbar war. Ohne Verbindung (`online == false`) wird nichts versucht.
+    ///
+    /// Beim Puffern bleibt ein fehlgeschlagenes Ereignis samt allen späteren liegen, sonst wird es
+    /// verworfen, ebenso alles, was ohne Verbindung wartet.
+    pub fn flush(&mut self, online: bool, mut send: impl FnMut(&T) -> bool) {
+        if !online {
+            if self.policy == OfflinePolicy::Drop && !self.items.is_empty() {
+                debug!("{}: offline, {} Ereignis(se) verworfen", self.name, self.items.len());
+                self.items.clear();
+            }
+            return;
+        }
+        let waiting = self.items.len();
+        while let Some(item) = self.items.front() {
+            if !send(item) && self.policy == OfflinePolicy::Buffer {
+                break;
+            }
+            self.items.pop_front();
+        }
+        if waiting > 1 && self.items.is_empty() {
+            info!("{}: {} gepufferte Ereignisse gesendet", self.name, waiting);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Long-Polling unter `/poll?since=<seq>` für Dashboards, denen `/events` zu viel ist.
+//!
+//! Gibt es schon Logeinträge nach `since`, kommt die Antwort sofort. Sonst wird der Request wie
+//! bei `/events` per `httpd_req_async_handler_begin` vom Server abgekoppelt und erst beantwortet,
+//! wenn ein neuer Eintrag kommt, sich der Zustand des Relais ändert oder nach [`POLL_TIMEOUT`].
+//! Die Antwort enthält immer die aktuelle Nummer, mit der der Client als `since` weitermacht:
+//!
+//! `{ "seq": 42, "entries": [...], "relay": { "active": false, "latched": false } }`
+//!
+//! Ohne `since` kommt sofort der aktuelle Stand ohne Einträge. Der Request selbst landet nicht im
+//! Log, sonst würde jede Antwort den nächsten wartenden Client sofort wecken.
+//!
+//! Geantwortet wird aus einem eigenen Thread, ein neuer Logeintrag weckt ihn nur. Wartende
+//! Clients zählen wie `/events` und `/ws/logs` gegen die Grenze aus [`crate::streams`].
+
+use anyhow::Result;
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_sys::{esp, httpd_req_t};
+use log::*;
+use std::ffi::{CStr, CString};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::{cors_origin, Routes};
+use crate::keepalive;
+use crate::logs::LogQueue;
+use crate::relay::{Relay, RelayState};
+use crate::streams::{self, Slot};
+
+/// Nach dieser Zeit wird auch ohne Änderung geantwortet, unter den üblichen 30 s von Proxys.
+const POLL_TIMEOUT: Duration = Duration::from_secs(25);
+
+// So oft werden Zeitlimit und Relais-Zustand der wartenden Clients geprüft
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Vom Server abgekoppelter Request, der auf eine Änderung wartet.
+struct Waiter {
+    req: *mut httpd_req_t,
+    since: u64,
+    relay: (bool, bool),
+    deadline: Instant,
+    _slot: Slot,
+}
+
+// Der kopierte Request darf laut ESP-IDF aus jedem Task heraus beantwortet werden
+unsafe impl Send for Waiter {}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        unsafe { esp_idf_sys::httpd_req_async_handler_complete(self.req) };
+    }
+}
+
+struct Poll {
+    log_queue: Arc<LogQueue>,
+    relay: Relay,
+    waiters: Mutex<Vec<Waiter>>,
+    cors_origin: CString,
+}
+
+/// Was der Client über das Relais erfährt, eine Änderung daran beendet das Warten.
+fn relay_key(state: RelayState) -> (bool, bool) {
+    (state.is_active(), state.latched)
+}
+
+impl Poll {
+    fn body(&self, since: u64) -> String {
+        let state = self.relay.state();
+        serde_json::json!({
+            "seq": self.log_queue.seq(),
+            "entries": self.log_queue.after(since),
+            "relay": { "active": state.is_active(), "latched": state.latched },
+        })
+        .to_string()
+    }
+
+    /// Beantwortet `req` mit allem nach `since`.
+    unsafe fn respond(&self, req: *mut httpd_req_t, since: u64) -> esp_idf_sys::esp_err_t {
+        let body = self.body(since);
+        esp_idf_sys::httpd_resp_set_type(req, c"application/json".as_ptr());
+        esp_idf_sys::httpd_resp_set_hdr(req, c"Cache-Control".as_ptr(), c"no-store".as_ptr());
+        esp_idf_sys::httpd_resp_set_hdr(req, c"Access-Control-Allow-Origin".as_ptr(), self.cors_origin.as_ptr());
+        esp_idf_sys::httpd_resp_send(req, body.as_ptr() as *const _, body.len() as _)
+    }
+
+    /// Beantwortet und entlässt alle Clients, für die `done` zutrifft.
+    fn release(&self, done: impl Fn(&Waiter) -> bool) {
+        let mut waiters = self.waiters.lock().unwrap();
+        waiters.retain(|waiter| {
+            if !done(waiter) {
+                return true;
+            }
+            // Danach gilt die Verbindung wieder als unbenutzt, bis der Client erneut fragt
+            let fd = unsafe { esp_idf_sys::httpd_req_to_sockfd(waiter.req) };
+            unsafe { self.respond(waiter.req, waiter.since) };
+            keepalive::finished(fd, false);
+            false
+        });
+    }
+}
+
+/// Registriert `/poll`, das bei neuen Logeinträgen oder Schaltvorgängen von `relay` antwortet.
+pub fn register_long_poll(
+    server: &mut EspHttpServer<'static>,
+    routes: &Routes,
+    log_queue: Arc<LogQueue>,
+    relay: Relay,
+) -> Result<()> {
+    // Lebt so lange wie der Server, der Handler bekommt nur einen Zeiger darauf
+    let poll: &'static Poll = Box::leak(Box::new(Poll {
+        log_queue: log_queue.clone(),
+        relay,
+        waiters: Mutex::new(Vec::new()),
+        cors_origin: CString::new(cors_origin())?,
+    }));
+
+    let uri = esp_idf_sys::httpd_uri_t {
+        uri: c"/poll".as_ptr(),
+        method: esp_idf_sys::http_method_HTTP_GET,
+        handler: Some(handle_poll),
+        user_ctx: poll as *const Poll as *mut _,
+        ..Default::default()
+    };
+    esp!(unsafe { esp_idf_sys::httpd_register_uri_handler(server.handle(), &uri) })?;
+    routes.record("/poll", embedded_svc::http::Method::Get);
+
+    // Ein Platz reicht, mehrere Einträge hintereinander wecken den Thread nur einmal
+    let (wake, woken) = mpsc::sync_channel::<()>(1);
+    std::thread::Builder::new()
+        .name("poll".into())
+        .stack_size(4096)
+        .spawn(move || loop {
+            if let Err(RecvTimeoutError::Disconnected) = woken.recv_timeout(CHECK_INTERVAL) {
+                break;
+            }
+            let now = Instant::now();
+            let seq = poll.log_queue.seq();
+            let relay = relay_key(poll.relay.state());
+            poll.release(|waiter| seq > waiter.since || now >= waiter.deadline || waiter.relay != relay);
+        })?;
+
+    // Der Eintrag steht schon in der Queue, wenn die Listener laufen
+    log_queue.subscribe(move |_| {
+        let _ = wake.try_send(());
+    });
+
+    Ok(())
+}
+
+unsafe extern "C" fn handle_poll(req: *mut httpd_req_t) -> esp_idf_sys::esp_err_t {
+    let poll = &*((*req).user_ctx as *const Poll);
+    let uri = CStr::from_ptr((*req).uri.as_ptr()).to_str().unwrap_or_default();
+    let since = match crate::query_param(uri, "since").map(str::parse::<u64>) {
+        Some(Ok(since)) => since,
+        None => return poll.respond(req, poll.log_queue.seq()),
+        Some(Err(_)) => {
+            esp_idf_sys::httpd_resp_set_status(req, c"400 Bad Request".as_ptr());
+            esp_idf_sys::httpd_resp_set_type(req, c"application/json".as_ptr());
+            let body = r#"{ "error": "since must be a number" }"#;
+            return esp_idf_sys::httpd_resp_send(req, body.as_ptr() as *const _, body.len() as _);
+        }
+    };
+
+    // Unter der Sperre prüfen, damit kein Eintrag zwischen Prüfen und Einreihen verloren geht.
+    // Eine Nummer aus der Zukunft stammt von vor einem Neustart, dann gleich neu aufsetzen.
+    let mut waiters = poll.waiters.lock().unwrap();
+    if poll.log_queue.seq() != since {
+        drop(waiters);
+        return poll.respond(req, since);
+    }
+    let Some(slot) = streams::acquire() else {
+        warn!("Long-Poll abgelehnt, bereits {} Streaming-Clients", streams::active());
+        esp_idf_sys::httpd_resp_set_status(req, c"503 Service Unavailable".as_ptr());
+        esp_idf_sys::httpd_resp_set_hdr(req, c"Retry-After".as_ptr(), c"5".as_ptr());
+        return esp_idf_sys::httpd_resp_send(req, std::ptr::null(), 0);
+    };
+
+    let mut detached = std::ptr::null_mut();
+    let err = esp_idf_sys::httpd_req_async_handler_begin(req, &mut detached);
+    if err != 0 {
+        return err;
+    }
+    keepalive::forget(esp_idf_sys::httpd_req_to_sockfd(detached));
+    waiters.push(Waiter {
+        req: detached,
+        since,
+        relay: relay_key(poll.relay.state()),
+        deadline: Instant::now() + POLL_TIMEOUT,
+        _slot: slot,
+    });
+    esp_idf_sys::ESP_OK as _
+}
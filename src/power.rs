@@ -0,0 +1,201 @@
+//! Optionaler Schlafmodus für den Batteriebetrieb.
+//!
+//! Ohne `SLEEP_AFTER_S` bleibt das Gerät dauerhaft wach. Mit gesetztem Wert schläft es nach so
+//! vielen Sekunden ohne Request ein und wacht über den Taster (Low-Pegel) oder, mit
+//! `SLEEP_WAKE_S`, zusätzlich nach einer festen Zeit wieder auf.
+//!
+//! `SLEEP_MODE=light` hält RAM und Programmzustand, nach dem Aufwachen geht es in der
+//! Hauptschleife weiter. Das WLAN ist währenddessen aus und muss sich neu verbinden, Requests
+//! kommen im Schlaf nicht an. `SLEEP_MODE=deep` (Standard) spart deutlich mehr Strom, startet
+//! beim Aufwachen aber komplett neu: WLAN-Verbindung, SNTP und Logs gehen verloren, bis das Gerät
+//! wieder erreichbar ist, vergehen einige Sekunden. Der Grund des Aufwachens steht unter
+//! `/status` in `wake_reason`.
+//!
+//! Unabhängig davon senkt `POWER_SAVE_AFTER_S` im Dauerbetrieb den Verbrauch: nach so vielen
+//! Sekunden ohne Request geht das WLAN in Modem-Sleep und der Takt der CPU auf
+//! `POWER_SAVE_MHZ` (Standard 80 MHz). Das Gerät bleibt erreichbar, ein Request braucht im
+//! Modem-Sleep aber bis zu einem DTIM-Intervall länger, typisch 100 bis 300 ms. Mit dem ersten
+//! Request läuft es wieder mit vollem Takt und ohne Modem-Sleep.
+
+use anyhow::Result;
+use esp_idf_sys::EspError;
+use log::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// Ob gerade gespart wird, global wie der Takt selbst und für /status
+static SAVING: AtomicBool = AtomicBool::new(false);
+
+/// Volle Taktfrequenz des Chips, der ESP32-C3 schafft nur 160 MHz.
+#[cfg(not(feature = "board-c3"))]
+const FULL_MHZ: i32 = 240;
+#[cfg(feature = "board-c3")]
+const FULL_MHZ: i32 = 160;
+/// Taktfrequenz beim Sparen ohne `POWER_SAVE_MHZ`, darunter läuft das WLAN nicht zuverlässig.
+const DEFAULT_SAVE_MHZ: i32 = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepMode {
+    Light,
+    Deep,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SleepConfig {
+    pub mode: SleepMode,
+    /// Inaktivität, nach der eingeschlafen wird
+    pub idle: Duration,
+    /// Aufwachen nach fester Zeit, zusätzlich zum Taster
+    pub wake_after: Option<Duration>,
+    /// Taster, der das Gerät bei Low-Pegel weckt
+    pub wake_gpio: i32,
+}
+
+impl SleepConfig {
+    /// Liest `SLEEP_AFTER_S`, `SLEEP_MODE` und `SLEEP_WAKE_S`, `None` wenn der Schlafmodus aus ist.
+    pub fn from_env(wake_gpio: i32) -> Option<Self> {
+        let idle = option_env!("SLEEP_AFTER_S")
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|secs| *secs > 0)?;
+        let mode = match option_env!("SLEEP_MODE") {
+            Some("light") => SleepMode::Light,
+            _ => SleepMode::Deep,
+        };
+        let wake_after = option_env!("SLEEP_WAKE_S")
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs);
+        Some(Self {
+            mode,
+            idle: Duration::from_secs(idle),
+            wake_after,
+            wake_gpio,
+        })
+    }
+}
+
+/// Stromsparen im Dauerbetrieb, wechselt abhängig von der Inaktivität zwischen vollem Takt und
+/// Modem-Sleep mit reduziertem Takt.
+pub struct PowerSave {
+    idle: Duration,
+    save_mhz: i32,
+}
+
+impl PowerSave {
+    /// Liest `POWER_SAVE_AFTER_S` und `POWER_SAVE_MHZ`, `None` wenn nicht gespart werden soll.
+    /// Mehr als [`FULL_MHZ`] wird nicht eingestellt.
+    pub fn from_env() -> Option<Self> {
+        let idle = option_env!("POWER_SAVE_AFTER_S")
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|secs| *secs > 0)?;
+        let save_mhz = option_env!("POWER_SAVE_MHZ")
+            .and_then(|value| value.parse().ok())
+            .filter(|mhz| [80, 160, 240].contains(mhz))
+            .unwrap_or(DEFAULT_SAVE_MHZ)
+            .min(FULL_MHZ);
+        Some(Self {
+            idle: Duration::from_secs(idle),
+            save_mhz,
+        })
+    }
+
+    /// Voller Takt ohne Modem-Sleep, das WLAN startet sonst schon mit Modem-Sleep.
+    pub fn start(&self) -> Result<()> {
+        apply(FULL_MHZ, esp_idf_sys::wifi_ps_type_t_WIFI_PS_NONE)?;
+        Ok(())
+    }
+
+    /// Spart nach `idle` ohne Request und kehrt bei neuer Aktivität zum vollen Takt zurück.
+    pub fn update(&self, idle: Duration) -> Result<()> {
+        let save = idle >= self.idle;
+        if save == is_saving() {
+            return Ok(());
+        }
+        if save {
+            apply(self.save_mhz, esp_idf_sys::wifi_ps_type_t_WIFI_PS_MIN_MODEM)?;
+            info!("Stromsparen nach {}s ohne Request, {} MHz", idle.as_secs(), self.save_mhz);
+        } else {
+            apply(FULL_MHZ, esp_idf_sys::wifi_ps_type_t_WIFI_PS_NONE)?;
+            info!("Stromsparen beendet");
+        }
+        SAVING.store(save, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+fn apply(mhz: i32, wifi_ps: esp_idf_sys::wifi_ps_type_t) -> Result<(), EspError> {
+    let config = esp_idf_sys::esp_pm_config_t {
+        max_freq_mhz: mhz,
+        min_freq_mhz: mhz.min(DEFAULT_SAVE_MHZ),
+        light_sleep_enable: false,
+    };
+    unsafe {
+        esp_idf_sys::esp!(esp_idf_sys::esp_pm_configure(&config as *const _ as *const _))?;
+        esp_idf_sys::esp!(esp_idf_sys::esp_wifi_set_ps(wifi_ps))?;
+    }
+    Ok(())
+}
+
+pub fn is_saving() -> bool {
+    SAVING.load(Ordering::SeqCst)
+}
+
+/// Energiemodus für `/status`.
+pub fn mode() -> &'static str {
+    if is_saving() {
+        "save"
+    } else {
+        "normal"
+    }
+}
+
+/// Weckt per Low-Pegel an `gpio` aus dem Deep Sleep, auf dem ESP32 und ESP32-S3 über EXT0.
+#[cfg(not(feature = "board-c3"))]
+unsafe fn enable_deep_sleep_wakeup(gpio: i32) {
+    esp_idf_sys::esp_sleep_enable_ext0_wakeup(gpio, 0);
+    info!("Deep Sleep, Aufwachen über GPIO{}", gpio);
+}
+
+/// Der ESP32-C3 hat kein EXT0 und weckt nur über GPIO0 bis GPIO5 aus dem Deep Sleep.
+#[cfg(feature = "board-c3")]
+unsafe fn enable_deep_sleep_wakeup(gpio: i32) {
+    if gpio > 5 {
+        warn!("GPIO{} kann nicht aus dem Deep Sleep wecken, nur der Timer", gpio);
+        return;
+    }
+    esp_idf_sys::esp_deep_sleep_enable_gpio_wakeup(
+        1 << gpio,
+        esp_idf_sys::esp_deepsleep_gpio_wake_up_mode_t_ESP_GPIO_WAKEUP_GPIO_LOW,
+    );
+    info!("Deep Sleep, Aufwachen über GPIO{}", gpio);
+}
+
+/// Schläft ein. Im Deep Sleep kehrt die Funktion nicht zurück, das Gerät startet beim Aufwachen neu.
+///
+/// Das Relais muss vorher abgeschaltet und sein Pin gehalten sein, siehe `Relay::prepare_sleep`.
+pub fn sleep(config: &SleepConfig) {
+    unsafe {
+        if let Some(wake_after) = config.wake_after {
+            esp_idf_sys::esp_sleep_enable_timer_wakeup(wake_after.as_micros() as u64);
+        }
+        match config.mode {
+            SleepMode::Deep => {
+                enable_deep_sleep_wakeup(config.wake_gpio);
+                esp_idf_sys::esp_deep_sleep_start();
+            }
+            SleepMode::Light => {
+                esp_idf_sys::gpio_wakeup_enable(
+                    config.wake_gpio,
+                    esp_idf_sys::gpio_int_type_t_GPIO_INTR_LOW_LEVEL,
+                );
+                esp_idf_sys::esp_sleep_enable_gpio_wakeup();
+                info!("Light Sleep, Aufwachen über GPIO{}", config.wake_gpio);
+                esp_idf_sys::esp_light_sleep_start();
+                esp_idf_sys::gpio_wakeup_disable(config.wake_gpio);
+                esp_idf_sys::esp_sleep_disable_wakeup_source(
+                    esp_idf_sys::esp_sleep_source_t_ESP_SLEEP_WAKEUP_ALL,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! Sanftes Ein- und Ausschalten des Relais-Pins per LEDC-PWM, für Halbleiterrelais an
+//! induktiven Lasten wie Motoren.
+//!
+//! Mit `RELAY_RAMP_UP_MS` und/oder `RELAY_RAMP_DOWN_MS` fährt ein Puls die Einschaltdauer über
+//! die Rampe hoch, hält sie für die Pulsdauer voll und fährt sie wieder herunter, statt den Pin
+//! hart zu schalten. `RELAY_PWM_FREQ_HZ` setzt die PWM-Frequenz (Standard 1000 Hz). Das taugt nur
+//! für DC-SSRs und SSRs ohne Nulldurchgangsschaltung, mechanische Relais würden nur brummen.
+//!
+//! Ohne die Variablen bleibt es beim einfachen Schalten. Die Rampe gilt nur für Pulse des ersten
+//! Relais, Pulsfolgen und der Dauerbetrieb schalten weiter hart. Rampen zählen zur
+//! Einschaltdauer, die Sicherheitsabschaltung nach `max_on_time` bricht auch mitten in einer
+//! Rampe ab.
+
+use anyhow::Result;
+use esp_idf_hal::ledc::{LedcChannel, LedcTimer};
+use esp_idf_hal::peripheral::Peripheral;
+use esp_idf_sys::{esp, ledc_channel_t, ledc_timer_t};
+use log::*;
+use std::time::{Duration, Instant};
+
+const DEFAULT_FREQ_HZ: u32 = 1000;
+
+const SPEED_MODE: esp_idf_sys::ledc_mode_t = esp_idf_sys::ledc_mode_t_LEDC_LOW_SPEED_MODE;
+const RESOLUTION: esp_idf_sys::ledc_timer_bit_t = esp_idf_sys::ledc_timer_bit_t_LEDC_TIMER_10_BIT;
+const MAX_DUTY: u32 = (1 << 10) - 1;
+
+// Abstand der Schritte einer Rampe, zugleich die Reaktionszeit auf eine Sicherheitsabschaltung
+const STEP: Duration = Duration::from_millis(10);
+
+/// Rampenprofil samt dem dafür reservierten LEDC-Timer und -Kanal.
+#[derive(Debug, Clone, Copy)]
+pub struct Ramp {
+    pub up: Duration,
+    pub down: Duration,
+    freq_hz: u32,
+    timer: ledc_timer_t,
+    channel: ledc_channel_t,
+}
+
+impl Ramp {
+    /// Profil aus `RELAY_RAMP_UP_MS`/`RELAY_RAMP_DOWN_MS`, ohne beide `None`.
+    ///
+    /// Timer und Kanal werden in jedem Fall verbraucht, damit sie niemand sonst belegt.
+    pub fn from_env<T: LedcTimer, C: LedcChannel>(
+        _timer: impl Peripheral<P = T> + 'static,
+        _channel: impl Peripheral<P = C> + 'static,
+    ) -> Option<Self> {
+        let millis = |value: Option<&str>| {
+            Duration::from_millis(value.and_then(|value| value.parse().ok()).unwrap_or_default())
+        };
+        let up = millis(option_env!("RELAY_RAMP_UP_MS"));
+        let down = millis(option_env!("RELAY_RAMP_DOWN_MS"));
+        if up.is_zero() && down.is_zero() {
+            return None;
+        }
+        let freq_hz = option_env!("RELAY_PWM_FREQ_HZ")
+            .and_then(|value| value.parse().ok())
+            .filter(|freq_hz| *freq_hz > 0)
+            .unwrap_or(DEFAULT_FREQ_HZ);
+        info!("Relais mit PWM-Rampe: {:?} hoch, {:?} runter, {} Hz", up, down, freq_hz);
+        Some(Self {
+            up,
+            down,
+            freq_hz,
+            timer: T::timer(),
+            channel: C::channel(),
+        })
+    }
+
+    /// Fährt einen Puls auf `pin`: Rampe hoch, `hold` voll an, Rampe runter.
+    ///
+    /// `cut_off` wird vor jedem Schritt gefragt, liefert es `true`, endet der Puls sofort. Danach
+    /// treibt die LEDC den Pin nicht mehr, zurückholen muss ihn der Aufrufer.
+    pub fn pulse(&self, pin: i32, active_low: bool, hold: Duration, cut_off: impl Fn() -> bool) -> Result<()> {
+        self.attach(pin, active_low)?;
+        let completed = self.fade(0, MAX_DUTY, self.up, &cut_off)
+            && wait(hold, &cut_off)
+            && self.fade(MAX_DUTY, 0, self.down, &cut_off);
+        if !completed {
+            warn!("PWM-Puls vorzeitig beendet");
+        }
+        esp!(unsafe { esp_idf_sys::ledc_stop(SPEED_MODE, self.channel, 0) })?;
+        Ok(())
+    }
+
+    /// Verbindet den Pin mit dem LEDC-Kanal, bei `active_low` mit invertiertem Ausgang.
+    fn attach(&self, pin: i32, active_low: bool) -> Result<()> {
+        let timer_config = esp_idf_sys::ledc_timer_config_t {
+            speed_mode: SPEED_MODE,
+            duty_resolution: RESOLUTION,
+            timer_num: self.timer,
+            freq_hz: self.freq_hz,
+            clk_cfg: esp_idf_sys::ledc_clk_cfg_t_LEDC_AUTO_CLK,
+            ..Default::default()
+        };
+        esp!(unsafe { esp_idf_sys::ledc_timer_config(&timer_config) })?;
+
+        let mut channel_config = esp_idf_sys::ledc_channel_config_t {
+            gpio_num: pin,
+            speed_mode: SPEED_MODE,
+            channel: self.channel,
+            timer_sel: self.timer,
+            duty: 0,
+            hpoint: 0,
+            ..Default::default()
+        };
+        channel_config.flags.set_output_invert(active_low as u32);
+        esp!(unsafe { esp_idf_sys::ledc_channel_config(&channel_config) })?;
+        Ok(())
+    }
+
+    /// Ändert die Einschaltdauer in `duration` gleichmäßig von `from` nach `to`.
+    fn fade(&self, from: u32, to: u32, duration: Duration, cut_off: &impl Fn() -> bool) -> bool {
+        let steps = (duration.as_millis() / STEP.as_millis()).max(1) as u32;
+        for step in 1..=steps {
+            if cut_off() {
+                return false;
+            }
+            let duty = if to > from {
+                from + (to - from) * step / steps
+            } else {
+                from - (from - to) * step / steps
+            };
+            let result = esp!(unsafe { esp_idf_sys::ledc_set_duty(SPEED_MODE, self.channel, duty) })
+                .and_then(|_| esp!(unsafe { esp_idf_sys::ledc_update_duty(SPEED_MODE, self.channel) }));
+            if let Err(err) = result {
+                warn!("PWM-Rampe: {:?}", err);
+                return false;
+            }
+            if steps > 1 {
+                std::thread::sleep(STEP);
+            }
+        }
+        true
+    }
+}
+
+/// Wartet `duration`, bricht aber nach spätestens [`STEP`] ab, wenn `cut_off` zutrifft.
+fn wait(duration: Duration, cut_off: &impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + duration;
+    loop {
+        if cut_off() {
+            return false;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        std::thread::sleep(left.min(STEP));
+    }
+}
@@ -0,0 +1,851 @@
+use anyhow::Result;
+use esp_idf_hal::gpio::{Level, Output, OutputPin, PinDriver};
+use log::*;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock;
+use crate::ramp::Ramp;
+
+/// Warum ein Relais-Auftrag abgelehnt wurde.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayError {
+    /// Es läuft bereits ein Puls.
+    Busy,
+    /// Das Relais ist dauerhaft eingeschaltet, ein Puls würde es danach abschalten.
+    Latched,
+    /// Der letzte Puls liegt weniger als `min_interval` zurück, frühestens nach der
+    /// angegebenen Zeit ist ein neuer möglich.
+    TooSoon(Duration),
+}
+
+impl RelayError {
+    pub fn message(self) -> &'static str {
+        match self {
+            RelayError::Busy => "pulse already active",
+            RelayError::Latched => "relay is latched on",
+            RelayError::TooSoon(_) => "too many requests",
+        }
+    }
+
+    /// Passender HTTP-Status für die Antwort.
+    pub fn status(self) -> u16 {
+        match self {
+            RelayError::Busy | RelayError::Latched => 409,
+            RelayError::TooSoon(_) => 429,
+        }
+    }
+
+    /// Wert für den `Retry-After`-Header in ganzen Sekunden, aufgerundet.
+    pub fn retry_after_secs(self) -> Option<u64> {
+        match self {
+            RelayError::TooSoon(wait) => Some(wait.as_millis().div_ceil(1000) as u64),
+            _ => None,
+        }
+    }
+}
+
+/// Einstellungen des Relais.
+#[derive(Debug, Clone, Copy)]
+pub struct RelayConfig {
+    /// Das Relais zieht bei Low-Pegel an.
+    pub active_low: bool,
+    /// Mindestabstand zwischen zwei Pulsen, schont Relais und Schloss.
+    pub min_interval: Duration,
+    /// Nach dieser Zeit wird der Pin zwangsweise abgeschaltet, egal wie er eingeschaltet wurde.
+    pub max_on_time: Duration,
+    /// Mindestzeit, die das Relais nach dem Abfallen aus bleibt, damit die Spule abkühlt.
+    ///
+    /// Anders als bei `min_interval` wird ein Puls in dieser Zeit nicht abgelehnt, sondern
+    /// zurückgestellt und nach Ablauf ausgeführt.
+    pub cooldown: Duration,
+    /// Rückmeldekontakt, der den tatsächlichen Schaltzustand liefert.
+    pub feedback: Option<Feedback>,
+    /// Pulse per PWM-Rampe statt hart schalten, siehe [`crate::ramp`].
+    pub ramp: Option<Ramp>,
+    /// Was mit einem Puls passiert, während schon einer läuft.
+    pub busy_policy: BusyPolicy,
+    /// Alles läuft wie sonst, nur der Pin wird nie eingeschaltet, für Tests und Vorführungen.
+    pub simulate: bool,
+}
+
+/// Umgang mit einem Puls, der während eines laufenden ankommt.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BusyPolicy {
+    /// Sofort mit [`RelayError::Busy`] ablehnen.
+    #[default]
+    Reject,
+    /// Einen Puls einreihen, er startet nach dem laufenden samt Mindestabstand und Abkühlzeit.
+    /// Jeder weitere wird abgelehnt, solange einer wartet.
+    Queue,
+}
+
+impl BusyPolicy {
+    /// `PUSH_BUSY_POLICY=reject` (Standard) oder `queue`.
+    pub fn from_env() -> Self {
+        match option_env!("PUSH_BUSY_POLICY") {
+            None | Some("reject") => Self::Reject,
+            Some("queue") => Self::Queue,
+            Some(other) => {
+                warn!("Ungültige PUSH_BUSY_POLICY {:?}, lehne ab", other);
+                Self::Reject
+            }
+        }
+    }
+}
+
+/// Eingang, an dem ein Hilfskontakt des Relais den tatsächlichen Zustand meldet.
+#[derive(Debug, Clone, Copy)]
+pub struct Feedback {
+    pub gpio: i32,
+    /// Der Kontakt zieht bei geschlossenem Relais auf High statt auf Masse.
+    pub active_high: bool,
+}
+
+impl Feedback {
+    /// Rückmeldung aus `RELAY_FEEDBACK_GPIO`, ohne die Variable keine.
+    ///
+    /// Standard ist ein potentialfreier Kontakt gegen Masse mit internem Pull-up, Module mit
+    /// eigenem Ausgang brauchen `RELAY_FEEDBACK_ACTIVE_HIGH=1`.
+    pub fn from_env() -> Option<Self> {
+        let gpio = option_env!("RELAY_FEEDBACK_GPIO")?.parse().ok()?;
+        Some(Self {
+            gpio,
+            active_high: option_env!("RELAY_FEEDBACK_ACTIVE_HIGH") == Some("1"),
+        })
+    }
+
+    fn init(self) {
+        unsafe {
+            esp_idf_sys::gpio_reset_pin(self.gpio);
+            esp_idf_sys::gpio_set_direction(self.gpio, esp_idf_sys::gpio_mode_t_GPIO_MODE_INPUT);
+            let pull = if self.active_high {
+                esp_idf_sys::gpio_pull_mode_t_GPIO_PULLDOWN_ONLY
+            } else {
+                esp_idf_sys::gpio_pull_mode_t_GPIO_PULLUP_ONLY
+            };
+            esp_idf_sys::gpio_set_pull_mode(self.gpio, pull);
+        }
+    }
+
+    /// `true`, wenn der Kontakt meldet, dass das Relais angezogen ist.
+    fn is_on(self) -> bool {
+        let high = unsafe { esp_idf_sys::gpio_get_level(self.gpio) } != 0;
+        high == self.active_high
+    }
+}
+
+// Prüfintervall der Sicherheitsabschaltung
+const SAFETY_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// So lange braucht der Kontakt nach dem Schalten, bevor die Rückmeldung gelesen wird
+const FEEDBACK_SETTLE: Duration = Duration::from_millis(20);
+
+/// Länge des Testpulses beim Selbsttest, hörbar, aber kurz genug für kein Türschloss.
+const SELF_TEST_PULSE: Duration = Duration::from_millis(150);
+
+/// Dauerbetrieb über `/relay/on`, `/relay/off` und `/relay/toggle`.
+#[derive(Debug, Clone, Copy)]
+pub enum LatchAction {
+    On,
+    Off,
+    Toggle,
+}
+
+/// Momentaufnahme des Relais für Anzeige und `/status`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RelayState {
+    pub pulsing: bool,
+    pub latched: bool,
+    /// Beginn des letzten Pulses
+    pub last_pulse: Option<Instant>,
+    /// Seit wann der Pin angezogen ist
+    pub on_since: Option<Instant>,
+    /// Die Rückmeldung passte bei der letzten Prüfung nicht zum geschalteten Zustand
+    pub mismatch: bool,
+    /// Seit wann der Pin wieder abgefallen ist, Beginn der Abkühlzeit
+    pub off_since: Option<Instant>,
+    /// Ein zurückgestellter Puls wartet bis zu diesem Zeitpunkt auf das Ende der Abkühlzeit
+    pub deferred_until: Option<Instant>,
+    /// Ein eingereihter Puls wartet auf das Ende des laufenden, siehe [`BusyPolicy::Queue`]
+    pub queued: bool,
+}
+
+impl RelayState {
+    /// `true`, solange der Pin angezogen ist.
+    pub fn is_active(self) -> bool {
+        self.pulsing || self.latched
+    }
+}
+
+/// Pegel, bei dem das Relais angezogen (`on`) bzw. abgefallen ist.
+fn level(on: bool, active_low: bool) -> Level {
+    if on != active_low {
+        Level::High
+    } else {
+        Level::Low
+    }
+}
+
+/// Schaltet den Pin direkt über den GPIO-Treiber ab, ohne den Worker zu benötigen.
+///
+/// Wird von der Sicherheitsabschaltung und im Panic-Hook benutzt, wenn der Worker womöglich
+/// hängt oder gar nicht mehr läuft.
+///
+/// Treibt gerade die PWM-Rampe den Pin, holt ihn das Setzen der Richtung von der LEDC zurück.
+fn force_off(pin: i32, active_low: bool) {
+    unsafe {
+        esp_idf_sys::gpio_set_level(pin, active_low as u32);
+        esp_idf_sys::gpio_set_direction(pin, esp_idf_sys::gpio_mode_t_GPIO_MODE_OUTPUT);
+    }
+}
+
+/// Wartet vor einem zurückgestellten Puls das Ende der Abkühlzeit ab und setzt dann dessen Beginn.
+///
+/// Liefert `false`, wenn der Puls inzwischen verworfen wurde, etwa durch [`Relay::force_off`].
+fn wait_for_cooldown(state: &Mutex<RelayState>) -> bool {
+    let Some(until) = state.lock().unwrap().deferred_until else {
+        return true;
+    };
+    std::thread::sleep(until.saturating_duration_since(Instant::now()));
+    let mut state = state.lock().unwrap();
+    if state.deferred_until.take().is_none() {
+        state.pulsing = false;
+        return false;
+    }
+    let now = Instant::now();
+    state.last_pulse = Some(now);
+    state.on_since = Some(now);
+    true
+}
+
+/// Wartet vor einem eingereihten Puls Mindestabstand und Abkühlzeit ab und setzt dann dessen Beginn.
+///
+/// Liefert `false`, wenn der Puls inzwischen verworfen wurde, etwa durch [`Relay::force_off`].
+fn begin_queued(state: &Mutex<RelayState>, config: &RelayConfig) -> bool {
+    let ready = {
+        let state = state.lock().unwrap();
+        if !state.queued {
+            return false;
+        }
+        let after_cooldown = state.off_since.map(|off_since| off_since + config.cooldown);
+        let after_interval = state.last_pulse.map(|last_pulse| last_pulse + config.min_interval);
+        after_cooldown.max(after_interval)
+    };
+    if let Some(ready) = ready {
+        std::thread::sleep(ready.saturating_duration_since(Instant::now()));
+    }
+    let mut state = state.lock().unwrap();
+    if !std::mem::take(&mut state.queued) {
+        return false;
+    }
+    let now = Instant::now();
+    state.pulsing = true;
+    state.last_pulse = Some(now);
+    state.on_since = Some(now);
+    true
+}
+
+/// So viele Schaltvorgänge merkt sich jedes Relais für `/status`.
+pub const ACTIVATION_LOG_LEN: usize = 10;
+
+/// Wer ein Relais geschaltet hat, für die Liste der letzten Schaltvorgänge.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    #[default]
+    Http,
+    Button,
+    Mqtt,
+    Schedule,
+    #[cfg(feature = "coap")]
+    Coap,
+    #[cfg(feature = "encoder")]
+    Encoder,
+    /// Selbsttest und wiederhergestellter Dauerbetrieb nach dem Start
+    System,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ActivationKind {
+    Pulse,
+    Sequence,
+    Latch,
+}
+
+/// Ein Schaltvorgang, wie ihn der Worker gemessen hat.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Activation {
+    /// Unix-Zeit des Einschaltens, `None` solange die Uhr nicht synchronisiert ist
+    pub timestamp: Option<i64>,
+    pub source: Trigger,
+    pub kind: ActivationKind,
+    /// Verlangte Einschaltdauer, `None` im Dauerbetrieb
+    pub requested_ms: Option<u64>,
+    /// Tatsächlich gemessene Einschaltdauer, `None` solange der Dauerbetrieb noch läuft
+    pub on_ms: Option<u64>,
+}
+
+/// Ringpuffer der letzten [`ACTIVATION_LOG_LEN`] Schaltvorgänge.
+///
+/// Während des Dauerbetriebs kann kein Puls starten, ein offener Dauerbetrieb ist also immer der
+/// letzte Eintrag.
+#[derive(Default)]
+struct Activations {
+    entries: VecDeque<Activation>,
+    latched_since: Option<Instant>,
+}
+
+impl Activations {
+    fn record(&mut self, activation: Activation) {
+        if self.entries.len() == ACTIVATION_LOG_LEN {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(activation);
+    }
+
+    fn open_latch(&mut self, source: Trigger) {
+        if self.latched_since.is_some() {
+            return;
+        }
+        self.latched_since = Some(Instant::now());
+        self.record(Activation {
+            timestamp: unix_now(),
+            source,
+            kind: ActivationKind::Latch,
+            requested_ms: None,
+            on_ms: None,
+        });
+    }
+
+    fn close_latch(&mut self) {
+        let Some(since) = self.latched_since.take() else {
+            return;
+        };
+        if let Some(last) = self.entries.back_mut() {
+            last.on_ms = Some(since.elapsed().as_millis() as u64);
+        }
+    }
+}
+
+fn unix_now() -> Option<i64> {
+    clock::is_synced().then(|| clock::now().timestamp())
+}
+
+/// Ein Schritt einer Pulsfolge: erst `on` angezogen, dann `off` abgefallen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceStep {
+    pub on: Duration,
+    pub off: Duration,
+}
+
+enum Command {
+    Pulse(Duration),
+    /// Puls, der während eines laufenden angenommen wurde
+    Queued(Duration),
+    Sequence(Vec<SequenceStep>),
+    Set(bool),
+}
+
+/// Steuert das Relais über einen eigenen Worker-Thread.
+///
+/// Die HTTP-Handler geben den Puls nur in Auftrag und antworten sofort, das Setzen und
+/// Zurücksetzen des Pins passiert ausschließlich im Worker. Dadurch ist der Pin auch dann
+/// wieder abgefallen, wenn das Schreiben der Antwort fehlschlägt.
+///
+/// Ein `/push` während eines laufenden Pulses wird abgelehnt (409 Conflict) und verlängert
+/// den Puls nicht, mit [`BusyPolicy::Queue`] wird stattdessen einer eingereiht. Ist das Relais
+/// per Latch eingeschaltet, wird `/push` ebenfalls abgelehnt, statt den Dauerbetrieb nach dem
+/// Puls zu beenden. Umgekehrt kann während eines Pulses nicht eingeschaltet werden.
+///
+/// Bei `active_low` zieht das Relais bei Low-Pegel an, alle Schaltvorgänge werden entsprechend
+/// invertiert. Pulse, die früher als `min_interval` nach dem letzten kommen, werden abgelehnt.
+///
+/// Ein zweiter Thread überwacht die Einschaltdauer und schaltet den Pin nach `max_on_time`
+/// zwangsweise ab, auch im Dauerbetrieb oder wenn der Worker hängt. Bei einem Panic wird der Pin
+/// ebenfalls abgeschaltet, bevor das Programm abbricht.
+///
+/// Eine Pulsfolge belegt das Relais wie ein einzelner Puls, bis ihr letzter Schritt vorbei ist.
+///
+/// Kommt ein Puls oder eine Pulsfolge innerhalb von `cooldown` nach dem Abfallen, wartet der
+/// Worker das Ende der Abkühlzeit ab. Das Relais gilt solange schon als belegt.
+///
+/// Mit einem [`Feedback`]-Kontakt wird nach jedem Schaltvorgang geprüft, ob das Relais wirklich
+/// gefolgt ist, um verklebte Kontakte oder ein totes Modul zu erkennen.
+///
+/// Mit `simulate` gelten alle Regeln und Zustände wie sonst, eingeschaltet wird aber nur im
+/// Zustand, der Pin bleibt abgefallen. Die Rückmeldung wird dann nicht geprüft.
+///
+/// Jeden Schaltvorgang misst der Worker und hält ihn samt Auslöser in [`Relay::activations`] fest,
+/// den Auslöser setzt [`Relay::with_trigger`].
+#[derive(Clone)]
+pub struct Relay {
+    commands: Sender<(Command, Trigger)>,
+    state: Arc<Mutex<RelayState>>,
+    config: RelayConfig,
+    cutoff: Arc<AtomicBool>,
+    sequence_done: Arc<AtomicBool>,
+    mismatch: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    activations: Arc<Mutex<Activations>>,
+    trigger: Trigger,
+    pin_number: i32,
+}
+
+impl Relay {
+    pub fn spawn(mut pin: PinDriver<'static, impl OutputPin, Output>, config: RelayConfig) -> Result<Self> {
+        let active_low = config.active_low;
+        let pin_number = pin.pin();
+        // Nach dem Deep Sleep ist der Pin noch gehalten, siehe `prepare_sleep`
+        unsafe { esp_idf_sys::gpio_hold_dis(pin_number) };
+        // Sofort in den abgefallenen Zustand, unabhängig von der Polarität
+        pin.set_level(level(false, active_low))?;
+
+        // Bei einem Panic das Relais abschalten, bevor der vorherige Hook ausgibt und abbricht
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            force_off(pin_number, active_low);
+            previous_hook(info);
+        }));
+
+        let (commands, receiver) = mpsc::channel::<(Command, Trigger)>();
+        let state = Arc::new(Mutex::new(RelayState::default()));
+        let cutoff = Arc::new(AtomicBool::new(false));
+        let sequence_done = Arc::new(AtomicBool::new(false));
+        let mismatch = Arc::new(AtomicBool::new(false));
+        let running = Arc::new(AtomicBool::new(false));
+        let activations = Arc::new(Mutex::new(Activations::default()));
+        if let Some(feedback) = config.feedback {
+            feedback.init();
+        }
+
+        {
+            let state = state.clone();
+            let sequence_done = sequence_done.clone();
+            let mismatch = mismatch.clone();
+            let running = running.clone();
+            let activations = activations.clone();
+            // Vergleicht die Rückmeldung mit dem geschalteten Zustand, braucht `FEEDBACK_SETTLE`
+            let check_feedback = move |on: bool, state: &Mutex<RelayState>| {
+                let Some(feedback) = config.feedback.filter(|_| !config.simulate) else {
+                    return;
+                };
+                std::thread::sleep(FEEDBACK_SETTLE);
+                let matches = feedback.is_on() == on;
+                if !matches {
+                    warn!("Relais-Rückmeldung passt nicht: geschaltet {}, gemeldet {}", on, !on);
+                    mismatch.store(true, Ordering::Relaxed);
+                }
+                state.lock().unwrap().mismatch = !matches;
+            };
+            // Schaltet den Pin, in der Simulation nur ab
+            let mut set = move |on: bool| {
+                if on && config.simulate {
+                    debug!("Simulation: Relais an");
+                    return Ok(());
+                }
+                pin.set_level(level(on, active_low))
+            };
+            std::thread::Builder::new()
+                .name("relay".into())
+                .stack_size(4096)
+                .spawn(move || {
+                    running.store(true, Ordering::Relaxed);
+                    for (command, trigger) in receiver {
+                        let command = match command {
+                            Command::Queued(duration) => {
+                                if !begin_queued(&state, &config) {
+                                    continue;
+                                }
+                                Command::Pulse(duration)
+                            }
+                            command => command,
+                        };
+                        if matches!(command, Command::Pulse(_) | Command::Sequence(_))
+                            && !wait_for_cooldown(&state)
+                        {
+                            continue;
+                        }
+                        match command {
+                            Command::Pulse(duration) => {
+                                let timestamp = unix_now();
+                                let switched_on = Instant::now();
+                                let mut switched = true;
+                                if let Some(ramp) = config.ramp.filter(|_| !config.simulate) {
+                                    // Die Sicherheitsabschaltung löscht `on_since`, dann sofort aus
+                                    let cut_off = || state.lock().unwrap().on_since.is_none();
+                                    if let Err(err) = ramp.pulse(pin_number, active_low, duration, cut_off) {
+                                        error!("PWM-Rampe fehlgeschlagen: {:?}", err);
+                                    }
+                                    force_off(pin_number, active_low);
+                                } else if let Err(err) = set(true) {
+                                    error!("Relais konnte nicht geschaltet werden: {:?}", err);
+                                    switched = false;
+                                } else {
+                                    let started = Instant::now();
+                                    if duration > FEEDBACK_SETTLE {
+                                        check_feedback(true, &state);
+                                    }
+                                    std::thread::sleep(duration.saturating_sub(started.elapsed()));
+                                }
+                                // Unabhängig vom Ergebnis immer zurücksetzen
+                                if let Err(err) = set(false) {
+                                    error!("Relais konnte nicht zurückgesetzt werden: {:?}", err);
+                                }
+                                let on_time = if switched { switched_on.elapsed() } else { Duration::ZERO };
+                                activations.lock().unwrap().record(Activation {
+                                    timestamp,
+                                    source: trigger,
+                                    kind: ActivationKind::Pulse,
+                                    requested_ms: Some(duration.as_millis() as u64),
+                                    on_ms: Some(on_time.as_millis() as u64),
+                                });
+                                check_feedback(false, &state);
+                                let mut state = state.lock().unwrap();
+                                state.pulsing = false;
+                                state.on_since = None;
+                                state.off_since = Some(Instant::now());
+                            }
+                            Command::Sequence(steps) => {
+                                let timestamp = unix_now();
+                                let requested: Duration = steps.iter().map(|step| step.on).sum();
+                                let mut on_time = Duration::ZERO;
+                                for step in steps {
+                                    state.lock().unwrap().on_since = Some(Instant::now());
+                                    let started = Instant::now();
+                                    if let Err(err) = set(true) {
+                                        error!("Relais konnte nicht geschaltet werden: {:?}", err);
+                                    } else {
+                                        if step.on > FEEDBACK_SETTLE {
+                                            check_feedback(true, &state);
+                                        }
+                                        std::thread::sleep(step.on.saturating_sub(started.elapsed()));
+                                        on_time += started.elapsed();
+                                    }
+                                    if let Err(err) = set(false) {
+                                        error!("Relais konnte nicht zurückgesetzt werden: {:?}", err);
+                                    }
+                                    if step.off > FEEDBACK_SETTLE {
+                                        check_feedback(false, &state);
+                                    }
+                                    // Hat die Sicherheitsabschaltung zugeschlagen, nicht wieder einschalten
+                                    if state.lock().unwrap().on_since.take().is_none() {
+                                        break;
+                                    }
+                                    std::thread::sleep(step.off);
+                                }
+                                activations.lock().unwrap().record(Activation {
+                                    timestamp,
+                                    source: trigger,
+                                    kind: ActivationKind::Sequence,
+                                    requested_ms: Some(requested.as_millis() as u64),
+                                    on_ms: Some(on_time.as_millis() as u64),
+                                });
+                                let mut state = state.lock().unwrap();
+                                state.pulsing = false;
+                                state.on_since = None;
+                                state.off_since = Some(Instant::now());
+                                sequence_done.store(true, Ordering::Relaxed);
+                            }
+                            Command::Set(on) => {
+                                if let Err(err) = set(on) {
+                                    error!("Relais konnte nicht geschaltet werden: {:?}", err);
+                                }
+                                let mut activations = activations.lock().unwrap();
+                                if on {
+                                    activations.open_latch(trigger);
+                                } else {
+                                    activations.close_latch();
+                                }
+                                drop(activations);
+                                if !on {
+                                    state.lock().unwrap().off_since = Some(Instant::now());
+                                }
+                                check_feedback(on, &state);
+                            }
+                        }
+                    }
+                    running.store(false, Ordering::Relaxed);
+                })?;
+        }
+
+        {
+            let state = state.clone();
+            let cutoff = cutoff.clone();
+            let activations = activations.clone();
+            std::thread::Builder::new()
+                .name("relay-safety".into())
+                .stack_size(3072)
+                .spawn(move || loop {
+                    std::thread::sleep(SAFETY_CHECK_INTERVAL);
+                    let mut state = state.lock().unwrap();
+                    let Some(on_since) = state.on_since else {
+                        continue;
+                    };
+                    if on_since.elapsed() < config.max_on_time {
+                        continue;
+                    }
+                    // `pulsing` bleibt gesetzt, bis der Worker fertig ist, damit er nicht
+                    // gleichzeitig einen neuen Puls bekommt
+                    force_off(pin_number, active_low);
+                    state.latched = false;
+                    state.on_since = None;
+                    state.off_since = Some(Instant::now());
+                    activations.lock().unwrap().close_latch();
+                    cutoff.store(true, Ordering::Relaxed);
+                    error!(
+                        "Sicherheitsabschaltung: Relais war länger als {:?} angezogen",
+                        config.max_on_time
+                    );
+                })?;
+        }
+
+        Ok(Self {
+            commands,
+            state,
+            config,
+            cutoff,
+            sequence_done,
+            mismatch,
+            running,
+            activations,
+            trigger: Trigger::default(),
+            pin_number,
+        })
+    }
+
+    /// Dasselbe Relais, dessen Schaltvorgänge aber als von `trigger` ausgelöst gelten.
+    pub fn with_trigger(&self, trigger: Trigger) -> Self {
+        Self {
+            trigger,
+            ..self.clone()
+        }
+    }
+
+    /// Die letzten Schaltvorgänge, älteste zuerst.
+    pub fn activations(&self) -> Vec<Activation> {
+        self.activations.lock().unwrap().entries.iter().copied().collect()
+    }
+
+    /// Startet einen Puls der Länge `duration`, ohne auf dessen Ende zu warten.
+    pub fn pulse(&self, duration: Duration) -> Result<(), RelayError> {
+        self.pulse_or_queue(duration).map(|_| ())
+    }
+
+    /// Wie [`Relay::pulse`], reiht den Puls aber mit [`BusyPolicy::Queue`] hinter einem laufenden
+    /// ein. Liefert `true`, wenn er eingereiht wurde.
+    pub fn pulse_or_queue(&self, duration: Duration) -> Result<bool, RelayError> {
+        match self.start(Command::Pulse(duration)) {
+            Err(RelayError::Busy) if self.config.busy_policy == BusyPolicy::Queue => {
+                self.enqueue(duration)?;
+                Ok(true)
+            }
+            result => result.map(|_| false),
+        }
+    }
+
+    fn enqueue(&self, duration: Duration) -> Result<(), RelayError> {
+        let mut state = self.state.lock().unwrap();
+        if state.latched {
+            return Err(RelayError::Latched);
+        }
+        if state.queued {
+            return Err(RelayError::Busy);
+        }
+        self.commands
+            .send((Command::Queued(duration), self.trigger))
+            .map_err(|_| RelayError::Busy)?;
+        state.queued = true;
+        info!("Puls eingereiht, startet nach dem laufenden");
+        Ok(())
+    }
+
+    /// Startet eine Pulsfolge, ohne auf deren Ende zu warten.
+    ///
+    /// Es gelten dieselben Regeln wie für [`Relay::pulse`], eine laufende Folge ergibt
+    /// also `Busy`. Wann sie fertig ist, meldet [`Relay::take_sequence_done`].
+    pub fn sequence(&self, steps: Vec<SequenceStep>) -> Result<(), RelayError> {
+        self.start(Command::Sequence(steps))
+    }
+
+    fn start(&self, command: Command) -> Result<(), RelayError> {
+        let mut state = self.state.lock().unwrap();
+        if state.latched {
+            return Err(RelayError::Latched);
+        }
+        // Auch ein wartender Puls belegt das Relais, sonst käme ein neuer ihm zuvor
+        if state.pulsing || state.queued {
+            return Err(RelayError::Busy);
+        }
+        let now = Instant::now();
+        if let Some(last_pulse) = state.last_pulse {
+            let since = now.duration_since(last_pulse);
+            if since < self.config.min_interval {
+                return Err(RelayError::TooSoon(self.config.min_interval - since));
+            }
+        }
+        // In der Abkühlzeit startet der Worker den Puls erst später und setzt dann den Beginn
+        if let Some(until) = self.cooldown_until(&state).filter(|until| *until > now) {
+            self.commands.send((command, self.trigger)).map_err(|_| RelayError::Busy)?;
+            state.pulsing = true;
+            state.deferred_until = Some(until);
+            info!("Puls in der Abkühlzeit, startet in {:?}", until - now);
+            return Ok(());
+        }
+        self.commands.send((command, self.trigger)).map_err(|_| RelayError::Busy)?;
+        state.pulsing = true;
+        state.last_pulse = Some(now);
+        state.on_since = Some(now);
+        Ok(())
+    }
+
+    /// Ende der Abkühlzeit nach dem letzten Abfallen, `None` ohne `cooldown`.
+    fn cooldown_until(&self, state: &RelayState) -> Option<Instant> {
+        if self.config.cooldown.is_zero() {
+            return None;
+        }
+        state.off_since.map(|off_since| off_since + self.config.cooldown)
+    }
+
+    /// Verbleibende Abkühlzeit, `None` wenn das Relais wieder schalten darf.
+    pub fn cooldown_remaining(&self) -> Option<Duration> {
+        let state = self.state();
+        if state.on_since.is_some() {
+            return None;
+        }
+        let until = state.deferred_until.or_else(|| self.cooldown_until(&state))?;
+        Some(until.saturating_duration_since(Instant::now())).filter(|left| !left.is_zero())
+    }
+
+    /// Schaltet den Dauerbetrieb und liefert den neuen Zustand.
+    pub fn latch(&self, action: LatchAction) -> Result<bool, RelayError> {
+        let mut state = self.state.lock().unwrap();
+        let on = match action {
+            LatchAction::On => true,
+            LatchAction::Off => false,
+            LatchAction::Toggle => !state.latched,
+        };
+        if on && (state.pulsing || state.queued) {
+            return Err(RelayError::Busy);
+        }
+        self.commands
+            .send((Command::Set(on), self.trigger))
+            .map_err(|_| RelayError::Busy)?;
+        state.latched = on;
+        state.on_since = match (on, state.on_since) {
+            (true, Some(since)) => Some(since),
+            (true, None) => Some(Instant::now()),
+            (false, _) => None,
+        };
+        Ok(on)
+    }
+
+    /// Schaltet den Pin vor dem Schlafen ab und hält ihn auf diesem Pegel, auch im Deep Sleep.
+    ///
+    /// Liefert `false`, solange das Relais angezogen ist, dann wird nicht geschlafen.
+    pub fn prepare_sleep(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        if state.is_active() {
+            return false;
+        }
+        force_off(self.pin_number, self.config.active_low);
+        unsafe {
+            esp_idf_sys::gpio_hold_en(self.pin_number);
+            esp_idf_sys::gpio_deep_sleep_hold_en();
+        }
+        true
+    }
+
+    /// Schaltet den Pin sofort ab, auch mitten in einem Puls, z.B. vor einem gewollten Neustart.
+    ///
+    /// Der Worker läuft danach weiter, der Pin ist nur bis zu dessen nächstem Schaltvorgang aus.
+    pub fn force_off(&self) {
+        force_off(self.pin_number, self.config.active_low);
+        let mut state = self.state.lock().unwrap();
+        state.latched = false;
+        state.on_since = None;
+        // Ein zurückgestellter oder eingereihter Puls wird verworfen, siehe `wait_for_cooldown`
+        // und `begin_queued`
+        state.deferred_until = None;
+        state.queued = false;
+        drop(state);
+        self.activations.lock().unwrap().close_latch();
+    }
+
+    /// Gibt den Pin nach einem Light Sleep wieder frei.
+    pub fn resume(&self) {
+        unsafe {
+            esp_idf_sys::gpio_deep_sleep_hold_dis();
+            esp_idf_sys::gpio_hold_dis(self.pin_number);
+        }
+    }
+
+    /// `true`, wenn seit dem letzten Aufruf eine Sicherheitsabschaltung stattgefunden hat.
+    pub fn take_cutoff(&self) -> bool {
+        self.cutoff.swap(false, Ordering::Relaxed)
+    }
+
+    /// Schaltet einmal kurz wie ein normaler Puls und wartet dessen Ende ab.
+    ///
+    /// Liefert mit [`Feedback`]-Kontakt, ob das Relais gefolgt ist, ohne `None`.
+    pub fn self_test(&self) -> Result<Option<bool>, RelayError> {
+        self.with_trigger(Trigger::System).pulse(SELF_TEST_PULSE)?;
+        // Der Worker braucht neben dem Puls noch die Prüfzeit der Rückmeldung und ggf. die Abkühlzeit
+        let cooldown = self.cooldown_remaining().unwrap_or_default();
+        let deadline = Instant::now() + cooldown + SELF_TEST_PULSE + FEEDBACK_SETTLE * 4;
+        while self.state().pulsing && Instant::now() < deadline {
+            std::thread::sleep(FEEDBACK_SETTLE);
+        }
+        Ok(self.config.feedback.map(|_| !self.state().mismatch))
+    }
+
+    /// Wartet, bis der laufende oder zurückgestellte Puls vorbei ist, samt einem eingereihten.
+    ///
+    /// Spätestens nach Abkühlzeit und `max_on_time` hat die Sicherheitsabschaltung gegriffen, mit
+    /// etwas Spielraum für die Rückmeldung wird danach mit `false` aufgegeben.
+    pub fn wait_idle(&self) -> bool {
+        let per_pulse = self.config.max_on_time + SAFETY_CHECK_INTERVAL + FEEDBACK_SETTLE * 4;
+        let queued = if self.state().queued {
+            per_pulse + self.config.cooldown.max(self.config.min_interval)
+        } else {
+            Duration::ZERO
+        };
+        let deadline = Instant::now() + self.cooldown_remaining().unwrap_or_default() + per_pulse + queued;
+        while self.state().pulsing || self.state().queued {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(FEEDBACK_SETTLE);
+        }
+        true
+    }
+
+    /// `true`, wenn seit dem letzten Aufruf eine Pulsfolge zu Ende gegangen ist.
+    pub fn take_sequence_done(&self) -> bool {
+        self.sequence_done.swap(false, Ordering::Relaxed)
+    }
+
+    /// `true`, wenn seit dem letzten Aufruf die Rückmeldung nicht zum Schaltzustand gepasst hat.
+    pub fn take_mismatch(&self) -> bool {
+        self.mismatch.swap(false, Ordering::Relaxed)
+    }
+
+    /// `true`, sobald der Worker Aufträge annimmt, und solange er läuft.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Nummer des geschalteten Pins.
+    pub fn pin_number(&self) -> i32 {
+        self.pin_number
+    }
+
+    pub fn state(&self) -> RelayState {
+        *self.state.lock().unwrap()
+    }
+}
@@ -0,0 +1,238 @@
+//! Mehrere unabhängige Relais an einem Gerät, z.B. Tor, Garage und Licht.
+//!
+//! Das erste Relais ist immer das Relais am Board-Pin, `RELAY_NAME` benennt es (Standard `door`).
+//! Weitere kommen aus `RELAYS` als kommagetrennte Liste `name:gpio[:pulse_ms[:low|high]]`, z.B.
+//! `RELAYS=garage:25:800,light:26::high`. Ohne Pulsdauer gilt die Einstellung aus `/config`, ohne
+//! Polarität `RELAY_ACTIVE_LOW`. Mindestabstand und Höchstdauer teilen sich alle Relais, eine
+//! Rückmeldung gibt es nur für das erste.
+//!
+//! Geschaltet wird über `/relay/<name>/push`, `/relay/<name>/on`, `/relay/<name>/off` und
+//! `/relay/<name>/toggle`. `/push` und `/relay/on` usw. bleiben Aliase für das erste Relais.
+
+use anyhow::Result;
+use esp_idf_hal::gpio::{AnyOutputPin, PinDriver};
+use log::*;
+use std::sync::Arc;
+
+use crate::board;
+use crate::relay::{LatchAction, Relay, RelayConfig};
+use crate::settings::{MAX_PULSE_MS, MIN_PULSE_MS};
+
+/// Name des ersten Relais ohne `RELAY_NAME`.
+const DEFAULT_PRIMARY_NAME: &str = "door";
+
+/// Höchstzahl der Relais samt dem ersten, jedes belegt zwei Threads.
+pub const MAX_RELAYS: usize = 4;
+
+/// Ein zusätzliches Relais, wie es in `RELAYS` steht.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelaySpec {
+    pub name: String,
+    pub gpio: i32,
+    pub pulse_ms: Option<u64>,
+    pub active_low: Option<bool>,
+}
+
+/// Ein Relais samt Namen und eigener Pulsdauer.
+#[derive(Clone)]
+pub struct NamedRelay {
+    pub name: String,
+    pub relay: Relay,
+    /// Pulsdauer für `/relay/<name>/push`, ohne gilt die Einstellung aus `/config`
+    pub pulse_ms: Option<u64>,
+}
+
+/// Alle Relais nach Namen, geteilt zwischen Handlern und Hauptschleife.
+///
+/// Bei höchstens [`MAX_RELAYS`] Einträgen reicht eine Liste, die zugleich die Reihenfolge für
+/// `/status` und das Display festhält.
+#[derive(Clone)]
+pub struct RelaySet {
+    relays: Arc<Vec<NamedRelay>>,
+}
+
+impl RelaySet {
+    /// Legt zum ersten Relais die aus `RELAYS` an, fehlerhafte Einträge werden mit Warnung
+    /// übersprungen.
+    pub fn spawn(primary: Relay, config: RelayConfig) -> Result<Self> {
+        let primary_name = option_env!("RELAY_NAME")
+            .filter(|name| {
+                let valid = is_valid_name(name);
+                if !valid {
+                    warn!("Ungültiger RELAY_NAME {:?}, verwende {}", name, DEFAULT_PRIMARY_NAME);
+                }
+                valid
+            })
+            .unwrap_or(DEFAULT_PRIMARY_NAME);
+        let mut relays = vec![NamedRelay {
+            name: primary_name.to_string(),
+            relay: primary,
+            pulse_ms: None,
+        }];
+
+        let used = board::used_gpios();
+        for spec in option_env!("RELAYS").map(parse_specs).unwrap_or_default() {
+            let spec = match spec {
+                Ok(spec) => spec,
+                Err(err) => {
+                    warn!("RELAYS: {}", err);
+                    continue;
+                }
+            };
+            if relays.len() >= MAX_RELAYS {
+                warn!("RELAYS: höchstens {} Relais, {} übersprungen", MAX_RELAYS, spec.name);
+                continue;
+            }
+            if relays.iter().any(|relay| relay.name == spec.name) {
+                warn!("RELAYS: {} ist doppelt", spec.name);
+                continue;
+            }
+            if let Some(reason) = reserved_gpio(spec.gpio, &relays, &used) {
+                warn!("RELAYS: GPIO{} für {} ist {}", spec.gpio, spec.name, reason);
+                continue;
+            }
+
+            let active_low = spec.active_low.unwrap_or(config.active_low);
+            // Wie beim ersten Relais den Ruhepegel setzen, bevor der Pin Ausgang wird
+            unsafe { esp_idf_sys::gpio_set_level(spec.gpio, active_low as u32) };
+            // Der Pin steht nur in `RELAYS`, `reserved_gpio` hat ihn gegen alle übrigen
+            // konfigurierten Pins geprüft
+            let pin = unsafe { AnyOutputPin::new(spec.gpio) };
+            let relay = PinDriver::output(pin).map_err(anyhow::Error::from).and_then(|driver| {
+                Relay::spawn(
+                    driver,
+                    RelayConfig {
+                        active_low,
+                        feedback: None,
+                        // LEDC-Kanal und Rampe gehören dem ersten Relais
+                        ramp: None,
+                        ..config
+                    },
+                )
+            });
+            let relay = match relay {
+                Ok(relay) => relay,
+                Err(err) => {
+                    warn!("RELAYS: {} an GPIO{} nicht angelegt: {:?}", spec.name, spec.gpio, err);
+                    continue;
+                }
+            };
+            info!("Relais {} an GPIO{}", spec.name, spec.gpio);
+            relays.push(NamedRelay {
+                name: spec.name,
+                relay,
+                pulse_ms: spec.pulse_ms,
+            });
+        }
+
+        Ok(Self {
+            relays: Arc::new(relays),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NamedRelay> {
+        self.relays.iter().find(|relay| relay.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &NamedRelay> {
+        self.relays.iter()
+    }
+
+    /// Schaltet den Dauerbetrieb aller Relais ab, etwa bei kritischem Akku.
+    pub fn all_off(&self) {
+        for named in self.iter() {
+            let _ = named.relay.latch(LatchAction::Off);
+        }
+    }
+
+    /// Schaltet alle Relais sofort ab, auch laufende Pulse, vor einem Neustart.
+    pub fn shutdown(&self) {
+        for named in self.iter() {
+            named.relay.force_off();
+        }
+    }
+
+    /// Wie [`Relay::prepare_sleep`] für alle Relais, schon eines angezogen verhindert das Schlafen.
+    pub fn prepare_sleep(&self) -> bool {
+        if self.iter().any(|named| named.relay.state().is_active()) {
+            return false;
+        }
+        self.iter().all(|named| named.relay.prepare_sleep())
+    }
+
+    pub fn resume(&self) {
+        for named in self.iter() {
+            named.relay.resume();
+        }
+    }
+}
+
+/// Liest die Einträge aus `RELAYS`, jeder für sich gültig oder mit Fehlermeldung.
+pub fn parse_specs(value: &str) -> Vec<Result<RelaySpec, String>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(parse_spec)
+        .collect()
+}
+
+fn parse_spec(entry: &str) -> Result<RelaySpec, String> {
+    let mut parts = entry.split(':');
+    let name = parts.next().unwrap_or_default();
+    if !is_valid_name(name) {
+        return Err(format!("ungültiger Name in {:?}", entry));
+    }
+    let gpio = parts
+        .next()
+        .and_then(|gpio| gpio.parse().ok())
+        .ok_or_else(|| format!("GPIO fehlt in {:?}", entry))?;
+    let pulse_ms = match parts.next() {
+        None | Some("") => None,
+        Some(ms) => Some(
+            ms.parse()
+                .ok()
+                .filter(|ms| (MIN_PULSE_MS..=MAX_PULSE_MS).contains(ms))
+                .ok_or_else(|| format!("ungültige Pulsdauer in {:?}", entry))?,
+        ),
+    };
+    let active_low = match parts.next() {
+        None | Some("") => None,
+        Some("low") => Some(true),
+        Some("high") => Some(false),
+        Some(_) => return Err(format!("Polarität muss low oder high sein in {:?}", entry)),
+    };
+    if parts.next().is_some() {
+        return Err(format!("zu viele Felder in {:?}", entry));
+    }
+    Ok(RelaySpec {
+        name: name.to_string(),
+        gpio,
+        pulse_ms,
+        active_low,
+    })
+}
+
+/// Kleinbuchstaben, Ziffern und `-`, aber nicht die Aktionen der alten `/relay/<aktion>`-Pfade.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 16
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !matches!(name, "on" | "off" | "toggle" | "push")
+}
+
+/// Warum `gpio` nicht für ein weiteres Relais taugt, `None` wenn er frei ist. `used` sind die
+/// Pins aus [`board::used_gpios`].
+fn reserved_gpio(gpio: i32, relays: &[NamedRelay], used: &[(i32, &str)]) -> Option<String> {
+    if !(0..esp_idf_sys::SOC_GPIO_PIN_COUNT as i32).contains(&gpio) {
+        return Some("ungültig".to_string());
+    }
+    if relays.iter().any(|relay| relay.relay.pin_number() == gpio) {
+        return Some("schon ein Relais".to_string());
+    }
+    used.iter()
+        .find(|(pin, _)| *pin == gpio)
+        .map(|(_, owner)| format!("belegt ({})", owner))
+}
@@ -0,0 +1,219 @@
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Timelike};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::clock;
+use crate::config;
+use crate::logs::{log_event, log_relay_event, Event, LogQueue};
+use crate::metrics::Metrics;
+use crate::relay::Relay;
+use crate::settings::Settings;
+
+/// Höchstzahl der Einträge, mehr passen nicht sinnvoll in einen NVS-Eintrag.
+pub const MAX_ENTRIES: usize = 8;
+
+// So oft wird geprüft, ob eine neue Minute begonnen hat
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Ein geplanter Puls, wie er im NVS und unter `/schedule` steht.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduleEntry {
+    /// Cron-Ausdruck `Minute Stunde Tag Monat Wochentag`, z.B. `0 8 * * 1-5`
+    pub cron: String,
+    /// Pulsdauer, ohne Angabe der Standardwert
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ms: Option<u64>,
+}
+
+/// Geparster Cron-Ausdruck, jedes Feld als Bitmaske der erlaubten Werte.
+///
+/// Unterstützt `*`, Zahlen, Bereiche `a-b`, Listen `a,b` und Schritte `*/n` bzw. `a-b/n`.
+/// Wie bei cron gilt ein Tag, wenn Tag des Monats *oder* Wochentag passen, sofern beide
+/// eingeschränkt sind. Wochentag 0 und 7 sind Sonntag.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(anyhow!("cron needs 5 fields: minute hour day month weekday"));
+        };
+        let mut weekdays = parse_field(weekday, 0, 7)? as u8;
+        // 7 ist wie 0 Sonntag
+        if weekdays & 0x80 != 0 {
+            weekdays = (weekdays | 1) & 0x7f;
+        }
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)? as u32,
+            days: parse_field(day, 1, 31)? as u32,
+            months: parse_field(month, 1, 12)? as u16,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    pub fn matches(&self, time: &(impl Datelike + Timelike)) -> bool {
+        let day = self.days & (1 << time.day()) != 0;
+        let weekday = self.weekdays & (1 << time.weekday().num_days_from_sunday()) != 0;
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        self.minutes & (1 << time.minute()) != 0
+            && self.hours & (1 << time.hour()) != 0
+            && self.months & (1 << time.month()) != 0
+            && day_matches
+    }
+}
+
+/// Bitmaske der Werte eines Cron-Felds zwischen `min` und `max`.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0)),
+            None => (part, Some(1)),
+        };
+        let step = step.ok_or_else(|| anyhow!("invalid step in \"{}\"", part))?;
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (parse_value(start, min, max)?, parse_value(end, min, max)?),
+                None => {
+                    let value = parse_value(range, min, max)?;
+                    // `5/15` heißt ab 5 in 15er-Schritten
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        if start > end {
+            return Err(anyhow!("invalid range \"{}\"", part));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+fn parse_value(value: &str, min: u32, max: u32) -> Result<u32> {
+    value
+        .parse::<u32>()
+        .ok()
+        .filter(|value| (min..=max).contains(value))
+        .ok_or_else(|| anyhow!("\"{}\" must be between {} and {}", value, min, max))
+}
+
+/// Der aktuelle Zeitplan, von `/schedule` und dem Scheduler-Thread geteilt.
+pub struct Schedule {
+    nvs: EspDefaultNvsPartition,
+    entries: Mutex<Vec<(ScheduleEntry, Cron)>>,
+}
+
+impl Schedule {
+    /// Lädt den Zeitplan aus dem NVS, ungültige Einträge werden übersprungen.
+    pub fn load(nvs: EspDefaultNvsPartition) -> Self {
+        let entries = config::load_schedule(&nvs)
+            .unwrap_or_else(|err| {
+                warn!("Zeitplan nicht lesbar: {:?}", err);
+                Vec::new()
+            })
+            .into_iter()
+            .filter_map(|entry| match Cron::parse(&entry.cron) {
+                Ok(cron) => Some((entry, cron)),
+                Err(err) => {
+                    warn!("Ungültiger Zeitplan-Eintrag \"{}\": {}", entry.cron, err);
+                    None
+                }
+            })
+            .collect();
+        Self {
+            nvs,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub fn entries(&self) -> Vec<ScheduleEntry> {
+        self.entries.lock().unwrap().iter().map(|(entry, _)| entry.clone()).collect()
+    }
+
+    /// Ersetzt den Zeitplan und speichert ihn. Die Einträge müssen bereits geprüft sein.
+    pub fn replace(&self, entries: Vec<(ScheduleEntry, Cron)>) -> Result<()> {
+        let plain: Vec<ScheduleEntry> = entries.iter().map(|(entry, _)| entry.clone()).collect();
+        config::store_schedule(&self.nvs, &plain)?;
+        *self.entries.lock().unwrap() = entries;
+        Ok(())
+    }
+}
+
+/// Prüft einmal pro Minute, ob ein Eintrag fällig ist, und löst dann den Puls aus.
+///
+/// Es zählt die Uhrzeit aus [`clock::now`], also mit `TZ_OFFSET`. Sommer- und Winterzeit
+/// werden nicht automatisch umgestellt, dafür muss `TZ_OFFSET` angepasst werden. Ohne
+/// SNTP-Synchronisation wird nichts ausgelöst.
+pub fn spawn(
+    schedule: Arc<Schedule>,
+    settings: Arc<Settings>,
+    relay: Relay,
+    metrics: Arc<Metrics>,
+    log_queue: Arc<LogQueue>,
+) -> Result<()> {
+    std::thread::Builder::new()
+        .name("schedule".into())
+        .stack_size(4096)
+        .spawn(move || {
+            let mut last_minute = None;
+            loop {
+                std::thread::sleep(CHECK_INTERVAL);
+                if !clock::is_synced() {
+                    continue;
+                }
+                let now = clock::now();
+                let minute = now.timestamp() / 60;
+                if last_minute == Some(minute) {
+                    continue;
+                }
+                last_minute = Some(minute);
+
+                let default_pulse = settings.pulse();
+                let due: Vec<Duration> = schedule
+                    .entries
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|(_, cron)| cron.matches(&now))
+                    .map(|(entry, _)| entry.ms.map_or(default_pulse, Duration::from_millis))
+                    .collect();
+                // Mehrere passende Einträge lösen nur einen Puls aus
+                let Some(pulse) = due.into_iter().max() else {
+                    continue;
+                };
+                match relay.pulse(pulse) {
+                    Ok(()) => {
+                        metrics.record_push(pulse.as_millis() as u64);
+                        let detail = format!(" {}ms", pulse.as_millis());
+                        log_relay_event(&log_queue, Event::ScheduledPush, &detail);
+                    }
+                    Err(err) => {
+                        log_event(&log_queue, &format!("scheduled push failed: {}", err.message()));
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
@@ -0,0 +1,361 @@
+//! Optionaler Temperatur- und Feuchtesensor, nur mit dem Cargo-Feature `sensor`.
+//!
+//! `SENSOR_TYPE` wählt den Sensor:
+//!
+//! - `dht22`: Eindraht-Bus an `SENSOR_GPIO`, mit internem Pull-up, ein externer mit 10 kΩ ist
+//!   bei längeren Leitungen trotzdem besser
+//! - `bme280`: I2C an `SENSOR_SDA_GPIO`/`SENSOR_SCL_GPIO` mit Adresse `SENSOR_I2C_ADDR`
+//!   (Standard 0x76, bei SDO auf VCC 0x77). Ein BMP280 ohne Feuchte wird ebenfalls erkannt.
+//!
+//! Gemessen wird alle `SENSOR_INTERVAL_S` Sekunden (Standard 30) in einem eigenen Thread, Display
+//! und `/status` lesen nur den letzten Wert. Schlägt eine Messung fehl, gibt es keinen Wert, bis
+//! die nächste klappt, statt eine veraltete Temperatur anzuzeigen.
+
+use anyhow::{anyhow, bail, Result};
+use esp_idf_hal::delay::TickType;
+use esp_idf_hal::gpio::AnyIOPin;
+use esp_idf_hal::i2c::{I2cConfig, I2cDriver};
+use esp_idf_hal::prelude::*;
+use log::*;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::board;
+#[cfg(feature = "display")]
+use crate::lang::Msg;
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_BME280_ADDR: u8 = 0x76;
+
+/// Welcher Sensor wo angeschlossen ist.
+#[derive(Debug, Clone, Copy)]
+pub enum SensorKind {
+    Dht22 { gpio: i32 },
+    Bme280 { sda: i32, scl: i32, addr: u8 },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SensorConfig {
+    pub kind: SensorKind,
+    pub interval: Duration,
+}
+
+impl SensorConfig {
+    /// Liest `SENSOR_TYPE` samt Pins, `None` ohne Sensor oder bei unvollständiger Angabe.
+    pub fn from_env() -> Option<Self> {
+        let gpio = |name: &str, value: Option<&str>| {
+            let gpio = value.and_then(|value| value.parse().ok());
+            if gpio.is_none() {
+                warn!("Sensor: {} fehlt", name);
+            }
+            gpio
+        };
+        let kind = match option_env!("SENSOR_TYPE")? {
+            "dht22" => SensorKind::Dht22 {
+                gpio: gpio("SENSOR_GPIO", option_env!("SENSOR_GPIO"))?,
+            },
+            "bme280" => SensorKind::Bme280 {
+                sda: gpio("SENSOR_SDA_GPIO", option_env!("SENSOR_SDA_GPIO"))?,
+                scl: gpio("SENSOR_SCL_GPIO", option_env!("SENSOR_SCL_GPIO"))?,
+                addr: option_env!("SENSOR_I2C_ADDR")
+                    .and_then(|value| u8::from_str_radix(value.trim_start_matches("0x"), 16).ok())
+                    .unwrap_or(DEFAULT_BME280_ADDR),
+            },
+            other => {
+                warn!("Unbekannter SENSOR_TYPE \"{}\", erwartet dht22 oder bme280", other);
+                return None;
+            }
+        };
+        Some(Self {
+            kind,
+            interval: option_env!("SENSOR_INTERVAL_S")
+                .and_then(|value| value.parse().ok())
+                .filter(|secs| *secs >= 2)
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_INTERVAL),
+        })
+    }
+}
+
+/// Eine Messung.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Reading {
+    pub temperature_c: f32,
+    /// Relative Feuchte, `None` beim BMP280
+    pub humidity_pct: Option<f32>,
+    /// Luftdruck, nur beim BME280/BMP280
+    pub pressure_hpa: Option<f32>,
+}
+
+impl Reading {
+    /// Kopfzeile für das Display, `--` ohne gültige Messung.
+    #[cfg(feature = "display")]
+    pub fn display_line(reading: Option<Self>) -> String {
+        let Some(reading) = reading else {
+            return format!("{}: --", Msg::Climate.text());
+        };
+        match reading.humidity_pct {
+            Some(humidity) => format!("{}: {:.1} C, {:.0} %", Msg::Climate.text(), reading.temperature_c, humidity),
+            None => format!("{}: {:.1} C", Msg::Climate.text(), reading.temperature_c),
+        }
+    }
+}
+
+/// Griff auf den letzten Messwert.
+#[derive(Clone)]
+pub struct Sensor {
+    latest: Arc<Mutex<Option<Reading>>>,
+}
+
+impl Sensor {
+    pub fn start(config: SensorConfig) -> Result<Self> {
+        let mut driver = match config.kind {
+            SensorKind::Dht22 { gpio } => Driver::Dht22(gpio),
+            SensorKind::Bme280 { sda, scl, addr } => Driver::Bme280(Bme280::new(sda, scl, addr)?),
+        };
+        let sensor = Self {
+            latest: Arc::new(Mutex::new(None)),
+        };
+        {
+            let latest = sensor.latest.clone();
+            std::thread::Builder::new()
+                .name("sensor".into())
+                .stack_size(4096)
+                .spawn(move || loop {
+                    let reading = driver.read().map_err(|err| warn!("Sensor nicht lesbar: {:?}", err)).ok();
+                    *latest.lock().unwrap() = reading;
+                    std::thread::sleep(config.interval);
+                })?;
+        }
+        info!("Sensor {:?}, alle {}s", config.kind, config.interval.as_secs());
+        Ok(sensor)
+    }
+
+    /// Letzte gültige Messung, `None` vor der ersten oder nach einem Fehler.
+    pub fn latest(&self) -> Option<Reading> {
+        *self.latest.lock().unwrap()
+    }
+}
+
+enum Driver {
+    Dht22(i32),
+    Bme280(Bme280),
+}
+
+impl Driver {
+    fn read(&mut self) -> Result<Reading> {
+        match self {
+            Driver::Dht22(gpio) => read_dht22(*gpio),
+            Driver::Bme280(bme280) => bme280.read(),
+        }
+    }
+}
+
+/// Wartet höchstens `timeout_us` auf `level` und liefert die Wartezeit in µs.
+fn wait_level(gpio: i32, level: bool, timeout_us: i64) -> Option<i64> {
+    let started = unsafe { esp_idf_sys::esp_timer_get_time() };
+    loop {
+        let elapsed = unsafe { esp_idf_sys::esp_timer_get_time() } - started;
+        if (unsafe { esp_idf_sys::gpio_get_level(gpio) } != 0) == level {
+            return Some(elapsed);
+        }
+        if elapsed > timeout_us {
+            return None;
+        }
+    }
+}
+
+/// Eine Messung des DHT22: Startsignal, dann 40 Bits, deren Wert in der Länge der High-Phase
+/// steckt (rund 27 µs für 0, 70 µs für 1).
+fn read_dht22(gpio: i32) -> Result<Reading> {
+    unsafe {
+        esp_idf_sys::gpio_set_direction(gpio, esp_idf_sys::gpio_mode_t_GPIO_MODE_INPUT_OUTPUT_OD);
+        esp_idf_sys::gpio_set_pull_mode(gpio, esp_idf_sys::gpio_pull_mode_t_GPIO_PULLUP_ONLY);
+        esp_idf_sys::gpio_set_level(gpio, 0);
+        esp_idf_sys::esp_rom_delay_us(1100);
+    }
+
+    let mut data = [0u8; 5];
+    // Ohne Interrupts, sonst verfälscht schon ein WLAN-Interrupt die Länge eines Bits
+    let complete = esp_idf_hal::interrupt::free(|| {
+        unsafe { esp_idf_sys::gpio_set_level(gpio, 1) };
+        // Antwort des Sensors: 80 µs low, 80 µs high
+        wait_level(gpio, false, 100)?;
+        wait_level(gpio, true, 100)?;
+        wait_level(gpio, false, 100)?;
+        for bit in 0..40 {
+            wait_level(gpio, true, 80)?;
+            if wait_level(gpio, false, 100)? > 45 {
+                data[bit / 8] |= 0x80 >> (bit % 8);
+            }
+        }
+        Some(())
+    });
+    if complete.is_none() {
+        bail!("DHT22 antwortet nicht");
+    }
+    let checksum = data[..4].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    if checksum != data[4] {
+        bail!("DHT22-Prüfsumme falsch");
+    }
+
+    let humidity = u16::from_be_bytes([data[0], data[1]]) as f32 / 10.0;
+    let mut temperature = u16::from_be_bytes([data[2] & 0x7f, data[3]]) as f32 / 10.0;
+    if data[2] & 0x80 != 0 {
+        temperature = -temperature;
+    }
+    Ok(Reading {
+        temperature_c: temperature,
+        humidity_pct: Some(humidity),
+        pressure_hpa: None,
+    })
+}
+
+// Register des BME280 laut Datenblatt
+const REG_CHIP_ID: u8 = 0xd0;
+const REG_CALIB_TP: u8 = 0x88;
+const REG_CALIB_H1: u8 = 0xa1;
+const REG_CALIB_H: u8 = 0xe1;
+const REG_CTRL_HUM: u8 = 0xf2;
+const REG_STATUS: u8 = 0xf3;
+const REG_CTRL_MEAS: u8 = 0xf4;
+const REG_DATA: u8 = 0xf7;
+const CHIP_ID_BME280: u8 = 0x60;
+const CHIP_ID_BMP280: u8 = 0x58;
+
+const I2C_TIMEOUT_MS: u64 = 100;
+
+/// Kalibrierwerte aus dem Sensor, ohne sie sind die Rohwerte nutzlos.
+struct Calibration {
+    t: [f64; 3],
+    p: [f64; 9],
+    h: Option<[f64; 6]>,
+}
+
+struct Bme280 {
+    i2c: I2cDriver<'static>,
+    addr: u8,
+    calibration: Calibration,
+}
+
+impl Bme280 {
+    fn new(sda: i32, scl: i32, addr: u8) -> Result<Self> {
+        // Der Bus und die Pins kommen erst zur Build-Zeit, siehe board.rs
+        let mut i2c = unsafe {
+            I2cDriver::new(
+                board::SensorI2c::new(),
+                AnyIOPin::new(sda),
+                AnyIOPin::new(scl),
+                &I2cConfig::new().baudrate(100.kHz().into()),
+            )?
+        };
+        let timeout = TickType::new_millis(I2C_TIMEOUT_MS).ticks();
+        let mut id = [0u8];
+        i2c.write_read(addr, &[REG_CHIP_ID], &mut id, timeout)?;
+        let humidity = match id[0] {
+            CHIP_ID_BME280 => true,
+            CHIP_ID_BMP280 => false,
+            other => bail!("Unbekannte Chip-ID 0x{:02x} an 0x{:02x}", other, addr),
+        };
+
+        let mut tp = [0u8; 24];
+        i2c.write_read(addr, &[REG_CALIB_TP], &mut tp, timeout)?;
+        let u16_at = |i: usize| u16::from_le_bytes([tp[i], tp[i + 1]]) as f64;
+        let i16_at = |i: usize| i16::from_le_bytes([tp[i], tp[i + 1]]) as f64;
+        let mut p = [u16_at(6), 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        for (index, value) in p.iter_mut().enumerate().skip(1) {
+            *value = i16_at(6 + index * 2);
+        }
+
+        let h = if humidity {
+            let mut h1 = [0u8];
+            i2c.write_read(addr, &[REG_CALIB_H1], &mut h1, timeout)?;
+            let mut raw = [0u8; 7];
+            i2c.write_read(addr, &[REG_CALIB_H], &mut raw, timeout)?;
+            Some([
+                h1[0] as f64,
+                i16::from_le_bytes([raw[0], raw[1]]) as f64,
+                raw[2] as f64,
+                // H4 und H5 teilen sich ein Byte, beide mit Vorzeichen
+                (((raw[3] as i8 as i16) << 4) | (raw[4] & 0x0f) as i16) as f64,
+                (((raw[5] as i8 as i16) << 4) | (raw[4] >> 4) as i16) as f64,
+                raw[6] as i8 as f64,
+            ])
+        } else {
+            None
+        };
+
+        Ok(Self {
+            i2c,
+            addr,
+            calibration: Calibration {
+                t: [u16_at(0), i16_at(2), i16_at(4)],
+                p,
+                h,
+            },
+        })
+    }
+
+    /// Eine Messung im Forced Mode, danach schläft der Sensor wieder und erwärmt sich nicht selbst.
+    fn read(&mut self) -> Result<Reading> {
+        let timeout = TickType::new_millis(I2C_TIMEOUT_MS).ticks();
+        // Je einfache Überabtastung, Feuchte muss vor ctrl_meas geschrieben werden
+        self.i2c.write(self.addr, &[REG_CTRL_HUM, 0x01], timeout)?;
+        self.i2c.write(self.addr, &[REG_CTRL_MEAS, 0x25], timeout)?;
+        let mut status = [0u8];
+        for _ in 0..10 {
+            std::thread::sleep(Duration::from_millis(10));
+            self.i2c.write_read(self.addr, &[REG_STATUS], &mut status, timeout)?;
+            if status[0] & 0x08 == 0 {
+                break;
+            }
+        }
+        let mut raw = [0u8; 8];
+        self.i2c.write_read(self.addr, &[REG_DATA], &mut raw, timeout)?;
+        let adc_p = ((raw[0] as u32) << 12 | (raw[1] as u32) << 4 | (raw[2] as u32) >> 4) as f64;
+        let adc_t = ((raw[3] as u32) << 12 | (raw[4] as u32) << 4 | (raw[5] as u32) >> 4) as f64;
+        let adc_h = u16::from_be_bytes([raw[6], raw[7]]) as f64;
+        self.calibration.compensate(adc_t, adc_p, adc_h)
+    }
+}
+
+impl Calibration {
+    /// Gleitkomma-Formeln aus dem Datenblatt des BME280, Abschnitt 8.1.
+    fn compensate(&self, adc_t: f64, adc_p: f64, adc_h: f64) -> Result<Reading> {
+        let [t1, t2, t3] = self.t;
+        let var1 = (adc_t / 16384.0 - t1 / 1024.0) * t2;
+        let var2 = (adc_t / 131072.0 - t1 / 8192.0).powi(2) * t3;
+        let t_fine = var1 + var2;
+        let temperature = t_fine / 5120.0;
+
+        let [p1, p2, p3, p4, p5, p6, p7, p8, p9] = self.p;
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * p6 / 32768.0;
+        var2 += var1 * p5 * 2.0;
+        var2 = var2 / 4.0 + p4 * 65536.0;
+        var1 = (p3 * var1 * var1 / 524288.0 + p2 * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * p1;
+        if var1 == 0.0 {
+            return Err(anyhow!("BME280-Kalibrierung ungültig"));
+        }
+        let mut pressure = 1048576.0 - adc_p;
+        pressure = (pressure - var2 / 4096.0) * 6250.0 / var1;
+        let var1 = p9 * pressure * pressure / 2147483648.0;
+        let var2 = pressure * p8 / 32768.0;
+        pressure += (var1 + var2 + p7) / 16.0;
+
+        let humidity = self.h.map(|[h1, h2, h3, h4, h5, h6]| {
+            let var = t_fine - 76800.0;
+            let var = (adc_h - (h4 * 64.0 + h5 / 16384.0 * var))
+                * (h2 / 65536.0 * (1.0 + h6 / 67108864.0 * var * (1.0 + h3 / 67108864.0 * var)));
+            (var * (1.0 - h1 * var / 524288.0)).clamp(0.0, 100.0) as f32
+        });
+
+        Ok(Reading {
+            temperature_c: temperature as f32,
+            humidity_pct: humidity,
+            pressure_hpa: Some((pressure / 100.0) as f32),
+        })
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::Result;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config;
+
+/// Pulsdauer in Millisekunden, solange keine andere eingestellt ist.
+pub const DEFAULT_PULSE_MS: u64 = 500;
+
+/// Grenzen der Pulsdauer in Millisekunden, auch für `/push?ms=`.
+pub const MIN_PULSE_MS: u64 = 50;
+pub const MAX_PULSE_MS: u64 = 5000;
+
+/// Grenzen der Entprellzeit des Tasters in Millisekunden.
+const MIN_DEBOUNCE_MS: u64 = 5;
+const MAX_DEBOUNCE_MS: u64 = 500;
+
+/// Takt der Hauptschleife in Millisekunden, solange kein anderer eingestellt ist.
+pub const DEFAULT_TICK_MS: u64 = 1000;
+
+/// Grenzen des Takts in Millisekunden, auch für `DISPLAY_TICK_MS`.
+pub const MIN_TICK_MS: u64 = 100;
+pub const MAX_TICK_MS: u64 = 10_000;
+
+/// Einstellungen, die sich über `/config` ändern lassen.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DeviceSettings {
+    /// Pulsdauer ohne `?ms=` und für Taster, MQTT und Zeitplan
+    pub pulse_ms: u64,
+    pub debounce_ms: u64,
+    pub brightness: u8,
+    /// Takt für Uhr, RSSI und Display, gezeichnet wird trotzdem nur bei Änderungen
+    pub tick_ms: u64,
+    /// Erst nach einem Neustart wirksam
+    pub relay_active_low: bool,
+    /// Dauerbetrieb speichern und nach einem Neustart wieder einschalten, für Licht statt Türöffner
+    pub restore_latch: bool,
+    /// Erst nach einem Neustart wirksam
+    pub hostname: String,
+}
+
+/// Änderungen aus `PUT /config`, fehlende Felder bleiben wie sie sind.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsUpdate {
+    pub pulse_ms: Option<u64>,
+    pub debounce_ms: Option<u64>,
+    pub brightness: Option<u8>,
+    pub tick_ms: Option<u64>,
+    pub relay_active_low: Option<bool>,
+    pub restore_latch: Option<bool>,
+    pub hostname: Option<String>,
+}
+
+/// Aktuelle Einstellungen, im NVS gespeichert und von allen Auslösern geteilt.
+///
+/// Pulsdauer, Entprellzeit, Helligkeit und Takt wirken sofort, Polarität und Hostname werden beim Start
+/// gelesen und brauchen einen Neustart.
+pub struct Settings {
+    nvs: EspDefaultNvsPartition,
+    current: Mutex<DeviceSettings>,
+    boot: DeviceSettings,
+}
+
+impl Settings {
+    /// Gespeicherte Einstellungen, nicht gespeicherte Felder aus `defaults`.
+    pub fn load(nvs: EspDefaultNvsPartition, defaults: DeviceSettings) -> Result<Self> {
+        let settings = config::load_settings(&nvs, defaults)?;
+        Ok(Self {
+            nvs,
+            current: Mutex::new(settings.clone()),
+            boot: settings,
+        })
+    }
+
+    pub fn get(&self) -> DeviceSettings {
+        self.current.lock().unwrap().clone()
+    }
+
+    pub fn pulse(&self) -> Duration {
+        Duration::from_millis(self.current.lock().unwrap().pulse_ms)
+    }
+
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.current.lock().unwrap().debounce_ms)
+    }
+
+    pub fn tick(&self) -> Duration {
+        Duration::from_millis(self.current.lock().unwrap().tick_ms)
+    }
+
+    /// Prüft und speichert `update`. Bei einem ungültigen Feld ändert sich nichts.
+    pub fn apply(&self, update: SettingsUpdate) -> Result<DeviceSettings, String> {
+        let mut settings = self.get();
+        if let Some(pulse_ms) = update.pulse_ms {
+            if !(MIN_PULSE_MS..=MAX_PULSE_MS).contains(&pulse_ms) {
+                return Err(format!("pulse_ms must be between {} and {}", MIN_PULSE_MS, MAX_PULSE_MS));
+            }
+            settings.pulse_ms = pulse_ms;
+        }
+        if let Some(debounce_ms) = update.debounce_ms {
+            if !(MIN_DEBOUNCE_MS..=MAX_DEBOUNCE_MS).contains(&debounce_ms) {
+                return Err(format!(
+                    "debounce_ms must be between {} and {}",
+                    MIN_DEBOUNCE_MS, MAX_DEBOUNCE_MS
+                ));
+            }
+            settings.debounce_ms = debounce_ms;
+        }
+        if let Some(brightness) = update.brightness {
+            if brightness > 100 {
+                return Err("brightness must be between 0 and 100".to_string());
+            }
+            settings.brightness = brightness;
+        }
+        if let Some(tick_ms) = update.tick_ms {
+            if !(MIN_TICK_MS..=MAX_TICK_MS).contains(&tick_ms) {
+                return Err(format!("tick_ms must be between {} and {}", MIN_TICK_MS, MAX_TICK_MS));
+            }
+            settings.tick_ms = tick_ms;
+        }
+        if let Some(relay_active_low) = update.relay_active_low {
+            settings.relay_active_low = relay_active_low;
+        }
+        if let Some(restore_latch) = update.restore_latch {
+            settings.restore_latch = restore_latch;
+        }
+        if let Some(hostname) = update.hostname {
+            if !is_valid_hostname(&hostname) {
+                return Err("hostname must be 1 to 32 letters, digits or dashes".to_string());
+            }
+            settings.hostname = hostname;
+        }
+
+        config::store_settings(&self.nvs, &settings).map_err(|err| err.to_string())?;
+        *self.current.lock().unwrap() = settings.clone();
+        Ok(settings)
+    }
+
+    /// Geänderte Felder, die erst nach einem Neustart wirken.
+    pub fn reboot_required(&self) -> Vec<&'static str> {
+        let current = self.current.lock().unwrap();
+        let mut fields = Vec::new();
+        if current.relay_active_low != self.boot.relay_active_low {
+            fields.push("relay_active_low");
+        }
+        if current.hostname != self.boot.hostname {
+            fields.push("hostname");
+        }
+        fields
+    }
+}
+
+/// Ein einzelnes DNS-Label, damit der Name auch per mDNS funktioniert.
+pub fn is_valid_hostname(hostname: &str) -> bool {
+    (1..=32).contains(&hostname.len())
+        && hostname.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+        && !hostname.starts_with('-')
+        && !hostname.ends_with('-')
+}
@@ -0,0 +1,144 @@
+//! Server-Sent Events unter `/events`, leichter als `/ws/logs` und direkt mit `EventSource`
+//! im Browser nutzbar.
+//!
+//! Jeder neue Logeintrag geht als `data:`-Event mit dem Eintrag als JSON an alle Clients. Die
+//! Verbindung bleibt offen, ohne den Server zu blockieren: der Handler übergibt den Request per
+//! `httpd_req_async_handler_begin` an die Client-Liste und kehrt sofort zurück. Schlägt ein
+//! Senden fehl, weil der Client weg ist, wird der Request abgeschlossen und der Socket ist
+//! wieder frei. Alle [`KEEPALIVE_INTERVAL`] geht ein Kommentar raus, damit Proxys die Verbindung
+//! nicht schließen und tote Clients auch ohne neue Logeinträge bald ihren Platz freigeben.
+//!
+//! Gesendet wird aus einem eigenen Thread, der Logeintrag wartet dafür in einer kurzen
+//! Warteschlange. So hält ein langsamer Client weder `/push` noch den Taster auf, ist die
+//! Warteschlange voll, fehlt den Clients der Eintrag.
+//!
+//! Die Zahl der Clients begrenzt [`crate::streams`] zusammen mit `/ws/logs`.
+
+use anyhow::Result;
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_sys::{esp, httpd_req_t};
+use log::*;
+use std::ffi::CString;
+use std::sync::mpsc::{self, RecvTimeoutError, TrySendError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::api::{cors_origin, Routes};
+use crate::keepalive;
+use crate::logs::LogQueue;
+use crate::streams::{self, Slot};
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5);
+// So viele Events warten höchstens auf den Versand
+const QUEUE_LEN: usize = 8;
+
+/// Vom Server abgekoppelter Request eines Clients, gehört bis zum Abschluss diesem Modul.
+struct Client(*mut httpd_req_t, Slot);
+
+// Der kopierte Request darf laut ESP-IDF aus jedem Task heraus beschrieben werden
+unsafe impl Send for Client {}
+
+impl Client {
+    fn send(&self, data: &str) -> bool {
+        unsafe { esp_idf_sys::httpd_resp_send_chunk(self.0, data.as_ptr() as *const _, data.len() as _) == 0 }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        unsafe { esp_idf_sys::httpd_req_async_handler_complete(self.0) };
+    }
+}
+
+struct Stream {
+    clients: Mutex<Vec<Client>>,
+    cors_origin: CString,
+}
+
+impl Stream {
+    /// Sendet `data` an alle Clients, wer nicht mehr erreichbar ist, fliegt raus.
+    fn broadcast(&self, data: &str) {
+        let mut clients = self.clients.lock().unwrap();
+        let before = clients.len();
+        clients.retain(|client| client.send(data));
+        if clients.len() < before {
+            info!("{} SSE-Client(s) getrennt", before - clients.len());
+        }
+    }
+}
+
+/// Registriert `/events` und leitet jeden neuen Logeintrag an alle verbundenen Clients weiter.
+pub fn register_event_stream(server: &mut EspHttpServer<'static>, routes: &Routes, log_queue: &LogQueue) -> Result<()> {
+    // Lebt so lange wie der Server, der Handler bekommt nur einen Zeiger darauf
+    let stream: &'static Stream = Box::leak(Box::new(Stream {
+        clients: Mutex::new(Vec::new()),
+        cors_origin: CString::new(cors_origin())?,
+    }));
+
+    let uri = esp_idf_sys::httpd_uri_t {
+        uri: c"/events".as_ptr(),
+        method: esp_idf_sys::http_method_HTTP_GET,
+        handler: Some(handle_events),
+        user_ctx: stream as *const Stream as *mut _,
+        ..Default::default()
+    };
+    esp!(unsafe { esp_idf_sys::httpd_register_uri_handler(server.handle(), &uri) })?;
+    routes.record("/events", embedded_svc::http::Method::Get);
+
+    let (events, receiver) = mpsc::sync_channel::<String>(QUEUE_LEN);
+    std::thread::Builder::new()
+        .name("sse".into())
+        .stack_size(3072)
+        .spawn(move || loop {
+            match receiver.recv_timeout(KEEPALIVE_INTERVAL) {
+                Ok(event) => stream.broadcast(&event),
+                Err(RecvTimeoutError::Timeout) => stream.broadcast(": keepalive\n\n"),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        })?;
+
+    log_queue.subscribe(move |entry| {
+        let Ok(json) = serde_json::to_string(entry) else {
+            return;
+        };
+        match events.try_send(format!("data: {}\n\n", json)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("SSE-Warteschlange voll, Eintrag verworfen"),
+            Err(TrySendError::Disconnected(_)) => warn!("SSE-Thread läuft nicht mehr"),
+        }
+    });
+
+    Ok(())
+}
+
+unsafe extern "C" fn handle_events(req: *mut httpd_req_t) -> esp_idf_sys::esp_err_t {
+    let stream = &*((*req).user_ctx as *const Stream);
+    let mut clients = stream.clients.lock().unwrap();
+    let Some(slot) = streams::acquire() else {
+        warn!("SSE abgelehnt, bereits {} Streaming-Clients", streams::active());
+        esp_idf_sys::httpd_resp_set_status(req, c"503 Service Unavailable".as_ptr());
+        esp_idf_sys::httpd_resp_set_hdr(req, c"Retry-After".as_ptr(), c"30".as_ptr());
+        return esp_idf_sys::httpd_resp_send(req, std::ptr::null(), 0);
+    };
+
+    esp_idf_sys::httpd_resp_set_type(req, c"text/event-stream".as_ptr());
+    esp_idf_sys::httpd_resp_set_hdr(req, c"Cache-Control".as_ptr(), c"no-cache".as_ptr());
+    esp_idf_sys::httpd_resp_set_hdr(req, c"Access-Control-Allow-Origin".as_ptr(), stream.cors_origin.as_ptr());
+    // Erster Chunk schickt die Header, danach hängt der Client an der Verbindung
+    let retry = "retry: 5000\n\n";
+    let err = esp_idf_sys::httpd_resp_send_chunk(req, retry.as_ptr() as *const _, retry.len() as _);
+    if err != 0 {
+        return err;
+    }
+
+    let mut detached = std::ptr::null_mut();
+    let err = esp_idf_sys::httpd_req_async_handler_begin(req, &mut detached);
+    if err != 0 {
+        return err;
+    }
+    keepalive::forget(esp_idf_sys::httpd_req_to_sockfd(detached));
+    clients.push(Client(detached, slot));
+    info!("SSE-Client verbunden, {} aktiv", clients.len());
+    esp_idf_sys::ESP_OK as _
+}
@@ -0,0 +1,80 @@
+//! Kleinster, größter und mittlerer Wert von RSSI und freiem Heap seit dem Start.
+//!
+//! Die Hauptschleife trägt bei jeder Speichermessung einen Wert ein, `/status` zeigt die Zahlen,
+//! `/status/reset` beginnt von vorn. Alles liegt in ein paar Atomics, ohne Sperre. Eine Messung
+//! genau während des Zurücksetzens kann halb in die alte und halb in die neue Runde fallen, für
+//! einen groben Überblick reicht das.
+
+use std::sync::atomic::{AtomicI32, AtomicI64, AtomicU32, Ordering};
+
+/// Laufende Kennzahlen einer Messgröße.
+pub struct Range {
+    min: AtomicI32,
+    max: AtomicI32,
+    sum: AtomicI64,
+    count: AtomicU32,
+}
+
+impl Range {
+    const fn new() -> Self {
+        Self {
+            min: AtomicI32::new(i32::MAX),
+            max: AtomicI32::new(i32::MIN),
+            sum: AtomicI64::new(0),
+            count: AtomicU32::new(0),
+        }
+    }
+
+    fn record(&self, value: i32) {
+        self.min.fetch_min(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+        self.sum.fetch_add(value as i64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        self.count.store(0, Ordering::Relaxed);
+        self.sum.store(0, Ordering::Relaxed);
+        self.min.store(i32::MAX, Ordering::Relaxed);
+        self.max.store(i32::MIN, Ordering::Relaxed);
+    }
+
+    /// `{ "min": .., "max": .., "avg": .., "samples": .. }`, ohne Messung `null` statt der Werte.
+    pub fn to_json(&self) -> serde_json::Value {
+        let count = self.count.load(Ordering::Relaxed);
+        if count == 0 {
+            return serde_json::json!({ "min": null, "max": null, "avg": null, "samples": 0 });
+        }
+        let avg = self.sum.load(Ordering::Relaxed) as f64 / count as f64;
+        serde_json::json!({
+            "min": self.min.load(Ordering::Relaxed),
+            "max": self.max.load(Ordering::Relaxed),
+            "avg": (avg * 10.0).round() / 10.0,
+            "samples": count,
+        })
+    }
+}
+
+static RSSI: Range = Range::new();
+static FREE_HEAP: Range = Range::new();
+
+/// Trägt eine Messung ein, ohne WLAN-Verbindung nur den Heap.
+pub fn record(rssi: Option<i8>, free_heap: u32) {
+    if let Some(rssi) = rssi {
+        RSSI.record(rssi as i32);
+    }
+    FREE_HEAP.record(free_heap.min(i32::MAX as u32) as i32);
+}
+
+pub fn reset() {
+    RSSI.reset();
+    FREE_HEAP.reset();
+}
+
+pub fn rssi() -> &'static Range {
+    &RSSI
+}
+
+pub fn free_heap() -> &'static Range {
+    &FREE_HEAP
+}
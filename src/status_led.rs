@@ -0,0 +1,164 @@
+//! Optionale RGB-Status-LED (WS2812/SK6812) über den RMT-Peripheriebaustein.
+//!
+//! Mit `STATUS_LED_GPIO` zeigt die LED auf einen Blick, was los ist:
+//!
+//! - blau: WLAN-Verbindung wird aufgebaut oder das Einrichtungsportal läuft
+//! - grün: verbunden, nichts zu tun
+//! - weiß: das Relais ist gerade angezogen, also bei jedem Puls ein kurzes Aufblitzen
+//! - rot: Verbindung verloren oder ein Fehler wie eine Sicherheitsabschaltung
+//!
+//! `STATUS_LED_TYPE=sk6812` stellt auf dessen Timing um, Standard ist WS2812. Die Helligkeit ist
+//! bewusst niedrig, eine volle NeoPixel blendet in einem dunklen Flur.
+
+use anyhow::Result;
+use esp_idf_hal::gpio::AnyOutputPin;
+use esp_idf_hal::peripheral::Peripheral;
+use esp_idf_hal::rmt::config::TransmitConfig;
+use esp_idf_hal::rmt::{FixedLengthSignal, PinState, Pulse, RmtChannel, TxRmtDriver};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::relay::Relay;
+
+const UPDATE_INTERVAL: Duration = Duration::from_millis(20);
+
+/// So lange bleibt die LED nach einem Fehler rot.
+const ERROR_HOLD: Duration = Duration::from_secs(5);
+
+/// Dauer der High- und Low-Phasen in Nanosekunden für eine 0 bzw. eine 1.
+#[derive(Clone, Copy)]
+struct Timing {
+    t0h: u64,
+    t0l: u64,
+    t1h: u64,
+    t1l: u64,
+}
+
+const WS2812: Timing = Timing {
+    t0h: 350,
+    t0l: 800,
+    t1h: 700,
+    t1l: 600,
+};
+
+const SK6812: Timing = Timing {
+    t0h: 300,
+    t0l: 900,
+    t1h: 600,
+    t1l: 600,
+};
+
+/// Dauerzustand der LED, ohne Pulse und Fehler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LedState {
+    Connecting = 0,
+    Connected = 1,
+    Disconnected = 2,
+}
+
+impl LedState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => LedState::Connected,
+            2 => LedState::Disconnected,
+            _ => LedState::Connecting,
+        }
+    }
+}
+
+/// Farbe als `(r, g, b)`, gedimmt.
+fn color(state: LedState, relay_on: bool, error: bool) -> (u8, u8, u8) {
+    if relay_on {
+        (48, 48, 48)
+    } else if error || state == LedState::Disconnected {
+        (48, 0, 0)
+    } else if state == LedState::Connected {
+        (0, 24, 0)
+    } else {
+        (0, 0, 48)
+    }
+}
+
+/// Griff auf die LED, der Zustand wird aus Hauptschleife und Ereignissen gesetzt.
+#[derive(Clone)]
+pub struct StatusLed {
+    state: Arc<AtomicU8>,
+    error_at: Arc<Mutex<Option<Instant>>>,
+}
+
+impl StatusLed {
+    /// Startet die LED an `STATUS_LED_GPIO`, ohne die Variable `None`.
+    ///
+    /// Die LED beginnt blau, bis die Hauptschleife einen anderen Zustand meldet. Das Aufblitzen
+    /// bei Pulsen liest sie selbst aus `relay`, damit es nicht vom Takt der Hauptschleife abhängt.
+    pub fn from_env<C: RmtChannel>(channel: impl Peripheral<P = C> + 'static, relay: Relay) -> Result<Option<Self>> {
+        let Some(gpio) = option_env!("STATUS_LED_GPIO").and_then(|value| value.parse().ok()) else {
+            return Ok(None);
+        };
+        let timing = match option_env!("STATUS_LED_TYPE") {
+            Some("sk6812") => SK6812,
+            _ => WS2812,
+        };
+        // Der Pin kommt erst zur Build-Zeit, der Typ `Gpio<N>` steht hier deshalb nicht fest
+        let pin = unsafe { AnyOutputPin::new(gpio) };
+        let mut tx = TxRmtDriver::new(channel, pin, &TransmitConfig::new().clock_divider(1))?;
+
+        let led = Self {
+            state: Arc::new(AtomicU8::new(LedState::Connecting as u8)),
+            error_at: Arc::new(Mutex::new(None)),
+        };
+        {
+            let led = led.clone();
+            std::thread::Builder::new()
+                .name("status-led".into())
+                .stack_size(3072)
+                .spawn(move || {
+                    let mut shown = None;
+                    loop {
+                        let wanted = color(led.state(), relay.state().is_active(), led.has_error());
+                        if shown != Some(wanted) && write(&mut tx, timing, wanted).is_ok() {
+                            shown = Some(wanted);
+                        }
+                        std::thread::sleep(UPDATE_INTERVAL);
+                    }
+                })?;
+        }
+        Ok(Some(led))
+    }
+
+    pub fn set_state(&self, state: LedState) {
+        self.state.store(state as u8, Ordering::Relaxed);
+    }
+
+    /// Zeigt für einige Sekunden rot, z.B. nach einer Sicherheitsabschaltung.
+    pub fn error(&self) {
+        *self.error_at.lock().unwrap() = Some(Instant::now());
+    }
+
+    fn state(&self) -> LedState {
+        LedState::from_u8(self.state.load(Ordering::Relaxed))
+    }
+
+    fn has_error(&self) -> bool {
+        self.error_at.lock().unwrap().is_some_and(|at| at.elapsed() < ERROR_HOLD)
+    }
+}
+
+/// Schickt eine Farbe an die LED, WS2812 und SK6812 erwarten die Bits in der Reihenfolge G, R, B.
+fn write(tx: &mut TxRmtDriver<'static>, timing: Timing, (r, g, b): (u8, u8, u8)) -> Result<()> {
+    let ticks_hz = tx.counter_clock()?;
+    let pulse = |state, ns| Pulse::new_with_duration(ticks_hz, state, &Duration::from_nanos(ns));
+    let zero = (pulse(PinState::High, timing.t0h)?, pulse(PinState::Low, timing.t0l)?);
+    let one = (pulse(PinState::High, timing.t1h)?, pulse(PinState::Low, timing.t1l)?);
+
+    let grb = (u32::from(g) << 16) | (u32::from(r) << 8) | u32::from(b);
+    let mut signal = FixedLengthSignal::<24>::new();
+    for bit in 0..24 {
+        let set = grb & (1 << (23 - bit)) != 0;
+        signal.set(bit, if set { &one } else { &zero })?;
+    }
+    tx.start_blocking(&signal)?;
+    Ok(())
+}
@@ -0,0 +1,55 @@
+//! Gemeinsame Obergrenze für Streaming-Clients auf `/events`, `/ws/logs` und `/poll`.
+//!
+//! Jeder Stream belegt dauerhaft einen Socket des Servers. Damit Dashboards `/push` und die
+//! übrigen Endpunkte nicht verdrängen, teilen sich alle Streams `MAX_STREAM_CLIENTS` Plätze
+//! (Standard 2), höchstens aber einen weniger als der Server Sockets hat. Weitere Clients
+//! bekommen 503. Ein Platz wird frei, sobald sein [`Slot`] fällt, also wenn der Client
+//! getrennt oder der wartende Long-Poll beantwortet wird. Die aktuelle Zahl steht in `/status`.
+
+use log::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const DEFAULT_MAX_CLIENTS: usize = 2;
+
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+static MAX_CLIENTS: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_CLIENTS);
+
+/// Setzt die Grenze aus `MAX_STREAM_CLIENTS`, mindestens ein Socket bleibt für andere Requests.
+pub fn init(max_sockets: usize) {
+    let wanted = option_env!("MAX_STREAM_CLIENTS")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_CLIENTS);
+    let limit = wanted.min(max_sockets.saturating_sub(1));
+    if limit < wanted {
+        warn!("MAX_STREAM_CLIENTS={} bei {} Sockets, begrenzt auf {}", wanted, max_sockets, limit);
+    }
+    MAX_CLIENTS.store(limit, Ordering::Relaxed);
+    info!("Höchstens {} Streaming-Client(s)", limit);
+}
+
+/// Belegter Platz eines Streaming-Clients, gibt ihn beim Drop wieder frei.
+pub struct Slot(());
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        ACTIVE.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Belegt einen Platz, `None` wenn alle vergeben sind.
+pub fn acquire() -> Option<Slot> {
+    let max = MAX_CLIENTS.load(Ordering::Relaxed);
+    ACTIVE
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |active| (active < max).then_some(active + 1))
+        .ok()
+        .map(|_| Slot(()))
+}
+
+/// Zahl der gerade verbundenen Streaming-Clients.
+pub fn active() -> usize {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+pub fn max_clients() -> usize {
+    MAX_CLIENTS.load(Ordering::Relaxed)
+}
@@ -0,0 +1,117 @@
+//! Weiterleitung aller Logeinträge an einen Syslog-Server per UDP nach RFC 5424.
+//!
+//! Die Einträge kommen über einen Listener der [`LogQueue`] und werden nur eingereiht, gesendet
+//! wird aus einem eigenen Thread. Schlägt das Senden fehl, etwa weil das WLAN gerade weg ist,
+//! bleiben bis zu [`BUFFER_LEN`] Zeilen liegen und gehen beim nächsten Versuch mit raus. Läuft die
+//! Warteschlange über, wird verworfen, Handler und Relais warten nie auf das Netz.
+
+use anyhow::Result;
+use log::*;
+use std::collections::VecDeque;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, RecvTimeoutError, TrySendError};
+use std::time::Duration;
+
+use crate::config::SyslogConfig;
+use crate::logs::{LogEntry, LogQueue};
+
+/// So viele Einträge warten höchstens auf den Sende-Thread.
+const QUEUE_LEN: usize = 16;
+
+/// So viele Zeilen werden aufgehoben, solange der Server nicht erreichbar ist.
+const BUFFER_LEN: usize = 8;
+
+/// Abstand der Wiederholungen, wenn keine neuen Einträge kommen.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+const APP_NAME: &str = "doofman";
+
+/// Startet den Sende-Thread und hängt ihn an `log_queue`.
+pub fn start(config: SyslogConfig, hostname: &str, log_queue: &LogQueue) -> Result<()> {
+    let (entries, receiver) = mpsc::sync_channel::<LogEntry>(QUEUE_LEN);
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let hostname = hostname.to_string();
+    info!("Syslog an {}:{}, Facility {}", config.host, config.port, config.facility);
+
+    std::thread::Builder::new()
+        .name("syslog".into())
+        .stack_size(4096)
+        .spawn(move || {
+            let mut pending = VecDeque::with_capacity(BUFFER_LEN);
+            loop {
+                match receiver.recv_timeout(RETRY_INTERVAL) {
+                    Ok(entry) => {
+                        if pending.len() == BUFFER_LEN {
+                            pending.pop_front();
+                        }
+                        pending.push_back(format_message(&config, &hostname, &entry));
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                flush(&socket, &config, &mut pending);
+            }
+        })?;
+
+    log_queue.subscribe(move |entry| match entries.try_send(entry.clone()) {
+        Ok(()) | Err(TrySendError::Full(_)) => {}
+        Err(TrySendError::Disconnected(_)) => warn!("Syslog-Thread läuft nicht mehr"),
+    });
+    Ok(())
+}
+
+/// Sendet die wartenden Zeilen der Reihe nach, beim ersten Fehler bleibt der Rest liegen.
+fn flush(socket: &UdpSocket, config: &SyslogConfig, pending: &mut VecDeque<String>) {
+    if pending.is_empty() {
+        return;
+    }
+    // Bei jedem Versuch neu auflösen, ein Hostname kann nach einem Reconnect woanders hinzeigen
+    let addr = match (config.host.as_str(), config.port).to_socket_addrs() {
+        Ok(mut addrs) => addrs.next(),
+        Err(err) => {
+            debug!("Syslog-Server nicht auflösbar: {:?}", err);
+            None
+        }
+    };
+    let Some(addr) = addr else {
+        return;
+    };
+    while let Some(message) = pending.front() {
+        if let Err(err) = socket.send_to(message.as_bytes(), addr) {
+            debug!("Syslog nicht gesendet: {:?}", err);
+            return;
+        }
+        pending.pop_front();
+    }
+}
+
+/// Zeile nach RFC 5424, z.B. `<134>1 2024-05-01T12:34:56Z doofman doofman - http - 200 /push 500ms`.
+fn format_message(config: &SyslogConfig, hostname: &str, entry: &LogEntry) -> String {
+    // Schweregrad: 3 Fehler, 4 Warnung, 5 Hinweis für Ereignisse, 6 Information
+    let severity = match entry.status {
+        Some(status) if status >= 500 => 3,
+        Some(status) if status >= 400 => 4,
+        Some(_) => 6,
+        None => 5,
+    };
+    let timestamp = entry
+        .timestamp
+        .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+        .map_or("-".to_string(), |time| time.format("%Y-%m-%dT%H:%M:%SZ").to_string());
+    let msg_id = match (entry.source, entry.status) {
+        (Some(source), _) => source.label(),
+        (None, Some(_)) => "http",
+        (None, None) => "event",
+    };
+    let status = entry.status.map(|status| format!("{} ", status)).unwrap_or_default();
+    format!(
+        "<{}>1 {} {} {} - {} - {}{}",
+        config.facility as u16 * 8 + severity,
+        timestamp,
+        hostname,
+        APP_NAME,
+        msg_id,
+        status,
+        entry.path
+    )
+}
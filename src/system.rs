@@ -0,0 +1,117 @@
+use serde::Serialize;
+use std::ffi::CStr;
+use std::sync::OnceLock;
+
+/// Freier Heap in Bytes über alle für `malloc` nutzbaren Bereiche.
+pub fn free_heap() -> u32 {
+    unsafe { esp_idf_sys::heap_caps_get_free_size(esp_idf_sys::MALLOC_CAP_DEFAULT) as u32 }
+}
+
+/// Kleinster freier Heap seit dem Start in Bytes.
+pub fn min_free_heap() -> u32 {
+    unsafe { esp_idf_sys::esp_get_minimum_free_heap_size() }
+}
+
+/// Nie genutzter Stack des aufrufenden Tasks in Bytes (High-Water-Mark).
+pub fn current_task_stack_free() -> u32 {
+    unsafe { esp_idf_sys::uxTaskGetStackHighWaterMark(std::ptr::null_mut()) }
+}
+
+/// Signalstärke des verbundenen Access Points, `None` wenn keine Verbindung besteht.
+pub fn wifi_rssi() -> Option<i8> {
+    let mut ap_info = esp_idf_sys::wifi_ap_record_t::default();
+    let result = unsafe { esp_idf_sys::esp_wifi_sta_get_ap_info(&mut ap_info) };
+    (result == esp_idf_sys::ESP_OK).then_some(ap_info.rssi)
+}
+
+/// Grund des letzten Neustarts, z.B. um Watchdog-Resets von Stromausfällen zu unterscheiden.
+pub fn reset_reason() -> &'static str {
+    #[allow(non_upper_case_globals)]
+    match unsafe { esp_idf_sys::esp_reset_reason() } {
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_POWERON => "power_on",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_SW => "software",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_PANIC => "panic",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_INT_WDT => "interrupt_watchdog",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_TASK_WDT => "task_watchdog",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_WDT => "watchdog",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_DEEPSLEEP => "deep_sleep",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_BROWNOUT => "brownout",
+        esp_idf_sys::esp_reset_reason_t_ESP_RST_EXT => "external",
+        _ => "unknown",
+    }
+}
+
+/// Warum das Gerät zuletzt aus dem Schlaf aufgewacht ist, `none` nach einem normalen Start.
+pub fn wake_reason() -> &'static str {
+    #[allow(non_upper_case_globals)]
+    match unsafe { esp_idf_sys::esp_sleep_get_wakeup_cause() } {
+        esp_idf_sys::esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT0
+        | esp_idf_sys::esp_sleep_source_t_ESP_SLEEP_WAKEUP_GPIO => "button",
+        esp_idf_sys::esp_sleep_source_t_ESP_SLEEP_WAKEUP_TIMER => "timer",
+        esp_idf_sys::esp_sleep_source_t_ESP_SLEEP_WAKEUP_UNDEFINED => "none",
+        _ => "other",
+    }
+}
+
+/// Werks-MAC-Adresse als Hex ohne Trennzeichen, z.B. `a4cf12345678`. Bleibt über Neuflashen
+/// hinweg gleich und taugt daher als eindeutige Geräte-ID.
+pub fn chip_id() -> String {
+    let mut mac = [0u8; 6];
+    unsafe { esp_idf_sys::esp_efuse_mac_get_default(mac.as_mut_ptr()) };
+    mac.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Hardware und Firmware des Geräts für `/status`, einmal beim Start ermittelt.
+#[derive(Debug, Serialize)]
+pub struct ChipInfo {
+    /// Werks-MAC mit Doppelpunkten, z.B. `a4:cf:12:34:56:78`
+    pub mac: String,
+    pub model: &'static str,
+    pub cores: u8,
+    /// Chip-Revision wie `3.0`
+    pub revision: String,
+    pub flash_bytes: Option<u32>,
+    pub idf_version: String,
+    pub firmware_version: &'static str,
+    /// Zeitpunkt des Builds als Unix-Zeit, `None`, falls das Build-Skript ihn nicht kannte
+    pub build_time: Option<i64>,
+}
+
+static CHIP_INFO: OnceLock<ChipInfo> = OnceLock::new();
+
+/// Chip-Infos, beim ersten Aufruf gelesen und danach zwischengespeichert.
+pub fn chip_info() -> &'static ChipInfo {
+    CHIP_INFO.get_or_init(|| {
+        let mut info = esp_idf_sys::esp_chip_info_t::default();
+        unsafe { esp_idf_sys::esp_chip_info(&mut info) };
+        #[allow(non_upper_case_globals)]
+        let model = match info.model {
+            esp_idf_sys::esp_chip_model_t_CHIP_ESP32 => "ESP32",
+            esp_idf_sys::esp_chip_model_t_CHIP_ESP32S2 => "ESP32-S2",
+            esp_idf_sys::esp_chip_model_t_CHIP_ESP32S3 => "ESP32-S3",
+            esp_idf_sys::esp_chip_model_t_CHIP_ESP32C3 => "ESP32-C3",
+            esp_idf_sys::esp_chip_model_t_CHIP_ESP32C6 => "ESP32-C6",
+            _ => "unknown",
+        };
+
+        let mut mac = [0u8; 6];
+        unsafe { esp_idf_sys::esp_efuse_mac_get_default(mac.as_mut_ptr()) };
+        let mac = mac.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(":");
+
+        // Ohne Angabe eines Chips gilt der Flash, von dem gebootet wurde
+        let mut flash_bytes = 0;
+        let flash_ok = unsafe { esp_idf_sys::esp_flash_get_size(std::ptr::null_mut(), &mut flash_bytes) } == 0;
+
+        let idf_version = unsafe { CStr::from_ptr(esp_idf_sys::esp_get_idf_version()) };
+        ChipInfo {
+            mac,
+            model,
+            cores: info.cores,
+            revision: format!("{}.{}", info.revision / 100, info.revision % 100),
+            flash_bytes: flash_ok.then_some(flash_bytes),
+            idf_version: idf_version.to_string_lossy().into_owned(),
+            firmware_version: env!("CARGO_PKG_VERSION"),
+            build_time: option_env!("BUILD_TIMESTAMP").and_then(|value| value.parse().ok()),
+        }
+    })
+}
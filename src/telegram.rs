@@ -0,0 +1,72 @@
+//! Benachrichtigung über den Telegram-Bot-API, wenn das Relais auslöst.
+//!
+//! Gesendet wird aus einem eigenen Thread über HTTPS, geprüft gegen das Zertifikats-Bundle von
+//! ESP-IDF. Die Auslöser reihen ihre Nachricht nur ein, ist die Warteschlange voll, etwa weil
+//! Telegram nicht erreichbar ist, wird die Nachricht verworfen statt das Relais aufzuhalten.
+
+use anyhow::{bail, Result};
+use log::*;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::time::Duration;
+
+use crate::clock;
+use crate::config::TelegramConfig;
+use crate::https;
+use crate::lang::{self, Msg};
+
+// So viele Nachrichten warten höchstens auf den Versand
+const QUEUE_LEN: usize = 4;
+// Zeitlimit für eine Anfrage an Telegram
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reiht Benachrichtigungen für den Telegram-Thread ein.
+#[derive(Clone)]
+pub struct Notifier {
+    messages: SyncSender<String>,
+    device_name: String,
+}
+
+impl Notifier {
+    pub fn start(config: TelegramConfig, device_name: &str) -> Result<Self> {
+        let (messages, receiver) = mpsc::sync_channel::<String>(QUEUE_LEN);
+        std::thread::Builder::new()
+            .name("telegram".into())
+            .stack_size(https::STACK_SIZE)
+            .spawn(move || {
+                for text in receiver {
+                    if let Err(err) = send(&config, &text) {
+                        warn!("Telegram-Nachricht nicht gesendet: {:?}", err);
+                    }
+                }
+            })?;
+        Ok(Self {
+            messages,
+            device_name: device_name.to_string(),
+        })
+    }
+
+    /// Meldet eine Auslösung über `source`, z.B. `/push` oder `Taster`, ohne zu warten.
+    pub fn notify_push(&self, source: &str) {
+        let time = if clock::is_synced() {
+            clock::now().format("%d.%m.%Y %H:%M:%S").to_string()
+        } else {
+            Msg::TimeUnknown.text().to_string()
+        };
+        let text = lang::triggered(&self.device_name, source, &time);
+        match self.messages.try_send(text) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("Telegram-Warteschlange voll, Nachricht verworfen"),
+            Err(TrySendError::Disconnected(_)) => warn!("Telegram-Thread läuft nicht mehr"),
+        }
+    }
+}
+
+fn send(config: &TelegramConfig, text: &str) -> Result<()> {
+    let url = format!("https://api.telegram.org/bot{}/sendMessage", config.bot_token);
+    let body = serde_json::json!({ "chat_id": config.chat_id, "text": text }).to_string();
+    let status = https::post_json(&url, &body, &[], SEND_TIMEOUT)?;
+    if !(200..300).contains(&status) {
+        bail!("Telegram antwortet mit {}", status);
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//! Optionales HTTPS für den Webserver, damit das Token nicht im Klartext übers Netz geht.
+//!
+//! Zertifikat und privater Schlüssel im PEM-Format kommen aus einer der beiden Quellen:
+//!
+//! - NVS, Namespace `tls`, Schlüssel `cert` und `key` (je höchstens 4 KB), z.B. mit einer
+//!   Partition, die `nvs_partition_gen.py` aus einer CSV erzeugt und die per `espflash` geflasht
+//!   wird. Das eignet sich für ein eigenes Zertifikat pro Gerät ohne neue Firmware.
+//! - Zur Build-Zeit über `TLS_CERT_FILE` und `TLS_KEY_FILE`, die Pfade zu den PEM-Dateien.
+//!
+//! Das NVS hat Vorrang. Ohne Zertifikat bleibt es bei HTTP. Ein selbst signiertes Zertifikat für
+//! den Hostnamen lässt sich z.B. so erzeugen:
+//!
+//! ```text
+//! openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes -days 3650 \
+//!     -subj "/CN=doofman.local" -addext "subjectAltName=DNS:doofman.local" \
+//!     -keyout server_key.pem -out server_cert.pem
+//! ```
+//!
+//! EC-Schlüssel sind deutlich schneller im Handshake als RSA, was auf dem ESP32 spürbar ist.
+
+use anyhow::Result;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::tls::X509;
+use log::*;
+
+use crate::config;
+
+const EMBEDDED_CERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/server_cert.pem"));
+const EMBEDDED_KEY: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/server_key.pem"));
+
+/// Zertifikat und Schlüssel des Servers, nullterminiert.
+#[derive(Clone, Copy)]
+pub struct TlsIdentity {
+    pub cert: X509<'static>,
+    pub key: X509<'static>,
+}
+
+/// Lädt Zertifikat und Schlüssel aus dem NVS oder aus der Firmware, `None` für reines HTTP.
+pub fn load(partition: &EspDefaultNvsPartition) -> Result<Option<TlsIdentity>> {
+    if let Some((cert, key)) = config::load_tls_identity(partition)? {
+        info!("HTTPS mit Zertifikat aus dem NVS");
+        // Der Server braucht die Daten für seine ganze Laufzeit, geladen wird nur einmal beim Start
+        return Ok(Some(TlsIdentity {
+            cert: X509::pem_until_nul(leak_with_nul(cert)),
+            key: X509::pem_until_nul(leak_with_nul(key)),
+        }));
+    }
+    if !EMBEDDED_CERT.is_empty() && !EMBEDDED_KEY.is_empty() {
+        info!("HTTPS mit eingebautem Zertifikat");
+        return Ok(Some(TlsIdentity {
+            cert: X509::pem_until_nul(EMBEDDED_CERT),
+            key: X509::pem_until_nul(EMBEDDED_KEY),
+        }));
+    }
+    Ok(None)
+}
+
+fn leak_with_nul(pem: String) -> &'static [u8] {
+    let mut bytes = pem.into_bytes();
+    bytes.push(0);
+    Box::leak(bytes.into_boxed_slice())
+}
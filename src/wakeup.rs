@@ -0,0 +1,35 @@
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// Weckt die Hauptschleife vor Ablauf ihres Takts, sobald sich etwas Sichtbares geändert hat.
+///
+/// Mehrere [`notify`](Wakeup::notify) vor dem nächsten [`wait`](Wakeup::wait) fallen zu einem
+/// Durchlauf zusammen, ein Schwall von Requests zeichnet das Display also nicht einzeln neu.
+pub struct Wakeup {
+    pending: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Wakeup {
+    pub fn new() -> Self {
+        Self {
+            pending: Mutex::new(false),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn notify(&self) {
+        *self.pending.lock().unwrap() = true;
+        self.condvar.notify_one();
+    }
+
+    /// Wartet auf ein [`notify`](Wakeup::notify), höchstens `timeout`, und liefert, ob eins kam.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let pending = self.pending.lock().unwrap();
+        let (mut pending, _) = self
+            .condvar
+            .wait_timeout_while(pending, timeout, |pending| !*pending)
+            .unwrap();
+        std::mem::take(&mut *pending)
+    }
+}
@@ -0,0 +1,82 @@
+use log::*;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+/// Pfad, den die Lebendprüfung abfragt. Wird nicht geloggt.
+pub const PROBE_PATH: &str = "/watchdog";
+
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Nach so vielen Fehlschlägen in Folge wird neu gestartet.
+const MAX_FAILURES: u32 = 3;
+
+/// Prüft regelmäßig, ob der HTTP-Server noch antwortet.
+///
+/// Der Task-Watchdog sieht nur die Hauptschleife. Hängt dagegen der Task des HTTP-Servers,
+/// nimmt lwIP Verbindungen zwar noch an, beantwortet werden sie aber nicht mehr.
+///
+/// Mit HTTPS wird kein Handshake gemacht: der Klartext-Request bringt einen arbeitenden Server
+/// dazu, die Verbindung sofort zu beenden, nur ein hängender lässt ihn unbeantwortet.
+pub struct HttpProbe {
+    addr: SocketAddr,
+    tls: bool,
+    last_probe: Instant,
+    failures: u32,
+}
+
+impl HttpProbe {
+    pub fn new(port: u16, tls: bool) -> Self {
+        Self {
+            addr: SocketAddr::from(([127, 0, 0, 1], port)),
+            tls,
+            last_probe: Instant::now(),
+            failures: 0,
+        }
+    }
+
+    /// Fragt den Server ab, sobald das Intervall abgelaufen ist, und startet das Gerät neu,
+    /// wenn er zu oft nicht antwortet.
+    pub fn check(&mut self) {
+        if self.last_probe.elapsed() < PROBE_INTERVAL {
+            return;
+        }
+        self.last_probe = Instant::now();
+
+        match self.probe() {
+            Ok(()) => self.failures = 0,
+            Err(err) => {
+                self.failures += 1;
+                warn!("HTTP-Server antwortet nicht ({}/{}): {}", self.failures, MAX_FAILURES, err);
+                if self.failures >= MAX_FAILURES {
+                    error!("HTTP-Server hängt, starte neu");
+                    unsafe { esp_idf_sys::esp_restart() };
+                }
+            }
+        }
+    }
+
+    fn probe(&self) -> std::io::Result<()> {
+        let mut stream = TcpStream::connect_timeout(&self.addr, PROBE_TIMEOUT)?;
+        stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+        stream.set_write_timeout(Some(PROBE_TIMEOUT))?;
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", PROBE_PATH)?;
+
+        let mut buf = [0u8; 12];
+        if self.tls {
+            // Schließen, TLS-Alert oder Reset: Hauptsache, der Server hat reagiert
+            return match stream.read(&mut buf) {
+                Err(err) if matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
+                    Err(err)
+                }
+                _ => Ok(()),
+            };
+        }
+        stream.read_exact(&mut buf)?;
+        if buf.starts_with(b"HTTP/1.1 200") {
+            Ok(())
+        } else {
+            Err(std::io::Error::new(std::io::ErrorKind::Other, "unerwartete Antwort"))
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Allgemeiner Webhook: jedes Relais-Ereignis geht als kleines JSON per POST an `WEBHOOK_URL`.
+//!
+//! Die Ereignisse kommen wie beim Syslog über einen Listener der [`LogQueue`], gemeldet werden
+//! aber nur Einträge mit einer [`Event`]-Art: erfolgreiche Schaltvorgänge (HTTP, Taster, MQTT,
+//! CoAP, Zeitplan) und Ereignisse wie Sicherheitsabschaltung oder Rückmeldungsfehler:
+//!
+//! ```json
+//! { "event": "switched", "detail": "/push 500ms", "timestamp": 1700000000, "source": "http",
+//!   "device": "doofman", "relay": "door", "state": "on" }
+//! ```
+//!
+//! `event` ist einer der festen Namen aus [`Event::name`], `detail` der Text des Logeintrags und
+//! nur zum Anzeigen gedacht. `timestamp` ist `null`, solange die Uhr nicht synchronisiert ist. Gesendet wird aus einem
+//! eigenen Thread mit kurzem Zeitlimit und höchstens [`MAX_ATTEMPTS`] Versuchen am Stück. Ohne
+//! WLAN oder bei einem nicht erreichbaren Empfänger warten die Ereignisse im [`Outbox`]-Puffer
+//! und gehen nach dem Wiederverbinden in der richtigen Reihenfolge raus, mit `OFFLINE_EVENTS=drop`
+//! werden sie verworfen. Das Relais wartet nie auf den Empfänger.
+//!
+//! Mit `WEBHOOK_SECRET` trägt jeder Request `X-Doofman-Signature: sha256=<hex>`, die
+//! HMAC-SHA256 des Bodys mit dem Secret als Schlüssel.
+
+use anyhow::{bail, Result};
+use log::*;
+use serde::Serialize;
+use std::sync::mpsc::{self, RecvTimeoutError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config::WebhookConfig;
+use crate::https;
+use crate::logs::{Event, LogQueue, Source};
+use crate::outbox::Outbox;
+use crate::relays::RelaySet;
+use crate::wifi::{LinkState, NetworkStatus};
+
+// So viele Ereignisse warten höchstens auf den Versand
+const QUEUE_LEN: usize = 8;
+// Zeitlimit für einen Versuch, kurz, damit sich bei einem toten Empfänger nichts staut
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+/// Versuche je Ereignis, dazwischen 1s, 2s, ... Pause.
+const MAX_ATTEMPTS: u32 = 3;
+// So oft wird ein gefüllter Puffer ohne neue Ereignisse erneut versucht
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+const SIGNATURE_HEADER: &str = "X-Doofman-Signature";
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    event: &'static str,
+    detail: &'a str,
+    timestamp: Option<i64>,
+    source: &'a str,
+    device: &'a str,
+    relay: &'a str,
+    state: &'static str,
+}
+
+/// Startet den Sende-Thread und hängt ihn an `log_queue`, `network` zeigt, ob gesendet werden kann.
+pub fn start(
+    config: WebhookConfig,
+    hostname: &str,
+    log_queue: &LogQueue,
+    relays: RelaySet,
+    network: Arc<Mutex<NetworkStatus>>,
+) -> Result<()> {
+    let (bodies, receiver) = mpsc::sync_channel::<String>(QUEUE_LEN);
+    info!(
+        "Webhook an {}{}",
+        config.url,
+        if config.secret.is_some() { ", signiert" } else { "" }
+    );
+
+    std::thread::Builder::new()
+        .name("webhook".into())
+        .stack_size(https::STACK_SIZE)
+        .spawn(move || {
+            let mut outbox = Outbox::from_env("Webhook");
+            loop {
+                match receiver.recv_timeout(RETRY_INTERVAL) {
+                    Ok(body) => outbox.push(body),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                // Was während eines langsamen Versands ankam, gleich mitnehmen
+                while let Ok(body) = receiver.try_recv() {
+                    outbox.push(body);
+                }
+                let online = network.lock().unwrap().link == LinkState::Connected;
+                outbox.flush(online, |body| deliver(&config, body));
+            }
+        })?;
+
+    let hostname = hostname.to_string();
+    log_queue.subscribe(move |entry| {
+        let Some(event) = entry.event else {
+            return;
+        };
+        // Das Relais aus `/relay/<name>/...`, sonst das erste
+        let named = relay_name(&entry.path)
+            .and_then(|name| relays.get(name))
+            .or_else(|| relays.iter().next());
+        let Some(named) = named else {
+            return;
+        };
+        let payload = Payload {
+            event: event.name(),
+            detail: &entry.path,
+            timestamp: entry.timestamp,
+            source: event_source(event, entry.source),
+            device: &hostname,
+            relay: &named.name,
+            state: if named.relay.state().is_active() { "on" } else { "off" },
+        };
+        let Ok(body) = serde_json::to_string(&payload) else {
+            return;
+        };
+        match bodies.try_send(body) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("Webhook-Warteschlange voll, Ereignis verworfen"),
+            Err(TrySendError::Disconnected(_)) => warn!("Webhook-Thread läuft nicht mehr"),
+        }
+    });
+    Ok(())
+}
+
+/// Auslöser für `source`: der Kanal eines Schaltbefehls, sonst Zeitplan oder das Gerät selbst.
+fn event_source(event: Event, source: Option<Source>) -> &'static str {
+    match event {
+        Event::Switched => source.map_or("http", Source::label),
+        Event::ScheduledPush => "schedule",
+        Event::SafetyCutoff | Event::RelayMismatch | Event::SequenceFinished | Event::LatchRestored => "device",
+    }
+}
+
+/// Name aus `/relay/<name>/<aktion>`, `None` für die Aliase des ersten Relais.
+fn relay_name(path: &str) -> Option<&str> {
+    let rest = path.split(' ').next()?.strip_prefix("/relay/")?;
+    rest.split_once('/').map(|(name, _)| name)
+}
+
+/// Sendet `body` mit bis zu [`MAX_ATTEMPTS`] Versuchen, Antworten mit 4xx werden nicht wiederholt.
+/// `false`, wenn der Empfänger nicht erreichbar war und es später noch einmal versucht werden kann.
+fn deliver(config: &WebhookConfig, body: &str) -> bool {
+    let signature = match &config.secret {
+        Some(secret) => match hmac_sha256(secret.as_bytes(), body.as_bytes()) {
+            Ok(mac) => {
+                let hex: String = mac.iter().map(|byte| format!("{:02x}", byte)).collect();
+                Some(format!("sha256={}", hex))
+            }
+            Err(err) => {
+                // Ohne Signatur würde der Empfänger ablehnen, ein neuer Versuch hilft nicht
+                warn!("Webhook nicht signiert, Ereignis verworfen: {:?}", err);
+                return true;
+            }
+        },
+        None => None,
+    };
+    let headers: Vec<_> = signature.iter().map(|signature| (SIGNATURE_HEADER, signature.as_str())).collect();
+    for attempt in 1..=MAX_ATTEMPTS {
+        match https::post_json(&config.url, body, &headers, SEND_TIMEOUT) {
+            Ok(status) if (200..300).contains(&status) => return true,
+            Ok(status) if (400..500).contains(&status) => {
+                warn!("Webhook abgelehnt mit {}", status);
+                return true;
+            }
+            Ok(status) => warn!("Webhook antwortet mit {} (Versuch {})", status, attempt),
+            Err(err) => warn!("Webhook nicht gesendet (Versuch {}): {:?}", attempt, err),
+        }
+        if attempt < MAX_ATTEMPTS {
+            std::thread::sleep(Duration::from_secs(attempt as u64));
+        }
+    }
+    warn!("Webhook nach {} Versuchen nicht zugestellt", MAX_ATTEMPTS);
+    false
+}
+
+/// HMAC-SHA256 über das mbedTLS von ESP-IDF.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Result<[u8; 32]> {
+    let mut mac = [0u8; 32];
+    let result = unsafe {
+        let info = esp_idf_sys::mbedtls_md_info_from_type(esp_idf_sys::mbedtls_md_type_t_MBEDTLS_MD_SHA256);
+        esp_idf_sys::mbedtls_md_hmac(info, key.as_ptr(), key.len(), message.as_ptr(), message.len(), mac.as_mut_ptr())
+    };
+    if result != 0 {
+        bail!("mbedtls_md_hmac fehlgeschlagen: {}", result);
+    }
+    Ok(mac)
+}
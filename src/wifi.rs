@@ -0,0 +1,513 @@
+use anyhow::{anyhow, Result};
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::ipv4::{self, ClientSettings, Mask, Subnet};
+use esp_idf_svc::netif::{EspNetif, NetifConfiguration};
+use esp_idf_svc::wifi::{
+    AccessPointConfiguration, AuthMethod, ClientConfiguration, Configuration, EspWifi, ScanMethod, ScanSortMethod,
+};
+use log::*;
+use std::ffi::{c_void, CString};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Once, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::config::{parse_bssid, StaticIpConfig, WifiConfig};
+use crate::lang::{self, Msg};
+
+/// So lange wird ohne `WIFI_CONNECT_TIMEOUT_S` pro Netz auf die Verbindung gewartet.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// So oft werden ohne `WIFI_CONNECT_ATTEMPTS` alle Netze probiert, bevor der AP-Modus startet.
+const DEFAULT_CONNECT_ATTEMPTS: u32 = 2;
+
+/// Wartezeit pro Verbindungsversuch aus `WIFI_CONNECT_TIMEOUT_S`.
+pub fn connect_timeout() -> Duration {
+    option_env!("WIFI_CONNECT_TIMEOUT_S")
+        .and_then(|value| value.parse().ok())
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_CONNECT_TIMEOUT)
+}
+
+fn connect_attempts() -> u32 {
+    option_env!("WIFI_CONNECT_ATTEMPTS")
+        .and_then(|value| value.parse().ok())
+        .filter(|attempts| *attempts > 0)
+        .unwrap_or(DEFAULT_CONNECT_ATTEMPTS)
+}
+
+/// Warum keine Verbindung zustande kam, nach Aussagekraft geordnet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConnectFailure {
+    /// Keine Antwort innerhalb der Wartezeit
+    Timeout,
+    /// Das Netz war nicht zu sehen
+    NotFound,
+    /// Der Access Point hat die Anmeldung abgelehnt, meist ein falsches Passwort
+    AuthFailed,
+}
+
+impl ConnectFailure {
+    fn from_reason(reason: u16) -> Option<Self> {
+        use esp_idf_sys::*;
+        match reason as u32 {
+            wifi_err_reason_t_WIFI_REASON_AUTH_FAIL
+            | wifi_err_reason_t_WIFI_REASON_AUTH_EXPIRE
+            | wifi_err_reason_t_WIFI_REASON_4WAY_HANDSHAKE_TIMEOUT
+            | wifi_err_reason_t_WIFI_REASON_HANDSHAKE_TIMEOUT
+            | wifi_err_reason_t_WIFI_REASON_MIC_FAILURE
+            | wifi_err_reason_t_WIFI_REASON_802_1X_AUTH_FAILED => Some(ConnectFailure::AuthFailed),
+            wifi_err_reason_t_WIFI_REASON_NO_AP_FOUND => Some(ConnectFailure::NotFound),
+            wifi_err_reason_t_WIFI_REASON_BEACON_TIMEOUT
+            | wifi_err_reason_t_WIFI_REASON_ASSOC_FAIL
+            | wifi_err_reason_t_WIFI_REASON_CONNECTION_FAIL => Some(ConnectFailure::Timeout),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConnectFailure::Timeout => "timeout",
+            ConnectFailure::NotFound => "not_found",
+            ConnectFailure::AuthFailed => "auth_failed",
+        }
+    }
+
+    /// Zeile für das Display.
+    #[cfg(feature = "display")]
+    pub fn message(self) -> &'static str {
+        match self {
+            ConnectFailure::Timeout => Msg::WifiTimeout,
+            ConnectFailure::NotFound => Msg::WifiNotFound,
+            ConnectFailure::AuthFailed => Msg::WifiAuthFailed,
+        }
+        .text()
+    }
+}
+
+// Grund der letzten Trennung laut Treiber, 0 seit dem letzten Verbindungsversuch keiner
+static DISCONNECT_REASON: AtomicU16 = AtomicU16::new(0);
+
+unsafe extern "C" fn on_disconnected(
+    _arg: *mut c_void,
+    _base: esp_idf_sys::esp_event_base_t,
+    _id: i32,
+    data: *mut c_void,
+) {
+    let event = &*(data as *const esp_idf_sys::wifi_event_sta_disconnected_t);
+    DISCONNECT_REASON.store(event.reason as u16, Ordering::Relaxed);
+}
+
+/// Merkt sich den Trennungsgrund, den `EspWifi` nicht weitergibt.
+fn watch_disconnects() {
+    static REGISTERED: Once = Once::new();
+    REGISTERED.call_once(|| {
+        let result = esp_idf_sys::esp!(unsafe {
+            esp_idf_sys::esp_event_handler_register(
+                esp_idf_sys::WIFI_EVENT,
+                esp_idf_sys::wifi_event_t_WIFI_EVENT_STA_DISCONNECTED as i32,
+                Some(on_disconnected),
+                std::ptr::null_mut(),
+            )
+        });
+        if let Err(err) = result {
+            warn!("Trennungsgrund nicht verfügbar: {:?}", err);
+        }
+    });
+}
+
+/// Name des offenen Access Points für die Einrichtung.
+pub const AP_SSID: &str = "doofman-setup";
+
+/// Längste SSID und längstes Passwort, die der Treiber annimmt, in Byte.
+pub const MAX_SSID_LEN: usize = 32;
+pub const MAX_PASSWORD_LEN: usize = 64;
+
+fn client_configuration(credentials: &WifiConfig) -> Result<Configuration> {
+    let ssid = credentials.ssid.as_str().try_into().map_err(|_| anyhow!("SSID zu lang"))?;
+    let bssid = match &credentials.bssid {
+        Some(bssid) => Some(parse_bssid(bssid).ok_or_else(|| anyhow!("ungültige BSSID {:?}", bssid))?),
+        None => None,
+    };
+    // Der schnelle Scan hört auf, sobald ein Access Point mit der SSID antwortet. Versteckte
+    // Netze antworten nur auf gezielte Anfragen, dafür alle Kanäle absuchen und den stärksten
+    // nehmen, falls Kanal oder BSSID fehlen.
+    let scan_method = if credentials.hidden {
+        ScanMethod::CompleteScan(ScanSortMethod::Signal)
+    } else {
+        ScanMethod::default()
+    };
+    let base = ClientConfiguration {
+        ssid,
+        bssid,
+        channel: credentials.channel,
+        scan_method,
+        ..Default::default()
+    };
+    // Bei WPA2-Enterprise läuft das Passwort über den Supplicant, nicht über die Konfiguration
+    if credentials.enterprise.is_some() {
+        return Ok(Configuration::Client(ClientConfiguration {
+            auth_method: AuthMethod::WPA2Enterprise,
+            ..base
+        }));
+    }
+    Ok(Configuration::Client(ClientConfiguration {
+        password: credentials
+            .password
+            .as_str()
+            .try_into()
+            .map_err(|_| anyhow!("Passwort zu lang"))?,
+        ..base
+    }))
+}
+
+/// Richtet den WPA2-Enterprise-Supplicant für `credentials` ein oder schaltet ihn bei PSK-Netzen ab.
+///
+/// Ein CA-Zertifikat im PEM-Format aus `WIFI_EAP_CA_CERT` wird zur Prüfung des Servers benutzt,
+/// ohne wird dem Server ungeprüft vertraut.
+fn configure_enterprise(credentials: &WifiConfig) -> Result<()> {
+    use esp_idf_sys::{esp, esp_wifi_sta_wpa2_ent_disable};
+
+    let Some(enterprise) = &credentials.enterprise else {
+        esp!(unsafe { esp_wifi_sta_wpa2_ent_disable() })?;
+        return Ok(());
+    };
+    unsafe {
+        esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_set_identity(
+            enterprise.identity.as_ptr(),
+            enterprise.identity.len() as i32,
+        ))?;
+        esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_set_username(
+            enterprise.username.as_ptr(),
+            enterprise.username.len() as i32,
+        ))?;
+        esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_set_password(
+            credentials.password.as_ptr(),
+            credentials.password.len() as i32,
+        ))?;
+        if let Some(ca_cert) = option_env!("WIFI_EAP_CA_CERT").filter(|cert| !cert.is_empty()) {
+            // mbedTLS erwartet beim PEM-Format die abschließende Null mitgezählt, der Supplicant
+            // behält nur den Zeiger, deshalb einmal für die ganze Laufzeit anlegen
+            static CA_CERT: OnceLock<CString> = OnceLock::new();
+            let ca_cert = CA_CERT.get_or_init(|| CString::new(ca_cert).unwrap_or_default());
+            esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_set_ca_cert(
+                ca_cert.as_ptr() as *const u8,
+                ca_cert.to_bytes_with_nul().len() as i32,
+            ))?;
+        }
+        esp!(esp_idf_sys::esp_wifi_sta_wpa2_ent_enable())?;
+    }
+    info!("WPA2-Enterprise für \"{}\" als {}", credentials.ssid, enterprise.username);
+    Ok(())
+}
+
+/// Ersetzt das Station-Netif durch eines mit fester IP, DHCP ist damit abgeschaltet.
+pub fn apply_static_ip(wifi: &mut EspWifi<'static>, config: &StaticIpConfig) -> Result<()> {
+    let netif = EspNetif::new_with_conf(&NetifConfiguration {
+        ip_configuration: ipv4::Configuration::Client(ipv4::ClientConfiguration::Fixed(ClientSettings {
+            ip: config.ip,
+            subnet: Subnet {
+                gateway: config.gateway,
+                mask: Mask(config.prefix_len),
+            },
+            dns: config.dns,
+            secondary_dns: None,
+        })),
+        ..NetifConfiguration::wifi_default_client()
+    })?;
+    wifi.swap_netif_sta(netif)?;
+    info!("Feste IP {}/{} über {}", config.ip, config.prefix_len, config.gateway);
+    Ok(())
+}
+
+/// Verbindet sich als Station mit dem WLAN und wartet höchstens `timeout`.
+///
+/// Kam die Verbindung in dieser Zeit nicht zustande, steht im inneren `Err` warum. Lehnt der
+/// Access Point die Anmeldung ab, wird nicht bis zum Ende der Wartezeit gewartet.
+pub fn connect(
+    wifi: &mut EspWifi<'static>,
+    credentials: &WifiConfig,
+    timeout: Duration,
+) -> Result<Result<(), ConnectFailure>> {
+    wifi.set_configuration(&client_configuration(credentials)?)?;
+    if !wifi.is_started()? {
+        wifi.start()?;
+        info!("{}", Msg::WifiStarted.text());
+    }
+    configure_enterprise(credentials)?;
+    watch_disconnects();
+    DISCONNECT_REASON.store(0, Ordering::Relaxed);
+    // Ein fehlgeschlagener Start des Verbindungsaufbaus führt nur zum Timeout, nicht zum Abbruch
+    if let Err(err) = wifi.connect() {
+        warn!("Verbindungsaufbau fehlgeschlagen: {:?}", err);
+    }
+    info!("{}", lang::connecting(&credentials.ssid, credentials.hidden));
+
+    // Warte auf Verbindung, Treiberfehler zählen dabei als "noch nicht verbunden"
+    let started = Instant::now();
+    while started.elapsed() < timeout {
+        let connected = wifi.is_connected().unwrap_or_else(|err| {
+            warn!("WLAN-Status nicht lesbar: {:?}", err);
+            false
+        });
+        if connected && wifi.sta_netif().is_up().unwrap_or(false) {
+            info!("{}", Msg::WifiConnected.text());
+            enable_ipv6(wifi);
+            return Ok(Ok(()));
+        }
+        // Ohne automatisches Neuverbinden ist der Versuch mit der Trennung vorbei
+        let reason = DISCONNECT_REASON.load(Ordering::Relaxed);
+        if let Some(failure) = ConnectFailure::from_reason(reason) {
+            warn!("Keine WLAN-Verbindung mit \"{}\": {} (Grund {})", credentials.ssid, failure.as_str(), reason);
+            let _ = wifi.disconnect();
+            return Ok(Err(failure));
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+
+    warn!("Keine WLAN-Verbindung nach {}s", timeout.as_secs());
+    let _ = wifi.disconnect();
+    Ok(Err(ConnectFailure::Timeout))
+}
+
+/// Probiert die bekannten Netze nacheinander und liefert das, mit dem es geklappt hat.
+///
+/// Bei mehreren Netzen wird vorher gescannt: zuerst kommt `preferred` (das zuletzt erfolgreiche
+/// Netz), dann die sichtbaren Netze nach Signalstärke, zuletzt die nicht gefundenen, da
+/// versteckte Netze im Scan fehlen.
+///
+/// Klappt es mit keinem, wird das Ganze bis zu `WIFI_CONNECT_ATTEMPTS` Mal wiederholt, ohne die
+/// Netze, die die Anmeldung abgelehnt haben oder sich nicht einrichten ließen. Das `Err` ist dann
+/// der aussagekräftigste Grund.
+/// Vor jedem Verbindungsversuch bekommt `progress` SSID und Nummer des Durchlaufs, etwa für den
+/// Startbildschirm.
+pub fn connect_any(
+    wifi: &mut EspWifi<'static>,
+    networks: &[WifiConfig],
+    preferred: Option<&str>,
+    timeout: Duration,
+    mut progress: impl FnMut(&str, u32),
+) -> Result<WifiConfig, ConnectFailure> {
+    let visible = if networks.len() > 1 { scan(wifi) } else { Vec::new() };
+
+    let mut ordered: Vec<&WifiConfig> = networks.iter().collect();
+    ordered.sort_by_key(|network| {
+        let rssi = visible
+            .iter()
+            .find(|(ssid, _)| *ssid == network.ssid)
+            .map(|(_, rssi)| *rssi);
+        (
+            Some(network.ssid.as_str()) != preferred,
+            rssi.is_none(),
+            std::cmp::Reverse(rssi.unwrap_or(i8::MIN)),
+        )
+    });
+
+    let mut failure = ConnectFailure::Timeout;
+    for attempt in 1..=connect_attempts() {
+        if ordered.is_empty() {
+            break;
+        }
+        if attempt > 1 {
+            info!("WLAN-Verbindung, Versuch {}", attempt);
+        }
+        let mut rejected = Vec::new();
+        for network in &ordered {
+            progress(&network.ssid, attempt);
+            let result = match connect(wifi, network, timeout) {
+                Ok(result) => result,
+                Err(err) => {
+                    // Etwa eine zu lange SSID im NVS, das darf den AP-Modus nicht verhindern
+                    warn!("WLAN \"{}\" nicht nutzbar: {:?}", network.ssid, err);
+                    rejected.push(network.ssid.clone());
+                    continue;
+                }
+            };
+            match result {
+                Ok(()) => return Ok((*network).clone()),
+                Err(reason) => {
+                    failure = failure.max(reason);
+                    // Ein abgelehntes Passwort wird beim nächsten Durchlauf nicht richtiger
+                    if reason == ConnectFailure::AuthFailed {
+                        rejected.push(network.ssid.clone());
+                    }
+                }
+            }
+        }
+        ordered.retain(|network| !rejected.contains(&network.ssid));
+    }
+    Err(failure)
+}
+
+/// Sichtbare Netze mit Signalstärke, leer wenn der Scan fehlschlägt.
+fn scan(wifi: &mut EspWifi<'static>) -> Vec<(String, i8)> {
+    let result = (|| -> Result<_> {
+        wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
+        if !wifi.is_started()? {
+            wifi.start()?;
+        }
+        Ok(wifi.scan()?)
+    })();
+    match result {
+        Ok(access_points) => access_points
+            .into_iter()
+            .map(|ap| (ap.ssid.to_string(), ap.signal_strength))
+            .collect(),
+        Err(err) => {
+            warn!("WLAN-Scan fehlgeschlagen: {:?}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// Legt nach dem Verbinden die Link-Local-Adresse an, globale Adressen kommen danach per SLAAC.
+///
+/// Muss nach jedem Verbindungsaufbau erneut passieren, ein Abbruch verwirft alle IPv6-Adressen.
+fn enable_ipv6(wifi: &EspWifi<'static>) {
+    let netif = wifi.sta_netif().handle();
+    if let Err(err) = esp_idf_sys::esp!(unsafe { esp_idf_sys::esp_netif_create_ip6_linklocal(netif) }) {
+        warn!("Keine IPv6-Adresse: {:?}", err);
+    }
+}
+
+/// Alle gültigen IPv6-Adressen der Station, globale vor Link-Local.
+///
+/// Leer, wenn das Netz kein IPv6 anbietet oder die Adressen noch nicht bereit sind.
+pub fn ipv6_addresses(wifi: &EspWifi<'static>) -> Vec<Ipv6Addr> {
+    let mut raw = [esp_idf_sys::esp_ip6_addr_t::default(); esp_idf_sys::LWIP_IPV6_NUM_ADDRESSES as usize];
+    let count = unsafe { esp_idf_sys::esp_netif_get_all_ip6(wifi.sta_netif().handle(), raw.as_mut_ptr()) };
+    let mut addresses: Vec<Ipv6Addr> = raw[..count.max(0) as usize]
+        .iter()
+        .map(|addr| {
+            // lwIP legt die Wörter in Netzwerk-Byte-Reihenfolge ab
+            let mut octets = [0u8; 16];
+            for (chunk, word) in octets.chunks_exact_mut(4).zip(addr.addr) {
+                chunk.copy_from_slice(&word.to_ne_bytes());
+            }
+            Ipv6Addr::from(octets)
+        })
+        .collect();
+    addresses.sort_by_key(is_link_local);
+    addresses
+}
+
+fn is_link_local(addr: &Ipv6Addr) -> bool {
+    addr.segments()[0] & 0xffc0 == 0xfe80
+}
+
+/// Startet einen offenen Access Point für das Einrichtungsportal und liefert dessen IP.
+pub fn start_access_point(wifi: &mut EspWifi<'static>) -> Result<Ipv4Addr> {
+    wifi.set_configuration(&Configuration::AccessPoint(AccessPointConfiguration {
+        ssid: AP_SSID.try_into().map_err(|_| anyhow!("AP-SSID zu lang"))?,
+        auth_method: AuthMethod::None,
+        ..Default::default()
+    }))?;
+    if !wifi.is_started()? {
+        wifi.start()?;
+    }
+
+    let ip = wifi.ap_netif().get_ip_info()?.ip;
+    info!("Access Point \"{}\" gestartet unter {}", AP_SSID, ip);
+    Ok(ip)
+}
+
+/// Aktueller Netzwerkzustand für Anzeige und `/status`.
+#[derive(Clone, Debug)]
+pub struct NetworkStatus {
+    pub link: LinkState,
+    pub ip: String,
+    /// IPv6-Adressen der Station, globale zuerst, leer ohne IPv6 im Netz
+    pub ip6: Vec<Ipv6Addr>,
+    /// SSID des verbundenen Netzes bzw. des eigenen Access Points
+    pub ssid: String,
+    /// Warum der letzte Verbindungsaufbau gescheitert ist, `None` nach Erfolg
+    pub failure: Option<ConnectFailure>,
+}
+
+impl NetworkStatus {
+    /// Adresse für Links auf das Gerät: IPv4, wenn vorhanden, sonst die bevorzugte IPv6-Adresse.
+    pub fn host(&self) -> String {
+        match self.ip6.first() {
+            Some(ip6) if self.ip.is_empty() || self.ip == "0.0.0.0" => format!("[{}]", ip6),
+            _ => self.ip.clone(),
+        }
+    }
+}
+
+/// Verbindungszustand für Anzeige und `/status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkState {
+    Connected,
+    Reconnecting,
+    AccessPoint,
+}
+
+impl LinkState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LinkState::Connected => "connected",
+            LinkState::Reconnecting => "reconnecting",
+            LinkState::AccessPoint => "access_point",
+        }
+    }
+
+    /// Zustand in der Sprache des Displays.
+    #[cfg(feature = "display")]
+    pub fn message(self) -> &'static str {
+        match self {
+            LinkState::Connected => Msg::LinkConnected,
+            LinkState::Reconnecting => Msg::LinkReconnecting,
+            LinkState::AccessPoint => Msg::LinkAccessPoint,
+        }
+        .text()
+    }
+}
+
+// Wartezeit zwischen Verbindungsversuchen, verdoppelt sich bis zur Obergrenze
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Überwacht die Station-Verbindung und verbindet bei Abbruch mit exponentiellem Backoff neu.
+///
+/// `check` blockiert nicht, sondern stößt nur `connect()` an, sobald der nächste Versuch fällig
+/// ist. Der HTTP-Server läuft währenddessen unverändert weiter.
+pub struct Supervisor {
+    backoff: Duration,
+    next_attempt: Option<Instant>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self {
+            backoff: MIN_BACKOFF,
+            next_attempt: None,
+        }
+    }
+
+    pub fn check(&mut self, wifi: &mut EspWifi<'static>) -> LinkState {
+        if wifi.is_connected().unwrap_or(false) {
+            if self.next_attempt.take().is_some() {
+                info!("{}", Msg::WifiReconnected.text());
+                enable_ipv6(wifi);
+            }
+            self.backoff = MIN_BACKOFF;
+            return LinkState::Connected;
+        }
+
+        let now = Instant::now();
+        let next_attempt = *self.next_attempt.get_or_insert_with(|| {
+            warn!("{}", Msg::WifiLost.text());
+            now + MIN_BACKOFF
+        });
+        if now >= next_attempt {
+            info!("Neuer Verbindungsversuch, nächster in {}s", self.backoff.as_secs());
+            if let Err(err) = wifi.connect() {
+                warn!("Verbindungsversuch fehlgeschlagen: {:?}", err);
+            }
+            self.next_attempt = Some(now + self.backoff);
+            self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        }
+        LinkState::Reconnecting
+    }
+}
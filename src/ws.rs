@@ -0,0 +1,87 @@
+use anyhow::Result;
+use esp_idf_svc::http::server::ws::EspHttpWsDetachedSender;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::ws::FrameType;
+use log::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::{Arc, Mutex};
+
+use crate::keepalive;
+use crate::logs::LogQueue;
+use crate::streams::{self, Slot};
+
+// So viele Einträge warten höchstens auf den Versand
+const QUEUE_LEN: usize = 8;
+
+/// Verbundene Clients, jeweils mit Session-ID, abgekoppeltem Sender und Platz aus
+/// [`crate::streams`].
+type Clients = Arc<Mutex<Vec<(i32, EspHttpWsDetachedSender, Slot)>>>;
+
+/// Registriert `/ws/logs` und leitet jeden neuen Logeintrag an alle verbundenen Clients weiter.
+pub fn register_log_stream(server: &mut EspHttpServer<'static>, log_queue: &LogQueue) -> Result<()> {
+    let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+
+    {
+        let clients = clients.clone();
+        server.ws_handler("/ws/logs", move |ws| {
+            let session = ws.session();
+            if ws.is_new() {
+                let mut clients = clients.lock().unwrap();
+                let Some(slot) = streams::acquire() else {
+                    warn!("WebSocket abgelehnt, bereits {} Streaming-Clients", streams::active());
+                    return ws.send(FrameType::Close, &[]);
+                };
+                keepalive::forget(session);
+                clients.push((session, ws.create_detached_sender()?, slot));
+                info!("WebSocket-Client {} verbunden", session);
+            } else if ws.is_closed() {
+                clients.lock().unwrap().retain(|(id, _, _)| *id != session);
+                info!("WebSocket-Client {} getrennt", session);
+            } else {
+                // Eingehende Nachrichten werden nicht ausgewertet, nur gelesen
+                let mut buf = [0u8; 64];
+                ws.recv(&mut buf)?;
+            }
+            Ok::<(), esp_idf_sys::EspError>(())
+        })?;
+    }
+
+    // Der abgekoppelte Sender wartet auf den httpd-Task, deshalb sendet ein eigener Thread statt
+    // des Loggers, der oft selbst ein HTTP-Handler ist
+    let (lines, receiver) = mpsc::sync_channel::<String>(QUEUE_LEN);
+    let overflowed = Arc::new(AtomicBool::new(false));
+    {
+        let overflowed = overflowed.clone();
+        std::thread::Builder::new()
+            .name("ws-logs".into())
+            .stack_size(3072)
+            .spawn(move || {
+                for line in receiver {
+                    // Ohne Sperre senden, sonst wartet der Handler im httpd-Task auf diesen Thread
+                    let mut sending = std::mem::take(&mut *clients.lock().unwrap());
+                    // Wem Einträge fehlen, der soll neu verbinden statt eine lückenhafte Liste zu zeigen
+                    if overflowed.swap(false, Ordering::Relaxed) && !sending.is_empty() {
+                        warn!("{} WebSocket-Client(s) getrennt, Warteschlange war voll", sending.len());
+                        for (_, sender, _) in &mut sending {
+                            let _ = sender.send(FrameType::Close, &[]);
+                        }
+                        sending.clear();
+                    }
+                    // Clients, an die nicht mehr gesendet werden kann, fliegen raus
+                    sending.retain_mut(|(_, sender, _)| sender.send(FrameType::Text(false), line.as_bytes()).is_ok());
+                    let mut clients = clients.lock().unwrap();
+                    sending.append(&mut clients);
+                    *clients = sending;
+                }
+            })?;
+    }
+
+    log_queue.subscribe(move |entry| match lines.try_send(entry.to_string()) {
+        Ok(()) => {}
+        Err(TrySendError::Full(_)) => overflowed.store(true, Ordering::Relaxed),
+        Err(TrySendError::Disconnected(_)) => warn!("WebSocket-Thread läuft nicht mehr"),
+    });
+
+    Ok(())
+}